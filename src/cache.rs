@@ -0,0 +1,172 @@
+//! Persistent cache of item timestamps.
+//!
+//! With `--source-metadata-cache`, the timestamp each item's age is counted
+//! from is stored in the base folder, together with the item's modification
+//! time and size and where the timestamp came from. On the next run, items
+//! whose modification time and size are unchanged reuse the stored timestamp
+//! instead of looking it up again (which, for the `exif` age source, means
+//! reading and parsing the file), unless it is to come from elsewhere now.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File name of the cache inside the base folder.
+pub const CACHE_NAME: &str = ".refile-cache.json";
+
+/// A point in time, stored as seconds and nanoseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+    secs: u64,
+    nanos: u32,
+}
+
+impl Stamp {
+    /// Converts a point in time, if it isn't before the Unix epoch.
+    fn from_system_time(time: SystemTime) -> Option<Self> {
+        let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            secs: since_epoch.as_secs(),
+            nanos: since_epoch.subsec_nanos(),
+        })
+    }
+
+    fn to_system_time(self) -> SystemTime {
+        UNIX_EPOCH + Duration::new(self.secs, self.nanos)
+    }
+}
+
+/// What was known about an item when its timestamp was looked up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheEntry {
+    /// Modification time of the item, the cache key's validity check
+    mtime: Stamp,
+    /// Size of the item in bytes
+    size: u64,
+    /// Where the timestamp came from, e.g. `exif/mtime` (empty in caches
+    /// written before it was recorded, which are never reused)
+    #[serde(default)]
+    source: String,
+    /// The timestamp the item's age is counted from
+    timestamp: Stamp,
+}
+
+/// Item timestamps from the previous run, and those looked up in this one.
+#[derive(Debug, Default)]
+pub struct MetadataCache {
+    previous: BTreeMap<PathBuf, CacheEntry>,
+    current: BTreeMap<PathBuf, CacheEntry>,
+    hits: usize,
+}
+
+impl MetadataCache {
+    /// Loads the cache stored in a base folder.
+    ///
+    /// A missing cache file yields an empty cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache exists but cannot be read or parsed.
+    pub fn load(refile_base: &Path) -> io::Result<Self> {
+        let path = cache_path(refile_base);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        let previous = serde_json::from_str(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse metadata cache {}: {e}", path.display()),
+            )
+        })?;
+        Ok(Self {
+            previous,
+            ..Self::default()
+        })
+    }
+
+    /// Stores the entries used in this run in a base folder, replacing the
+    /// previous cache. Entries of items not seen in this run are dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be written.
+    pub fn save(&self, refile_base: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(&self.current).map_err(io::Error::other)?;
+        fs::write(cache_path(refile_base), json + "\n")
+    }
+
+    /// Returns the timestamp an item's age is counted from.
+    ///
+    /// The stored timestamp is reused if the item's modification time and
+    /// size are unchanged and it came from the same `source` (see
+    /// [`crate::filesystem::timestamp_source`]); otherwise `lookup` is called
+    /// and its result cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `lookup`.
+    pub fn timestamp(
+        &mut self,
+        path: &Path,
+        source: &str,
+        lookup: impl FnOnce() -> io::Result<SystemTime>,
+    ) -> io::Result<SystemTime> {
+        let Some((mtime, size)) = fs::metadata(path)
+            .ok()
+            .and_then(|m| Some((Stamp::from_system_time(m.modified().ok()?)?, m.len())))
+        else {
+            return lookup();
+        };
+
+        if let Some(entry) = self.previous.get(path)
+            && entry.mtime == mtime
+            && entry.size == size
+            && entry.source == source
+        {
+            self.hits += 1;
+            self.current.insert(path.to_path_buf(), entry.clone());
+            return Ok(entry.timestamp.to_system_time());
+        }
+
+        let timestamp = lookup()?;
+        if let Some(stamp) = Stamp::from_system_time(timestamp) {
+            self.current.insert(
+                path.to_path_buf(),
+                CacheEntry {
+                    mtime,
+                    size,
+                    source: source.to_string(),
+                    timestamp: stamp,
+                },
+            );
+        }
+        Ok(timestamp)
+    }
+
+    /// Moves an item's entry along with the item itself.
+    pub fn rename(&mut self, from: &Path, to: &Path) {
+        if let Some(entry) = self.current.remove(from) {
+            self.current.insert(to.to_path_buf(), entry);
+        }
+    }
+
+    /// Returns how many timestamps were reused from the previous run.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+}
+
+/// Returns the path of the cache inside a base folder.
+pub fn cache_path(refile_base: &Path) -> PathBuf {
+    refile_base.join(CACHE_NAME)
+}
+
+/// Returns true if `path` is the cache of the given base folder.
+pub fn is_cache(path: &Path, refile_base: &Path) -> bool {
+    path == cache_path(refile_base)
+}
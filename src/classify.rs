@@ -0,0 +1,108 @@
+//! External bucket choice.
+//!
+//! With `--classify-command`, the bucket of each item is chosen by an external
+//! program instead of by age. The program is run once per item with the item's
+//! path as its only argument and these environment variables:
+//!
+//! - `REFILE_AGE_DAYS`: the item's age in whole days
+//! - `REFILE_SIZE`: the item's size in bytes (recursive for directories)
+//! - `REFILE_IS_DIR`: `true` for directories, `false` otherwise
+//! - `REFILE_AGE_BUCKET`: the bucket refile would pick on its own (by age, or
+//!   by size with `--bucket-mode size`), empty if none matches (see
+//!   `--leave-unmatched`)
+//!
+//! It prints the name of the target bucket on stdout.
+
+use crate::config::{BucketConfig, BucketDef};
+use crate::core::pick_bucket_for;
+use crate::filesystem::item_size;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+/// Asks an external program which bucket an item belongs to.
+///
+/// # Arguments
+///
+/// * `program` - The classify command
+/// * `path` - The item to classify
+/// * `age` - The item's age
+/// * `bucket_config` - The configured buckets
+/// * `unknown_to_catch_all` - Route names that are not a configured bucket to
+///   the catch-all bucket instead of failing
+///
+/// # Returns
+///
+/// The chosen bucket, or `None` for an unknown name routed to a catch-all
+/// bucket that does not exist (see [`BucketConfig::leave_unmatched`]).
+///
+/// # Errors
+///
+/// Returns an error if the program cannot be run, exits unsuccessfully, or
+/// prints a name that is not a configured bucket (unless routed to the
+/// catch-all).
+pub fn classify<'a>(
+    program: &Path,
+    path: &Path,
+    age: Duration,
+    bucket_config: &'a BucketConfig,
+    unknown_to_catch_all: bool,
+) -> io::Result<Option<&'a BucketDef>> {
+    let is_dir = fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+    let size = item_size(path).unwrap_or(0);
+    let output = Command::new(program)
+        .arg(path)
+        .env("REFILE_AGE_DAYS", (age.as_secs() / (24 * 3600)).to_string())
+        .env("REFILE_SIZE", size.to_string())
+        .env("REFILE_IS_DIR", is_dir.to_string())
+        .env(
+            "REFILE_AGE_BUCKET",
+            pick_bucket_for(path, is_dir, age, size, bucket_config).map_or("", BucketDef::name),
+        )
+        .output()
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to run classify command {}: {e}", program.display()),
+            )
+        })?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Classify command {} failed for {} ({}): {}",
+            program.display(),
+            path.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let name = stdout.trim();
+    if let Some(bucket) = bucket_config
+        .all_buckets()
+        .into_iter()
+        .find(|b| b.name() == name)
+    {
+        return Ok(Some(bucket));
+    }
+    if unknown_to_catch_all {
+        return Ok(pick_bucket_for(
+            path,
+            is_dir,
+            Duration::MAX,
+            u64::MAX,
+            bucket_config,
+        ));
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Classify command {} chose unknown bucket \"{name}\" for {}\n\
+             Use --unknown-bucket catch-all to route such items to the catch-all bucket",
+            program.display(),
+            path.display()
+        ),
+    ))
+}
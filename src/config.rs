@@ -1,11 +1,31 @@
-use serde::Deserialize;
-use std::collections::BTreeMap;
-use std::fmt::Write as FmtWrite;
+use crate::core::{DateFormat, Layout};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use globset::{GlobBuilder, GlobMatcher};
+use serde::de::{Error as DeError, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write as FmtWrite};
 use std::fs;
 use std::io;
+use std::num::NonZeroUsize;
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
 
+/// File name of a project config file (see [`find_project_config`]).
+pub const PROJECT_CONFIG_NAME: &str = ".refile.toml";
+
+/// Environment variable holding a bucket spec (`--buckets` format), read with
+/// `--config-env-overlay`.
+pub const BUCKETS_ENV: &str = "REFILE_BUCKETS";
+
+/// Environment variable pinning the current time, in seconds since the Unix
+/// epoch. A testing aid: ages and calendar periods are measured as if refile
+/// ran at that time.
+pub const NOW_ENV: &str = "REFILE_NOW";
+
 /// Errors that can occur during configuration operations.
 #[derive(Debug, Error)]
 pub enum ConfigError {
@@ -34,17 +54,45 @@ pub enum ConfigError {
     MissingConfig(String),
 }
 
-/// Represents a single bucket configuration with name and maximum age.
+/// Represents a single bucket configuration with name and maximum age (or
+/// maximum size, for size buckets, or calendar period, for calendar buckets).
 #[derive(Debug, Clone, PartialEq)]
 pub struct BucketDef {
     name: String,
-    max_age_days: Option<u64>, // None means infinity (catch-all)
+    max_age_days: Option<u64>,      // None means infinity (catch-all)
+    max_size_bytes: Option<u64>,    // None means infinity (catch-all)
+    period: Option<CalendarPeriod>, // None means any time (catch-all)
 }
 
 impl BucketDef {
     /// Creates a new bucket definition.
     pub fn new(name: String, max_age_days: Option<u64>) -> Self {
-        Self { name, max_age_days }
+        Self {
+            name,
+            max_age_days,
+            max_size_bytes: None,
+            period: None,
+        }
+    }
+
+    /// Creates a new size bucket definition (see [`BucketMode::Size`]).
+    pub fn with_max_size(name: String, max_size_bytes: Option<u64>) -> Self {
+        Self {
+            name,
+            max_age_days: None,
+            max_size_bytes,
+            period: None,
+        }
+    }
+
+    /// Creates a new calendar bucket definition (see [`BucketMode::Calendar`]).
+    pub fn with_period(name: String, period: Option<CalendarPeriod>) -> Self {
+        Self {
+            name,
+            max_age_days: None,
+            max_size_bytes: None,
+            period,
+        }
     }
 
     /// Returns the bucket name.
@@ -53,9 +101,237 @@ impl BucketDef {
     }
 
     /// Returns the maximum age in days, or None for catch-all buckets.
+    ///
+    /// With reversed age semantics (see [`BucketConfig::reverse_age`]) this
+    /// is the bucket's minimum age instead.
+    #[cfg(test)]
     pub fn max_age_days(&self) -> Option<u64> {
         self.max_age_days
     }
+
+    /// Returns the calendar period, or None for catch-all buckets.
+    pub fn period(&self) -> Option<CalendarPeriod> {
+        self.period
+    }
+
+    /// Returns the limit compared in the given mode: days or bytes. Calendar
+    /// buckets have periods instead (see [`Self::period`]).
+    pub fn limit(&self, mode: BucketMode) -> Option<u64> {
+        match mode {
+            BucketMode::Age => self.max_age_days,
+            BucketMode::Size => self.max_size_bytes,
+            BucketMode::Calendar => None,
+        }
+    }
+}
+
+/// What bucket limits are compared against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BucketMode {
+    /// The item's age in days
+    #[default]
+    Age,
+    /// The item's size in bytes (recursive for directories)
+    Size,
+    /// The calendar period (this week, last month, ...) of the item's date, in local time
+    Calendar,
+}
+
+impl BucketMode {
+    /// Returns the mode's name, as written in config files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Age => "age",
+            Self::Size => "size",
+            Self::Calendar => "calendar",
+        }
+    }
+}
+
+/// A calendar period for [`BucketMode::Calendar`], relative to the current
+/// date in local time. Weeks start on Monday.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CalendarPeriod {
+    Today,
+    ThisWeek,
+    LastWeek,
+    ThisMonth,
+    LastMonth,
+    ThisYear,
+    LastYear,
+}
+
+impl CalendarPeriod {
+    /// Every period, from the most recent one.
+    pub const ALL: [Self; 7] = [
+        Self::Today,
+        Self::ThisWeek,
+        Self::LastWeek,
+        Self::ThisMonth,
+        Self::LastMonth,
+        Self::ThisYear,
+        Self::LastYear,
+    ];
+
+    /// Returns the period's name, as written in config files and bucket specs.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Today => "today",
+            Self::ThisWeek => "this-week",
+            Self::LastWeek => "last-week",
+            Self::ThisMonth => "this-month",
+            Self::LastMonth => "last-month",
+            Self::ThisYear => "this-year",
+            Self::LastYear => "last-year",
+        }
+    }
+
+    /// Looks a period up by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|period| period.name() == name)
+    }
+}
+
+/// The time zone calendar periods are counted in (see `--time-zone`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CalendarZone {
+    /// The system's local time zone
+    #[default]
+    Local,
+    /// A fixed offset from UTC
+    Fixed(FixedOffset),
+}
+
+impl std::str::FromStr for CalendarZone {
+    type Err = String;
+
+    /// Parses `local`, `utc`, or an offset from UTC such as `+02:00` or `-0530`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "local" => return Ok(Self::Local),
+            "utc" | "z" => return Ok(Self::Fixed(FixedOffset::east_opt(0).expect("UTC is valid"))),
+            _ => {}
+        }
+        let invalid = || format!("expected local, utc or an offset such as +02:00, got '{value}'");
+        let (sign, digits) = match value.split_at_checked(1) {
+            Some(("+", rest)) => (1, rest),
+            Some(("-", rest)) => (-1, rest),
+            _ => return Err(invalid()),
+        };
+        let digits = digits.replace(':', "");
+        if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let (hours, minutes) = digits.split_at(2);
+        let hours: i32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+        if minutes >= 60 {
+            return Err(invalid());
+        }
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .map(Self::Fixed)
+            .ok_or_else(invalid)
+    }
+}
+
+impl CalendarZone {
+    /// Returns the calendar date of `time` in this zone.
+    pub fn date_of(self, time: SystemTime) -> NaiveDate {
+        match self {
+            Self::Local => DateTime::<Local>::from(time).date_naive(),
+            Self::Fixed(offset) => DateTime::<Utc>::from(time)
+                .with_timezone(&offset)
+                .date_naive(),
+        }
+    }
+
+    /// Returns the moment a wall-clock date and time in this zone denotes. A
+    /// time skipped by a daylight saving change is read as UTC.
+    pub fn wall_clock_time(self, datetime: NaiveDateTime) -> SystemTime {
+        match self {
+            Self::Local => datetime
+                .and_local_timezone(Local)
+                .earliest()
+                .map(SystemTime::from),
+            Self::Fixed(offset) => datetime
+                .and_local_timezone(offset)
+                .earliest()
+                .map(SystemTime::from),
+        }
+        .unwrap_or_else(|| datetime.and_utc().into())
+    }
+
+    /// Returns the most recent midnight in this zone, as of `now`.
+    pub fn last_midnight(self, now: SystemTime) -> SystemTime {
+        match self {
+            Self::Local => start_of_day(&DateTime::<Local>::from(now)),
+            Self::Fixed(offset) => start_of_day(&DateTime::<Utc>::from(now).with_timezone(&offset)),
+        }
+        .unwrap_or(now)
+    }
+}
+
+/// Returns the start of `now`'s day in its zone, if that moment exists.
+fn start_of_day<Tz: TimeZone>(now: &DateTime<Tz>) -> Option<SystemTime> {
+    let midnight: NaiveDateTime = now.date_naive().and_hms_opt(0, 0, 0)?;
+    midnight
+        .and_local_timezone(now.timezone())
+        .earliest()
+        .map(SystemTime::from)
+}
+
+/// Strategy used to determine a file's age.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgeSource {
+    /// Filesystem modification time (falls back to creation time).
+    #[default]
+    Mtime,
+    /// EXIF `DateTimeOriginal` tag embedded in images.
+    Exif,
+    /// A `YYYY-MM-DD` date found in the file name.
+    Filename,
+    /// The `date:` field of a text file's YAML front matter.
+    Frontmatter,
+}
+
+impl AgeSource {
+    /// Returns the name used for this source in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Mtime => "mtime",
+            Self::Exif => "exif",
+            Self::Filename => "filename",
+            Self::Frontmatter => "frontmatter",
+        }
+    }
+}
+
+/// Filesystem timestamp an item's age is counted from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Timestamp {
+    /// Last modification
+    #[default]
+    Mtime,
+    /// Last access
+    Atime,
+    /// Last status change (inode change)
+    Ctime,
+    /// Creation (birth), where the filesystem records it
+    Btime,
+}
+
+impl Timestamp {
+    /// Returns the name used for this timestamp on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Mtime => "mtime",
+            Self::Atime => "atime",
+            Self::Ctime => "ctime",
+            Self::Btime => "btime",
+        }
+    }
 }
 
 /// Runtime bucket configuration.
@@ -63,6 +339,20 @@ impl BucketDef {
 pub struct BucketConfig {
     base_folder: String,
     buckets: Vec<BucketDef>,
+    extension_buckets: BTreeMap<String, Vec<BucketDef>>,
+    age_sources: BTreeMap<String, AgeSource>,
+    date_format: DateFormat,
+    age_anchor: Option<SystemTime>,
+    layout: Layout,
+    reverse_age: bool,
+    target_dir: Option<PathBuf>,
+    timestamp: Timestamp,
+    bucket_mode: BucketMode,
+    half_open: bool,
+    leave_unmatched: bool,
+    glyphs: BTreeMap<String, String>,
+    time_zone: CalendarZone,
+    max_files: Option<NonZeroUsize>,
 }
 
 impl BucketConfig {
@@ -76,12 +366,197 @@ impl BucketConfig {
         &self.buckets
     }
 
+    /// Returns the buckets for a file: those configured for its extension
+    /// (case-insensitive) in an `[extensions]` table, or the default buckets.
+    ///
+    /// Directories have no extension; callers pass only files here.
+    pub fn buckets_for(&self, path: &Path) -> &[BucketDef] {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|ext| self.extension_buckets.get(&ext.to_lowercase()))
+            .map_or(&self.buckets, Vec::as_slice)
+    }
+
+    /// Returns every bucket a directory below the base folder can be named
+    /// after: the default buckets, then those only found in per-extension
+    /// bucket sets.
+    pub fn all_buckets(&self) -> Vec<&BucketDef> {
+        let mut all: Vec<&BucketDef> = self.buckets.iter().collect();
+        for bucket in self.extension_buckets.values().flatten() {
+            if !all.iter().any(|b| b.name == bucket.name) {
+                all.push(bucket);
+            }
+        }
+        all
+    }
+
+    /// Returns the age source to use for the given path.
+    ///
+    /// Looks up the path's extension (case-insensitive) in the configured
+    /// `[age_sources]` mapping, defaulting to modification time.
+    pub fn age_source_for(&self, path: &Path) -> AgeSource {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|ext| self.age_sources.get(&ext.to_lowercase()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Sets the age source for an extension, overriding `[age_sources]`.
+    pub fn set_age_source(&mut self, ext: &str, source: AgeSource) {
+        self.age_sources
+            .insert(ext.trim_start_matches('.').to_lowercase(), source);
+    }
+
+    /// Returns the date layout used by the `filename` age source.
+    pub fn date_format(&self) -> &DateFormat {
+        &self.date_format
+    }
+
+    /// Overrides the date layout used by the `filename` age source.
+    pub fn set_date_format(&mut self, date_format: DateFormat) {
+        self.date_format = date_format;
+    }
+
+    /// Returns the filesystem timestamp ages are counted from.
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    /// Counts ages from another filesystem timestamp than modification time.
+    pub fn set_timestamp(&mut self, timestamp: Timestamp) {
+        self.timestamp = timestamp;
+    }
+
+    /// Returns the point in time ages are measured against.
+    ///
+    /// This is the anchor set with [`Self::set_age_anchor`], or the current
+    /// time (see [`current_time`]) if there is none.
+    pub fn age_reference(&self) -> SystemTime {
+        self.age_anchor.unwrap_or_else(current_time)
+    }
+
+    /// Measures every age against one fixed point in time instead of the
+    /// time each item happens to be looked at.
+    pub fn set_age_anchor(&mut self, anchor: SystemTime) {
+        self.age_anchor = Some(anchor);
+    }
+
+    /// Returns the time zone calendar periods are counted in.
+    pub fn time_zone(&self) -> CalendarZone {
+        self.time_zone
+    }
+
+    /// Counts calendar periods in another time zone than the local one.
+    pub fn set_time_zone(&mut self, time_zone: CalendarZone) {
+        self.time_zone = time_zone;
+    }
+
+    /// Returns how many entries a bucket directory holds before spilling
+    /// over into numbered overflow directories (`<bucket>-<n>`), if capped.
+    pub fn max_files(&self) -> Option<NonZeroUsize> {
+        self.max_files
+    }
+
+    /// Caps bucket directories (`--bucket-max-files`), which makes their
+    /// overflow directories count as directories of the bucket.
+    pub fn set_max_files(&mut self, max_files: Option<NonZeroUsize>) {
+        self.max_files = max_files;
+    }
+
+    /// Returns the directory layout below the base folder.
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Overrides the directory layout below the base folder.
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    /// Returns true if bucket ages are lower bounds rather than upper bounds.
+    ///
+    /// Reversed buckets are listed from the oldest lower bound down, and an
+    /// item goes into the first bucket it is at least as old as; the catch-all
+    /// takes the items younger than every bound.
+    pub fn reverse_age(&self) -> bool {
+        self.reverse_age
+    }
+
+    /// Returns whether buckets are chosen by age or by size.
+    pub fn bucket_mode(&self) -> BucketMode {
+        self.bucket_mode
+    }
+
+    /// Returns true if buckets are half-open intervals `[previous, limit)`.
+    ///
+    /// An item then belongs to a bucket if it is at least as old (or large)
+    /// as the previous bucket's limit and younger (or smaller) than its own;
+    /// an item exactly at a limit goes to the next bucket. By default limits
+    /// are inclusive, and such an item stays in the bucket.
+    pub fn half_open(&self) -> bool {
+        self.half_open
+    }
+
+    /// Switches buckets to half-open intervals (see [`Self::half_open`]).
+    pub fn set_half_open(&mut self, half_open: bool) {
+        self.half_open = half_open;
+    }
+
+    /// Returns true if items that fall into no bucket are left where they
+    /// are, so that no catch-all bucket is needed.
+    pub fn leave_unmatched(&self) -> bool {
+        self.leave_unmatched
+    }
+
+    /// Returns a bucket's name for human output, prefixed with its glyph if
+    /// one is configured (e.g. `📦 old-stuff`).
+    ///
+    /// Glyphs never appear in folder names or machine-readable output.
+    pub fn label(&self, name: &str) -> String {
+        match self.glyphs.get(name) {
+            Some(glyph) => format!("{glyph} {name}"),
+            None => name.to_string(),
+        }
+    }
+
+    /// Sets the glyph shown before a bucket's name in human output.
+    pub fn set_glyph(&mut self, name: String, glyph: String) {
+        self.glyphs.insert(name, glyph);
+    }
+
+    /// Drops every glyph, so bucket names are shown plain (`--color never`).
+    pub fn clear_glyphs(&mut self) {
+        self.glyphs.clear();
+    }
+
+    /// Returns the target directory set by the matching config rule, if any.
+    ///
+    /// It takes the place of the target directory given on the command line.
+    pub fn target_dir(&self) -> Option<&Path> {
+        self.target_dir.as_deref()
+    }
+
     /// Creates a new bucket configuration (for testing).
     #[cfg(test)]
     pub fn new_for_test(base_folder: String, buckets: Vec<BucketDef>) -> Self {
         Self {
             base_folder,
             buckets,
+            extension_buckets: BTreeMap::new(),
+            age_sources: BTreeMap::new(),
+            date_format: DateFormat::default(),
+            age_anchor: None,
+            layout: Layout::default(),
+            reverse_age: false,
+            target_dir: None,
+            timestamp: Timestamp::default(),
+            bucket_mode: BucketMode::default(),
+            half_open: false,
+            leave_unmatched: false,
+            glyphs: BTreeMap::new(),
+            time_zone: CalendarZone::Local,
+            max_files: None,
         }
     }
 }
@@ -97,6 +572,20 @@ impl Default for BucketConfig {
                 BucketDef::new("last-months".to_string(), Some(92)),
                 BucketDef::new("old-stuff".to_string(), None),
             ],
+            extension_buckets: BTreeMap::new(),
+            age_sources: BTreeMap::new(),
+            date_format: DateFormat::default(),
+            age_anchor: None,
+            layout: Layout::default(),
+            reverse_age: false,
+            target_dir: None,
+            timestamp: Timestamp::default(),
+            bucket_mode: BucketMode::default(),
+            half_open: false,
+            leave_unmatched: false,
+            glyphs: BTreeMap::new(),
+            time_zone: CalendarZone::Local,
+            max_files: None,
         }
     }
 }
@@ -106,25 +595,158 @@ impl BucketConfig {
     ///
     /// Returns an error if:
     /// - No buckets are defined
-    /// - Age thresholds are not in ascending order
-    /// - No catch-all bucket (with None age) exists
-    /// - Bucket names contain invalid characters
+    /// - Age thresholds are not in ascending order (descending with
+    ///   reversed age semantics)
+    /// - Size thresholds are not in ascending order, or a bucket has an age
+    ///   limit, in size mode
+    /// - No catch-all bucket (with None age or size) exists
+    /// - Bucket names are invalid (empty, `.` or `..`, or with a path
+    ///   separator or control character) or not unique
     pub fn validate(&self) -> Result<(), ConfigError> {
+        self.validate_buckets()?;
+        for (ext, buckets) in &self.extension_buckets {
+            let config = BucketConfig {
+                buckets: buckets.clone(),
+                ..self.clone()
+            };
+            config
+                .validate_buckets()
+                .map_err(|e| ConfigError::InvalidConfig(format!("[extensions.{ext}]: {e}")))?;
+        }
+        Ok(())
+    }
+
+    /// Validates the default bucket set (see [`Self::validate`]).
+    fn validate_buckets(&self) -> Result<(), ConfigError> {
         if self.buckets.is_empty() {
             return Err(ConfigError::InvalidConfig(
                 "At least one bucket must be defined".to_string(),
             ));
         }
 
+        if self.bucket_mode == BucketMode::Calendar {
+            return self.validate_periods();
+        }
+        if let Some(bucket) = self.buckets.iter().find(|b| b.period.is_some()) {
+            return Err(ConfigError::InvalidConfig(format!(
+                "Bucket '{}' has a calendar period, which needs mode = \"calendar\" \
+                 (or --bucket-mode calendar)",
+                bucket.name
+            )));
+        }
+        if self.bucket_mode == BucketMode::Size {
+            return self.validate_sizes();
+        }
+        if let Some(bucket) = self.buckets.iter().find(|b| b.max_size_bytes.is_some()) {
+            return Err(ConfigError::InvalidConfig(format!(
+                "Bucket '{}' has a size limit, which needs mode = \"size\" \
+                 (or --bucket-mode size)",
+                bucket.name
+            )));
+        }
+
         // Check for catch-all bucket
-        if !self.buckets.iter().any(|b| b.max_age_days.is_none()) {
+        if !self.leave_unmatched && !self.buckets.iter().any(|b| b.max_age_days.is_none()) {
             return Err(ConfigError::InvalidConfig(
                 "At least one bucket must have no age limit (null) to catch all old files"
                     .to_string(),
             ));
         }
 
-        // Validate bucket names
+        self.validate_names()?;
+
+        // Check that ages are in ascending order (excluding None), or
+        // descending for lower bounds
+        let mut prev_age: Option<u64> = None;
+        for bucket in &self.buckets {
+            if let Some(age) = bucket.max_age_days {
+                if let Some(prev) = prev_age {
+                    if self.reverse_age && age >= prev {
+                        return Err(ConfigError::InvalidConfig(format!(
+                            "Bucket ages must be in descending order with --reverse-age: {age} >= {prev}"
+                        )));
+                    }
+                    if !self.reverse_age && age <= prev {
+                        return Err(ConfigError::InvalidConfig(format!(
+                            "Bucket ages must be in ascending order: {age} <= {prev}"
+                        )));
+                    }
+                }
+                prev_age = Some(age);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates size buckets: a catch-all, valid names, and ascending sizes.
+    fn validate_sizes(&self) -> Result<(), ConfigError> {
+        // Buckets from config tables carry ages, which mean nothing here
+        if let Some(bucket) = self.buckets.iter().find(|b| b.max_age_days.is_some()) {
+            return Err(ConfigError::InvalidConfig(format!(
+                "Bucket '{}' has an age limit, but size mode needs size limits \
+                 (e.g. --buckets \"small=10MB,large=null\")",
+                bucket.name
+            )));
+        }
+        if !self.leave_unmatched && !self.buckets.iter().any(|b| b.max_size_bytes.is_none()) {
+            return Err(ConfigError::InvalidConfig(
+                "At least one bucket must have no size limit (null) to catch all large files"
+                    .to_string(),
+            ));
+        }
+
+        self.validate_names()?;
+
+        let mut prev_size: Option<u64> = None;
+        for size in self.buckets.iter().filter_map(|b| b.max_size_bytes) {
+            if let Some(prev) = prev_size
+                && size <= prev
+            {
+                return Err(ConfigError::InvalidConfig(format!(
+                    "Bucket sizes must be in ascending order: {size} <= {prev}"
+                )));
+            }
+            prev_size = Some(size);
+        }
+
+        Ok(())
+    }
+
+    /// Validates calendar buckets: every bucket has a period or is the
+    /// catch-all, and there is a catch-all. Periods are checked in the order
+    /// given, so they need no particular order.
+    fn validate_periods(&self) -> Result<(), ConfigError> {
+        if self.reverse_age {
+            return Err(ConfigError::InvalidConfig(
+                "Calendar buckets cannot be used with --reverse-age".to_string(),
+            ));
+        }
+        if let Some(bucket) = self
+            .buckets
+            .iter()
+            .find(|b| b.max_age_days.is_some() || b.max_size_bytes.is_some())
+        {
+            return Err(ConfigError::InvalidConfig(format!(
+                "Bucket '{}' has an age or size limit, but calendar mode needs calendar periods \
+                 (e.g. this-week = \"this-week\")",
+                bucket.name
+            )));
+        }
+        if !self.leave_unmatched && !self.buckets.iter().any(|b| b.period.is_none()) {
+            return Err(ConfigError::InvalidConfig(
+                "At least one bucket must have no period (null) to catch all older files"
+                    .to_string(),
+            ));
+        }
+
+        self.validate_names()
+    }
+
+    /// Checks that every bucket name can name a directory of its own: it is
+    /// not empty, `.` or `..`, holds no path separator nor control character,
+    /// and no other bucket has it.
+    fn validate_names(&self) -> Result<(), ConfigError> {
         for bucket in &self.buckets {
             if bucket.name.is_empty() {
                 return Err(ConfigError::InvalidBucketName(
@@ -132,137 +754,780 @@ impl BucketConfig {
                     "Bucket names cannot be empty".to_string(),
                 ));
             }
+            if bucket.name == "." || bucket.name == ".." {
+                return Err(ConfigError::InvalidBucketName(
+                    bucket.name.clone(),
+                    "is reserved for directory navigation".to_string(),
+                ));
+            }
             if bucket.name.contains('/') || bucket.name.contains('\\') {
                 return Err(ConfigError::InvalidBucketName(
                     bucket.name.clone(),
                     "contains invalid characters (/ or \\)".to_string(),
                 ));
             }
+            if bucket.name.chars().any(char::is_control) {
+                return Err(ConfigError::InvalidBucketName(
+                    bucket.name.escape_default().to_string(),
+                    "contains control characters".to_string(),
+                ));
+            }
         }
 
-        // Check that ages are in ascending order (excluding None)
-        let mut prev_age: Option<u64> = None;
-        for bucket in &self.buckets {
-            if let Some(age) = bucket.max_age_days {
-                if let Some(prev) = prev_age
-                    && age <= prev
-                {
-                    return Err(ConfigError::InvalidConfig(format!(
-                        "Bucket ages must be in ascending order: {age} <= {prev}"
-                    )));
+        // Buckets sharing a name would share a directory
+        let mut seen = BTreeSet::new();
+        let duplicates: BTreeSet<&str> = self
+            .buckets
+            .iter()
+            .map(|b| b.name.as_str())
+            .filter(|name| !seen.insert(*name))
+            .collect();
+        if !duplicates.is_empty() {
+            return Err(ConfigError::InvalidConfig(format!(
+                "Duplicate bucket names: {}",
+                duplicates.into_iter().collect::<Vec<_>>().join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// TOML Configuration Structures
+// ============================================================================
+
+#[derive(Debug, Deserialize)]
+pub struct RefileConfigFile {
+    #[serde(default)]
+    default: Option<DefaultConfig>,
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+    #[serde(default)]
+    age_sources: BTreeMap<String, AgeSource>,
+    #[serde(default)]
+    bucket_glyphs: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DefaultConfig {
+    #[serde(default = "default_base_folder")]
+    base_folder: String,
+    #[serde(default)]
+    mode: Option<BucketMode>,
+    #[serde(deserialize_with = "deserialize_buckets")]
+    buckets: BucketMap,
+    #[serde(default)]
+    extensions: BTreeMap<String, BucketTable>,
+    #[serde(default)]
+    leave_unmatched: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleConfig {
+    path: String,
+    #[serde(default)]
+    base_folder: Option<String>,
+    #[serde(default)]
+    target: Option<String>,
+    /// Wins over other matching rules with a lower priority (default 0)
+    #[serde(default)]
+    priority: Option<i64>,
+    /// Overrides the default section's `mode`
+    #[serde(default)]
+    mode: Option<BucketMode>,
+    #[serde(deserialize_with = "deserialize_buckets")]
+    buckets: BucketMap,
+    /// Bucket sets for files with these extensions, instead of `buckets`
+    #[serde(default)]
+    extensions: BTreeMap<String, BucketTable>,
+    /// Overrides the default section's `leave_unmatched`
+    #[serde(default)]
+    leave_unmatched: Option<bool>,
+}
+
+impl RefileConfigFile {
+    /// Describes every bucket table whose buckets are not written in the
+    /// order they apply in, which is likely not what the user meant.
+    pub fn misordered_buckets(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let Some(default) = &self.default {
+            warnings.extend(default.buckets.misordering("[default.buckets]"));
+            for (ext, table) in &default.extensions {
+                warnings.extend(table.0.misordering(&format!("[default.extensions.{ext}]")));
+            }
+        }
+        for (i, rule) in self.rules.iter().enumerate() {
+            let section = format!("rule {} ({})", i + 1, rule.path);
+            warnings.extend(rule.buckets.misordering(&format!("{section} buckets")));
+            for (ext, table) in &rule.extensions {
+                warnings.extend(table.0.misordering(&format!("{section} .{ext} buckets")));
+            }
+        }
+        warnings
+    }
+}
+
+impl ConfigLayer {
+    /// Describes the layer's bucket table if its buckets are not written in
+    /// the order they apply in.
+    pub fn misordered_buckets(&self) -> Option<String> {
+        self.buckets.misordering("[buckets]")
+    }
+}
+
+impl RuleConfig {
+    /// Returns the rule's bucket mode, falling back to the default section's.
+    fn bucket_mode(&self, config_file: &RefileConfigFile) -> BucketMode {
+        self.mode
+            .or_else(|| config_file.default.as_ref().and_then(|d| d.mode))
+            .unwrap_or_default()
+    }
+}
+
+/// A bucket table of its own, for the files of one extension.
+#[derive(Debug, Clone, Deserialize)]
+struct BucketTable(#[serde(deserialize_with = "deserialize_buckets")] BucketMap);
+
+/// A one-off override of the buckets and base folder, for a single run.
+///
+/// Unlike a full config file, a layer only holds a top-level `base_folder`
+/// and a `[buckets]` table, and is merged on top of whatever else resolves.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLayer {
+    #[serde(default)]
+    base_folder: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_buckets")]
+    buckets: BucketMap,
+}
+
+fn default_base_folder() -> String {
+    "refile".to_string()
+}
+
+/// A bucket's bound in a TOML bucket table; the catch-all bucket has none.
+///
+/// Bounds are ordered by number, whatever the unit, then by period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TableBound {
+    /// A bare number: days, or bytes in size mode
+    Number(u64),
+    /// A size with a unit, such as `"10MB"`, in bytes
+    Size(u64),
+    Period(CalendarPeriod),
+}
+
+impl TableBound {
+    /// Returns the key tables are sorted by.
+    fn sort_key(self) -> (Option<CalendarPeriod>, u64) {
+        match self {
+            Self::Number(n) | Self::Size(n) => (None, n),
+            Self::Period(period) => (Some(period), 0),
+        }
+    }
+}
+
+/// Writes a size in bytes with the largest unit that divides it, as accepted
+/// by `parse_size`.
+fn size_spec(bytes: u64) -> String {
+    [("GB", 1u64 << 30), ("MB", 1 << 20), ("KB", 1 << 10)]
+        .into_iter()
+        .find(|(_, unit)| bytes != 0 && bytes.is_multiple_of(*unit))
+        .map_or_else(
+            || format!("{bytes}B"),
+            |(suffix, unit)| format!("{}{suffix}", bytes / unit),
+        )
+}
+
+/// A bucket table as parsed from TOML, keeping note of whether its buckets
+/// were written in the order they apply in (see [`sorted_table`]).
+#[derive(Debug, Clone, Default)]
+struct BucketMap {
+    bounds: BTreeMap<String, Option<TableBound>>,
+    /// The first bucket written before one that applies ahead of it, and
+    /// that bucket
+    misordered: Option<(String, String)>,
+}
+
+impl BucketMap {
+    /// Describes how the table's buckets are out of order, if they are.
+    fn misordering(&self, section: &str) -> Option<String> {
+        self.misordered.as_ref().map(|(written, first)| {
+            format!(
+                "{section}: '{first}' applies before '{written}' but is written after it; \
+                 buckets are applied from youngest to oldest, with the catch-all last, \
+                 whatever the order they are written in (`refile --normalize-config` \
+                 rewrites them in that order)"
+            )
+        })
+    }
+}
+
+impl Deref for BucketMap {
+    type Target = BTreeMap<String, Option<TableBound>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.bounds
+    }
+}
+
+/// A bucket's bound as written in TOML, before it is parsed.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawAge {
+    Days(u64),
+    Keyword(String),
+}
+
+/// Collects the entries of a TOML table in the order they are written.
+struct WrittenOrder;
+
+impl<'de> Visitor<'de> for WrittenOrder {
+    type Value = Vec<(String, RawAge)>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a table of buckets")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut entries = Vec::new();
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+/// Deserializes a bucket table, where each value is a number of days (of
+/// bytes in size mode), a size such as `"10MB"`, a calendar period name, or
+/// the string `"null"` for the catch-all bucket (TOML has no null value).
+fn deserialize_buckets<'de, D>(deserializer: D) -> Result<BucketMap, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let written = deserializer.deserialize_map(WrittenOrder)?;
+    let names: Vec<String> = written.iter().map(|(name, _)| name.clone()).collect();
+    let bounds: BTreeMap<String, Option<TableBound>> = written
+        .into_iter()
+        .map(|(name, age)| match age {
+            RawAge::Days(days) => Ok((name, Some(TableBound::Number(days)))),
+            RawAge::Keyword(keyword) if keyword == "null" => Ok((name, None)),
+            RawAge::Keyword(keyword) => match CalendarPeriod::from_name(&keyword) {
+                Some(period) => Ok((name, Some(TableBound::Period(period)))),
+                None => match parse_size(&keyword) {
+                    Ok(bytes) => Ok((name, Some(TableBound::Size(bytes)))),
+                    Err(_) => Err(D::Error::custom(format!(
+                        "invalid age '{keyword}' for bucket '{name}': expected a number of days, \
+                         a size (\"10MB\"), a calendar period (today, this-week, last-week, \
+                         this-month, last-month, this-year, last-year) or \"null\""
+                    ))),
+                },
+            },
+        })
+        .collect::<Result<_, _>>()?;
+    let misordered = names
+        .iter()
+        .zip(sorted_table(&bounds))
+        .find(|(written, (first, _))| written.as_str() != *first)
+        .map(|(written, (first, _))| (written.clone(), first.to_string()));
+    Ok(BucketMap { bounds, misordered })
+}
+
+/// Orders a bucket table: TOML tables carry no order once parsed, so buckets
+/// are sorted by bound (ages or sizes ascending, periods from the most
+/// recent) with the catch-all bucket last rather than by name.
+fn sorted_table(map: &BTreeMap<String, Option<TableBound>>) -> Vec<(&str, Option<TableBound>)> {
+    let mut bounded: Vec<_> = map
+        .iter()
+        .map(|(name, bound)| (name.as_str(), *bound))
+        .collect();
+    bounded.sort_by_key(|(_, bound)| (bound.is_none(), bound.map(TableBound::sort_key)));
+    bounded
+}
+
+/// Converts a `BTreeMap` of bucket definitions to a Vec<BucketDef>, in the
+/// order of [`sorted_table`]. Bare numbers are days, or bytes in size mode.
+fn buckets_from_map(
+    map: &BTreeMap<String, Option<TableBound>>,
+    mode: BucketMode,
+) -> Vec<BucketDef> {
+    sorted_table(map)
+        .into_iter()
+        .map(|(name, bound)| {
+            let name = name.to_string();
+            match bound {
+                Some(TableBound::Number(bytes)) if mode == BucketMode::Size => {
+                    BucketDef::with_max_size(name, Some(bytes))
                 }
-                prev_age = Some(age);
+                Some(TableBound::Number(days)) => BucketDef::new(name, Some(days)),
+                Some(TableBound::Size(bytes)) => BucketDef::with_max_size(name, Some(bytes)),
+                Some(TableBound::Period(period)) => BucketDef::with_period(name, Some(period)),
+                None => BucketDef::new(name, None),
             }
+        })
+        .collect()
+}
+
+/// Converts `[extensions]` tables to bucket sets keyed by lowercase extension.
+fn extension_buckets_from(
+    extensions: &BTreeMap<String, BucketTable>,
+    mode: BucketMode,
+) -> BTreeMap<String, Vec<BucketDef>> {
+    extensions
+        .iter()
+        .map(|(ext, table)| {
+            (
+                ext.trim_start_matches('.').to_lowercase(),
+                buckets_from_map(&table.0, mode),
+            )
+        })
+        .collect()
+}
+
+/// Loads the refile configuration from the default config file location.
+///
+/// Returns Ok(None) if the config file doesn't exist.
+pub fn load_config_file() -> Result<Option<RefileConfigFile>, ConfigError> {
+    let config_path = config_file_path()?;
+
+    if !config_path.exists() {
+        return Ok(None);
+    }
+
+    load_config_file_at(&config_path).map(Some)
+}
+
+/// Loads the refile configuration from an explicit path.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read (including when it doesn't
+/// exist) or cannot be parsed.
+pub fn load_config_file_at(config_path: &Path) -> Result<RefileConfigFile, ConfigError> {
+    let contents = fs::read_to_string(config_path).map_err(|e| {
+        ConfigError::Io(io::Error::new(
+            e.kind(),
+            format!(
+                "Failed to read config file {}: {}",
+                config_path.display(),
+                e
+            ),
+        ))
+    })?;
+
+    toml::from_str(&contents).map_err(|e| ConfigError::ParseError(format!("{e}")))
+}
+
+/// Loads a config layer file.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or parsed, or holds anything
+/// other than `base_folder` and `[buckets]`.
+pub fn load_config_layer(path: &Path) -> Result<ConfigLayer, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(|e| {
+        ConfigError::Io(io::Error::new(
+            e.kind(),
+            format!("Failed to read config layer {}: {e}", path.display()),
+        ))
+    })?;
+
+    toml::from_str(&contents)
+        .map_err(|e| ConfigError::ParseError(format!("{}: {e}", path.display())))
+}
+
+/// Finds the project config file for a source directory.
+///
+/// Looks for a `.refile.toml` in `source_dir`, then in each directory above
+/// it, and returns the first one found. The walk stops below the home
+/// directory (which is never searched) or at the filesystem root. A project
+/// config holds the same keys as a config layer (see [`ConfigLayer`]).
+///
+/// Returns `None` if there is none, or if `source_dir` does not exist.
+pub fn find_project_config(source_dir: &Path) -> Option<PathBuf> {
+    let home = dirs::home_dir().and_then(|home| fs::canonicalize(home).ok());
+    let source_dir = fs::canonicalize(source_dir).ok()?;
+
+    source_dir
+        .ancestors()
+        .take_while(|dir| Some(*dir) != home.as_deref())
+        .map(|dir| dir.join(PROJECT_CONFIG_NAME))
+        .find(|file| file.is_file())
+}
+
+/// Validates every bucket set in a config file, independently of any source.
+///
+/// The default section and each rule are turned into a `BucketConfig` and
+/// validated on their own, so a broken rule is reported even if no source
+/// directory currently matches it.
+///
+/// # Errors
+///
+/// Returns the first validation error, prefixed with the section it came from.
+pub fn check_config_file(config_file: &RefileConfigFile) -> Result<(), ConfigError> {
+    if let Some(default) = &config_file.default {
+        let mode = default.mode.unwrap_or_default();
+        let config = BucketConfig {
+            base_folder: default.base_folder.clone(),
+            buckets: buckets_from_map(&default.buckets, mode),
+            extension_buckets: extension_buckets_from(&default.extensions, mode),
+            bucket_mode: mode,
+            leave_unmatched: default.leave_unmatched,
+            ..BucketConfig::default()
+        };
+        config
+            .validate()
+            .map_err(|e| ConfigError::InvalidConfig(format!("[default]: {e}")))?;
+    }
+
+    for (i, rule) in config_file.rules.iter().enumerate() {
+        rule_glob(&rule.path).map_err(|e| {
+            ConfigError::InvalidConfig(format!(
+                "rule {} ({}): invalid path pattern: {e}",
+                i + 1,
+                rule.path
+            ))
+        })?;
+        let mode = rule.bucket_mode(config_file);
+        let config = BucketConfig {
+            base_folder: rule.base_folder.clone().unwrap_or_else(default_base_folder),
+            buckets: buckets_from_map(&rule.buckets, mode),
+            extension_buckets: extension_buckets_from(&rule.extensions, mode),
+            bucket_mode: mode,
+            leave_unmatched: rule.leave_unmatched.unwrap_or_else(|| {
+                config_file
+                    .default
+                    .as_ref()
+                    .is_some_and(|default| default.leave_unmatched)
+            }),
+            ..BucketConfig::default()
+        };
+        config.validate().map_err(|e| {
+            ConfigError::InvalidConfig(format!("rule {} ({}): {e}", i + 1, rule.path))
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Renders a config file in canonical form.
+///
+/// Sections come in a fixed order (`[default]`, `[age_sources]`,
+/// `[bucket_glyphs]`, then each rule), buckets are listed by age with the
+/// catch-all last, and every section gets a short explanatory comment.
+/// Loading the result yields the same configuration, but comments of the
+/// user's own are not kept: the parsed config has no trace of them.
+pub fn normalize_config_file(config_file: &RefileConfigFile) -> String {
+    let mut out = String::new();
+    out.push_str(
+        "# Refile configuration\n\
+         #\n\
+         # Normalized by `refile --normalize-config`: buckets are listed from\n\
+         # youngest to oldest, with the catch-all bucket (\"null\") last.\n",
+    );
+
+    if let Some(default) = &config_file.default {
+        out.push_str("\n# Used when no rule matches the source directory\n[default]\n");
+        writeln!(out, "base_folder = {}", toml_string(&default.base_folder))
+            .expect("Writing to String should not fail");
+        if let Some(mode) = default.mode {
+            writeln!(out, "mode = \"{}\"", mode.name()).expect("Writing to String should not fail");
+        }
+        if default.leave_unmatched {
+            out.push_str("leave_unmatched = true\n");
+        }
+        out.push_str("\n[default.buckets]\n");
+        write_buckets(&mut out, &default.buckets);
+        write_extensions(&mut out, "default", &default.extensions);
+    }
+
+    if !config_file.age_sources.is_empty() {
+        out.push_str("\n# Where file ages come from, by extension (default: mtime)\n");
+        out.push_str("[age_sources]\n");
+        for (ext, source) in &config_file.age_sources {
+            writeln!(out, "{} = \"{}\"", toml_key(ext), source.name())
+                .expect("Writing to String should not fail");
         }
+    }
 
-        Ok(())
+    if !config_file.bucket_glyphs.is_empty() {
+        out.push_str("\n# Shown before bucket names in human output\n");
+        out.push_str("[bucket_glyphs]\n");
+        for (name, glyph) in &config_file.bucket_glyphs {
+            writeln!(out, "{} = {}", toml_key(name), toml_string(glyph))
+                .expect("Writing to String should not fail");
+        }
     }
-}
 
-// ============================================================================
-// TOML Configuration Structures
-// ============================================================================
+    for (i, rule) in config_file.rules.iter().enumerate() {
+        writeln!(out, "\n# Rule {}", i + 1).expect("Writing to String should not fail");
+        out.push_str("[[rules]]\n");
+        writeln!(out, "path = {}", toml_string(&rule.path))
+            .expect("Writing to String should not fail");
+        if let Some(base_folder) = &rule.base_folder {
+            writeln!(out, "base_folder = {}", toml_string(base_folder))
+                .expect("Writing to String should not fail");
+        }
+        if let Some(target) = &rule.target {
+            writeln!(out, "target = {}", toml_string(target))
+                .expect("Writing to String should not fail");
+        }
+        if let Some(priority) = rule.priority {
+            writeln!(out, "priority = {priority}").expect("Writing to String should not fail");
+        }
+        if let Some(mode) = rule.mode {
+            writeln!(out, "mode = \"{}\"", mode.name()).expect("Writing to String should not fail");
+        }
+        if let Some(leave_unmatched) = rule.leave_unmatched {
+            writeln!(out, "leave_unmatched = {leave_unmatched}")
+                .expect("Writing to String should not fail");
+        }
+        out.push_str("\n[rules.buckets]\n");
+        write_buckets(&mut out, &rule.buckets);
+        write_extensions(&mut out, "rules", &rule.extensions);
+    }
 
-#[derive(Debug, Deserialize)]
-pub struct RefileConfigFile {
-    #[serde(default)]
-    default: Option<DefaultConfig>,
-    #[serde(default)]
-    rules: Vec<RuleConfig>,
+    out
 }
 
-#[derive(Debug, Deserialize)]
-struct DefaultConfig {
-    #[serde(default = "default_base_folder")]
-    base_folder: String,
-    buckets: BTreeMap<String, Option<u64>>,
+/// Writes bucket entries in bound order, catch-all last.
+fn write_buckets(out: &mut String, buckets: &BTreeMap<String, Option<TableBound>>) {
+    for (name, bound) in sorted_table(buckets) {
+        let key = toml_key(name);
+        match bound {
+            Some(TableBound::Number(n)) => writeln!(out, "{key} = {n}"),
+            Some(TableBound::Size(bytes)) => writeln!(out, "{key} = \"{}\"", size_spec(bytes)),
+            Some(TableBound::Period(period)) => writeln!(out, "{key} = \"{}\"", period.name()),
+            None => writeln!(out, "{key} = \"null\""),
+        }
+        .expect("Writing to String should not fail");
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct RuleConfig {
-    path: String,
-    #[serde(default)]
-    base_folder: Option<String>,
-    buckets: BTreeMap<String, Option<u64>>,
+/// Writes a section's per-extension bucket tables, one per extension.
+fn write_extensions(out: &mut String, section: &str, extensions: &BTreeMap<String, BucketTable>) {
+    for (ext, table) in extensions {
+        writeln!(
+            out,
+            "\n# Buckets for .{ext} files\n[{section}.extensions.{}]",
+            toml_key(ext)
+        )
+        .expect("Writing to String should not fail");
+        write_buckets(out, &table.0);
+    }
 }
 
-fn default_base_folder() -> String {
-    "refile".to_string()
+/// Formats a TOML key, quoting it unless it is a valid bare key.
+fn toml_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if bare {
+        key.to_string()
+    } else {
+        toml_string(key)
+    }
 }
 
-/// Converts a `BTreeMap` of bucket definitions to a Vec<BucketDef>.
-fn buckets_from_map(map: BTreeMap<String, Option<u64>>) -> Vec<BucketDef> {
-    map.into_iter()
-        .map(|(name, max_age_days)| BucketDef::new(name, max_age_days))
-        .collect()
+/// Formats a TOML basic string.
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
 }
 
-/// Loads the refile configuration from the default config file location.
+/// Returns the path to the config file: `refile/config.toml` in the config
+/// directory.
 ///
-/// Returns Ok(None) if the config file doesn't exist.
-pub fn load_config_file() -> Result<Option<RefileConfigFile>, ConfigError> {
-    let config_path = config_file_path()?;
-
-    if !config_path.exists() {
-        return Ok(None);
-    }
+/// An absolute `XDG_CONFIG_HOME` is honored on every platform. Otherwise the
+/// platform's config directory is used: `~/.config` on Linux,
+/// `~/Library/Application Support` on macOS and `%APPDATA%` on Windows.
+fn config_file_path() -> Result<PathBuf, ConfigError> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(dirs::config_dir)
+        .ok_or_else(|| {
+            ConfigError::MissingConfig("Could not determine config directory".to_string())
+        })?;
 
-    let contents = fs::read_to_string(&config_path).map_err(|e| {
-        ConfigError::Io(io::Error::new(
-            e.kind(),
-            format!(
-                "Failed to read config file {}: {}",
-                config_path.display(),
-                e
-            ),
-        ))
-    })?;
+    Ok(config_dir.join("refile").join("config.toml"))
+}
 
-    let config: RefileConfigFile =
-        toml::from_str(&contents).map_err(|e| ConfigError::ParseError(format!("{e}")))?;
+/// Returns the time pinned with `REFILE_NOW` (see [`NOW_ENV`]), if it is set.
+///
+/// The variable is read and parsed once per process.
+///
+/// # Errors
+///
+/// Returns an error if the variable is set but is not a whole number of
+/// seconds since the Unix epoch.
+pub fn pinned_now() -> Result<Option<SystemTime>, ConfigError> {
+    static PINNED_NOW: OnceLock<Result<Option<SystemTime>, String>> = OnceLock::new();
+
+    PINNED_NOW
+        .get_or_init(|| {
+            std::env::var(NOW_ENV)
+                .ok()
+                .map(|value| parse_unix_time(&value))
+                .transpose()
+        })
+        .clone()
+        .map_err(ConfigError::InvalidConfig)
+}
 
-    Ok(Some(config))
+/// Returns the current time: the one pinned with `REFILE_NOW`, or else the
+/// system clock's.
+///
+/// An invalid `REFILE_NOW` is ignored here; [`pinned_now`] reports it.
+pub fn current_time() -> SystemTime {
+    pinned_now().ok().flatten().unwrap_or_else(SystemTime::now)
 }
 
-/// Returns the path to the config file: $HOME/.config/refile/config.toml
-fn config_file_path() -> Result<PathBuf, ConfigError> {
-    let config_dir = dirs::config_dir().ok_or_else(|| {
-        ConfigError::MissingConfig("Could not determine config directory".to_string())
-    })?;
+/// Parses a Unix timestamp in seconds.
+fn parse_unix_time(value: &str) -> Result<SystemTime, String> {
+    value
+        .trim()
+        .parse::<u64>()
+        .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        .map_err(|_| format!("{NOW_ENV} must be a Unix timestamp in seconds, got '{value}'"))
+}
 
-    Ok(config_dir.join("refile").join("config.toml"))
+/// The sources and overrides a bucket configuration is resolved from, for
+/// [`resolve_bucket_config`]. Whatever is left at its default has no say.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResolveOptions<'a> {
+    /// The config file, if there is one
+    pub config_file: Option<&'a RefileConfigFile>,
+    /// The one-off config layer (`--config-layer`)
+    pub layer: Option<&'a ConfigLayer>,
+    /// A bucket spec from the environment (see [`BUCKETS_ENV`])
+    pub env_buckets: Option<&'a str>,
+    /// The base folder name given on the command line
+    pub base_folder: Option<&'a str>,
+    /// The bucket spec given on the command line
+    pub buckets: Option<&'a str>,
+    /// Treat bucket ages as lower bounds (see [`BucketConfig::reverse_age`])
+    pub reverse_age: bool,
+    /// The bucket mode given on the command line
+    pub bucket_mode: Option<BucketMode>,
+    /// Require no catch-all bucket (see [`BucketConfig::leave_unmatched`])
+    pub leave_unmatched: bool,
 }
 
 /// Resolves the bucket configuration for a given source directory.
 ///
 /// Precedence (highest to lowest):
-/// 1. CLI overrides (`base_folder_override`, `buckets_override`)
-/// 2. Directory-specific rule from config file
-/// 3. Default section from config file
-/// 4. Built-in default
+/// 1. CLI overrides (`base_folder`, `buckets`)
+/// 2. Bucket spec from the environment (`env_buckets`, see `BUCKETS_ENV`)
+/// 3. Config layer (`--config-layer`)
+/// 4. Project config, the nearest `.refile.toml` (see [`find_project_config`])
+/// 5. Directory-specific rule from config file
+/// 6. Default section from config file
+/// 7. Built-in default
+///
+/// With `reverse_age`, bucket ages are lower bounds (see
+/// [`BucketConfig::reverse_age`]). Buckets from TOML tables are then ordered
+/// from the oldest bound down; bucket specs must be written in that order.
+///
+/// The bucket mode is `bucket_mode` if given, else the matching rule's or the
+/// default section's `mode`. With [`BucketMode::Size`], bucket specs hold
+/// sizes instead of ages, and with [`BucketMode::Calendar`] calendar periods.
+///
+/// With `leave_unmatched` (or `leave_unmatched = true` in the config file),
+/// no catch-all bucket is required (see [`BucketConfig::leave_unmatched`]).
 pub fn resolve_bucket_config(
     source_dir: &Path,
-    config_file: Option<&RefileConfigFile>,
-    base_folder_override: Option<&str>,
-    buckets_override: Option<&str>,
+    options: &ResolveOptions,
 ) -> Result<BucketConfig, ConfigError> {
+    let ResolveOptions {
+        config_file,
+        layer,
+        env_buckets,
+        base_folder: base_folder_override,
+        buckets: buckets_override,
+        reverse_age,
+        bucket_mode,
+        leave_unmatched,
+    } = *options;
+
+    // The mode says how bucket tables are read, so settle it first
+    let rule = config_file.and_then(|cfg_file| find_matching_rule(source_dir, &cfg_file.rules));
+    let mode = bucket_mode
+        .or_else(|| rule.and_then(|rule| rule.mode))
+        .or_else(|| {
+            config_file
+                .and_then(|f| f.default.as_ref())
+                .and_then(|d| d.mode)
+        })
+        .unwrap_or_default();
+
     // Start with built-in default
-    let mut config = BucketConfig::default();
+    let mut config = BucketConfig {
+        leave_unmatched,
+        bucket_mode: mode,
+        ..BucketConfig::default()
+    };
 
     // Apply config file default section
     if let Some(cfg_file) = config_file {
         if let Some(default) = &cfg_file.default {
             config.base_folder.clone_from(&default.base_folder);
-            config.buckets = buckets_from_map(default.buckets.clone());
+            config.buckets = buckets_from_map(&default.buckets, mode);
+            config.extension_buckets = extension_buckets_from(&default.extensions, mode);
+            config.leave_unmatched |= default.leave_unmatched;
         }
 
         // Apply matching rule
-        if let Some(rule) = find_matching_rule(source_dir, &cfg_file.rules) {
+        if let Some(rule) = rule {
             if let Some(base) = &rule.base_folder {
                 config.base_folder.clone_from(base);
             }
-            config.buckets = buckets_from_map(rule.buckets.clone());
+            config.target_dir = rule.target.as_deref().map(expand_tilde);
+            config.buckets = buckets_from_map(&rule.buckets, mode);
+            config.extension_buckets = extension_buckets_from(&rule.extensions, mode);
+            if let Some(rule_leave_unmatched) = rule.leave_unmatched {
+                config.leave_unmatched = leave_unmatched || rule_leave_unmatched;
+            }
+        }
+
+        // Apply bucket glyphs
+        config.glyphs.clone_from(&cfg_file.bucket_glyphs);
+
+        // Apply per-extension age sources
+        config.age_sources = cfg_file
+            .age_sources
+            .iter()
+            .map(|(ext, source)| (ext.trim_start_matches('.').to_lowercase(), *source))
+            .collect();
+    }
+
+    // Apply the project config, then the one-off config layer
+    let project = find_project_config(source_dir)
+        .map(|path| load_config_layer(&path))
+        .transpose()?;
+    for layer in project.iter().chain(layer) {
+        if let Some(base) = &layer.base_folder {
+            config.base_folder.clone_from(base);
+        }
+        if !layer.buckets.is_empty() {
+            config.buckets = buckets_from_map(&layer.buckets, mode);
+        }
+    }
+
+    // Tables are sorted by ascending age; lower bounds go the other way
+    if reverse_age {
+        reverse_bounded(&mut config.buckets);
+        for buckets in config.extension_buckets.values_mut() {
+            reverse_bounded(buckets);
         }
+        config.reverse_age = true;
+    }
+
+    // Apply the bucket spec from the environment; a bucket spec is the whole
+    // bucket set for the run, so per-extension buckets no longer apply
+    if let Some(buckets_spec) = env_buckets {
+        config.buckets = parse_buckets_spec(buckets_spec, config.bucket_mode)?;
+        config.extension_buckets.clear();
     }
 
     // Apply CLI overrides
@@ -271,7 +1536,8 @@ pub fn resolve_bucket_config(
     }
 
     if let Some(buckets_spec) = buckets_override {
-        config.buckets = parse_buckets_spec(buckets_spec)?;
+        config.buckets = parse_buckets_spec(buckets_spec, config.bucket_mode)?;
+        config.extension_buckets.clear();
     }
 
     // Validate final configuration
@@ -280,32 +1546,93 @@ pub fn resolve_bucket_config(
     Ok(config)
 }
 
+/// Reverses the bounded buckets of a set, leaving the catch-all in place.
+fn reverse_bounded(buckets: &mut [BucketDef]) {
+    let catch_all = buckets.iter().rposition(|b| b.max_age_days.is_none());
+    let bounded = catch_all.unwrap_or(buckets.len());
+    buckets[..bounded].reverse();
+}
+
 /// Finds a matching rule for the given source directory.
 ///
-/// Currently does exact path matching (after canonicalization).
-/// Future: could support glob patterns.
+/// A rule path is either an exact path, matched after canonicalization, or a
+/// glob pattern such as `~/work/*` (see [`rule_glob`]). Among matching rules,
+/// the one with the highest `priority` wins; ties go to the most specific
+/// rule, the one with the longest literal path prefix (the part before any
+/// glob character), then to the first one in the file.
 fn find_matching_rule<'a>(source_dir: &Path, rules: &'a [RuleConfig]) -> Option<&'a RuleConfig> {
     let canonical_source = fs::canonicalize(source_dir).ok()?;
 
-    for rule in rules {
-        // Expand tilde in rule path
-        let rule_path = expand_tilde(&rule.path);
-        if let Ok(canonical_rule) = fs::canonicalize(&rule_path)
-            && canonical_source == canonical_rule
-        {
-            return Some(rule);
-        }
+    rules
+        .iter()
+        .enumerate()
+        .filter(|(_, rule)| rule_matches(&rule.path, &canonical_source))
+        .max_by_key(|(index, rule)| {
+            (
+                rule.priority.unwrap_or(0),
+                literal_prefix_len(&rule.path),
+                std::cmp::Reverse(*index),
+            )
+        })
+        .map(|(_, rule)| rule)
+}
+
+/// Returns true if a rule path names the (canonicalized) source directory.
+fn rule_matches(rule_path: &str, canonical_source: &Path) -> bool {
+    match rule_glob(rule_path) {
+        Ok(None) => fs::canonicalize(expand_tilde(rule_path))
+            .is_ok_and(|canonical_rule| canonical_source == canonical_rule),
+        Ok(Some((dir, glob))) => fs::canonicalize(dir).is_ok_and(|dir| {
+            canonical_source
+                .strip_prefix(dir)
+                .is_ok_and(|relative| glob.is_match(relative))
+        }),
+        // Reported by `check_config_file`; such a rule never matches
+        Err(_) => false,
+    }
+}
+
+/// Splits a rule path with glob characters into the directory before the
+/// first one and a pattern for the rest, or returns `None` for an exact path.
+///
+/// The directory is canonicalized like an exact path, and the pattern is
+/// matched against the source's path relative to it: `*` and `?` match
+/// within one path component, `**` across any number of them.
+///
+/// # Errors
+///
+/// Returns an error if the pattern is not a valid glob.
+fn rule_glob(path: &str) -> Result<Option<(PathBuf, GlobMatcher)>, globset::Error> {
+    let literal_len = literal_prefix_len(path);
+    if literal_len == path.len() {
+        return Ok(None);
     }
+    let dir_end = path[..literal_len].rfind('/').map_or(0, |i| i + 1);
+    let (dir, pattern) = path.split_at(dir_end);
+    let glob = GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()?
+        .compile_matcher();
+    let dir = if dir.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand_tilde(dir)
+    };
+    Ok(Some((dir, glob)))
+}
 
-    None
+/// Returns the length of a rule path up to its first glob character.
+fn literal_prefix_len(path: &str) -> usize {
+    path.find(['*', '?', '[', '{']).unwrap_or(path.len())
 }
 
-/// Expands ~ to the user's home directory.
+/// Expands a leading `~/` (or `~\` on Windows) to the user's home directory.
 fn expand_tilde(path: &str) -> PathBuf {
-    if path.starts_with("~/")
+    if let Some(rest) = path.strip_prefix('~')
+        && let Some(rest) = rest.strip_prefix(std::path::is_separator)
         && let Some(home) = dirs::home_dir()
     {
-        return home.join(&path[2..]);
+        return home.join(rest);
     }
     PathBuf::from(path)
 }
@@ -314,7 +1641,13 @@ fn expand_tilde(path: &str) -> PathBuf {
 ///
 /// Format: "name1=days1,name2=days2,name3=null"
 /// Example: "today=1,week=7,old=null"
-pub fn parse_buckets_spec(spec: &str) -> Result<Vec<BucketDef>, ConfigError> {
+///
+/// In size mode the limits are sizes in bytes, optionally with a `KB`, `MB`
+/// or `GB` suffix: "small=10MB,medium=500MB,large=null"
+///
+/// In calendar mode they are calendar periods, checked in the order given:
+/// "week=this-week,month=this-month,older=null"
+pub fn parse_buckets_spec(spec: &str, mode: BucketMode) -> Result<Vec<BucketDef>, ConfigError> {
     let mut buckets = Vec::new();
 
     for part in spec.split(',') {
@@ -340,6 +1673,31 @@ pub fn parse_buckets_spec(spec: &str) -> Result<Vec<BucketDef>, ConfigError> {
             })?
             .trim();
 
+        if mode == BucketMode::Calendar {
+            let period = if age_str == "null" {
+                None
+            } else {
+                Some(CalendarPeriod::from_name(age_str).ok_or_else(|| {
+                    ConfigError::InvalidBucketSpec(format!(
+                        "Invalid calendar period '{age_str}': expected today, this-week, \
+                         last-week, this-month, last-month, this-year or last-year"
+                    ))
+                })?)
+            };
+            buckets.push(BucketDef::with_period(name.to_string(), period));
+            continue;
+        }
+
+        if mode == BucketMode::Size {
+            let max_size_bytes = if age_str == "null" {
+                None
+            } else {
+                Some(parse_size(age_str)?)
+            };
+            buckets.push(BucketDef::with_max_size(name.to_string(), max_size_bytes));
+            continue;
+        }
+
         let max_age_days = if age_str == "null" {
             None
         } else {
@@ -360,7 +1718,36 @@ pub fn parse_buckets_spec(spec: &str) -> Result<Vec<BucketDef>, ConfigError> {
     Ok(buckets)
 }
 
-/// Returns the path to the config file: $HOME/.config/refile/config.toml
+/// Parses a size such as `500`, `10KB`, `500MB` or `2GB` into bytes.
+///
+/// Suffixes are case-insensitive and binary: `1KB` is 1024 bytes.
+fn parse_size(value: &str) -> Result<u64, ConfigError> {
+    let upper = value.to_ascii_uppercase();
+    let (number, multiplier) = [
+        ("GB", 1u64 << 30),
+        ("MB", 1 << 20),
+        ("KB", 1 << 10),
+        ("B", 1),
+    ]
+    .into_iter()
+    .find_map(|(suffix, multiplier)| {
+        upper
+            .strip_suffix(suffix)
+            .map(|number| (number.trim_end(), multiplier))
+    })
+    .unwrap_or((upper.as_str(), 1));
+
+    number
+        .parse::<u64>()
+        .map_err(|e| e.to_string())
+        .and_then(|n| {
+            n.checked_mul(multiplier)
+                .ok_or_else(|| "too large".to_string())
+        })
+        .map_err(|e| ConfigError::InvalidBucketSpec(format!("Invalid size value '{value}': {e}")))
+}
+
+/// Returns the path to the config file (see [`config_file_path`]).
 ///
 /// This is a public function that can be used by CLI commands.
 pub fn get_config_file_path() -> Result<PathBuf, ConfigError> {
@@ -439,6 +1826,9 @@ pub fn validate_config_file() -> Result<String, ConfigError> {
 
     // Try to load the config
     let config = load_config_file()?;
+    if let Some(config) = &config {
+        check_config_file(config)?;
+    }
 
     match config {
         Some(config) => {
@@ -455,51 +1845,49 @@ pub fn validate_config_file() -> Result<String, ConfigError> {
                 summary.push_str("Default configuration:\n");
                 writeln!(summary, "  Base folder: {}", default.base_folder)
                     .expect("Writing to String should not fail");
+                if let Some(mode) = default.mode {
+                    writeln!(summary, "  Mode: {}", mode.name())
+                        .expect("Writing to String should not fail");
+                }
+                if default.leave_unmatched {
+                    summary.push_str("  Unmatched items: left in place\n");
+                }
+                let mode = default.mode.unwrap_or_default();
                 summary.push_str("  Buckets:\n");
-
-                for (name, age) in &default.buckets {
-                    match age {
-                        Some(days) => {
-                            writeln!(summary, "    - {name} = {days} days")
-                                .expect("Writing to String should not fail");
-                        }
-                        None => {
-                            writeln!(summary, "    - {name} = catch-all")
-                                .expect("Writing to String should not fail");
-                        }
-                    }
+                summarize_buckets(&mut summary, "    ", &default.buckets, mode);
+                for (ext, table) in &default.extensions {
+                    writeln!(summary, "  Buckets for .{ext}:")
+                        .expect("Writing to String should not fail");
+                    summarize_buckets(&mut summary, "    ", &table.0, mode);
                 }
                 summary.push('\n');
             }
 
-            // Summarize rules
-            if !config.rules.is_empty() {
-                writeln!(summary, "Directory-specific rules: {}", config.rules.len())
-                    .expect("Writing to String should not fail");
-                for (i, rule) in config.rules.iter().enumerate() {
-                    writeln!(summary, "  Rule {}:", i + 1)
-                        .expect("Writing to String should not fail");
-                    writeln!(summary, "    Path: {}", rule.path)
+            // Summarize age sources
+            if !config.age_sources.is_empty() {
+                summary.push_str("Age sources:\n");
+                for (ext, source) in &config.age_sources {
+                    writeln!(summary, "  - {ext} = {source:?}")
                         .expect("Writing to String should not fail");
-                    let base_folder = rule.base_folder.as_deref().unwrap_or("refile");
-                    writeln!(summary, "    Base folder: {base_folder}")
+                }
+                summary.push('\n');
+            }
+
+            // Summarize bucket glyphs
+            if !config.bucket_glyphs.is_empty() {
+                summary.push_str("Bucket glyphs:\n");
+                for (name, glyph) in &config.bucket_glyphs {
+                    writeln!(summary, "  - {name} = {glyph}")
                         .expect("Writing to String should not fail");
-                    summary.push_str("    Buckets:\n");
-                    for (name, age) in &rule.buckets {
-                        match age {
-                            Some(days) => {
-                                writeln!(summary, "      - {name} = {days} days")
-                                    .expect("Writing to String should not fail");
-                            }
-                            None => {
-                                writeln!(summary, "      - {name} = catch-all")
-                                    .expect("Writing to String should not fail");
-                            }
-                        }
-                    }
                 }
+                summary.push('\n');
             }
 
+            summarize_rules(&mut summary, &config);
+
+            for warning in config.misordered_buckets() {
+                writeln!(summary, "\n⚠ {warning}").expect("Writing to String should not fail");
+            }
             Ok(summary)
         }
         None => Err(ConfigError::MissingConfig(
@@ -508,6 +1896,69 @@ pub fn validate_config_file() -> Result<String, ConfigError> {
     }
 }
 
+/// Lists the rules of a config file for the validation summary.
+fn summarize_rules(summary: &mut String, config: &RefileConfigFile) {
+    if config.rules.is_empty() {
+        return;
+    }
+    writeln!(summary, "Directory-specific rules: {}", config.rules.len())
+        .expect("Writing to String should not fail");
+    for (i, rule) in config.rules.iter().enumerate() {
+        writeln!(summary, "  Rule {}:", i + 1).expect("Writing to String should not fail");
+        writeln!(summary, "    Path: {}", rule.path).expect("Writing to String should not fail");
+        let base_folder = rule.base_folder.as_deref().unwrap_or("refile");
+        writeln!(summary, "    Base folder: {base_folder}")
+            .expect("Writing to String should not fail");
+        if let Some(target) = &rule.target {
+            writeln!(summary, "    Target: {target}").expect("Writing to String should not fail");
+        }
+        if let Some(priority) = rule.priority {
+            writeln!(summary, "    Priority: {priority}")
+                .expect("Writing to String should not fail");
+        }
+        if let Some(mode) = rule.mode {
+            writeln!(summary, "    Mode: {}", mode.name())
+                .expect("Writing to String should not fail");
+        }
+        if rule.leave_unmatched == Some(true) {
+            summary.push_str("    Unmatched items: left in place\n");
+        }
+        let mode = rule.bucket_mode(config);
+        summary.push_str("    Buckets:\n");
+        summarize_buckets(summary, "      ", &rule.buckets, mode);
+        for (ext, table) in &rule.extensions {
+            writeln!(summary, "    Buckets for .{ext}:")
+                .expect("Writing to String should not fail");
+            summarize_buckets(summary, "      ", &table.0, mode);
+        }
+    }
+}
+
+/// Lists a bucket table for the validation summary, in bound order.
+fn summarize_buckets(
+    summary: &mut String,
+    indent: &str,
+    buckets: &BTreeMap<String, Option<TableBound>>,
+    mode: BucketMode,
+) {
+    for (name, bound) in sorted_table(buckets) {
+        match bound {
+            Some(TableBound::Number(bytes)) if mode == BucketMode::Size => {
+                writeln!(summary, "{indent}- {name} = {}", size_spec(bytes))
+            }
+            Some(TableBound::Number(days)) => writeln!(summary, "{indent}- {name} = {days} days"),
+            Some(TableBound::Size(bytes)) => {
+                writeln!(summary, "{indent}- {name} = {}", size_spec(bytes))
+            }
+            Some(TableBound::Period(period)) => {
+                writeln!(summary, "{indent}- {name} = {}", period.name())
+            }
+            None => writeln!(summary, "{indent}- {name} = catch-all"),
+        }
+        .expect("Writing to String should not fail");
+    }
+}
+
 // ============================================================================
 // Tests
 // ============================================================================
@@ -526,54 +1977,138 @@ mod tests {
 
     #[test]
     fn test_validate_no_buckets() {
-        let config = BucketConfig {
-            base_folder: "test".to_string(),
-            buckets: vec![],
-        };
+        let config = BucketConfig::new_for_test("test".to_string(), vec![]);
         assert!(config.validate().is_err());
     }
 
     #[test]
     fn test_validate_no_catchall() {
-        let config = BucketConfig {
-            base_folder: "test".to_string(),
-            buckets: vec![
+        let config = BucketConfig::new_for_test(
+            "test".to_string(),
+            vec![
                 BucketDef::new("bucket1".to_string(), Some(7)),
                 BucketDef::new("bucket2".to_string(), Some(14)),
             ],
-        };
+        );
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn test_leave_unmatched_from_config_file() {
+        let cfg_file: RefileConfigFile = toml::from_str(
+            r#"
+            [default]
+            leave_unmatched = true
+            [default.buckets]
+            week = 7
+
+            [[rules]]
+            path = "/"
+            leave_unmatched = false
+            [rules.buckets]
+            day = 1
+            "#,
+        )
+        .unwrap();
+        let resolve = |source: &str| {
+            resolve_bucket_config(
+                Path::new(source),
+                &ResolveOptions {
+                    config_file: Some(&cfg_file),
+                    ..Default::default()
+                },
+            )
+        };
+
+        // The default section needs no catch-all, but the rule turns that off
+        let config = resolve("/nonexistent").unwrap();
+        assert!(config.leave_unmatched());
+        let err = resolve("/").unwrap_err().to_string();
+        assert!(err.contains("no age limit"));
+        let err = check_config_file(&cfg_file).unwrap_err().to_string();
+        assert!(err.contains("rule 1 (/)"));
+    }
+
     #[test]
     fn test_validate_ages_not_ascending() {
-        let config = BucketConfig {
-            base_folder: "test".to_string(),
-            buckets: vec![
+        let config = BucketConfig::new_for_test(
+            "test".to_string(),
+            vec![
                 BucketDef::new("bucket1".to_string(), Some(14)),
                 BucketDef::new("bucket2".to_string(), Some(7)),
                 BucketDef::new("bucket3".to_string(), None),
             ],
-        };
+        );
         assert!(config.validate().is_err());
     }
 
     #[test]
     fn test_validate_invalid_bucket_name() {
-        let config = BucketConfig {
-            base_folder: "test".to_string(),
-            buckets: vec![
+        let config = BucketConfig::new_for_test(
+            "test".to_string(),
+            vec![
                 BucketDef::new("bucket/invalid".to_string(), Some(7)),
                 BucketDef::new("old".to_string(), None),
             ],
-        };
+        );
         assert!(config.validate().is_err());
     }
 
+    #[test]
+    fn test_validate_duplicate_bucket_names() {
+        let config = BucketConfig::new_for_test(
+            "test".to_string(),
+            vec![
+                BucketDef::new("week".to_string(), Some(7)),
+                BucketDef::new("month".to_string(), Some(30)),
+                BucketDef::new("week".to_string(), Some(90)),
+                BucketDef::new("old".to_string(), None),
+            ],
+        );
+        let err = config.validate().unwrap_err();
+        assert!(matches!(err, ConfigError::InvalidConfig(_)));
+        assert!(err.to_string().contains("Duplicate bucket names: week"));
+
+        // The same goes for a spec, which keeps every bucket it lists
+        let config = BucketConfig::new_for_test(
+            "test".to_string(),
+            parse_buckets_spec("a=1,b=7,a=30,b=null", BucketMode::Age).unwrap(),
+        );
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("Duplicate bucket names: a, b"));
+    }
+
+    #[test]
+    fn test_validate_reserved_bucket_names() {
+        for name in [".", "..", "tab\there", "bell\u{7}"] {
+            let config = BucketConfig::new_for_test(
+                "test".to_string(),
+                vec![
+                    BucketDef::new(name.to_string(), Some(7)),
+                    BucketDef::new("old".to_string(), None),
+                ],
+            );
+            assert!(
+                matches!(config.validate(), Err(ConfigError::InvalidBucketName(..))),
+                "{name:?} was accepted"
+            );
+        }
+
+        // Dots are fine within a name
+        let config = BucketConfig::new_for_test(
+            "test".to_string(),
+            vec![
+                BucketDef::new("...".to_string(), Some(7)),
+                BucketDef::new("v1.2".to_string(), None),
+            ],
+        );
+        assert!(config.validate().is_ok());
+    }
+
     #[test]
     fn test_parse_buckets_spec() {
         let spec = "today=1,week=7,old=null";
-        let buckets = parse_buckets_spec(spec).unwrap();
+        let buckets = parse_buckets_spec(spec, BucketMode::Age).unwrap();
 
         assert_eq!(buckets.len(), 3);
         assert_eq!(buckets[0].name(), "today");
@@ -587,15 +2122,673 @@ mod tests {
     #[test]
     fn test_parse_buckets_spec_with_spaces() {
         let spec = " today = 1 , week = 7 , old = null ";
-        let buckets = parse_buckets_spec(spec).unwrap();
+        let buckets = parse_buckets_spec(spec, BucketMode::Age).unwrap();
         assert_eq!(buckets.len(), 3);
     }
 
     #[test]
     fn test_parse_buckets_spec_invalid() {
-        assert!(parse_buckets_spec("invalid").is_err());
-        assert!(parse_buckets_spec("name=abc").is_err());
-        assert!(parse_buckets_spec("").is_err());
+        assert!(parse_buckets_spec("invalid", BucketMode::Age).is_err());
+        assert!(parse_buckets_spec("name=abc", BucketMode::Age).is_err());
+        assert!(parse_buckets_spec("", BucketMode::Age).is_err());
+    }
+
+    #[test]
+    fn test_parse_buckets_spec_sizes() {
+        let spec = "tiny=512,small=10kb,medium=500MB,large=2GB,huge=null";
+        let buckets = parse_buckets_spec(spec, BucketMode::Size).unwrap();
+        let limits: Vec<_> = buckets.iter().map(|b| b.limit(BucketMode::Size)).collect();
+        assert_eq!(
+            limits,
+            [
+                Some(512),
+                Some(10 * 1024),
+                Some(500 * 1024 * 1024),
+                Some(2 * 1024 * 1024 * 1024),
+                None
+            ]
+        );
+        assert!(buckets.iter().all(|b| b.max_age_days().is_none()));
+
+        assert!(parse_buckets_spec("small=10TB,large=null", BucketMode::Size).is_err());
+        assert!(parse_buckets_spec("small=MB,large=null", BucketMode::Size).is_err());
+        // Sizes are not accepted as ages
+        assert!(parse_buckets_spec("small=10MB,large=null", BucketMode::Age).is_err());
+    }
+
+    #[test]
+    fn test_validate_size_buckets() {
+        let resolve = |spec| {
+            resolve_bucket_config(
+                Path::new("/nonexistent"),
+                &ResolveOptions {
+                    buckets: spec,
+                    bucket_mode: Some(BucketMode::Size),
+                    ..Default::default()
+                },
+            )
+        };
+        assert!(resolve(Some("small=10MB,medium=500MB,large=null")).is_ok());
+        assert!(resolve(Some("medium=500MB,small=10MB,large=null")).is_err());
+        assert!(resolve(Some("small=10MB,medium=500MB")).is_err());
+        // The built-in buckets are age buckets
+        assert!(resolve(None).is_err());
+    }
+
+    #[test]
+    fn test_size_buckets_from_toml() {
+        let cfg_file: RefileConfigFile = toml::from_str(
+            r#"
+            [default]
+            mode = "size"
+
+            [default.buckets]
+            large = "null"
+            medium = "500MB"
+            tiny = 512
+            "#,
+        )
+        .unwrap();
+        let resolve = |bucket_mode| {
+            resolve_bucket_config(
+                Path::new("/nonexistent-refile-size-test"),
+                &ResolveOptions {
+                    config_file: Some(&cfg_file),
+                    bucket_mode,
+                    ..Default::default()
+                },
+            )
+        };
+        let config = resolve(None).unwrap();
+        assert_eq!(config.bucket_mode(), BucketMode::Size);
+        let limits: Vec<_> = config
+            .buckets()
+            .iter()
+            .map(|b| (b.name(), b.limit(BucketMode::Size)))
+            .collect();
+        assert_eq!(
+            limits,
+            [
+                ("tiny", Some(512)),
+                ("medium", Some(500 * 1024 * 1024)),
+                ("large", None)
+            ]
+        );
+        assert!(check_config_file(&cfg_file).is_ok());
+
+        // Sizes mean nothing to age buckets
+        let err = resolve(Some(BucketMode::Age)).unwrap_err();
+        assert!(err.to_string().contains("has a size limit"));
+
+        // Sizes are written back with their unit
+        let normalized = normalize_config_file(&cfg_file);
+        assert!(normalized.contains("tiny = 512\nmedium = \"500MB\"\nlarge = \"null\""));
+    }
+
+    #[test]
+    fn test_age_sources_from_config_file() {
+        let cfg_file: RefileConfigFile = toml::from_str(
+            r#"
+            [age_sources]
+            JPG = "exif"
+            pdf = "filename"
+            "#,
+        )
+        .unwrap();
+        let config = resolve_bucket_config(
+            Path::new("/nonexistent"),
+            &ResolveOptions {
+                config_file: Some(&cfg_file),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(config.age_source_for(Path::new("a.jpg")), AgeSource::Exif);
+        assert_eq!(
+            config.age_source_for(Path::new("a.PDF")),
+            AgeSource::Filename
+        );
+        assert_eq!(config.age_source_for(Path::new("a.txt")), AgeSource::Mtime);
+        assert_eq!(config.age_source_for(Path::new("noext")), AgeSource::Mtime);
+    }
+
+    #[test]
+    fn test_extension_buckets_from_config_file() {
+        let cfg_file: RefileConfigFile = toml::from_str(
+            r#"
+            [default.buckets]
+            recent = 7
+            old = "null"
+
+            [default.extensions.PDF]
+            papers = 365
+            old-papers = "null"
+            "#,
+        )
+        .unwrap();
+        let config = resolve_bucket_config(
+            Path::new("/nonexistent"),
+            &ResolveOptions {
+                config_file: Some(&cfg_file),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let names = |buckets: &[BucketDef]| {
+            buckets
+                .iter()
+                .map(|b| b.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(config.buckets_for(Path::new("a.pdf"))),
+            ["papers", "old-papers"]
+        );
+        assert_eq!(
+            names(config.buckets_for(Path::new("a.txt"))),
+            ["recent", "old"]
+        );
+        assert_eq!(
+            names(config.buckets_for(Path::new("noext"))),
+            ["recent", "old"]
+        );
+        let all: Vec<_> = config
+            .all_buckets()
+            .into_iter()
+            .map(BucketDef::name)
+            .collect();
+        assert_eq!(all, ["recent", "old", "papers", "old-papers"]);
+
+        // An explicit bucket spec is the whole bucket set
+        let config = resolve_bucket_config(
+            Path::new("/nonexistent"),
+            &ResolveOptions {
+                config_file: Some(&cfg_file),
+                buckets: Some("x=null"),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(names(config.buckets_for(Path::new("a.pdf"))), ["x"]);
+    }
+
+    #[test]
+    fn test_calendar_buckets_from_toml() {
+        let cfg_file: RefileConfigFile = toml::from_str(
+            r#"
+            [default]
+            mode = "calendar"
+
+            [default.buckets]
+            older = "null"
+            month = "this-month"
+            week = "this-week"
+            "#,
+        )
+        .unwrap();
+        let config = resolve_bucket_config(
+            Path::new("/nonexistent-refile-calendar-test"),
+            &ResolveOptions {
+                config_file: Some(&cfg_file),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(config.bucket_mode(), BucketMode::Calendar);
+        let buckets: Vec<_> = config
+            .buckets()
+            .iter()
+            .map(|b| (b.name(), b.period()))
+            .collect();
+        assert_eq!(
+            buckets,
+            vec![
+                ("week", Some(CalendarPeriod::ThisWeek)),
+                ("month", Some(CalendarPeriod::ThisMonth)),
+                ("older", None),
+            ]
+        );
+
+        // Periods mean nothing to age buckets
+        let err = resolve_bucket_config(
+            Path::new("/nonexistent-refile-calendar-test"),
+            &ResolveOptions {
+                config_file: Some(&cfg_file),
+                bucket_mode: Some(BucketMode::Age),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("has a calendar period"));
+
+        // Nor ages to calendar buckets
+        let err = resolve_bucket_config(
+            Path::new("/nonexistent-refile-calendar-test"),
+            &ResolveOptions {
+                config_file: Some(&cfg_file),
+                buckets: Some("week=7,older=null"),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Invalid calendar period '7'"));
+    }
+
+    #[test]
+    fn test_parse_calendar_zone() {
+        let offset = |secs| CalendarZone::Fixed(FixedOffset::east_opt(secs).unwrap());
+        assert_eq!("local".parse(), Ok(CalendarZone::Local));
+        assert_eq!("UTC".parse(), Ok(offset(0)));
+        assert_eq!("+02:00".parse(), Ok(offset(2 * 3600)));
+        assert_eq!("-0530".parse(), Ok(offset(-(5 * 3600 + 30 * 60))));
+        for invalid in ["Europe/Paris", "+2", "+02:60", "02:00", "+99:00"] {
+            assert!(invalid.parse::<CalendarZone>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_catch_all_bucket_from_toml() {
+        let cfg_file: RefileConfigFile = toml::from_str(
+            r#"
+            [default.buckets]
+            recent = 7
+            old = "null"
+            "#,
+        )
+        .unwrap();
+        let buckets = &cfg_file.default.as_ref().unwrap().buckets;
+        assert_eq!(buckets["recent"], Some(TableBound::Number(7)));
+        assert_eq!(buckets["old"], None);
+
+        let err = toml::from_str::<RefileConfigFile>(
+            r#"
+            [default.buckets]
+            old = "forever"
+            "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid age 'forever'"));
+    }
+
+    #[test]
+    fn test_check_config_file_validates_rules() {
+        let valid: RefileConfigFile = toml::from_str(
+            r#"
+            [default.buckets]
+            a = 7
+            b = "null"
+
+            [[rules]]
+            path = "/nonexistent"
+            [rules.buckets]
+            x = "null"
+            "#,
+        )
+        .unwrap();
+        assert!(check_config_file(&valid).is_ok());
+
+        // A rule that matches no source is still validated
+        let invalid: RefileConfigFile = toml::from_str(
+            r#"
+            [[rules]]
+            path = "/nonexistent"
+            [rules.buckets]
+            x = 7
+            "#,
+        )
+        .unwrap();
+        let err = check_config_file(&invalid).unwrap_err().to_string();
+        assert!(err.contains("rule 1 (/nonexistent)"));
+        assert!(err.contains("no age limit"));
+    }
+
+    #[test]
+    fn test_rule_priority_wins_over_file_order() {
+        let dir = std::env::temp_dir().join(format!("refile-rule-priority-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.display().to_string();
+        let alias = format!("{path}/.");
+        let rules =
+            |first: Option<i64>, second: Option<i64>, second_path: &str| -> RefileConfigFile {
+                let priority =
+                    |p: Option<i64>| p.map(|p| format!("priority = {p}\n")).unwrap_or_default();
+                toml::from_str(&format!(
+                    "[[rules]]\npath = {}\n{}[rules.buckets]\nfirst = \"null\"\n\
+                 [[rules]]\npath = {}\n{}[rules.buckets]\nsecond = \"null\"\n",
+                    toml_string(&path),
+                    priority(first),
+                    toml_string(second_path),
+                    priority(second),
+                ))
+                .unwrap()
+            };
+        let winner = |cfg_file: &RefileConfigFile| {
+            find_matching_rule(&dir, &cfg_file.rules)
+                .unwrap()
+                .buckets
+                .keys()
+                .next()
+                .unwrap()
+                .clone()
+        };
+
+        // Both paths name the same directory; the higher priority wins
+        assert_eq!(winner(&rules(None, Some(10), &alias)), "second");
+        assert_eq!(winner(&rules(Some(5), Some(-1), &alias)), "first");
+        // On equal priority, the longer literal path wins, then file order
+        assert_eq!(winner(&rules(Some(1), Some(1), &alias)), "second");
+        assert_eq!(winner(&rules(None, None, &path)), "first");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_glob_rules_most_specific_wins() {
+        let dir = std::env::temp_dir().join(format!("refile-rule-glob-{}", std::process::id()));
+        let project = dir.join("work/project");
+        fs::create_dir_all(project.join("sub")).unwrap();
+        let base = dir.display().to_string();
+        let cfg_file: RefileConfigFile = toml::from_str(&format!(
+            "[[rules]]\npath = {}\n[rules.buckets]\nanywhere = \"null\"\n\
+             [[rules]]\npath = {}\n[rules.buckets]\nproject = \"null\"\n\
+             [[rules]]\npath = {}\n[rules.buckets]\nw-prefix = \"null\"\n",
+            toml_string(&format!("{base}/**")),
+            toml_string(&format!("{base}/work/*")),
+            toml_string(&format!("{base}/w*/project")),
+        ))
+        .unwrap();
+        let winner = |source: &Path| {
+            find_matching_rule(source, &cfg_file.rules)
+                .map(|rule| rule.buckets.keys().next().unwrap().clone())
+        };
+
+        // All three match; the longest literal prefix wins
+        assert_eq!(winner(&project).as_deref(), Some("project"));
+        // `*` stays within one path component, `**` does not
+        assert_eq!(winner(&project.join("sub")).as_deref(), Some("anywhere"));
+        assert_eq!(winner(Path::new("/nonexistent")), None);
+
+        // An exact path is more specific than any pattern above it
+        let mut exact = cfg_file.rules;
+        exact.extend(
+            toml::from_str::<RefileConfigFile>(&format!(
+                "[[rules]]\npath = {}\n[rules.buckets]\nexact = \"null\"\n",
+                toml_string(&project.display().to_string())
+            ))
+            .unwrap()
+            .rules,
+        );
+        let rule = find_matching_rule(&project, &exact).unwrap();
+        assert_eq!(rule.buckets.keys().next().unwrap(), "exact");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_config_file_rejects_invalid_rule_pattern() {
+        let cfg_file: RefileConfigFile = toml::from_str(
+            r#"
+            [[rules]]
+            path = "~/work/[unclosed"
+            [rules.buckets]
+            x = "null"
+            "#,
+        )
+        .unwrap();
+        let err = check_config_file(&cfg_file).unwrap_err().to_string();
+        assert!(err.contains("rule 1 (~/work/[unclosed): invalid path pattern"));
+    }
+
+    #[test]
+    fn test_project_config_two_levels_up() {
+        let dir = std::env::temp_dir().join(format!("refile-project-{}", std::process::id()));
+        let source = dir.join("notes/inbox");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(
+            dir.join(PROJECT_CONFIG_NAME),
+            "base_folder = \"sorted\"\n[buckets]\nproject-recent = 7\nproject-old = \"null\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            find_project_config(&source),
+            Some(fs::canonicalize(&dir).unwrap().join(PROJECT_CONFIG_NAME))
+        );
+
+        let cfg_file: RefileConfigFile = toml::from_str(&format!(
+            "[[rules]]\npath = {}\n[rules.buckets]\nrule = \"null\"\n",
+            toml_string(&source.display().to_string())
+        ))
+        .unwrap();
+        let resolve = |buckets_override: Option<&str>| {
+            resolve_bucket_config(
+                &source,
+                &ResolveOptions {
+                    config_file: Some(&cfg_file),
+                    buckets: buckets_override,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        // The project config wins over the matching rule
+        let config = resolve(None);
+        assert_eq!(config.base_folder(), "sorted");
+        let names: Vec<_> = config.buckets().iter().map(BucketDef::name).collect();
+        assert_eq!(names, ["project-recent", "project-old"]);
+        // CLI overrides still win over the project config
+        assert_eq!(resolve(Some("cli=null")).buckets()[0].name(), "cli");
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(find_project_config(&source), None);
+    }
+
+    #[test]
+    fn test_buckets_sorted_by_age() {
+        let cfg_file: RefileConfigFile = toml::from_str(
+            r#"
+            [default.buckets]
+            archive = "null"
+            month = 30
+            week = 7
+            "#,
+        )
+        .unwrap();
+        let config = resolve_bucket_config(
+            Path::new("/nonexistent"),
+            &ResolveOptions {
+                config_file: Some(&cfg_file),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let names: Vec<_> = config.buckets().iter().map(BucketDef::name).collect();
+        assert_eq!(names, ["week", "month", "archive"]);
+    }
+
+    #[test]
+    fn test_normalize_config_file_round_trips() {
+        let cfg_file: RefileConfigFile = toml::from_str(
+            r#"
+            [[rules]]
+            path = "~/My Files"
+            target = "~/Archive"
+            [rules.buckets]
+            "z old" = "null"
+            a = 9
+
+            [default]
+            [default.buckets]
+            b = "null"
+            a = 3
+
+            [default.extensions.pdf]
+            paper = "null"
+            fresh = 30
+
+            [age_sources]
+            jpg = "exif"
+
+            [bucket_glyphs]
+            "old stuff" = "📦"
+            "#,
+        )
+        .unwrap();
+        let warnings = cfg_file.misordered_buckets();
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].starts_with("[default.buckets]: 'a' applies before 'b'"));
+        assert!(warnings[1].starts_with("[default.extensions.pdf]: 'fresh' applies before"));
+        assert!(warnings[2].starts_with("rule 1 (~/My Files) buckets: 'a' applies before"));
+        let normalized = normalize_config_file(&cfg_file);
+
+        assert!(normalized.find("a = 3").unwrap() < normalized.find("b = \"null\"").unwrap());
+        assert!(normalized.contains("\"z old\" = \"null\""));
+        assert!(normalized.contains("path = \"~/My Files\""));
+        assert!(normalized.contains("target = \"~/Archive\""));
+        assert!(normalized.contains("jpg = \"exif\""));
+        assert!(normalized.contains("\"old stuff\" = \"📦\""));
+        assert!(
+            normalized.find("[default.extensions.pdf]\nfresh = 30\npaper = \"null\"")
+                > normalized.find("[default.buckets]")
+        );
+
+        let reparsed: RefileConfigFile = toml::from_str(&normalized).unwrap();
+        assert_eq!(normalize_config_file(&reparsed), normalized);
+        assert!(reparsed.misordered_buckets().is_empty());
+    }
+
+    #[test]
+    fn test_config_layer() {
+        let cfg_file: RefileConfigFile = toml::from_str(
+            r#"
+            [default]
+            base_folder = "archive"
+            [default.buckets]
+            a = 7
+            b = "null"
+            "#,
+        )
+        .unwrap();
+        let layer: ConfigLayer = toml::from_str(
+            r#"
+            [buckets]
+            x = 3
+            y = "null"
+            "#,
+        )
+        .unwrap();
+        let config = resolve_bucket_config(
+            Path::new("/nonexistent"),
+            &ResolveOptions {
+                config_file: Some(&cfg_file),
+                layer: Some(&layer),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // Buckets come from the layer, the base folder from the config file
+        assert_eq!(config.base_folder(), "archive");
+        assert_eq!(config.buckets()[0].name(), "x");
+
+        // A full config file is not a layer
+        assert!(toml::from_str::<ConfigLayer>("[default.buckets]\na = \"null\"\n").is_err());
+    }
+
+    #[test]
+    fn test_env_buckets_below_cli_override() {
+        let layer: ConfigLayer = toml::from_str("[buckets]\nx = 3\ny = \"null\"\n").unwrap();
+
+        // The environment spec wins over the layer
+        let config = resolve_bucket_config(
+            Path::new("/nonexistent"),
+            &ResolveOptions {
+                layer: Some(&layer),
+                env_buckets: Some("today=1,week=7,old=null"),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let names: Vec<_> = config.buckets().iter().map(BucketDef::name).collect();
+        assert_eq!(names, ["today", "week", "old"]);
+        assert_eq!(config.buckets()[1].max_age_days(), Some(7));
+
+        // --buckets still wins over the environment
+        let config = resolve_bucket_config(
+            Path::new("/nonexistent"),
+            &ResolveOptions {
+                env_buckets: Some("today=1,week=7,old=null"),
+                buckets: Some("recent=30,rest=null"),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let names: Vec<_> = config.buckets().iter().map(BucketDef::name).collect();
+        assert_eq!(names, ["recent", "rest"]);
+
+        // A malformed spec is rejected like --buckets
+        assert!(
+            resolve_bucket_config(
+                Path::new("/nonexistent"),
+                &ResolveOptions {
+                    env_buckets: Some("x=1"),
+                    ..Default::default()
+                }
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_reverse_age_orders_buckets_descending() {
+        let cfg_file: RefileConfigFile = toml::from_str(
+            r#"
+            [default.buckets]
+            today = "null"
+            yesterday = 1
+            rest = 7
+            "#,
+        )
+        .unwrap();
+        let config = resolve_bucket_config(
+            Path::new("/nonexistent"),
+            &ResolveOptions {
+                config_file: Some(&cfg_file),
+                reverse_age: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let names: Vec<_> = config.buckets().iter().map(BucketDef::name).collect();
+        assert_eq!(names, ["rest", "yesterday", "today"]);
+
+        // Specs keep their order, which must then be descending
+        let spec = |spec, reverse| {
+            resolve_bucket_config(
+                Path::new("/nonexistent"),
+                &ResolveOptions {
+                    buckets: Some(spec),
+                    reverse_age: reverse,
+                    ..Default::default()
+                },
+            )
+        };
+        assert!(spec("rest=7,yesterday=1,today=null", true).is_ok());
+        assert!(spec("yesterday=1,rest=7,today=null", true).is_err());
+        assert!(spec("rest=7,yesterday=1,today=null", false).is_err());
+    }
+
+    #[test]
+    fn test_parse_unix_time() {
+        assert_eq!(
+            parse_unix_time("1700000000").unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+        assert_eq!(parse_unix_time(" 0 ").unwrap(), SystemTime::UNIX_EPOCH);
+        assert!(parse_unix_time("yesterday").is_err());
+        assert!(parse_unix_time("-5").is_err());
+        assert!(parse_unix_time("1.5").is_err());
     }
 
     #[test]
@@ -4,17 +4,19 @@
 //! computing paths, and other operations that don't interact with the filesystem.
 //! These functions are easier to test and reason about since they have no side effects.
 
-use crate::config::{BucketConfig, BucketDef};
+use crate::config::{BucketConfig, BucketDef, BucketMode, CalendarPeriod, CalendarZone};
+use chrono::{DateTime, Datelike, Days, Local, Months, NaiveDate, TimeZone, Utc, Weekday};
 use std::fs;
-use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Checks if a path is a protected directory that should not be moved.
 ///
 /// Protected directories include:
-/// - Root directory (`/`)
-/// - User's home directory (detected via HOME env var)
-/// - Top-level directories (direct children of root, e.g., `/tmp`, `/var`, `/usr`)
+/// - Filesystem roots (`/`, or a drive root such as `C:\` on Windows)
+/// - User's home directory (the profile directory on Windows)
+/// - Top-level directories (direct children of a root, e.g., `/tmp`, `/var`,
+///   `/usr`, or `C:\Windows` and `C:\Users`)
 ///
 /// # Arguments
 ///
@@ -26,62 +28,238 @@ use std::time::Duration;
 pub fn is_protected_directory(path: &Path) -> bool {
     // Canonicalize the path if possible for accurate comparison
     let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let is_root = |path: &Path| path.has_root() && path.parent().is_none();
 
-    // Check if it's root
-    if canonical == Path::new("/") {
+    // Check if it's a root
+    if is_root(&canonical) {
         return true;
     }
 
     // Check if it's the user's home directory
-    if let Ok(home) = std::env::var("HOME") {
-        // If canonicalization fails for HOME, fall back to the raw path
-        let canonical_home = fs::canonicalize(&home).unwrap_or_else(|_| PathBuf::from(home));
+    if let Some(home) = dirs::home_dir() {
+        // If canonicalization fails for the home directory, fall back to the raw path
+        let canonical_home = fs::canonicalize(&home).unwrap_or(home);
         if canonical == canonical_home {
             return true;
         }
     }
 
-    // Check if this is a top-level directory (direct child of root)
-    if let Some(parent) = canonical.parent()
-        && parent == Path::new("/")
-    {
-        return true;
-    }
+    // Check if this is a top-level directory (direct child of a root)
+    canonical.parent().is_some_and(is_root)
+}
 
-    false
+/// File names operating systems leave behind in folders (Finder and Explorer
+/// metadata), matched case-insensitively.
+const SYSTEM_FILE_NAMES: [&str; 4] = [".DS_Store", "Thumbs.db", "ehthumbs.db", "desktop.ini"];
+
+/// Checks if a path names an operating system clutter file such as
+/// `.DS_Store` or `Thumbs.db`.
+#[must_use]
+pub fn is_system_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|name| {
+            SYSTEM_FILE_NAMES
+                .iter()
+                .any(|system| system.eq_ignore_ascii_case(name))
+        })
 }
 
-/// Determines which bucket a file belongs to based on its age.
+/// Checks if a path has one of the given extensions.
+///
+/// Extensions are compared case-insensitively and may be given with or
+/// without a leading dot.
+#[must_use]
+pub fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| {
+            extensions
+                .iter()
+                .any(|wanted| wanted.trim_start_matches('.').eq_ignore_ascii_case(ext))
+        })
+}
+
+/// Determines which bucket a file belongs to based on its age (or size).
 ///
 /// Iterates through bucket definitions and returns the first bucket
 /// whose `max_age_days` threshold is greater than or equal to the file's age.
+/// With reversed age semantics the threshold is a lower bound instead, and
+/// the first bucket whose threshold the file's age reaches is returned.
+/// In size mode the file's size is compared to `max_size_bytes` the same way.
+/// In calendar mode the bucket is picked by [`pick_bucket_calendar`], for the
+/// point in time `age` before now, in the time zone of
+/// [`BucketConfig::time_zone`].
+///
+/// With half-open buckets (see [`BucketConfig::half_open`]) a bucket holds
+/// the interval `[previous limit, limit)`, starting at 0 for the first
+/// bucket, and membership is checked against both ends.
 ///
 /// # Arguments
 ///
 /// * `age` - The duration since the file was last modified
+/// * `size` - The file's size in bytes (only used in size mode)
 /// * `bucket_config` - The bucket configuration to use
 ///
 /// # Returns
 ///
 /// A reference to the matching `BucketDef`, or the last bucket (catch-all) if none match.
+/// With [`BucketConfig::leave_unmatched`] there may be no catch-all, and an
+/// item beyond every limit matches no bucket: `None` is returned then.
+#[must_use]
+pub fn pick_bucket(age: Duration, size: u64, bucket_config: &BucketConfig) -> Option<&BucketDef> {
+    pick_from(bucket_config.buckets(), age, size, bucket_config)
+}
+
+/// Determines which bucket an item belongs to, taking per-extension buckets
+/// into account.
+///
+/// A file whose extension has its own bucket set in the config (see
+/// [`BucketConfig::buckets_for`]) is placed among those buckets; directories
+/// and every other file go through [`pick_bucket`].
+///
+/// # Arguments
+///
+/// * `path` - The item's path, for its extension
+/// * `is_dir` - Whether the item is a directory
+/// * `age` - The duration since the item was last modified
+/// * `size` - The item's size in bytes (only used in size mode)
+/// * `bucket_config` - The bucket configuration to use
 #[must_use]
-pub fn pick_bucket(age: Duration, bucket_config: &BucketConfig) -> &BucketDef {
+pub fn pick_bucket_for<'a>(
+    path: &Path,
+    is_dir: bool,
+    age: Duration,
+    size: u64,
+    bucket_config: &'a BucketConfig,
+) -> Option<&'a BucketDef> {
+    if is_dir {
+        return pick_bucket(age, size, bucket_config);
+    }
+    pick_from(bucket_config.buckets_for(path), age, size, bucket_config)
+}
+
+/// Picks a bucket among `buckets` (see [`pick_bucket`]).
+fn pick_from<'a>(
+    buckets: &'a [BucketDef],
+    age: Duration,
+    size: u64,
+    bucket_config: &BucketConfig,
+) -> Option<&'a BucketDef> {
+    let picked = if bucket_config.bucket_mode() == BucketMode::Calendar {
+        let now = bucket_config.age_reference();
+        let modified = now.checked_sub(age).unwrap_or(UNIX_EPOCH);
+        match bucket_config.time_zone() {
+            CalendarZone::Local => {
+                pick_bucket_calendar(buckets, modified, &DateTime::<Local>::from(now))
+            }
+            CalendarZone::Fixed(offset) => pick_bucket_calendar(
+                buckets,
+                modified,
+                &DateTime::<Utc>::from(now).with_timezone(&offset),
+            ),
+        }
+    } else {
+        pick_by_limit(buckets, age, size, bucket_config)
+    };
+
+    // Nothing matches only without a catch-all, which validation allows only
+    // when unmatched items are left in place; otherwise return the last bucket
+    if picked.is_none() && !bucket_config.leave_unmatched() {
+        return buckets.last();
+    }
+    picked
+}
+
+/// Picks the first bucket whose age or size limit an item is within, or
+/// the catch-all bucket.
+fn pick_by_limit<'a>(
+    buckets: &'a [BucketDef],
+    age: Duration,
+    size: u64,
+    bucket_config: &BucketConfig,
+) -> Option<&'a BucketDef> {
     let age_days = age.as_secs() / (24 * 3600);
+    let mode = bucket_config.bucket_mode();
+    let value = match mode {
+        BucketMode::Age | BucketMode::Calendar => age_days,
+        BucketMode::Size => size,
+    };
 
-    for bucket in bucket_config.buckets() {
-        if let Some(max_days) = bucket.max_age_days() {
-            if age_days <= max_days {
-                return bucket;
+    let mut prev_limit = 0;
+    for bucket in buckets {
+        if let Some(limit) = bucket.limit(mode) {
+            let matches = match mode {
+                _ if bucket_config.half_open() => prev_limit <= value && value < limit,
+                BucketMode::Size => size <= limit,
+                BucketMode::Age if bucket_config.reverse_age() => age_days >= limit,
+                BucketMode::Age | BucketMode::Calendar => age_days <= limit,
+            };
+            if matches {
+                return Some(bucket);
             }
+            prev_limit = limit;
         } else {
             // This is a catch-all bucket (None age)
-            return bucket;
+            return Some(bucket);
         }
     }
+    None
+}
+
+/// Determines which calendar bucket an item belongs to.
+///
+/// Returns the first bucket whose calendar period, in the time zone of `now`,
+/// holds the day the item was modified, or the catch-all bucket. Items
+/// modified after `now` (clock skew, or files from another time zone) go into
+/// the first bucket, the newest one.
+///
+/// # Arguments
+///
+/// * `buckets` - The calendar buckets, in the order they are checked
+/// * `modified` - When the item was last modified
+/// * `now` - The current time, in the time zone calendar days are counted in
+///
+/// # Returns
+///
+/// The matching bucket, or `None` if no period matches and there is no
+/// catch-all bucket.
+pub fn pick_bucket_calendar<'a, Tz: TimeZone>(
+    buckets: &'a [BucketDef],
+    modified: SystemTime,
+    now: &DateTime<Tz>,
+) -> Option<&'a BucketDef> {
+    let modified = DateTime::<Utc>::from(modified).with_timezone(&now.timezone());
+    if modified > *now {
+        return buckets.first();
+    }
+
+    let (day, today) = (modified.date_naive(), now.date_naive());
+    buckets.iter().find(|bucket| {
+        bucket
+            .period()
+            .is_none_or(|period| in_period(period, day, today))
+    })
+}
 
-    // Should never reach here if validation passed (ensures catch-all exists)
-    // Return last bucket as fallback
-    &bucket_config.buckets()[bucket_config.buckets().len() - 1]
+/// Returns true if `day` falls within a calendar period, as seen from `today`.
+fn in_period(period: CalendarPeriod, day: NaiveDate, today: NaiveDate) -> bool {
+    let week_start = |date: NaiveDate| date.week(Weekday::Mon).first_day();
+    let month = |date: NaiveDate| (date.year(), date.month());
+    match period {
+        CalendarPeriod::Today => day == today,
+        CalendarPeriod::ThisWeek => week_start(day) == week_start(today),
+        CalendarPeriod::LastWeek => today
+            .checked_sub_days(Days::new(7))
+            .is_some_and(|last_week| week_start(day) == week_start(last_week)),
+        CalendarPeriod::ThisMonth => month(day) == month(today),
+        CalendarPeriod::LastMonth => today
+            .with_day(1)
+            .and_then(|first| first.checked_sub_months(Months::new(1)))
+            .is_some_and(|last_month| month(day) == month(last_month)),
+        CalendarPeriod::ThisYear => day.year() == today.year(),
+        CalendarPeriod::LastYear => day.year() == today.year() - 1,
+    }
 }
 
 /// Computes the base refile directory path within the target directory.
@@ -99,24 +277,62 @@ pub fn refile_base_path(target_dir: &Path, bucket_config: &BucketConfig) -> Path
     target_dir.join(bucket_config.base_folder())
 }
 
-/// Computes the destination directory path for a specific bucket.
+/// Name of the directory in the base folder that `--on-conflict trash` moves
+/// displaced items into.
+pub const TRASH_DIR: &str = ".trash";
+
+/// Computes where `--on-conflict trash` puts an item displaced by a move.
 ///
 /// # Arguments
 ///
+/// * `refile_base` - The base folder holding the trash directory
+/// * `item` - The existing item being moved aside
+/// * `time` - The time of the run, appended as a `YYYYMMDDTHHMMSS` suffix
+///
+/// # Returns
+///
+/// `<refile_base>/.trash/<name>.<timestamp>`
+#[must_use]
+pub fn trash_path(refile_base: &Path, item: &Path, time: SystemTime) -> PathBuf {
+    let name = item
+        .file_name()
+        .unwrap_or(item.as_os_str())
+        .to_string_lossy();
+    let stamp = DateTime::<Utc>::from(time).format("%Y%m%dT%H%M%S");
+    refile_base.join(TRASH_DIR).join(format!("{name}.{stamp}"))
+}
+
+/// Computes the destination directory path for an item in a specific bucket.
+///
+/// The directories below the base folder follow the configured layout: one
+/// level per classifier, in order.
+///
+/// # Arguments
+///
+/// * `source` - The item being placed (for the `type` classifier)
 /// * `target_dir` - The target directory where refile structure exists
 /// * `bucket` - The bucket definition to get the directory for
-/// * `bucket_config` - The bucket configuration (for base folder name)
+/// * `bucket_config` - The bucket configuration (for base folder name and layout)
 ///
 /// # Returns
 ///
-/// Path to `<target_dir>/<base_folder>/<bucket_name>`
+/// Path to `<target_dir>/<base_folder>/<bucket_name>` with the default layout,
+/// or e.g. `<target_dir>/<base_folder>/<file_type>/<bucket_name>` with `type,age`
 #[must_use]
 pub fn bucket_dest_dir(
+    source: &Path,
     target_dir: &Path,
     bucket: &BucketDef,
     bucket_config: &BucketConfig,
 ) -> PathBuf {
-    refile_base_path(target_dir, bucket_config).join(bucket.name())
+    let mut dir = refile_base_path(target_dir, bucket_config);
+    for classifier in bucket_config.layout().classifiers() {
+        match classifier {
+            Classifier::Type => dir.push(FileType::of(source).name()),
+            Classifier::Age => dir.push(bucket.name()),
+        }
+    }
+    dir
 }
 
 /// Computes the full destination path for a file based on its bucket.
@@ -127,6 +343,11 @@ pub fn bucket_dest_dir(
 /// * `target_dir` - The target directory where refile structure exists
 /// * `bucket` - The bucket to place the file in
 /// * `bucket_config` - The bucket configuration (for base folder name)
+/// * `name_suffix` - Optional suffix appended to the file name as `<name>_<suffix>`,
+///   unless the name already carries one (see [`has_name_suffix`])
+/// * `source_dir` - With `--preserve-structure`, the source directory: the
+///   directories between it and the file are recreated inside the bucket.
+///   Files already filed keep the directories they have below their bucket.
 ///
 /// # Returns
 ///
@@ -137,27 +358,95 @@ pub fn compute_dest_path(
     target_dir: &Path,
     bucket: &BucketDef,
     bucket_config: &BucketConfig,
+    name_suffix: Option<&str>,
+    source_dir: Option<&Path>,
 ) -> Option<PathBuf> {
     let file_name = source.file_name()?;
-    let dest_dir = bucket_dest_dir(target_dir, bucket, bucket_config);
-    Some(dest_dir.join(file_name))
+    let mut dest_dir = bucket_dest_dir(source, target_dir, bucket, bucket_config);
+    if let Some(source_dir) = source_dir {
+        let refile_base = refile_base_path(target_dir, bucket_config);
+        let levels = bucket_config.layout().classifiers().len();
+        let subdir: Option<PathBuf> = match source.strip_prefix(&refile_base) {
+            Ok(filed) => filed
+                .parent()
+                .map(|p| p.components().skip(levels).collect()),
+            Err(_) => source
+                .parent()
+                .and_then(|p| p.strip_prefix(source_dir).ok())
+                .map(Path::to_path_buf),
+        };
+        dest_dir.extend(subdir);
+    }
+
+    let Some(suffix) = name_suffix else {
+        return Some(dest_dir.join(file_name));
+    };
+    if has_name_suffix(&file_name.to_string_lossy(), suffix) {
+        return Some(dest_dir.join(file_name));
+    }
+    let mut name = file_name.to_os_string();
+    name.push(format!("_{suffix}"));
+    Some(dest_dir.join(name))
+}
+
+/// Returns true if a name already ends with a suffix shaped like `suffix`, as
+/// `<name>_<suffix>`, so suffixing stays idempotent across runs.
+///
+/// Digits in `suffix` match any digit, so `project_2023-10` carries a
+/// `2023-11` suffix too, and a ` (N)` counter added by
+/// [`generate_unique_name`] after the suffix is ignored.
+fn has_name_suffix(name: &str, suffix: &str) -> bool {
+    let name = match name
+        .strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once(" ("))
+    {
+        Some((rest, counter))
+            if !counter.is_empty() && counter.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            rest
+        }
+        _ => name,
+    };
+    let Some(tail) = name
+        .len()
+        .checked_sub(suffix.len() + 1)
+        .and_then(|start| name.get(start..))
+    else {
+        return false;
+    };
+    let mut tail = tail.chars();
+    tail.next() == Some('_')
+        && tail
+            .zip(suffix.chars())
+            .all(|(have, want)| have == want || (have.is_ascii_digit() && want.is_ascii_digit()))
 }
 
 /// Generates a unique filename by appending a numeric suffix.
 ///
-/// The suffix is inserted before the file extension, if present.
+/// For files the suffix is inserted before the file extension, if present.
+/// Directories have no extension, so the suffix always goes at the end of
+/// the whole name (`my.project.v2 (1)`).
 ///
 /// # Arguments
 ///
 /// * `base` - The base path to generate a variant of
 /// * `suffix` - The numeric suffix to append
+/// * `is_dir` - Whether the path names a directory
 ///
 /// # Returns
 ///
 /// A new path with the suffix inserted: `filename (N).ext` or `filename (N)`
 #[must_use]
-pub fn generate_unique_name(base: &Path, suffix: usize) -> PathBuf {
+pub fn generate_unique_name(base: &Path, suffix: usize, is_dir: bool) -> PathBuf {
     let parent = base.parent().unwrap_or_else(|| Path::new("."));
+    if is_dir {
+        let name = base
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unnamed");
+        return parent.join(format!("{name} ({suffix})"));
+    }
+
     let stem = base
         .file_stem()
         .and_then(|s| s.to_str())
@@ -171,11 +460,142 @@ pub fn generate_unique_name(base: &Path, suffix: usize) -> PathBuf {
     }
 }
 
+/// Transliterates a file name to ASCII for `--ascii-only --transliterate`.
+///
+/// Accented Latin letters lose their accents (`é` → `e`), ligatures and a few
+/// special letters are spelled out (`æ` → `ae`, `ß` → `ss`), and any other
+/// non-ASCII character becomes `_`. ASCII characters are kept as they are.
+///
+/// # Examples
+///
+/// `Café Müller.pdf` → `Cafe Muller.pdf`, `Straße 5.txt` → `Strasse 5.txt`,
+/// `日記.md` → `__.md`
+#[must_use]
+pub fn transliterate_to_ascii(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii() {
+            out.push(c);
+            continue;
+        }
+        let ascii = match c {
+            'À'..='Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+            'à'..='å' | 'ā' | 'ă' | 'ą' => "a",
+            'Æ' => "AE",
+            'æ' => "ae",
+            'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "C",
+            'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+            'Ð' | 'Ď' | 'Đ' => "D",
+            'ð' | 'ď' | 'đ' => "d",
+            'È'..='Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => "E",
+            'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+            'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "G",
+            'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+            'Ĥ' | 'Ħ' => "H",
+            'ĥ' | 'ħ' => "h",
+            'Ì'..='Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => "I",
+            'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+            'Ĵ' => "J",
+            'ĵ' => "j",
+            'Ķ' => "K",
+            'ķ' => "k",
+            'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => "L",
+            'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+            'Ñ' | 'Ń' | 'Ņ' | 'Ň' => "N",
+            'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+            'Ò'..='Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => "O",
+            'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+            'Œ' => "OE",
+            'œ' => "oe",
+            'Ŕ' | 'Ŗ' | 'Ř' => "R",
+            'ŕ' | 'ŗ' | 'ř' => "r",
+            'Ś' | 'Ŝ' | 'Ş' | 'Š' => "S",
+            'ś' | 'ŝ' | 'ş' | 'š' => "s",
+            'ß' => "ss",
+            'Ţ' | 'Ť' | 'Ŧ' => "T",
+            'ţ' | 'ť' | 'ŧ' => "t",
+            'Þ' => "TH",
+            'þ' => "th",
+            'Ù'..='Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "U",
+            'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+            'Ŵ' => "W",
+            'ŵ' => "w",
+            'Ý' | 'Ŷ' | 'Ÿ' => "Y",
+            'ý' | 'ÿ' | 'ŷ' => "y",
+            'Ź' | 'Ż' | 'Ž' => "Z",
+            'ź' | 'ż' | 'ž' => "z",
+            _ => "_",
+        };
+        out.push_str(ascii);
+    }
+    out
+}
+
+/// Computes the path of the `n`th directory of a bucket.
+///
+/// The first directory is the bucket directory itself; overflow directories
+/// created by `--bucket-max-files` are its siblings named `<bucket>-<n>`.
+///
+/// # Arguments
+///
+/// * `bucket_dir` - The primary bucket directory
+/// * `n` - The 1-based directory number
+///
+/// # Returns
+///
+/// `bucket_dir` for `n <= 1`, otherwise `<bucket_dir>-<n>`
+#[must_use]
+pub fn overflow_dir(bucket_dir: &Path, n: usize) -> PathBuf {
+    if n <= 1 {
+        return bucket_dir.to_path_buf();
+    }
+    let mut name = bucket_dir.as_os_str().to_os_string();
+    name.push(format!("-{n}"));
+    PathBuf::from(name)
+}
+
+/// Returns which directory of a bucket a directory name refers to.
+///
+/// # Arguments
+///
+/// * `dir_name` - The directory name to check
+/// * `bucket_name` - The bucket name
+///
+/// # Returns
+///
+/// `Some(1)` for the bucket itself, `Some(n)` for the overflow directory
+/// `<bucket>-<n>` (with `n >= 2`), `None` otherwise
+#[must_use]
+pub fn overflow_index(dir_name: &str, bucket_name: &str) -> Option<usize> {
+    if dir_name == bucket_name {
+        return Some(1);
+    }
+    let n = dir_name.strip_prefix(bucket_name)?.strip_prefix('-')?;
+    if !n.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    n.parse().ok().filter(|n| *n >= 2)
+}
+
+/// Returns true if a directory name is a directory of a bucket: the bucket's
+/// own name, or when buckets are capped (see [`BucketConfig::max_files`]) one
+/// of its overflow directories too.
+fn names_bucket(dir_name: &str, bucket_name: &str, bucket_config: &BucketConfig) -> bool {
+    if bucket_config.max_files().is_some() {
+        overflow_index(dir_name, bucket_name).is_some()
+    } else {
+        dir_name == bucket_name
+    }
+}
+
 /// Checks if a path represents a bucket directory.
 ///
 /// A valid bucket directory must:
-/// 1. Have a parent directory that matches the base folder name
-/// 2. Have a name that matches one of the configured bucket names
+/// 1. Sit as many levels below a directory named after the base folder as
+///    the layout has classifiers (one level with the default layout)
+/// 2. Have a name at each level that matches that level's classifier: a
+///    configured bucket name for `age` (or one of its overflow directories,
+///    `<bucket>-<n>`, when buckets are capped), a file type name for `type`
 ///
 /// # Arguments
 ///
@@ -186,30 +606,200 @@ pub fn generate_unique_name(base: &Path, suffix: usize) -> PathBuf {
 ///
 /// `true` if the path is a valid bucket directory
 pub fn is_bucket_dir<P: AsRef<Path>>(path: P, bucket_config: &BucketConfig) -> bool {
-    let path = path.as_ref();
+    let mut path = path.as_ref();
 
-    // Check if parent directory is named with the base folder name
-    let Some(parent) = path.parent() else {
-        return false;
-    };
+    // Check each level's name, from the innermost outwards
+    for classifier in bucket_config.layout().classifiers().iter().rev() {
+        let Some(dir_name) = path.file_name().and_then(|s| s.to_str()) else {
+            return false;
+        };
+        if !classifier.matches(dir_name, bucket_config) {
+            return false;
+        }
+        let Some(parent) = path.parent() else {
+            return false;
+        };
+        path = parent;
+    }
 
-    let Some(parent_name) = parent.file_name().and_then(|s| s.to_str()) else {
-        return false;
-    };
+    // Check if the remaining directory is named with the base folder name
+    path.file_name().and_then(|s| s.to_str()) == Some(bucket_config.base_folder())
+}
 
-    if parent_name != bucket_config.base_folder() {
-        return false;
+/// Returns the bucket a bucket directory belongs to.
+///
+/// # Returns
+///
+/// The bucket named at the `age` level of the directory's path (an overflow
+/// directory counts as its bucket when buckets are capped), or
+/// `None` if the layout has no `age` level or the name matches no bucket
+#[must_use]
+pub fn bucket_of_dir<'a>(dir: &Path, bucket_config: &'a BucketConfig) -> Option<&'a BucketDef> {
+    let classifiers = bucket_config.layout().classifiers();
+    let level = classifiers.iter().position(|c| *c == Classifier::Age)?;
+    let dir_name = dir
+        .components()
+        .rev()
+        .nth(classifiers.len() - 1 - level)?
+        .as_os_str()
+        .to_str()?;
+    bucket_config
+        .all_buckets()
+        .into_iter()
+        .find(|bucket| names_bucket(dir_name, bucket.name(), bucket_config))
+}
+
+/// One axis along which items are sorted into directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Classifier {
+    /// The kind of file, by extension (see [`FileType`])
+    Type,
+    /// The age bucket
+    Age,
+}
+
+impl Classifier {
+    /// Returns the directory names this classifier can produce.
+    pub fn dir_names(self, bucket_config: &BucketConfig) -> Vec<&str> {
+        match self {
+            Self::Type => FileType::ALL.iter().map(|t| t.name()).collect(),
+            Self::Age => bucket_config
+                .all_buckets()
+                .into_iter()
+                .map(BucketDef::name)
+                .collect(),
+        }
     }
 
-    // Check if the directory name is a bucket name
-    let Some(dir_name) = path.file_name().and_then(|s| s.to_str()) else {
-        return false;
-    };
+    /// Returns true if `dir_name` is one of this classifier's directories
+    /// (see [`names_bucket`] for the overflow directories of buckets).
+    pub fn matches(self, dir_name: &str, bucket_config: &BucketConfig) -> bool {
+        let names = self.dir_names(bucket_config);
+        match self {
+            Self::Type => names.contains(&dir_name),
+            Self::Age => names
+                .iter()
+                .any(|name| names_bucket(dir_name, name, bucket_config)),
+        }
+    }
+}
 
-    bucket_config
-        .buckets()
-        .iter()
-        .any(|bucket| bucket.name() == dir_name)
+/// The directory layout below the base folder: classifiers applied in order,
+/// each adding one directory level.
+///
+/// The default layout is just `age`: `<base>/<bucket>/<item>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    classifiers: Vec<Classifier>,
+}
+
+impl Layout {
+    /// Returns the classifiers, outermost directory level first.
+    #[must_use]
+    pub fn classifiers(&self) -> &[Classifier] {
+        &self.classifiers
+    }
+
+    /// Returns true for the default, age-only layout.
+    #[must_use]
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            classifiers: vec![Classifier::Age],
+        }
+    }
+}
+
+impl std::str::FromStr for Layout {
+    type Err = String;
+
+    /// Parses a comma-separated list of classifiers, such as `type,age`.
+    ///
+    /// Each classifier may appear at most once.
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut classifiers = Vec::new();
+        for name in spec.split(',').map(str::trim) {
+            let classifier = match name {
+                "type" => Classifier::Type,
+                "age" => Classifier::Age,
+                other => {
+                    return Err(format!(
+                        "unknown classifier '{other}' (use 'type' or 'age')"
+                    ));
+                }
+            };
+            if classifiers.contains(&classifier) {
+                return Err(format!("classifier '{name}' is listed twice"));
+            }
+            classifiers.push(classifier);
+        }
+        Ok(Self { classifiers })
+    }
+}
+
+/// Broad kind of a file, derived from its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Images,
+    Documents,
+    Audio,
+    Video,
+    Archives,
+    /// Unknown extensions and names without one
+    Other,
+}
+
+impl FileType {
+    /// Every file type, in a fixed order.
+    pub const ALL: [Self; 6] = [
+        Self::Images,
+        Self::Documents,
+        Self::Audio,
+        Self::Video,
+        Self::Archives,
+        Self::Other,
+    ];
+
+    /// Returns the directory name of this file type.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Images => "images",
+            Self::Documents => "documents",
+            Self::Audio => "audio",
+            Self::Video => "video",
+            Self::Archives => "archives",
+            Self::Other => "other",
+        }
+    }
+
+    /// Classifies a path by its extension (case-insensitive).
+    ///
+    /// Only the name is looked at, so directories are classified the same
+    /// way; most have no extension and count as [`FileType::Other`].
+    #[must_use]
+    pub fn of(path: &Path) -> Self {
+        let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+            return Self::Other;
+        };
+        match ext.to_lowercase().as_str() {
+            "jpg" | "jpeg" | "png" | "gif" | "bmp" | "tif" | "tiff" | "webp" | "heic" | "heif"
+            | "svg" | "raw" | "cr2" | "nef" | "arw" | "dng" => Self::Images,
+            "pdf" | "doc" | "docx" | "odt" | "rtf" | "txt" | "md" | "xls" | "xlsx" | "ods"
+            | "csv" | "ppt" | "pptx" | "odp" | "epub" | "tex" => Self::Documents,
+            "mp3" | "wav" | "flac" | "aac" | "ogg" | "m4a" | "opus" | "wma" => Self::Audio,
+            "mp4" | "mkv" | "avi" | "mov" | "wmv" | "webm" | "m4v" | "flv" => Self::Video,
+            "zip" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "7z" | "rar" | "zst" | "iso" => {
+                Self::Archives
+            }
+            _ => Self::Other,
+        }
+    }
 }
 
 /// Compares two paths for equality, attempting canonical comparison.
@@ -238,3 +828,230 @@ pub fn paths_equal(a: &Path, b: &Path) -> bool {
         _ => false, // If either fails, assume they're different
     }
 }
+
+/// Compares two paths for being the same directory entry.
+///
+/// Unlike [`paths_equal`], the last component is not resolved: a symlink and
+/// its target are different entries. Only the parent directories are
+/// canonicalized. Returns false if either entry doesn't exist.
+///
+/// **Note**: This function performs IO and is not strictly pure.
+///
+/// # Arguments
+///
+/// * `a` - First path to compare
+/// * `b` - Second path to compare
+///
+/// # Returns
+///
+/// `true` if both entries exist and are the same entry, `false` otherwise
+pub fn same_entry(a: &Path, b: &Path) -> bool {
+    let entry = |path: &Path| -> Option<PathBuf> {
+        fs::symlink_metadata(path).ok()?;
+        let name = path.file_name()?;
+        let parent = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        Some(fs::canonicalize(parent).ok()?.join(name))
+    };
+    match (entry(a), entry(b)) {
+        (Some(ea), Some(eb)) => ea == eb,
+        _ => false,
+    }
+}
+
+/// A numeric date layout to look for in file names, such as `%d-%m-%Y`.
+///
+/// Supported specifiers are `%Y` (4-digit year), `%y` (2-digit year, 20xx),
+/// `%m` (month), `%d` (day) and `%%`; everything else matches literally. The
+/// default is ISO `%Y-%m-%d`, so `05-11-2023` is never guessed at unless a
+/// format saying which part is the day has been configured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DateFormat {
+    tokens: Vec<DateToken>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateToken {
+    Year,
+    ShortYear,
+    Month,
+    Day,
+    Literal(u8),
+}
+
+impl DateToken {
+    /// Number of bytes the token matches.
+    fn width(self) -> usize {
+        match self {
+            Self::Year => 4,
+            Self::ShortYear | Self::Month | Self::Day => 2,
+            Self::Literal(_) => 1,
+        }
+    }
+}
+
+impl Default for DateFormat {
+    fn default() -> Self {
+        "%Y-%m-%d".parse().expect("ISO date format is valid")
+    }
+}
+
+impl std::str::FromStr for DateFormat {
+    type Err = String;
+
+    /// Parses a strftime-style format.
+    ///
+    /// The format must contain exactly one year (`%Y` or `%y`), one `%m` and
+    /// one `%d`.
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut tokens = Vec::new();
+        let mut bytes = spec.bytes();
+        while let Some(b) = bytes.next() {
+            if b != b'%' {
+                tokens.push(DateToken::Literal(b));
+                continue;
+            }
+            tokens.push(match bytes.next() {
+                Some(b'Y') => DateToken::Year,
+                Some(b'y') => DateToken::ShortYear,
+                Some(b'm') => DateToken::Month,
+                Some(b'd') => DateToken::Day,
+                Some(b'%') => DateToken::Literal(b'%'),
+                Some(other) => {
+                    return Err(format!(
+                        "unsupported specifier '%{}' (use %Y, %y, %m, %d or %%)",
+                        char::from(other)
+                    ));
+                }
+                None => return Err("format ends with a lone '%'".to_string()),
+            });
+        }
+
+        let count = |wanted: &[DateToken]| tokens.iter().filter(|t| wanted.contains(t)).count();
+        if count(&[DateToken::Year, DateToken::ShortYear]) != 1
+            || count(&[DateToken::Month]) != 1
+            || count(&[DateToken::Day]) != 1
+        {
+            return Err(
+                "format must contain exactly one year (%Y or %y), one %m and one %d".to_string(),
+            );
+        }
+
+        Ok(Self { tokens })
+    }
+}
+
+/// Extracts the first date embedded in a file name.
+///
+/// # Arguments
+///
+/// * `name` - The file name to search
+/// * `format` - The date layout to look for
+///
+/// # Returns
+///
+/// `Some((year, month, day))` for the first valid date found, `None` otherwise
+#[must_use]
+pub fn parse_date_from_name(name: &str, format: &DateFormat) -> Option<(i64, u32, u32)> {
+    let bytes = name.as_bytes();
+    let width: usize = format.tokens.iter().map(|t| t.width()).sum();
+    let digits = |s: &[u8]| -> Option<u32> {
+        if s.iter().all(u8::is_ascii_digit) {
+            std::str::from_utf8(s).ok()?.parse().ok()
+        } else {
+            None
+        }
+    };
+
+    'windows: for start in 0..=bytes.len().saturating_sub(width) {
+        let Some(mut window) = bytes.get(start..start + width) else {
+            break;
+        };
+        let (mut year, mut month, mut day) = (0, 0, 0);
+        for token in &format.tokens {
+            let (field, rest) = window.split_at(token.width());
+            window = rest;
+            let value = match *token {
+                DateToken::Literal(b) if field == [b] => continue,
+                DateToken::Literal(_) => continue 'windows,
+                _ => match digits(field) {
+                    Some(value) => value,
+                    None => continue 'windows,
+                },
+            };
+            match token {
+                DateToken::Year => year = i64::from(value),
+                DateToken::ShortYear => year = 2000 + i64::from(value),
+                DateToken::Month => month = value,
+                DateToken::Day => day = value,
+                DateToken::Literal(_) => {}
+            }
+        }
+        if (1..=12).contains(&month) && (1..=31).contains(&day) {
+            return Some((year, month, day));
+        }
+    }
+
+    None
+}
+
+/// Normalizes a path lexically, resolving `.` and `..` without touching the filesystem.
+///
+/// # Arguments
+///
+/// * `path` - The path to normalize
+///
+/// # Returns
+///
+/// The normalized path; `..` components that would climb above the root are dropped
+#[must_use]
+pub fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Computes the relative path that leads from directory `from` to `to`.
+///
+/// Both paths should be absolute and normalized (see `normalize_lexically`).
+///
+/// # Arguments
+///
+/// * `from` - The directory the relative path starts from
+/// * `to` - The path to reach
+///
+/// # Returns
+///
+/// A relative path such that `from.join(result)` refers to `to`
+#[must_use]
+pub fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<_> = from.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to[common..] {
+        relative.push(component);
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
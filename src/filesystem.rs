@@ -3,22 +3,44 @@
 //! This module contains all functions that interact with the filesystem,
 //! including reading file metadata, moving files, and directory operations.
 
-use crate::config::BucketConfig;
-use crate::core::{generate_unique_name, is_bucket_dir};
+use crate::config::{self, AgeSource, BucketConfig, BucketMode, Timestamp};
+use crate::core::{
+    generate_unique_name, is_bucket_dir, normalize_lexically, overflow_dir, parse_date_from_name,
+    relative_path,
+};
+use crate::ignorefile::IgnoreFiles;
+use crate::output::{errln, outln};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use filetime::FileTime;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::io;
+use std::io::{self, BufRead};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime};
 
-/// Retrieves the age of a file based on its modification time.
+/// Retrieves the age of a file based on its configured age source.
 ///
-/// The age is calculated as the duration between now and the file's last
-/// modification time. Falls back to creation time if modification time is
-/// unavailable.
+/// The age source is looked up per file extension in the bucket
+/// configuration's `[age_sources]` mapping:
+/// - `exif`: the image's EXIF `DateTimeOriginal` tag
+/// - `filename`: a date embedded in the file name (`YYYY-MM-DD` unless
+///   `--date-format` says otherwise)
+/// - `frontmatter`: the `date:` field of the file's YAML front matter
+/// - `mtime` (default): the file's filesystem timestamp
+///
+/// EXIF, file name and front matter dates carry no time zone; they are read
+/// as wall-clock times in the `--time-zone`.
+///
+/// When the configured source yields no timestamp (no EXIF data, no date in
+/// the name), falls back to the filesystem timestamp. That is the one chosen
+/// with `--by` (modification time by default); if it is unavailable,
+/// modification time and then creation time are used instead.
 ///
 /// # Arguments
 ///
 /// * `path` - Path to the file or directory
+/// * `bucket_config` - The bucket configuration (for the age source mapping)
 ///
 /// # Returns
 ///
@@ -31,18 +53,485 @@ use std::time::{Duration, SystemTime};
 ///
 /// Returns an error if the file metadata cannot be accessed (e.g., file doesn't exist,
 /// permission denied), or if file timestamps are unavailable or invalid.
-pub fn get_file_age(path: &Path) -> io::Result<Duration> {
+pub fn get_file_age(path: &Path, bucket_config: &BucketConfig) -> io::Result<Duration> {
+    age_since(get_file_timestamp(path, bucket_config)?, bucket_config)
+}
+
+/// Retrieves the point in time a file's age is counted from.
+///
+/// See [`get_file_age`] for how the age source is chosen.
+///
+/// # Errors
+///
+/// Returns an error if the file metadata cannot be accessed, or if file
+/// timestamps are unavailable.
+pub fn get_file_timestamp(path: &Path, bucket_config: &BucketConfig) -> io::Result<SystemTime> {
+    let datetime = match bucket_config.age_source_for(path) {
+        AgeSource::Mtime => None,
+        AgeSource::Exif => read_exif_datetime(path),
+        AgeSource::Frontmatter => read_frontmatter_datetime(path),
+        AgeSource::Filename => path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|name| parse_date_from_name(name, bucket_config.date_format()))
+            .and_then(|(year, month, day)| {
+                NaiveDate::from_ymd_opt(i32::try_from(year).ok()?, month, day)
+            })
+            .map(|date| date.and_time(NaiveTime::MIN)),
+    };
+
+    match datetime {
+        Some(datetime) => Ok(bucket_config.time_zone().wall_clock_time(datetime)),
+        None => filesystem_time(path, bucket_config.timestamp()),
+    }
+}
+
+/// Describes where [`get_file_timestamp`] takes an item's timestamp from: its
+/// age source and the filesystem timestamp that source falls back to, as in
+/// `exif/mtime`.
+pub fn timestamp_source(path: &Path, bucket_config: &BucketConfig) -> String {
+    format!(
+        "{}/{}",
+        bucket_config.age_source_for(path).name(),
+        bucket_config.timestamp().name()
+    )
+}
+
+/// Computes an age from the timestamp it is counted from.
+///
+/// In calendar mode a timestamp in the future (clock skew, or a file from
+/// another time zone) counts as now, so the item goes into the bucket holding
+/// today.
+///
+/// # Errors
+///
+/// Returns an error if the timestamp is in the future (possible clock skew),
+/// outside calendar mode.
+pub fn age_since(timestamp: SystemTime, bucket_config: &BucketConfig) -> io::Result<Duration> {
+    if timestamp > config::current_time() && bucket_config.bucket_mode() != BucketMode::Calendar {
+        return Err(io::Error::other(
+            "File timestamp is in the future - check system clock",
+        ));
+    }
+
+    // Items changed after an anchor in the past count as brand new
+    Ok(bucket_config
+        .age_reference()
+        .duration_since(timestamp)
+        .unwrap_or(Duration::ZERO))
+}
+
+/// Reads one of a file's filesystem timestamps, falling back to its
+/// modification time and then its creation time.
+fn filesystem_time(path: &Path, timestamp: Timestamp) -> io::Result<SystemTime> {
     let meta = fs::metadata(path)?;
 
+    let selected = match timestamp {
+        Timestamp::Mtime => None,
+        Timestamp::Atime => meta.accessed().ok(),
+        Timestamp::Ctime => change_time(&meta),
+        Timestamp::Btime => meta.created().ok(),
+    };
+    if let Some(t) = selected {
+        return Ok(t);
+    }
+
     // Try modification time first, fall back to creation time
-    let timestamp = meta
-        .modified()
+    meta.modified()
         .or_else(|_| meta.created())
-        .map_err(|e| io::Error::other(format!("Cannot read file timestamp: {e}")))?;
+        .map_err(|e| io::Error::other(format!("Cannot read file timestamp: {e}")))
+}
+
+/// Returns a file's last status change time.
+#[cfg(unix)]
+fn change_time(meta: &fs::Metadata) -> Option<SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+
+    let secs = u64::try_from(meta.ctime()).ok()?;
+    let nanos = u32::try_from(meta.ctime_nsec()).ok()?;
+    SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+}
+
+/// Returns a file's last status change time (unavailable on this platform).
+#[cfg(not(unix))]
+fn change_time(_meta: &fs::Metadata) -> Option<SystemTime> {
+    None
+}
+
+/// Returns true if the filesystem holding a path is mounted `noatime`, so
+/// access times are never updated.
+///
+/// The mount is the one in `/proc/self/mounts` with the longest mount point
+/// containing the path.
+#[cfg(target_os = "linux")]
+pub fn mounted_noatime(path: &Path) -> bool {
+    let Ok(path) = fs::canonicalize(path) else {
+        return false;
+    };
+    let Ok(mounts) = fs::read_to_string("/proc/self/mounts") else {
+        return false;
+    };
+
+    let mut best: Option<(usize, bool)> = None;
+    for line in mounts.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(_), Some(mount_point), Some(_), Some(options)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        // Mount points escape whitespace as octal, e.g. `\040` for a space
+        let mount_point = mount_point
+            .replace("\\040", " ")
+            .replace("\\011", "\t")
+            .replace("\\012", "\n")
+            .replace("\\134", "\\");
+        let depth = Path::new(&mount_point).components().count();
+        // Later entries for the same mount point shadow earlier ones
+        if path.starts_with(&mount_point) && best.is_none_or(|(d, _)| depth >= d) {
+            best = Some((depth, options.split(',').any(|o| o == "noatime")));
+        }
+    }
+    best.is_some_and(|(_, noatime)| noatime)
+}
+
+/// Returns true if the filesystem holding a path is mounted `noatime`
+/// (never detected on this platform).
+#[cfg(not(target_os = "linux"))]
+pub fn mounted_noatime(_path: &Path) -> bool {
+    false
+}
+
+/// Reads the EXIF `DateTimeOriginal` tag from an image file.
+///
+/// EXIF timestamps carry no time zone, so the wall-clock time is returned.
+///
+/// # Returns
+///
+/// `Some(NaiveDateTime)` if the file contains a valid `DateTimeOriginal` tag,
+/// `None` if the file cannot be read or has no usable EXIF date.
+fn read_exif_datetime(path: &Path) -> Option<NaiveDateTime> {
+    let file = fs::File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut io::BufReader::new(file))
+        .ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+
+    let exif::Value::Ascii(ref values) = field.value else {
+        return None;
+    };
+    let dt = exif::DateTime::from_ascii(values.first()?).ok()?;
+
+    let date = NaiveDate::from_ymd_opt(i32::from(dt.year), dt.month.into(), dt.day.into())?;
+    let time = NaiveTime::from_hms_opt(dt.hour.into(), dt.minute.into(), dt.second.into())?;
+    Some(date.and_time(time))
+}
+
+/// Reads the `date:` field of a text file's YAML front matter.
+///
+/// The front matter must open on the file's first line with `---` and ends at
+/// the next `---` or `...` line; only a top-level `date:` key is looked at.
+/// The date is `YYYY-MM-DD`, optionally followed by a `HH:MM[:SS]` time
+/// (after a space or `T`); a time zone is ignored, as with EXIF dates.
+///
+/// # Returns
+///
+/// `Some(NaiveDateTime)` if the front matter has a valid date, `None` if the
+/// file cannot be read, has no front matter or no usable date.
+fn read_frontmatter_datetime(path: &Path) -> Option<NaiveDateTime> {
+    /// Front matter longer than this is not looked through.
+    const MAX_LINES: usize = 200;
+
+    let file = fs::File::open(path).ok()?;
+    let mut lines = io::BufReader::new(file).lines();
+    if lines.next()?.ok()?.trim_end() != "---" {
+        return None;
+    }
+    for line in lines.take(MAX_LINES) {
+        let line = line.ok()?;
+        let line = line.trim_end();
+        if line == "---" || line == "..." {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("date:") {
+            return parse_frontmatter_date(value);
+        }
+    }
+    None
+}
+
+/// Parses a front matter date such as `2023-11-05`, `"2023-11-05 14:30"` or
+/// `2023-11-05T14:30:00+01:00`.
+fn parse_frontmatter_date(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+    let (date, time) = value.split_at_checked(10)?;
+
+    let mut parts = date.split('-');
+    let mut field = |len: usize| {
+        parts
+            .next()
+            .filter(|p| p.len() == len && p.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|p| p.parse::<u32>().ok())
+    };
+    let date = NaiveDate::from_ymd_opt(field(4)?.try_into().ok()?, field(2)?, field(2)?)?;
+
+    let time = time.trim_start_matches(['T', ' ']);
+    let number = |range: std::ops::Range<usize>| {
+        time.get(range)
+            .filter(|p| p.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|p| p.parse::<u32>().ok())
+    };
+    let time = if time.is_empty() {
+        NaiveTime::MIN
+    } else {
+        if time.as_bytes().get(2) != Some(&b':') {
+            return None;
+        }
+        let (hour, minute) = (number(0..2)?, number(3..5)?);
+        let second = if time.as_bytes().get(5) == Some(&b':') {
+            number(6..8)?
+        } else {
+            0
+        };
+        NaiveTime::from_hms_opt(hour, minute, second)?
+    };
+
+    Some(date.and_time(time))
+}
+
+/// Returns the size of a file, or the size of a directory as computed by
+/// [`directory_size`].
+///
+/// Symlinks are not followed; their own size is counted.
+///
+/// # Errors
+///
+/// Returns an error if the metadata of the item or any entry beneath it
+/// cannot be read.
+pub fn item_size(path: &Path) -> io::Result<u64> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.is_dir() {
+        directory_size(path)
+    } else {
+        Ok(meta.len())
+    }
+}
+
+/// Returns the total size of the files in a directory tree.
+///
+/// This is the size of a directory wherever one is needed (size buckets,
+/// `--stats-json`, growth reports, progress): only file contents count, not
+/// the size the filesystem reports for the directories themselves, so an
+/// empty directory has size 0. Symlinks are not followed; their own size is
+/// counted.
+///
+/// # Errors
+///
+/// Returns an error if the metadata of any entry beneath `dir` cannot be read.
+pub fn directory_size(dir: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(dir)? {
+        total += item_size(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+/// Returns true if `path` is a regular file without content.
+#[must_use]
+pub fn is_empty_file(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.is_file() && m.len() == 0)
+}
+
+/// Finds a file in a bucket with exactly the same content as `file`.
+///
+/// The bucket directory and its overflow directories are searched. Candidates
+/// are compared by size first, then byte by byte.
+///
+/// # Arguments
+///
+/// * `file` - The incoming file
+/// * `bucket_dir` - The primary bucket directory
+///
+/// # Returns
+///
+/// `Ok(Some(path))` of the first identical file found, `Ok(None)` otherwise
+/// (including when `file` is not a regular file)
+///
+/// # Errors
+///
+/// Returns an error if the incoming file or a bucket directory cannot be read.
+pub fn find_duplicate_in_bucket(file: &Path, bucket_dir: &Path) -> io::Result<Option<PathBuf>> {
+    let meta = fs::symlink_metadata(file)?;
+    if !meta.is_file() {
+        return Ok(None);
+    }
+
+    for n in 1.. {
+        let dir = overflow_dir(bucket_dir, n);
+        if !dir.is_dir() {
+            break;
+        }
+        for entry in fs::read_dir(&dir)? {
+            let candidate = entry?.path();
+            let same_size = fs::symlink_metadata(&candidate)
+                .is_ok_and(|m| m.is_file() && m.len() == meta.len());
+            if same_size && candidate != file && same_contents(file, &candidate)? {
+                return Ok(Some(candidate));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Compares two files byte by byte.
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::io::Read;
+
+    let mut a = io::BufReader::new(fs::File::open(a)?);
+    let mut b = io::BufReader::new(fs::File::open(b)?);
+    let (mut buf_a, mut buf_b) = ([0u8; 8192], [0u8; 8192]);
+    loop {
+        let len = a.read(&mut buf_a)?;
+        if len == 0 {
+            return Ok(b.read(&mut buf_b[..1])? == 0);
+        }
+        if b.read_exact(&mut buf_b[..len]).is_err() || buf_a[..len] != buf_b[..len] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Returns the id of the device holding a path.
+///
+/// Paths that don't exist yet (such as a destination) report the device of
+/// their nearest existing ancestor.
+///
+/// # Returns
+///
+/// `Some(id)` on Unix, `None` if no ancestor can be read or on other platforms
+#[cfg(unix)]
+pub fn device_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    path.ancestors()
+        .find_map(|p| fs::metadata(p).ok())
+        .map(|m| m.dev())
+}
+
+/// Returns the id of the device holding a path (unavailable on this platform).
+#[cfg(not(unix))]
+pub fn device_id(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Returns the id of the device holding an item, without following symlinks.
+///
+/// Unlike [`device_id`], the item must exist. A mount point reports the
+/// device mounted on it.
+///
+/// # Returns
+///
+/// `Some(id)` on Unix, `None` if the item cannot be read or on other platforms
+#[cfg(unix)]
+pub fn item_device_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::symlink_metadata(path).ok().map(|m| m.dev())
+}
 
-    let now = SystemTime::now();
-    now.duration_since(timestamp)
-        .map_err(|_| io::Error::other("File timestamp is in the future - check system clock"))
+/// Returns the id of the device holding an item (unavailable on this platform).
+#[cfg(not(unix))]
+pub fn item_device_id(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Returns the device and inode of a file with more than one hard link,
+/// without following symlinks.
+///
+/// # Returns
+///
+/// `Some(id)` on Unix, `None` for a directory, a file with a single link, an
+/// item that cannot be read, or on other platforms
+#[cfg(unix)]
+pub fn hard_link_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::symlink_metadata(path)
+        .ok()
+        .filter(|m| m.is_file() && m.nlink() > 1)
+        .map(|m| (m.dev(), m.ino()))
+}
+
+/// Returns the device and inode of a hard-linked file (unavailable on this
+/// platform).
+#[cfg(not(unix))]
+pub fn hard_link_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Checks that files can be created in a directory by creating and removing
+/// an empty probe file.
+///
+/// A directory that doesn't exist yet is checked through its nearest existing
+/// ancestor, where it would be created.
+///
+/// # Errors
+///
+/// Returns the error of creating or removing the probe file.
+pub fn probe_writable(dir: &Path) -> io::Result<()> {
+    let Some(existing) = dir.ancestors().find(|p| p.is_dir()) else {
+        return Ok(());
+    };
+    let probe = existing.join(format!(".refile-preflight-{}", std::process::id()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    fs::remove_file(&probe)
+}
+
+/// Resolves a path that may not exist yet to an absolute, canonical form.
+///
+/// The longest existing ancestor is canonicalized (resolving symlinks) and
+/// the rest of the path is appended after lexical normalization, so an
+/// existing path and a path to be created below it compare consistently.
+///
+/// # Errors
+///
+/// Returns an error if the current directory is needed but cannot be read.
+pub fn resolve_path(path: &Path) -> io::Result<PathBuf> {
+    let path = normalize_lexically(&std::path::absolute(path)?);
+    for ancestor in path.ancestors() {
+        if let Ok(canonical) = ancestor.canonicalize() {
+            let rest = path.strip_prefix(ancestor).unwrap_or(Path::new(""));
+            return Ok(if rest.as_os_str().is_empty() {
+                canonical
+            } else {
+                canonical.join(rest)
+            });
+        }
+    }
+    Ok(path)
+}
+
+/// Returns true if `dir` is a directory (not a symlink to one) and `path` lies
+/// inside it.
+///
+/// Both paths are compared after resolving their existing portions with
+/// [`resolve_path`], so a path reaching into `dir` through a symlink, or one
+/// that does not exist yet, is still recognized.
+///
+/// # Errors
+///
+/// Returns an error if either path cannot be made absolute or resolved.
+pub fn is_inside_dir(path: &Path, dir: &Path) -> io::Result<bool> {
+    if !fs::symlink_metadata(dir).is_ok_and(|m| m.is_dir()) {
+        return Ok(false);
+    }
+    let dir = resolve_path(dir)?;
+    let path = resolve_path(path)?;
+    Ok(path != dir && path.starts_with(&dir))
 }
 
 /// Finds a unique destination path by trying numbered suffixes.
@@ -53,6 +542,8 @@ pub fn get_file_age(path: &Path) -> io::Result<Duration> {
 /// # Arguments
 ///
 /// * `base` - The base path to find a unique variant of
+/// * `is_dir` - Whether the item being placed is a directory (see `generate_unique_name`)
+/// * `claimed` - Paths already reserved by other planned moves, treated as taken
 ///
 /// # Returns
 ///
@@ -61,14 +552,19 @@ pub fn get_file_age(path: &Path) -> io::Result<Duration> {
 /// # Errors
 ///
 /// Returns an error if no unique path can be found after trying 10,000 suffixes.
-pub fn find_unique_dest(base: &Path) -> io::Result<PathBuf> {
-    if !base.exists() {
+pub fn find_unique_dest(
+    base: &Path,
+    is_dir: bool,
+    claimed: &HashSet<PathBuf>,
+) -> io::Result<PathBuf> {
+    let taken = |path: &Path| path.exists() || claimed.contains(path);
+    if !taken(base) {
         return Ok(base.to_path_buf());
     }
 
     for i in 1..10_000 {
-        let candidate = generate_unique_name(base, i);
-        if !candidate.exists() {
+        let candidate = generate_unique_name(base, i, is_dir);
+        if !taken(&candidate) {
             return Ok(candidate);
         }
     }
@@ -78,7 +574,7 @@ pub fn find_unique_dest(base: &Path) -> io::Result<PathBuf> {
         format!(
             "Cannot find a unique name for '{}' - files already exist with names up to '{} (10000)'.\n\
              \n\
-             You're using --allow-rename (-r), but there are too many conflicting files.\n\
+             You're using --on-conflict rename, but there are too many conflicting files.\n\
              Consider organizing the destination directory first or removing some duplicates.",
             base.display(),
             base.file_stem().and_then(|s| s.to_str()).unwrap_or("file")
@@ -86,10 +582,134 @@ pub fn find_unique_dest(base: &Path) -> io::Result<PathBuf> {
     ))
 }
 
+/// Finds a destination path numbered above every existing version.
+///
+/// Unlike [`find_unique_dest`], numbers freed up by deleted versions are never
+/// reused, not even the unnumbered base name, so the numbers keep following
+/// the order in which versions arrived. With only `file (3).txt` present,
+/// `file.txt` becomes `file (4).txt`. Used for every move with
+/// `--rename-numbering append`.
+///
+/// # Arguments
+///
+/// * `base` - The base path to find a numbered variant of
+/// * `is_dir` - Whether the item being placed is a directory (see `generate_unique_name`)
+/// * `claimed` - Paths already reserved by other planned moves, treated as taken
+///
+/// # Returns
+///
+/// `Ok(PathBuf)` with the base path if no version of it exists yet, otherwise
+/// the variant numbered one above the highest existing one
+///
+/// # Errors
+///
+/// Returns an error if the destination directory exists but cannot be read.
+pub fn find_appended_dest(
+    base: &Path,
+    is_dir: bool,
+    claimed: &HashSet<PathBuf>,
+) -> io::Result<PathBuf> {
+    let parent = base.parent().unwrap_or_else(|| Path::new("."));
+    let template = generate_unique_name(base, 0, is_dir);
+    let template = template
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let Some((prefix, suffix)) = template.rsplit_once("(0)") else {
+        return find_unique_dest(base, is_dir, claimed);
+    };
+    let version = |name: &str| -> Option<usize> {
+        name.strip_prefix(prefix)?
+            .strip_suffix(suffix)?
+            .strip_prefix('(')?
+            .strip_suffix(')')?
+            .parse()
+            .ok()
+    };
+
+    let mut names: Vec<String> = match fs::read_dir(parent) {
+        Ok(entries) => entries
+            .map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<_>>()?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    names.extend(
+        claimed
+            .iter()
+            .filter(|p| p.parent() == Some(parent))
+            .filter_map(|p| p.file_name())
+            .map(|n| n.to_string_lossy().into_owned()),
+    );
+
+    let highest = names.iter().filter_map(|n| version(n)).max();
+    if highest.is_none() && !base.exists() && !claimed.contains(base) {
+        return Ok(base.to_path_buf());
+    }
+    Ok(generate_unique_name(base, highest.unwrap_or(0) + 1, is_dir))
+}
+
+/// Spreads files over numbered overflow directories once a bucket is full.
+///
+/// Entry counts start from what is already on disk and include every
+/// destination handed out since, so a single run never overfills a directory.
+#[derive(Debug)]
+pub struct BucketRollover {
+    max_files: usize,
+    counts: HashMap<PathBuf, usize>,
+}
+
+impl BucketRollover {
+    /// Creates a rollover tracker allowing `max_files` entries per directory.
+    #[must_use]
+    pub fn new(max_files: NonZeroUsize) -> Self {
+        Self {
+            max_files: max_files.get(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Reserves a slot in the first non-full directory of a bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket_dir` - The primary bucket directory
+    ///
+    /// # Returns
+    ///
+    /// `bucket_dir` or its first overflow directory (`<bucket_dir>-2`, ...)
+    /// that still has room
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an existing directory cannot be read, or if every
+    /// numbered directory is full.
+    pub fn reserve(&mut self, bucket_dir: &Path) -> io::Result<PathBuf> {
+        for n in 1..=usize::MAX {
+            let dir = overflow_dir(bucket_dir, n);
+            let count = match self.counts.get(&dir) {
+                Some(count) => *count,
+                None if dir.is_dir() => fs::read_dir(&dir)?.count(),
+                None => 0,
+            };
+            if count < self.max_files {
+                self.counts.insert(dir.clone(), count + 1);
+                return Ok(dir);
+            }
+            self.counts.insert(dir, count);
+        }
+        Err(io::Error::other(format!(
+            "No overflow directory of {} has room",
+            bucket_dir.display()
+        )))
+    }
+}
+
 /// Creates the refile base directory and all bucket subdirectories.
 ///
 /// This function ensures that the complete directory structure exists based
-/// on the bucket configuration.
+/// on the bucket configuration. With a nested layout (see `--classify-by`)
+/// only the base folder is created up front.
 ///
 /// # Arguments
 ///
@@ -102,7 +722,10 @@ pub fn find_unique_dest(base: &Path) -> io::Result<PathBuf> {
 /// other filesystem issues.
 pub fn create_bucket_dirs(refile_base: &Path, bucket_config: &BucketConfig) -> io::Result<()> {
     fs::create_dir_all(refile_base)?;
-    for bucket in bucket_config.buckets() {
+    if !bucket_config.layout().is_default() {
+        return Ok(()); // Nested layouts are created as items are moved into them
+    }
+    for bucket in bucket_config.all_buckets() {
         fs::create_dir_all(refile_base.join(bucket.name()))?;
     }
     Ok(())
@@ -119,14 +742,226 @@ pub fn create_bucket_dirs(refile_base: &Path, bucket_config: &BucketConfig) -> i
 /// * `bucket_config` - The bucket configuration to use
 pub fn print_dry_run_dirs(refile_base: &Path, bucket_config: &BucketConfig) {
     if !refile_base.exists() {
-        println!("[dry-run] CREATE DIR {}", refile_base.display());
+        outln!("[dry-run] CREATE DIR {}", refile_base.display());
+    }
+    if !bucket_config.layout().is_default() {
+        return;
     }
-    for bucket in bucket_config.buckets() {
+    for bucket in bucket_config.all_buckets() {
         let dir = refile_base.join(bucket.name());
         if !dir.exists() {
-            println!("[dry-run] CREATE DIR {}", dir.display());
+            outln!("[dry-run] CREATE DIR {}", dir.display());
+        }
+    }
+}
+
+/// Removes bucket directories that are empty, then the base folder if empty.
+///
+/// Only directories recognized by `is_bucket_dir` are considered, so stray
+/// user data under the base folder is never removed. The base folder itself is
+/// only removed once it contains nothing at all.
+///
+/// # Arguments
+///
+/// * `refile_base` - Path to the refile base directory
+/// * `bucket_config` - The bucket configuration to use
+///
+/// # Errors
+///
+/// Returns an error if a directory cannot be read or removed.
+pub fn purge_empty_buckets(refile_base: &Path, bucket_config: &BucketConfig) -> io::Result<()> {
+    let walk = walk_layout(refile_base, bucket_config)?;
+    for dir in &walk.buckets {
+        if is_empty_dir(dir)? {
+            fs::remove_dir(dir)?;
+            outln!("Removed empty bucket {}", dir.display());
+        }
+    }
+
+    // Then the levels of a nested layout, innermost first
+    for dir in walk.branches.iter().rev() {
+        if is_empty_dir(dir)? {
+            fs::remove_dir(dir)?;
+            outln!("Removed empty directory {}", dir.display());
         }
     }
+
+    if refile_base.is_dir() && is_empty_dir(refile_base)? {
+        fs::remove_dir(refile_base)?;
+        outln!("Removed empty base folder {}", refile_base.display());
+    }
+
+    Ok(())
+}
+
+/// Returns the existing bucket directories, including overflow directories, sorted.
+///
+/// # Errors
+///
+/// Returns an error if the base folder or one of its directories cannot be read.
+pub fn bucket_dirs(refile_base: &Path, bucket_config: &BucketConfig) -> io::Result<Vec<PathBuf>> {
+    Ok(walk_layout(refile_base, bucket_config)?.buckets)
+}
+
+/// Returns true if `dir` is a refile archive: a directory named like the base
+/// folder that holds at least one bucket directory.
+///
+/// # Errors
+///
+/// Returns an error if the directory or one of its subdirectories cannot be
+/// read.
+pub fn is_archive(dir: &Path, bucket_config: &BucketConfig) -> io::Result<bool> {
+    let named_like_base =
+        dir.file_name().and_then(|n| n.to_str()) == Some(bucket_config.base_folder());
+    Ok(named_like_base && dir.is_dir() && !bucket_dirs(dir, bucket_config)?.is_empty())
+}
+
+/// What the base folder holds, sorted by role.
+#[derive(Debug, Default)]
+struct LayoutWalk {
+    /// Bucket directories, at the innermost level of the layout
+    buckets: Vec<PathBuf>,
+    /// Directories at the outer levels of a nested layout, outermost first
+    branches: Vec<PathBuf>,
+    /// Anything at any level that doesn't fit the layout
+    strays: Vec<PathBuf>,
+}
+
+/// Walks the base folder level by level along the layout.
+fn walk_layout(refile_base: &Path, bucket_config: &BucketConfig) -> io::Result<LayoutWalk> {
+    let mut walk = LayoutWalk::default();
+    if !refile_base.is_dir() {
+        return Ok(walk);
+    }
+
+    let classifiers = bucket_config.layout().classifiers();
+    let mut level = vec![refile_base.to_path_buf()];
+    for (depth, classifier) in classifiers.iter().enumerate() {
+        let innermost = depth + 1 == classifiers.len();
+        let mut next = Vec::new();
+        for dir in &level {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                let fits = path.is_dir()
+                    && if innermost {
+                        is_bucket_dir(&path, bucket_config)
+                    } else {
+                        path.file_name()
+                            .and_then(|n| n.to_str())
+                            .is_some_and(|n| classifier.matches(n, bucket_config))
+                    };
+                if fits {
+                    next.push(path);
+                } else {
+                    walk.strays.push(path);
+                }
+            }
+        }
+        next.sort();
+        if !innermost {
+            walk.branches.extend(next.iter().cloned());
+        }
+        level = next;
+    }
+
+    walk.buckets = level;
+    walk.strays.sort();
+    Ok(walk)
+}
+
+/// Returns true if the directory has no entries.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be read.
+pub fn is_empty_dir(dir: &Path) -> io::Result<bool> {
+    Ok(fs::read_dir(dir)?.next().is_none())
+}
+
+/// Returns true if a directory holds nothing but directories, at any depth.
+///
+/// # Errors
+///
+/// Returns an error if a directory cannot be read.
+pub fn is_empty_tree(dir: &Path) -> io::Result<bool> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !(entry.file_type()?.is_dir() && is_empty_tree(&entry.path())?) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Finds the directories below `source` that moving the `moved` items away
+/// leaves empty: those holding moved items and otherwise only directories
+/// left empty too. Of nested ones, only the outermost is listed.
+///
+/// The base folder is never looked into, and a directory that was empty
+/// before is not listed.
+///
+/// # Errors
+///
+/// Returns an error if a directory cannot be read.
+pub fn dirs_left_empty(
+    source: &Path,
+    refile_base: &Path,
+    moved: &HashSet<&Path>,
+) -> io::Result<Vec<PathBuf>> {
+    let mut emptied = Vec::new();
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir()
+            && !moved.contains(path.as_path())
+            && find_left_empty(&path, refile_base, moved, &mut emptied)?.is_some_and(|n| n > 0)
+        {
+            emptied.push(path);
+        }
+    }
+    emptied.sort();
+    Ok(emptied)
+}
+
+/// Walks a directory bottom-up for [`dirs_left_empty`], adding to `emptied`
+/// the subdirectories left empty if the directory itself is not.
+///
+/// # Returns
+///
+/// The number of moved items below the directory if that is all it holds
+/// besides directories, `None` otherwise
+fn find_left_empty(
+    dir: &Path,
+    refile_base: &Path,
+    moved: &HashSet<&Path>,
+    emptied: &mut Vec<PathBuf>,
+) -> io::Result<Option<usize>> {
+    if dir == refile_base {
+        return Ok(None);
+    }
+    let mut held = Some(0);
+    let mut subdirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let count = if moved.contains(path.as_path()) {
+            Some(1)
+        } else if entry.file_type()?.is_dir() {
+            let count = find_left_empty(&path, refile_base, moved, emptied)?;
+            if count.is_some_and(|n| n > 0) {
+                subdirs.push(path);
+            }
+            count
+        } else {
+            None
+        };
+        held = held.zip(count).map(|(a, b)| a + b);
+    }
+
+    if held.is_none() {
+        emptied.append(&mut subdirs);
+    }
+    Ok(held)
 }
 
 /// Collects all items (files and directories) that need to be processed.
@@ -134,13 +969,40 @@ pub fn print_dry_run_dirs(refile_base: &Path, bucket_config: &BucketConfig) {
 /// This function walks the source directory and:
 /// - Collects all top-level items
 /// - For the refile directory itself, collects items from inside bucket directories
-/// - Treats stray items under refile/ as items to be processed
+/// - Treats stray items under refile/ (at any level of the layout) as items to be processed
+///
+/// With `reconcile_existing` off, the refile directory is skipped entirely so
+/// only new top-level items are swept.
+///
+/// Items excluded by the source's `.refileignore` file are left out, and so
+/// are the ignore files themselves. With a `recursion` depth, the ignore
+/// files of the directories descended into apply as well.
+///
+/// With a `recursion` depth, directories in the source are not items
+/// themselves: the files inside them, down to that depth, are collected
+/// instead (leaving out the refile directory wherever it is), and the
+/// directories stay in place. Files directly in the source are at depth 0,
+/// files in its subdirectories at depth 1, and so on.
+///
+/// With `preserve_structure` as well, bucket directories hold the source's
+/// directory structure (see `--preserve-structure`), so the files at any depth
+/// inside them are collected rather than the directories.
+///
+/// Hidden items (dotfiles and dot-directories) are left out unless
+/// `include_hidden` is set; the refile directory is collected as described
+/// above even when its name starts with a dot.
 ///
 /// # Arguments
 ///
 /// * `source_dir` - The directory to scan for items
 /// * `refile_base` - Path to the refile base directory (for special handling)
 /// * `bucket_config` - The bucket configuration to check bucket directories
+/// * `reconcile_existing` - Whether to re-process items already under the refile directory
+/// * `recursion` - The depth to collect files down to, if the files inside
+///   source directories are collected instead of the directories
+/// * `include_hidden` - Whether to collect hidden items too
+/// * `preserve_structure` - Whether bucket directories mirror the source's
+///   directories, with `recursion`
 ///
 /// # Returns
 ///
@@ -154,11 +1016,16 @@ pub fn collect_items_to_process(
     source_dir: &Path,
     refile_base: &Path,
     bucket_config: &BucketConfig,
+    reconcile_existing: bool,
+    recursion: Option<usize>,
+    include_hidden: bool,
+    preserve_structure: bool,
 ) -> io::Result<Vec<PathBuf>> {
     let mut items = Vec::new();
+    let ignore_files = IgnoreFiles::default().enter(source_dir)?;
 
     let read_dir = fs::read_dir(source_dir).map_err(|e| {
-        eprintln!(
+        errln!(
             "Error reading source directory {}: {e}",
             source_dir.display()
         );
@@ -168,28 +1035,54 @@ pub fn collect_items_to_process(
     for entry_res in read_dir {
         let entry = entry_res?;
         let path = entry.path();
+        let is_dir = entry.file_type()?.is_dir();
 
         // Special handling for refile directory - look inside bucket dirs
+        if path == refile_base && !reconcile_existing {
+            continue;
+        }
+        if path != refile_base
+            && (ignore_files.is_ignored(&path, is_dir) || (!include_hidden && is_hidden(&path)))
+        {
+            continue;
+        }
         if path == refile_base {
-            for child in fs::read_dir(refile_base)? {
-                let child = child?;
-                let p = child.path();
-
-                if p.is_dir() {
-                    if is_bucket_dir(&p, bucket_config) {
-                        // Process items inside bucket directories
-                        for item in fs::read_dir(&p)? {
-                            items.push(item?.path());
-                        }
-                    } else {
-                        // Stray directory under refile/
-                        items.push(p);
-                    }
-                } else {
-                    // Stray file under refile/
-                    items.push(p);
+            let walk = walk_layout(refile_base, bucket_config)?;
+            // Process items inside bucket directories
+            for dir in &walk.buckets {
+                if preserve_structure && recursion.is_some() {
+                    let no_ignore_files = IgnoreFiles::default();
+                    collect_files_below(
+                        dir,
+                        refile_base,
+                        usize::MAX,
+                        &no_ignore_files,
+                        include_hidden,
+                        &mut items,
+                    )?;
+                    continue;
                 }
+                for item in fs::read_dir(dir)? {
+                    items.push(item?.path());
+                }
+            }
+            // Stray files and directories under refile/
+            items.extend(walk.strays);
+            if !include_hidden {
+                items.retain(|item| !item.starts_with(refile_base) || !is_hidden(item));
             }
+        } else if let Some(max_depth) = recursion
+            && is_dir
+        {
+            let ignore_files = ignore_files.enter(&path)?;
+            collect_files_below(
+                &path,
+                refile_base,
+                max_depth,
+                &ignore_files,
+                include_hidden,
+                &mut items,
+            )?;
         } else {
             items.push(path);
         }
@@ -198,42 +1091,560 @@ pub fn collect_items_to_process(
     Ok(items)
 }
 
+/// Collects the files below a top-level source directory for `--recursive`,
+/// down to `max_depth` (the directory's own entries are at depth 1), leaving
+/// out the refile directory's subtree and whatever the `.refileignore` files
+/// in effect exclude.
+///
+/// `ignore_files` are those in effect inside `dir`. Hidden files, and
+/// everything in hidden directories, are left out unless `include_hidden` is
+/// set.
+///
+/// Symlinks are collected as files and never followed.
+///
+/// # Errors
+///
+/// Returns an error if any directory cannot be read.
+fn collect_files_below(
+    dir: &Path,
+    refile_base: &Path,
+    max_depth: usize,
+    ignore_files: &IgnoreFiles,
+    include_hidden: bool,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let mut stack = vec![(dir.to_path_buf(), 1, ignore_files.clone())];
+    while let Some((current, depth, ignore_files)) = stack.pop() {
+        if depth > max_depth {
+            continue;
+        }
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            let is_dir = entry.file_type()?.is_dir();
+            if path == refile_base
+                || ignore_files.is_ignored(&path, is_dir)
+                || (!include_hidden && is_hidden(&path))
+            {
+                continue;
+            }
+            if is_dir {
+                let ignore_files = ignore_files.enter(&path)?;
+                stack.push((path, depth + 1, ignore_files));
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the item is hidden: its name starts with a dot.
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+}
+
+/// Collects the items inside every configured bucket directory and its overflow directories.
+///
+/// Unlike `collect_items_to_process`, the source directory is not scanned
+/// and stray items under the base folder are ignored: only what is already
+/// archived is returned, so it can be re-checked against the current buckets.
+///
+/// # Arguments
+///
+/// * `refile_base` - Path to the refile base directory
+/// * `bucket_config` - The bucket configuration to use
+///
+/// # Returns
+///
+/// `Ok(Vec<PathBuf>)` containing the entries of all existing bucket directories
+///
+/// # Errors
+///
+/// Returns an error if a bucket directory cannot be read.
+pub fn collect_bucket_items(
+    refile_base: &Path,
+    bucket_config: &BucketConfig,
+) -> io::Result<Vec<PathBuf>> {
+    let mut items = Vec::new();
+
+    for dir in bucket_dirs(refile_base, bucket_config)? {
+        for item in fs::read_dir(&dir)? {
+            items.push(item?.path());
+        }
+    }
+
+    Ok(items)
+}
+
+/// Lists the items directly in the source directory and those filed under the
+/// base folder, for comparing the two sides of a run (`--move-report`).
+///
+/// The base folder itself is replaced by its contents: the entries of every
+/// bucket directory and any stray items.
+///
+/// # Errors
+///
+/// Returns an error if the source directory or the base folder cannot be read.
+pub fn list_source_and_buckets(
+    source_dir: &Path,
+    refile_base: &Path,
+    bucket_config: &BucketConfig,
+) -> io::Result<BTreeSet<PathBuf>> {
+    let mut listing = BTreeSet::new();
+    for entry in fs::read_dir(source_dir)? {
+        let path = entry?.path();
+        if path != refile_base {
+            listing.insert(path);
+        }
+    }
+
+    let walk = walk_layout(refile_base, bucket_config)?;
+    for dir in &walk.buckets {
+        for item in fs::read_dir(dir)? {
+            listing.insert(item?.path());
+        }
+    }
+    listing.extend(walk.strays);
+    Ok(listing)
+}
+
+/// Collects the items under the base folder that don't fit its layout.
+///
+/// These are what `collect_items_to_process` treats as stray: files, or
+/// directories not named after a configured bucket (or one of its overflow
+/// directories), usually placed there by accident. With a nested layout,
+/// misplaced items at its outer levels count as well.
+///
+/// # Arguments
+///
+/// * `refile_base` - Path to the refile base directory
+/// * `bucket_config` - The bucket configuration to use
+///
+/// # Returns
+///
+/// `Ok(Vec<PathBuf>)` with the stray items, sorted by path; empty if the base
+/// folder doesn't exist
+///
+/// # Errors
+///
+/// Returns an error if the base folder or one of its directories cannot be read.
+pub fn collect_stray_items(
+    refile_base: &Path,
+    bucket_config: &BucketConfig,
+) -> io::Result<Vec<PathBuf>> {
+    Ok(walk_layout(refile_base, bucket_config)?.strays)
+}
+
+/// Recursively collects every non-directory entry beneath a directory,
+/// leaving out what the `.refileignore` files in effect exclude, like a
+/// `--recursive` scan does.
+///
+/// Symlinks are returned as entries and never followed.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to walk
+/// * `device` - If set, entries on any other device (such as mount points
+///   and everything below them) are left out
+/// * `ignore_files` - The ignore files in effect inside `dir`
+/// * `include_hidden` - Also collect hidden files and the contents of hidden
+///   directories
+///
+/// # Returns
+///
+/// `Ok(Vec<PathBuf>)` with the paths of all files at any depth
+///
+/// # Errors
+///
+/// Returns an error if any directory or ignore file cannot be read.
+pub fn collect_files_recursive(
+    dir: &Path,
+    device: Option<u64>,
+    ignore_files: &IgnoreFiles,
+    include_hidden: bool,
+) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut stack = vec![(dir.to_path_buf(), ignore_files.clone())];
+
+    while let Some((current, ignore_files)) = stack.pop() {
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            if device.is_some() && item_device_id(&path) != device {
+                continue;
+            }
+            let is_dir = entry.file_type()?.is_dir();
+            if ignore_files.is_ignored(&path, is_dir) || (!include_hidden && is_hidden(&path)) {
+                continue;
+            }
+            if is_dir {
+                let ignore_files = ignore_files.enter(&path)?;
+                stack.push((path, ignore_files));
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Deletes a file, symlink or directory tree.
+///
+/// An item that is already gone is not an error.
+///
+/// # Errors
+///
+/// Returns an error if the item exists but cannot be removed.
+pub fn remove_item(path: &Path) -> io::Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Removes a directory tree bottom-up, keeping any directory that still has files.
+///
+/// # Arguments
+///
+/// * `dir` - The root of the tree to prune
+///
+/// # Returns
+///
+/// `Ok(true)` if `dir` itself was removed
+///
+/// # Errors
+///
+/// Returns an error if a directory cannot be read or removed.
+pub fn remove_empty_dirs(dir: &Path) -> io::Result<bool> {
+    let mut empty = true;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !(entry.file_type()?.is_dir() && remove_empty_dirs(&entry.path())?) {
+            empty = false;
+        }
+    }
+
+    if empty {
+        fs::remove_dir(dir)?;
+        outln!("Removed empty directory {}", dir.display());
+    }
+    Ok(empty)
+}
+
+/// Moves a symlink by recreating it at the destination, then removing the original.
+///
+/// A relative link target is rewritten so it still points at the same file
+/// from the new location; with `absolute` set, it is replaced by the absolute
+/// target instead. Absolute targets are kept unchanged. The link itself is
+/// never followed, so this works the same across filesystems.
+///
+/// On non-Unix platforms the link is simply renamed.
+///
+/// # Arguments
+///
+/// * `from` - The symlink to move
+/// * `to` - Destination path for the symlink
+/// * `absolute` - Convert relative targets to absolute ones
+///
+/// # Errors
+///
+/// Returns an error if the link cannot be read, created, or removed.
+pub fn move_symlink(from: &Path, to: &Path, absolute: bool) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        relink(from, to, absolute)?;
+        if let Err(e) = fs::remove_file(from) {
+            errln!(
+                "Linked but failed to remove source symlink {}: {e}",
+                from.display()
+            );
+            return Err(e);
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = absolute;
+        fs::rename(from, to)?;
+    }
+
+    outln!("Moved {} -> {}", from.display(), to.display());
+    Ok(())
+}
+
+/// Creates a symlink at `to` pointing at what the symlink `from` points at,
+/// rewriting its target as [`move_symlink`] does. The original is left alone.
+///
+/// On non-Unix platforms the link's target is copied instead.
+fn relink(from: &Path, to: &Path, absolute: bool) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let target = fs::read_link(from)?;
+        let new_target = if target.is_absolute() {
+            target
+        } else {
+            let from_dir = std::path::absolute(from)?
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            let resolved = normalize_lexically(&from_dir.join(&target));
+            if absolute {
+                resolved
+            } else {
+                let to_dir = normalize_lexically(&std::path::absolute(to)?)
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+                relative_path(&to_dir, &resolved)
+            }
+        };
+        std::os::unix::fs::symlink(&new_target, to)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = absolute;
+        copy_symlink(from, to)
+    }
+}
+
+/// Copies a file, directory or symlink, leaving the original in place
+/// (`--copy`).
+///
+/// A symlink is recreated with its target rewritten as [`move_symlink`] does,
+/// unless `follow_symlinks` is set and it resolves, in which case what it
+/// points at is copied. See [`copy_dir_recursive`] for the symlinks inside a
+/// copied directory. Copies keep the permissions and timestamps of their
+/// originals.
+///
+/// # Errors
+///
+/// Returns an error if the item cannot be read or the copy cannot be created.
+pub fn copy_item(
+    from: &Path,
+    to: &Path,
+    follow_symlinks: bool,
+    absolute_symlinks: bool,
+) -> io::Result<()> {
+    let is_symlink = fs::symlink_metadata(from)?.file_type().is_symlink();
+    if is_symlink && !(follow_symlinks && from.exists()) {
+        relink(from, to, absolute_symlinks)?;
+    } else if from.is_dir() {
+        copy_dir_recursive(from, to, follow_symlinks)?;
+    } else {
+        copy_file(from, to)?;
+    }
+    outln!("Copied {} -> {}", from.display(), to.display());
+    Ok(())
+}
+
+/// Moves a hard link by linking its destination to `original`, another link
+/// to the same file that is already in place, then removing the source link
+/// (`--dedup-hardlinks`). The data is never copied, even across filesystems.
+///
+/// # Errors
+///
+/// Returns an error if the link cannot be created or the source link cannot
+/// be removed.
+pub fn move_hard_link(from: &Path, to: &Path, original: &Path) -> io::Result<()> {
+    fs::hard_link(original, to)?;
+    if let Err(e) = fs::remove_file(from) {
+        errln!(
+            "Linked but failed to remove source link {}: {e}",
+            from.display()
+        );
+        return Err(e);
+    }
+    outln!(
+        "Moved {} -> {} (hard link to {})",
+        from.display(),
+        to.display(),
+        original.display()
+    );
+    Ok(())
+}
+
+/// How often a rename failing with a transient error is attempted in total.
+const RENAME_ATTEMPTS: u32 = 3;
+
+/// Pause between rename attempts.
+const RENAME_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Moves a file or directory, renaming it in place where possible.
+///
+/// See [`move_item_with`].
+///
+/// # Errors
+///
+/// Returns an error if the item can be neither renamed nor copied.
+pub fn move_item(from: &Path, to: &Path, follow_symlinks: bool) -> io::Result<()> {
+    move_item_with(from, to, follow_symlinks, |from, to| fs::rename(from, to))
+}
+
+/// Moves a symlink by copying what it points at to the destination, then
+/// removing the link (`--follow-symlinks`). The link's target is left alone.
+///
+/// # Errors
+///
+/// Returns an error if the target cannot be copied (say, the link is
+/// dangling) or the link cannot be removed.
+pub fn move_symlink_target(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() {
+        copy_dir_recursive(from, to, true)?;
+    } else {
+        copy_file(from, to)?;
+    }
+    if let Err(e) = fs::remove_file(from) {
+        errln!(
+            "Copied but failed to remove source symlink {}: {e}",
+            from.display()
+        );
+        return Err(e);
+    }
+    outln!("Moved {} -> {}", from.display(), to.display());
+    Ok(())
+}
+
+/// Puts back a symlink that [`move_symlink_target`] replaced with a copy of
+/// its target (`refile undo`): the link is recreated at `link` pointing at
+/// `target`, as it was, and the copy at `copy` is removed.
+///
+/// On non-Unix platforms the copy is moved back instead.
+///
+/// # Errors
+///
+/// Returns an error if the link cannot be created or the copy cannot be
+/// removed.
+pub fn restore_symlink(copy: &Path, link: &Path, target: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)?;
+        let removed = if fs::symlink_metadata(copy)?.is_dir() {
+            fs::remove_dir_all(copy)
+        } else {
+            fs::remove_file(copy)
+        };
+        if let Err(e) = removed {
+            errln!(
+                "Restored symlink but failed to remove copy {}: {e}",
+                copy.display()
+            );
+            return Err(e);
+        }
+        outln!("Moved {} -> {}", copy.display(), link.display());
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = target;
+        move_item(copy, link, false)
+    }
+}
+
+/// Moves a file or directory using the given rename operation.
+///
+/// Only a rename that fails because source and destination are on different
+/// filesystems falls back to copy+delete. Transient failures (the destination
+/// being busy, an interrupted call) are retried a few times instead, and any
+/// other failure is returned as is, so a move never turns into a slow copy
+/// that duplicates data for no reason. See [`copy_dir_recursive`] for what
+/// `follow_symlinks` does to the copy.
+///
+/// # Errors
+///
+/// Returns the last rename error, or the error of the copy+delete fallback.
+pub fn move_item_with(
+    from: &Path,
+    to: &Path,
+    follow_symlinks: bool,
+    mut rename: impl FnMut(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut attempt = 1;
+    loop {
+        match rename(from, to) {
+            Ok(()) => {
+                outln!("Moved {} -> {}", from.display(), to.display());
+                return Ok(());
+            }
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                return move_cross_filesystem(from, to, &e, follow_symlinks);
+            }
+            Err(e) if is_transient(&e) && attempt < RENAME_ATTEMPTS => {
+                errln!(
+                    "Retrying move of {} after transient error: {e}",
+                    from.display()
+                );
+                std::thread::sleep(RENAME_RETRY_DELAY);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Returns true for errors that may go away when the operation is retried.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ResourceBusy
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+    )
+}
+
 /// Moves a file or directory across filesystem boundaries.
 ///
-/// This function is called as a fallback when `fs::rename` fails (typically
-/// because source and destination are on different filesystems). It performs
+/// This function is called as a fallback when `fs::rename` fails because
+/// source and destination are on different filesystems. It performs
 /// a copy+delete operation:
 /// - For directories: recursively copies all contents, then removes the source
 /// - For files: copies the file, then removes the source
 ///
+/// Copies keep the permissions and timestamps of the originals, so a moved
+/// item is as old afterwards as it was before.
+///
 /// # Arguments
 ///
 /// * `from` - Source path to move from
 /// * `to` - Destination path to move to
 /// * `rename_err` - The original rename error (used for error messages)
+/// * `follow_symlinks` - Copy what symlinks inside a directory point at
+///   instead of recreating the links
 ///
 /// # Errors
 ///
 /// Returns an error if:
 /// - Copying fails
 /// - Removing the source fails (after successful copy)
-pub fn move_cross_filesystem(from: &Path, to: &Path, rename_err: &io::Error) -> io::Result<()> {
+pub fn move_cross_filesystem(
+    from: &Path,
+    to: &Path,
+    rename_err: &io::Error,
+    follow_symlinks: bool,
+) -> io::Result<()> {
     if from.is_dir() {
-        match copy_dir_recursive(from, to) {
+        match copy_dir_recursive(from, to, follow_symlinks) {
             Ok(()) => {
                 if let Err(e) = fs::remove_dir_all(from) {
-                    eprintln!(
+                    errln!(
                         "Copied but failed to remove source dir {}: {e}",
                         from.display()
                     );
                     Err(e)
                 } else {
-                    println!("Moved {} -> {}", from.display(), to.display());
+                    outln!("Moved {} -> {}", from.display(), to.display());
                     Ok(())
                 }
             }
             Err(copy_err) => {
-                eprintln!(
+                errln!(
                     "Failed to move directory {} (rename: {}, copy: {})",
                     from.display(),
                     rename_err,
@@ -243,21 +1654,21 @@ pub fn move_cross_filesystem(from: &Path, to: &Path, rename_err: &io::Error) ->
             }
         }
     } else {
-        match fs::copy(from, to) {
-            Ok(_bytes) => {
+        match copy_file(from, to) {
+            Ok(()) => {
                 if let Err(e) = fs::remove_file(from) {
-                    eprintln!(
+                    errln!(
                         "Copied but failed to remove source file {}: {e}",
                         from.display()
                     );
                     Err(e)
                 } else {
-                    println!("Moved {} -> {}", from.display(), to.display());
+                    outln!("Moved {} -> {}", from.display(), to.display());
                     Ok(())
                 }
             }
             Err(copy_err) => {
-                eprintln!(
+                errln!(
                     "Failed to move file {} (rename: {}, copy: {})",
                     from.display(),
                     rename_err,
@@ -273,20 +1684,66 @@ pub fn move_cross_filesystem(from: &Path, to: &Path, rename_err: &io::Error) ->
 ///
 /// This function creates the destination directory if it doesn't exist,
 /// then recursively copies all files and subdirectories from source to
-/// destination. Used as part of cross-filesystem move operations.
+/// destination. Used as part of cross-filesystem move operations. Every file
+/// and directory copied keeps the permissions, access time and modification
+/// time of its original, the directories once their contents are copied.
+///
+/// Symlinks are recreated as links to the same target, never followed, so a
+/// link pointing back up the tree (or into the destination) cannot make the
+/// copy run away. With `follow_symlinks`, what a link points at is copied in
+/// its place instead; a link to a directory the copy is already inside is
+/// refused. When the copy fails, whatever part of a destination it created is
+/// removed again, so a refused loop leaves no half-copied directory behind.
 ///
 /// # Arguments
 ///
 /// * `src` - Source directory to copy from
 /// * `dst` - Destination directory to copy to
+/// * `follow_symlinks` - Copy the targets of symlinks instead of the links
 ///
 /// # Errors
 ///
 /// Returns an error if:
 /// - The destination cannot be created
 /// - Any file or directory cannot be read
-/// - Any file cannot be copied
-pub fn copy_dir_recursive(src: &Path, dst: &Path) -> io::Result<()> {
+/// - Any file or symlink cannot be copied
+/// - A followed symlink leads back into a directory being copied
+pub fn copy_dir_recursive(src: &Path, dst: &Path, follow_symlinks: bool) -> io::Result<()> {
+    let existed = fs::symlink_metadata(dst).is_ok();
+    let mut ancestors = Vec::new();
+    let result = copy_dir_inner(src, dst, follow_symlinks, &mut ancestors);
+    if result.is_err()
+        && !existed
+        && let Err(e) = fs::remove_dir_all(dst)
+        && e.kind() != io::ErrorKind::NotFound
+    {
+        errln!("Failed to remove partial copy {}: {e}", dst.display());
+    }
+    result
+}
+
+/// Copies a directory for [`copy_dir_recursive`], keeping the canonical
+/// paths of the directories being copied to detect symlink loops.
+fn copy_dir_inner(
+    src: &Path,
+    dst: &Path,
+    follow_symlinks: bool,
+    ancestors: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let canonical = fs::canonicalize(src)?;
+    if ancestors.contains(&canonical) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Symlink loop: {} leads back to {}",
+                src.display(),
+                canonical.display()
+            ),
+        ));
+    }
+    ancestors.push(canonical);
+    let meta = fs::metadata(src)?;
+
     if !dst.exists() {
         fs::create_dir_all(dst)?;
     }
@@ -294,11 +1751,52 @@ pub fn copy_dir_recursive(src: &Path, dst: &Path) -> io::Result<()> {
         let entry = entry?;
         let path = entry.path();
         let dest_path = dst.join(entry.file_name());
-        if path.is_dir() {
-            copy_dir_recursive(&path, &dest_path)?;
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() && !follow_symlinks {
+            copy_symlink(&path, &dest_path)?;
+        } else if path.is_dir() {
+            copy_dir_inner(&path, &dest_path, follow_symlinks, ancestors)?;
         } else {
-            fs::copy(&path, &dest_path)?;
+            copy_file(&path, &dest_path)?;
         }
     }
+
+    // Last, as copying the contents changes the times (and the permissions
+    // may forbid it)
+    copy_metadata(&meta, dst)?;
+    ancestors.pop();
     Ok(())
 }
+
+/// Copies a file with its permissions and timestamps.
+fn copy_file(from: &Path, to: &Path) -> io::Result<()> {
+    // Read before the copy reads the file and updates its access time
+    let meta = fs::metadata(from)?;
+    fs::copy(from, to)?;
+    copy_metadata(&meta, to)
+}
+
+/// Gives a copy the permissions, access time and modification time of its
+/// original, so it ages, and lands in buckets, like the original would have.
+fn copy_metadata(original: &fs::Metadata, to: &Path) -> io::Result<()> {
+    fs::set_permissions(to, original.permissions())?;
+    filetime::set_file_times(
+        to,
+        FileTime::from_last_access_time(original),
+        FileTime::from_last_modification_time(original),
+    )
+}
+
+/// Recreates a symlink with the same target at another path.
+///
+/// On non-Unix platforms the link's target is copied instead.
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)
+    }
+    #[cfg(not(unix))]
+    {
+        fs::copy(from, to).map(drop)
+    }
+}
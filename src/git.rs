@@ -0,0 +1,119 @@
+//! Git working tree awareness.
+//!
+//! When the source directory is the root of a git working tree, these helpers
+//! decide which items must be left alone: the `.git` directory itself, files
+//! ignored by `.gitignore`, and files tracked by git.
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Filter that protects the contents of a git working tree.
+#[derive(Debug)]
+pub struct GitFilter {
+    root: PathBuf,
+    ignored: Gitignore,
+    tracked: HashSet<OsString>,
+}
+
+impl GitFilter {
+    /// Loads the git state of a directory if it is a working tree root.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The directory to inspect (usually the source directory)
+    ///
+    /// # Returns
+    ///
+    /// `Ok(None)` if `root` has no `.git` directory, `Ok(Some(GitFilter))` otherwise
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ignore files cannot be parsed or if `git ls-files`
+    /// cannot be run. Refusing to continue is safer than moving tracked files.
+    pub fn load(root: &Path) -> io::Result<Option<Self>> {
+        if !root.join(".git").is_dir() {
+            return Ok(None);
+        }
+
+        let mut builder = GitignoreBuilder::new(root);
+        for ignore_file in [root.join(".gitignore"), root.join(".git/info/exclude")] {
+            if ignore_file.is_file()
+                && let Some(e) = builder.add(&ignore_file)
+            {
+                return Err(io::Error::other(format!(
+                    "Failed to parse {}: {e}",
+                    ignore_file.display()
+                )));
+            }
+        }
+        let ignored = builder.build().map_err(io::Error::other)?;
+
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .args(["ls-files", "-z", "--cached"])
+            .output()
+            .map_err(|e| io::Error::other(format!("Failed to run git ls-files: {e}")))?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "git ls-files failed in {}: {}",
+                root.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        // Only the first component matters: items are top-level entries of root
+        let tracked = output
+            .stdout
+            .split(|b| *b == 0)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                let entry = String::from_utf8_lossy(entry);
+                Path::new(entry.as_ref())
+                    .components()
+                    .next()
+                    .map(|c| c.as_os_str().to_os_string())
+            })
+            .collect();
+
+        Ok(Some(Self {
+            root: root.to_path_buf(),
+            ignored,
+            tracked,
+        }))
+    }
+
+    /// Returns the reason an item must be left in place, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A top-level item of the working tree root
+    ///
+    /// # Returns
+    ///
+    /// `Some(reason)` if the item is git metadata, ignored, or tracked; `None` otherwise
+    pub fn skip_reason(&self, path: &Path) -> Option<&'static str> {
+        if path.parent() != Some(self.root.as_path()) {
+            return None;
+        }
+        let name = path.file_name()?;
+
+        if name == ".git" {
+            Some("git metadata")
+        } else if self.tracked.contains(name) {
+            Some("tracked by git")
+        } else if self
+            .ignored
+            .matched_path_or_any_parents(path, path.is_dir())
+            .is_ignore()
+        {
+            Some("ignored by git")
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! External hooks.
+//!
+//! With `--post-move-hook`, an external program is run after every successful
+//! move, for integrations such as reindexing a search tool. It gets the old and
+//! the new path as its two arguments and in these environment variables:
+//!
+//! - `REFILE_FROM`: where the item was
+//! - `REFILE_TO`: where the item is now
+//!
+//! Whether a failing hook aborts the run is up to `--hook-failure`.
+//!
+//! `--pre-run-hook` and `--post-run-hook` run once per run, before planning
+//! (or once a reviewed plan passed) and after the moves, say to mount a disk
+//! or take a snapshot. Once the pre-run hook has run, the post-run hook runs
+//! too, even if the run fails. Both get the source and the target directory
+//! as their two arguments and in `REFILE_SOURCE` and `REFILE_TARGET`, and
+//! `REFILE_DRY_RUN` (`true` or `false`). The post-run hook also gets the
+//! run's summary in `REFILE_MOVED` and `REFILE_SKIPPED`. A failing run hook
+//! fails the run; a failing pre-run hook does so before anything is moved.
+
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// What a run did, for the post-run hook.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunSummary {
+    /// Moves made, or planned in a dry run
+    pub moved: usize,
+    pub skipped: usize,
+}
+
+/// Runs the post-move hook for a move that has succeeded.
+///
+/// # Errors
+///
+/// Returns an error if the program cannot be run or exits unsuccessfully.
+pub fn run_post_move_hook(program: &Path, from: &Path, to: &Path) -> io::Result<()> {
+    run_hook(
+        program,
+        "Post-move hook",
+        [from, to],
+        [
+            ("REFILE_FROM", from.as_os_str()),
+            ("REFILE_TO", to.as_os_str()),
+        ],
+    )
+}
+
+/// Runs the pre-run hook, before anything is planned.
+///
+/// # Errors
+///
+/// Returns an error if the program cannot be run or exits unsuccessfully.
+pub fn run_pre_run_hook(
+    program: &Path,
+    source: &Path,
+    target: &Path,
+    dry_run: bool,
+) -> io::Result<()> {
+    let dry_run = dry_run.to_string();
+    run_hook(
+        program,
+        "Pre-run hook",
+        [source, target],
+        [
+            ("REFILE_SOURCE", source.as_os_str()),
+            ("REFILE_TARGET", target.as_os_str()),
+            ("REFILE_DRY_RUN", OsStr::new(&dry_run)),
+        ],
+    )
+}
+
+/// Runs the post-run hook, once the moves are done.
+///
+/// # Errors
+///
+/// Returns an error if the program cannot be run or exits unsuccessfully.
+pub fn run_post_run_hook(
+    program: &Path,
+    source: &Path,
+    target: &Path,
+    dry_run: bool,
+    summary: RunSummary,
+) -> io::Result<()> {
+    let (dry_run, moved, skipped) = (
+        dry_run.to_string(),
+        summary.moved.to_string(),
+        summary.skipped.to_string(),
+    );
+    run_hook(
+        program,
+        "Post-run hook",
+        [source, target],
+        [
+            ("REFILE_SOURCE", source.as_os_str()),
+            ("REFILE_TARGET", target.as_os_str()),
+            ("REFILE_DRY_RUN", OsStr::new(&dry_run)),
+            ("REFILE_MOVED", OsStr::new(&moved)),
+            ("REFILE_SKIPPED", OsStr::new(&skipped)),
+        ],
+    )
+}
+
+/// Runs a hook program with the given arguments and environment.
+///
+/// # Errors
+///
+/// Returns an error naming the hook if the program cannot be run or exits
+/// unsuccessfully.
+fn run_hook<const N: usize>(
+    program: &Path,
+    hook: &str,
+    args: [&Path; 2],
+    envs: [(&str, &OsStr); N],
+) -> io::Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .envs(envs)
+        .output()
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed to run {} {}: {e}",
+                    hook.to_lowercase(),
+                    program.display()
+                ),
+            )
+        })?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{hook} {} failed for {} ({}): {}",
+            program.display(),
+            args[1].display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
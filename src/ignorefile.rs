@@ -0,0 +1,77 @@
+//! `.refileignore` files.
+//!
+//! A `.refileignore` file lists, in `.gitignore` syntax, items below its
+//! directory that refile must never move. Patterns are relative to the
+//! directory holding the file, `!` re-includes an item an earlier pattern
+//! excluded, and the file of a deeper directory takes precedence over the
+//! files of the directories above it.
+//!
+//! The ignore files themselves are never moved, and neither are project
+//! config files (`.refile.toml`).
+
+use crate::config::PROJECT_CONFIG_NAME;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+/// File name of an ignore file.
+pub const IGNORE_FILE_NAME: &str = ".refileignore";
+
+/// The ignore files in effect for a directory, outermost first.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreFiles {
+    matchers: Vec<Rc<Gitignore>>,
+}
+
+impl IgnoreFiles {
+    /// Returns the ignore files in effect inside `dir`: these plus the one in
+    /// `dir` itself, if there is one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ignore file in `dir` cannot be read or parsed.
+    pub fn enter(&self, dir: &Path) -> io::Result<Self> {
+        let file = dir.join(IGNORE_FILE_NAME);
+        if !file.is_file() {
+            return Ok(self.clone());
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(e) = builder.add(&file) {
+            return Err(io::Error::other(format!(
+                "Failed to parse {}: {e}",
+                file.display()
+            )));
+        }
+        let matcher = builder.build().map_err(io::Error::other)?;
+
+        let mut entered = self.clone();
+        entered.matchers.push(Rc::new(matcher));
+        Ok(entered)
+    }
+
+    /// Returns true if an item must be left alone: it is an ignore file or a
+    /// project config file, or the innermost ignore file with a say on it (or
+    /// on a directory it is in) excludes it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - An item below every directory these ignore files were
+    ///   entered from
+    /// * `is_dir` - Whether the item is a directory
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if path
+            .file_name()
+            .is_some_and(|name| name == IGNORE_FILE_NAME || name == PROJECT_CONFIG_NAME)
+        {
+            return true;
+        }
+        self.matchers
+            .iter()
+            .rev()
+            .map(|matcher| matcher.matched_path_or_any_parents(path, is_dir))
+            .find(|m| !m.is_none())
+            .is_some_and(|m| m.is_ignore())
+    }
+}
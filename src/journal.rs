@@ -0,0 +1,130 @@
+//! Journal of the moves of the last run, for `refile undo`.
+//!
+//! Every real run that moves something starts a fresh journal in the base
+//! folder and appends each move as a JSON line once it has succeeded, so the
+//! journal is accurate even if the run is interrupted. `refile undo` moves the
+//! items back in reverse order and then empties the journal, so a run cannot
+//! be undone twice.
+
+use crate::config;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::UNIX_EPOCH;
+
+/// File name of the journal inside the base folder.
+pub const JOURNAL_NAME: &str = ".refile-journal.jsonl";
+
+/// A move that was carried out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// When the move finished, in seconds since the Unix epoch
+    pub time: u64,
+    /// Where `from` pointed, if it was a symlink whose target was copied to
+    /// `to` (`--follow-symlinks`), so undo can put the link back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<PathBuf>,
+}
+
+/// The journal being written by the current run.
+#[derive(Debug)]
+pub struct Journal {
+    file: Mutex<File>,
+}
+
+impl Journal {
+    /// Starts a fresh journal in a base folder, replacing the previous one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the journal cannot be created.
+    pub fn create(refile_base: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: Mutex::new(File::create(journal_path(refile_base))?),
+        })
+    }
+
+    /// Appends a move that has succeeded, with the target of the symlink at
+    /// `from` if what it pointed at was moved instead of the link.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry cannot be written.
+    pub fn record(&self, from: &Path, to: &Path, link_target: Option<&Path>) -> io::Result<()> {
+        let entry = JournalEntry {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            link_target: link_target.map(Path::to_path_buf),
+            time: config::current_time()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        };
+        let line = serde_json::to_string(&entry).map_err(io::Error::other)?;
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        writeln!(file, "{line}")
+    }
+}
+
+/// Reads the moves recorded in a base folder's journal, oldest first.
+///
+/// A missing journal yields no moves.
+///
+/// # Errors
+///
+/// Returns an error if the journal exists but cannot be read or a line cannot
+/// be parsed.
+pub fn read_journal(refile_base: &Path) -> io::Result<Vec<JournalEntry>> {
+    let path = journal_path(refile_base);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Failed to parse journal {} line {}: {e}",
+                        path.display(),
+                        index + 1
+                    ),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Empties a base folder's journal once its moves have been undone.
+///
+/// # Errors
+///
+/// Returns an error if the journal cannot be truncated.
+pub fn clear_journal(refile_base: &Path) -> io::Result<()> {
+    match File::options()
+        .write(true)
+        .truncate(true)
+        .open(journal_path(refile_base))
+    {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result.map(drop),
+    }
+}
+
+/// Returns the path of the journal inside a base folder.
+pub fn journal_path(refile_base: &Path) -> PathBuf {
+    refile_base.join(JOURNAL_NAME)
+}
+
+/// Returns true if `path` is the journal of the given base folder.
+pub fn is_journal(path: &Path, refile_base: &Path) -> bool {
+    path == journal_path(refile_base)
+}
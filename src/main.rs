@@ -1,29 +1,163 @@
+mod cache;
+mod classify;
 mod config;
 mod core;
 mod filesystem;
+mod git;
+mod hook;
+mod ignorefile;
+mod journal;
+mod manifest;
+mod output;
+mod patterns;
+mod progress;
+mod schedule;
+mod stats;
 
-use clap::{Parser, Subcommand};
-use config::BucketConfig;
-use core::{compute_dest_path, is_protected_directory, paths_equal, pick_bucket, refile_base_path};
+use cache::MetadataCache;
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{
+    AgeSource, BucketConfig, BucketDef, BucketMode, CalendarZone, ConfigLayer, RefileConfigFile,
+    Timestamp,
+};
+use core::{
+    DateFormat, Layout, TRASH_DIR, compute_dest_path, has_extension, is_protected_directory,
+    is_system_file, overflow_index, parse_date_from_name, paths_equal, pick_bucket_for,
+    refile_base_path, same_entry, transliterate_to_ascii, trash_path,
+};
 use filesystem::{
-    collect_items_to_process, create_bucket_dirs, find_unique_dest, get_file_age,
-    move_cross_filesystem, print_dry_run_dirs,
+    BucketRollover, age_since, bucket_dirs, collect_bucket_items, collect_files_recursive,
+    collect_items_to_process, collect_stray_items, copy_item, create_bucket_dirs, device_id,
+    dirs_left_empty, find_appended_dest, find_duplicate_in_bucket, find_unique_dest, get_file_age,
+    get_file_timestamp, hard_link_id, is_archive, is_empty_dir, is_empty_file, is_empty_tree,
+    is_inside_dir, item_device_id, item_size, list_source_and_buckets, mounted_noatime,
+    move_hard_link, move_item, move_symlink, move_symlink_target, print_dry_run_dirs,
+    probe_writable, purge_empty_buckets, remove_empty_dirs, remove_item, resolve_path,
+    restore_symlink, timestamp_source,
 };
+use git::GitFilter;
+use ignorefile::IgnoreFiles;
+use journal::Journal;
+use manifest::{BucketStats, Manifest};
+use output::{Verbosity, errln, format_size, outln, reportln};
+use patterns::PatternFilter;
+use progress::Progress;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::io;
+use std::io::{self, IsTerminal, Write};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Organize files by age into categorized subdirectories
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
+    /// Use this configuration file instead of the default location
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Validate the configuration file (default section and every rule) and exit
+    #[arg(long, default_value_t = false)]
+    check_config: bool,
+
+    /// Rewrite the configuration file in canonical form (buckets sorted by age) and exit;
+    /// comments in the file are not kept
+    #[arg(long, default_value_t = false, conflicts_with = "check_config")]
+    normalize_config: bool,
+
     #[command(flatten)]
     refile: Option<RefileArgs>,
 }
 
+/// Arguments of `refile stats`: where the buckets are, and the bucket options
+/// of the runs that fill them.
+#[derive(clap::Args, Debug)]
+struct StatsArgs {
+    /// Source directory whose buckets to report on
+    source_dir: PathBuf,
+
+    /// Target directory of the buckets, if it differs from the source
+    target_dir: Option<PathBuf>,
+
+    /// Base folder name, if it was overridden
+    #[arg(long, value_name = "NAME")]
+    base_folder: Option<String>,
+
+    /// Override bucket configuration, as for a run (format: "name1=days1,name2=days2,name3=null")
+    #[arg(long)]
+    buckets: Option<String>,
+
+    /// Compare bucket limits against item age, size or calendar period, as for a run; overrides the
+    /// config's `mode` [default: age]
+    #[arg(long, value_enum)]
+    bucket_mode: Option<BucketMode>,
+
+    /// Filesystem timestamp that ages are counted from
+    #[arg(long, value_enum, value_name = "TIMESTAMP", default_value_t = Timestamp::Mtime)]
+    by: Timestamp,
+
+    /// Time zone calendar periods are counted in: "local", "utc" or an offset such as "+02:00"
+    #[arg(
+        long,
+        value_name = "ZONE",
+        default_value = "local",
+        allow_hyphen_values = true
+    )]
+    time_zone: CalendarZone,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// When to show bucket glyphs in the table
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = ColorWhen::Auto)]
+    color: ColorWhen,
+}
+
+/// Arguments of `refile flatten`: where the buckets are, the bucket and
+/// layout options of the runs that filled them, and how to handle conflicts.
+#[derive(clap::Args, Debug)]
+struct FlattenArgs {
+    /// Source directory to move the items back into
+    source_dir: PathBuf,
+
+    /// Target directory of the buckets, if it differs from the source
+    target_dir: Option<PathBuf>,
+
+    /// Base folder name, if it was overridden
+    #[arg(long, value_name = "NAME")]
+    base_folder: Option<String>,
+
+    /// Override bucket configuration, as for a run (format: "name1=days1,name2=days2,name3=null")
+    #[arg(long)]
+    buckets: Option<String>,
+
+    /// Compare bucket limits against item age, size or calendar period, as for a run; overrides the
+    /// config's `mode` [default: age]
+    #[arg(long, value_enum)]
+    bucket_mode: Option<BucketMode>,
+
+    /// Directory levels below the base folder, as for a run (e.g. "type,age")
+    #[arg(long, value_name = "CLASSIFIERS")]
+    classify_by: Option<Layout>,
+
+    /// Bucket cap of the runs, so their overflow directories (`<bucket>-2`, ...) are flattened too
+    #[arg(long, value_name = "N")]
+    bucket_max_files: Option<NonZeroUsize>,
+
+    /// What to do when an item's name is already taken in the source directory
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = OnConflict::Abort)]
+    on_conflict: OnConflict,
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Configuration file management
@@ -31,6 +165,22 @@ enum Commands {
         #[command(subcommand)]
         command: ConfigCommand,
     },
+    /// Move the items of the last run back where they came from
+    Undo {
+        /// Source directory of the run to undo
+        source_dir: PathBuf,
+
+        /// Target directory of the run, if one was given
+        target_dir: Option<PathBuf>,
+
+        /// Base folder name of the run, if it was overridden
+        #[arg(long, value_name = "NAME")]
+        base_folder: Option<String>,
+    },
+    /// Report what the buckets hold and which items are now in the wrong one, moving nothing
+    Stats(StatsArgs),
+    /// Move every item out of the buckets back into the source directory and remove the base folder
+    Flatten(FlattenArgs),
 }
 
 #[derive(Subcommand, Debug)]
@@ -50,8 +200,13 @@ enum ConfigCommand {
 }
 
 #[derive(Parser, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 struct RefileArgs {
     /// Source directory to scan for files and directories
+    #[arg(
+        required = false,
+        required_unless_present_any = ["check_config", "normalize_config"]
+    )]
     source_dir: PathBuf,
 
     /// Target directory where refile/* subdirectories will be created (defaults to `source_dir`)
@@ -61,14 +216,51 @@ struct RefileArgs {
     #[arg(short = 'n', long)]
     dry_run: bool,
 
-    /// Allow renaming files to avoid conflicts (default: abort on conflict)
-    #[arg(short = 'r', long, default_value_t = false)]
+    /// Print more detail, such as the items left in place and why
+    #[arg(short = 'v', long)]
+    verbose: bool,
+
+    /// Print only errors and warnings, no per-item or summary lines
+    #[arg(short = 'q', long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Ask before each move: y(es), n(o), a(ll remaining) or q(uit, moving nothing)
+    #[arg(short = 'i', long, default_value_t = false, conflicts_with = "dry_run")]
+    interactive: bool,
+
+    /// Read --interactive answers from stdin even when it is not a terminal (for tests)
+    #[arg(long, hide = true, requires = "interactive")]
+    assume_tty: bool,
+
+    /// Print a tally of the run at the end: moves and size per bucket, skips and conflicts (always shown after a dry-run; with --quiet, only the tally is printed)
+    #[arg(long, default_value_t = false)]
+    summary: bool,
+
+    /// What to do when a destination already exists
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = OnConflict::Abort)]
+    on_conflict: OnConflict,
+
+    /// Deprecated alias for `--on-conflict rename`
+    #[arg(
+        short = 'r',
+        long,
+        default_value_t = false,
+        conflicts_with = "on_conflict"
+    )]
     allow_rename: bool,
 
+    /// How --on-conflict rename numbers renamed items
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = RenameNumbering::Fill)]
+    rename_numbering: RenameNumbering,
+
     /// Allow moving protected directories (root, home, top-level directories) - USE WITH EXTREME CAUTION
     #[arg(long, default_value_t = false)]
     allow_dangerous_directories: bool,
 
+    /// Create the base folder in a subdirectory of the target named after the run's date (YYYY-MM-DD)
+    #[arg(long, default_value_t = false)]
+    target_date_subdir: bool,
+
     /// Override base folder name (default: "refile")
     #[arg(long)]
     base_folder: Option<String>,
@@ -76,12 +268,775 @@ struct RefileArgs {
     /// Override bucket configuration (format: "name1=days1,name2=days2,name3=null")
     #[arg(long)]
     buckets: Option<String>,
+
+    /// Treat bucket ages as lower bounds, listed oldest first; the catch-all takes the newest items
+    #[arg(long, default_value_t = false)]
+    reverse_age: bool,
+
+    /// Treat bucket limits as half-open intervals [previous, limit): an item exactly at a limit goes to the next bucket
+    #[arg(long, default_value_t = false, conflicts_with = "reverse_age")]
+    half_open: bool,
+
+    /// Leave items that match no bucket where they are; no catch-all bucket ("name=null") is needed then
+    #[arg(long, default_value_t = false)]
+    leave_unmatched: bool,
+
+    /// Compare bucket limits against item age (days), size (bytes, e.g. "small=10MB,large=null")
+    /// or calendar period ("week=this-week,older=null"); overrides the config's `mode` [default: age]
+    #[arg(long, value_enum, conflicts_with = "reverse_age")]
+    bucket_mode: Option<BucketMode>,
+
+    /// Time zone of calendar periods, --anchor-to midnight, dated names and the dates read from
+    /// EXIF, file names and front matter: "local", "utc" or an offset such as "+02:00"
+    #[arg(
+        long,
+        value_name = "ZONE",
+        default_value = "local",
+        allow_hyphen_values = true
+    )]
+    time_zone: CalendarZone,
+
+    /// Layer the buckets and base folder from this TOML file over the configuration
+    #[arg(long, value_name = "PATH")]
+    config_layer: Option<PathBuf>,
+
+    /// Read a bucket spec (same format as --buckets) from the `REFILE_BUCKETS` environment variable
+    #[arg(long, default_value_t = false)]
+    config_env_overlay: bool,
+
+    /// Remove empty bucket directories (and an empty base folder) at the end of the run
+    #[arg(long, default_value_t = false)]
+    purge_empty_buckets: bool,
+
+    /// Print per-bucket file counts and sizes of the source as JSON, without moving anything
+    #[arg(long, default_value_t = false)]
+    stats_json: bool,
+
+    /// Count empty files in --stats-json (default: leave them out)
+    #[arg(long, default_value_t = false, requires = "stats_json")]
+    include_empty_in_stats: bool,
+
+    /// List stray items directly under the base folder (not in a bucket), without moving anything
+    #[arg(long, default_value_t = false, conflicts_with = "stats_json")]
+    report_orphans: bool,
+
+    /// When the source is a git working tree, leave tracked and gitignored files in place
+    #[arg(long, default_value_t = false)]
+    respect_gitignore: bool,
+
+    /// Suffix moved directories with their year and month in the --time-zone (e.g. "project_2023-11")
+    #[arg(long, default_value_t = false)]
+    date_suffix_dirs: bool,
+
+    /// Only re-check items already in bucket directories and move misplaced ones
+    #[arg(long, default_value_t = false)]
+    reconcile: bool,
+
+    /// Re-process items already in the base folder so they move on as they age
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    reconcile_existing: bool,
+
+    /// Leave operating system clutter files such as `.DS_Store` and `Thumbs.db` in place
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    skip_system_files: bool,
+
+    /// Only organize files with this extension (case-insensitive, repeatable); skip everything else
+    #[arg(long, alias = "include-extension", value_name = "EXT")]
+    ext: Vec<String>,
+
+    /// Only organize items matching this glob (repeatable); matched like --exclude, which takes
+    /// precedence: an item must match an include and no exclude pattern to be moved
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Leave items matching this glob alone (repeatable); matched against the file name and the
+    /// path relative to the source, and covering everything inside a matching directory
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Bucket the files inside directories individually, then remove emptied directories
+    #[arg(long, default_value_t = false)]
+    explode_dirs: bool,
+
+    /// Also process hidden items (names starting with a dot), which are left alone by default
+    #[arg(long, default_value_t = false)]
+    hidden: bool,
+
+    /// Bucket every file at any depth by its own age, leaving the directories in place
+    #[arg(long, default_value_t = false, conflicts_with_all = ["explode_dirs", "dir_move_threshold"])]
+    recursive: bool,
+
+    /// With --recursive, only bucket files at most N directories deep (0 = directly in the source)
+    #[arg(long, value_name = "N", requires = "recursive")]
+    depth: Option<usize>,
+
+    /// With --recursive, recreate each file's directories below the source inside its bucket
+    /// (source/a/b/c.txt -> refile/<bucket>/a/b/c.txt) instead of putting all files side by side
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "recursive",
+        conflicts_with = "bucket_max_files"
+    )]
+    preserve_structure: bool,
+
+    /// With --recursive, what to do with the source directories the run leaves empty
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = EmptyDirs::Keep,
+        requires = "recursive",
+        conflicts_with = "copy"
+    )]
+    empty_dirs: EmptyDirs,
+
+    /// Only treat a folder named like the base folder as an archive if it holds bucket directories;
+    /// leave other archives in the source alone, move a source folder named like it without buckets
+    /// whole, and refuse a target's base folder that holds no buckets
+    #[arg(long, default_value_t = false)]
+    source_scan_exclude_base_always: bool,
+
+    /// Explode directories with at most N files; move larger ones whole
+    #[arg(long, value_name = "N", conflicts_with = "explode_dirs")]
+    dir_move_threshold: Option<usize>,
+
+    /// Leave items on other devices than the source (mount points) in place
+    #[arg(long, default_value_t = false)]
+    same_device_only: bool,
+
+    /// Cap each bucket at N entries, spilling over into `<bucket>-2`, `<bucket>-3`, ...
+    #[arg(long, value_name = "N")]
+    bucket_max_files: Option<NonZeroUsize>,
+
+    /// Keep the N most recently modified items in the bucket of new items, whatever their age
+    #[arg(long, value_name = "N")]
+    keep_recent: Option<usize>,
+
+    /// Move a file with several hard links once and recreate its other links next to it as links
+    #[arg(long, default_value_t = false, conflicts_with = "copy")]
+    dedup_hardlinks: bool,
+
+    /// Run up to N moves at a time (planning stays sequential)
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "1",
+        conflicts_with = "max_concurrency_per_device"
+    )]
+    jobs: NonZeroUsize,
+
+    /// Run moves in parallel, at most N at a time per destination device
+    #[arg(long, value_name = "N")]
+    max_concurrency_per_device: Option<NonZeroUsize>,
+
+    /// Order in which items are planned; earlier items keep their name on conflicts
+    #[arg(long, value_enum, default_value_t = ScanOrder::Name)]
+    scan_order: ScanOrder,
+
+    /// Filesystem timestamp that ages are counted from
+    #[arg(long, value_enum, value_name = "TIMESTAMP", default_value_t = Timestamp::Mtime)]
+    by: Timestamp,
+
+    /// Leave items younger than this many days in place, whatever their bucket
+    #[arg(long, value_name = "DAYS")]
+    min_age: Option<u64>,
+
+    /// Leave items older than this many days in place, whatever their bucket
+    #[arg(long, value_name = "DAYS")]
+    max_age: Option<u64>,
+
+    /// Only organize items modified after this reference file was (like `find -newer`)
+    #[arg(long, value_name = "PATH", alias = "newer-than-file")]
+    newer_than: Option<PathBuf>,
+
+    /// Only organize items modified before this reference file was
+    #[arg(long, value_name = "PATH", alias = "older-than-file")]
+    older_than: Option<PathBuf>,
+
+    /// Measure all ages against one fixed point in time instead of each item's lookup time
+    #[arg(long, value_enum, value_name = "ANCHOR")]
+    anchor_to: Option<AgeAnchor>,
+
+    /// Directory levels below the base folder, outermost first (e.g. "type,age" for images/last-week/)
+    #[arg(long, value_name = "CLASSIFIERS")]
+    classify_by: Option<Layout>,
+
+    /// Let this program choose each item's bucket: it gets the path as argument and prints a bucket name
+    #[arg(long, value_name = "PROG")]
+    classify_command: Option<PathBuf>,
+
+    /// What to do when the classify command prints a name that is not a configured bucket
+    #[arg(long, value_enum, default_value_t = UnknownBucket::Error, requires = "classify_command")]
+    unknown_bucket: UnknownBucket,
+
+    /// Run this program after each successful move, with the old and new path as arguments
+    /// (and in `REFILE_FROM` and `REFILE_TO`)
+    #[arg(long, value_name = "PROG")]
+    post_move_hook: Option<PathBuf>,
+
+    /// Run this program before planning, with the source and target as arguments;
+    /// the run is aborted if it fails
+    #[arg(long, value_name = "PROG")]
+    pre_run_hook: Option<PathBuf>,
+
+    /// Run this program after the moves, with the source and target as arguments and the
+    /// summary in `REFILE_MOVED` and `REFILE_SKIPPED`
+    #[arg(long, value_name = "PROG")]
+    post_run_hook: Option<PathBuf>,
+
+    /// What to do when the post-move hook fails
+    #[arg(long, value_enum, default_value_t = HookFailure::Warn, requires = "post_move_hook")]
+    hook_failure: HookFailure,
+
+    /// Layout of dates in file names for the `filename` age source (%Y, %y, %m, %d)
+    #[arg(long, value_name = "FORMAT")]
+    date_format: Option<DateFormat>,
+
+    /// Age files with these extensions by the `date:` field of their YAML front matter
+    /// (comma-separated or repeatable, e.g. "md,markdown"; overrides `[age_sources]`)
+    #[arg(long, value_name = "EXT", value_delimiter = ',')]
+    bucket_from_frontmatter: Vec<String>,
+
+    /// Keep destination names ASCII: skip items with non-ASCII names (see --transliterate)
+    #[arg(long, alias = "strict-ascii-names", default_value_t = false)]
+    ascii_only: bool,
+
+    /// With --ascii-only, move items with non-ASCII names under a transliterated name
+    /// ("Café.pdf" -> "Cafe.pdf") instead of skipping them
+    #[arg(long, default_value_t = false, requires = "ascii_only")]
+    transliterate: bool,
+
+    /// Process only the paths listed in this file (one per line, '#' comments allowed;
+    /// relative paths are resolved against the source directory)
+    #[arg(long, value_name = "PATH")]
+    from_file: Option<PathBuf>,
+
+    /// Move what symlinks point at (a copy of the file or directory) instead of the links
+    #[arg(long, default_value_t = false)]
+    follow_symlinks: bool,
+
+    /// Rewrite moved symlinks to absolute targets instead of keeping them relative
+    #[arg(long, default_value_t = false)]
+    absolute_symlinks: bool,
+
+    /// Execute only if at most N moves are planned; otherwise print the plan and abort
+    #[arg(long, value_name = "N", conflicts_with = "dry_run")]
+    apply_if_under: Option<usize>,
+
+    /// With --dry-run, print the plan, ask whether to execute it and, if confirmed, do so without planning again
+    #[arg(long, default_value_t = false, requires = "dry_run")]
+    execute_after_review: bool,
+
+    /// Skip files whose content is identical to a file already in the target bucket
+    #[arg(long, default_value_t = false)]
+    dedupe_bucket: bool,
+
+    /// Print progress with an ETA to stderr at most every SECS seconds
+    #[arg(long, value_name = "SECS")]
+    progress_interval: Option<u64>,
+
+    /// Skip the typed confirmation when moving protected directories
+    #[arg(short = 'y', long, default_value_t = false)]
+    yes: bool,
+
+    /// Skip checking that every destination directory is writable before moving anything
+    #[arg(long, default_value_t = false)]
+    no_preflight: bool,
+
+    /// Allow moves across devices, which copy and delete instead of renaming atomically
+    #[arg(long, default_value_t = false)]
+    allow_cross_device: bool,
+
+    /// Copy items into the buckets instead of moving them, leaving the originals in place
+    #[arg(long, default_value_t = false, conflicts_with_all = ["reconcile", "verify_idempotent"])]
+    copy: bool,
+
+    /// After the run, plan again and fail if the second plan would move anything
+    #[arg(long, default_value_t = false, conflicts_with = "dry_run")]
+    verify_idempotent: bool,
+
+    /// After the run, print what disappeared from and appeared in the source and buckets
+    #[arg(long, default_value_t = false, conflicts_with = "dry_run")]
+    move_report: bool,
+
+    /// Cache item timestamps in the base folder and reuse them for unchanged items on the next run
+    #[arg(long, default_value_t = false)]
+    source_metadata_cache: bool,
+
+    /// Report how each bucket grew since the previous run (stores a manifest in the base folder)
+    #[arg(long, default_value_t = false, conflicts_with = "dry_run")]
+    report_growth: bool,
+
+    /// Skip items that cannot be read for lack of permission instead of aborting the run
+    #[arg(long, default_value_t = false)]
+    skip_unreadable: bool,
+
+    /// Deprecated: a failed move no longer stops the others unless --fail-fast is given
+    #[arg(long, default_value_t = false, conflicts_with = "fail_fast")]
+    keep_going: bool,
+
+    /// Stop at the first failed move instead of moving the rest and failing at the end
+    #[arg(long, default_value_t = false)]
+    fail_fast: bool,
+
+    /// Write failed operations to this file as JSON lines ({path, operation, kind, error})
+    #[arg(long, value_name = "PATH")]
+    error_report: Option<PathBuf>,
+
+    /// Write the moves renamed to avoid conflicts to this file as JSON lines ({from, intended, to})
+    #[arg(long, value_name = "PATH")]
+    rename_report: Option<PathBuf>,
+
+    /// Append every planned action to this file as CSV rows (source, destination, bucket, age, size, action, note), also with --dry-run
+    #[arg(long, value_name = "PATH")]
+    report: Option<PathBuf>,
+
+    /// Show sizes in human output as KiB/MiB/GiB rather than bytes (default: on when stdout is a terminal)
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    humanize: Option<bool>,
+
+    /// Show this glyph before a bucket's name in human output, e.g. "old-stuff=📦" (repeatable;
+    /// overrides `[bucket_glyphs]` in the config file)
+    #[arg(long, value_name = "NAME=GLYPH", value_parser = parse_bucket_glyph)]
+    bucket_glyph: Vec<(String, String)>,
+
+    /// When to show bucket glyphs in human output
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = ColorWhen::Auto)]
+    color: ColorWhen,
+
+    /// Format of the run's result on stdout
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Also append run output to this log file
+    #[arg(long, value_name = "PATH")]
+    output_file: Option<PathBuf>,
+
+    /// Also send run output and errors to the system logger (syslog/journald)
+    #[arg(long, default_value_t = false)]
+    syslog: bool,
+
+    /// Rotate the output file once it would exceed this many bytes
+    #[arg(long, value_name = "BYTES", requires = "output_file")]
+    log_max_size: Option<u64>,
+
+    /// Number of rotated output files to keep
+    #[arg(long, value_name = "N", default_value_t = 3, requires = "output_file")]
+    log_keep: usize,
+}
+
+impl RefileArgs {
+    /// How much human output the run prints (`--quiet`, `--verbose`).
+    fn verbosity(&self) -> Verbosity {
+        if self.quiet {
+            Verbosity::Quiet
+        } else if self.verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        }
+    }
+
+    /// The depth `collect_items_to_process` collects files down to, with
+    /// `--recursive` (unlimited without `--depth`).
+    fn recursion_depth(&self) -> Option<usize> {
+        self.recursive.then_some(self.depth.unwrap_or(usize::MAX))
+    }
+
+    /// The dry-run name of what the run does with an item: `COPY` with
+    /// `--copy`, `MOVE` otherwise.
+    fn verb(&self) -> &'static str {
+        if self.copy { "COPY" } else { "MOVE" }
+    }
+
+    /// Whether items already in the base folder are processed again: not with
+    /// `--copy`, which would only duplicate them inside the base folder.
+    fn reconciles_existing(&self) -> bool {
+        self.reconcile_existing && !self.copy
+    }
+
+    /// The conflict handling of the run, with `--allow-rename` meaning
+    /// `--on-conflict rename`.
+    fn on_conflict(&self) -> OnConflict {
+        if self.allow_rename {
+            OnConflict::Rename
+        } else {
+            self.on_conflict
+        }
+    }
+
+    /// Whether sizes in human output use binary units, defaulting to whether
+    /// stdout is a terminal.
+    fn humanize(&self) -> bool {
+        self.humanize.unwrap_or_else(|| io::stdout().is_terminal())
+    }
+
+    /// Returns the directory the base folder is created in.
+    ///
+    /// This is the target of the matching config rule, the target directory
+    /// argument, or the source directory. With `--target-date-subdir` it is a
+    /// subdirectory of that named after the date of the run's age reference in
+    /// the `--time-zone`, e.g. `archive/2024-03-15`.
+    fn run_target_dir(&self, bucket_config: &BucketConfig) -> PathBuf {
+        let target_dir = bucket_config
+            .target_dir()
+            .unwrap_or(self.target_dir.as_ref().unwrap_or(&self.source_dir));
+        if !self.target_date_subdir {
+            return target_dir.to_path_buf();
+        }
+        let date = bucket_config
+            .time_zone()
+            .date_of(bucket_config.age_reference());
+        target_dir.join(date.format("%Y-%m-%d").to_string())
+    }
+
+    /// Returns true if `path` is the dated snapshot directory of a run with
+    /// `--target-date-subdir`, which is never refiled itself.
+    fn is_date_snapshot(&self, path: &Path) -> bool {
+        let target_dir = self.target_dir.as_ref().unwrap_or(&self.source_dir);
+        self.target_date_subdir
+            && path.parent() == Some(target_dir.as_path())
+            && path.is_dir()
+            && path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|name| {
+                    name.len() == 10 && parse_date_from_name(name, &DateFormat::default()).is_some()
+                })
+    }
+}
+
+/// Snapshot of the source categorized by the current bucket configuration.
+#[derive(Debug, Serialize)]
+struct SourceStats {
+    buckets: serde_json::Map<String, serde_json::Value>,
+    skipped: u64,
+}
+
+/// Order in which collected items are planned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ScanOrder {
+    /// By path
+    Name,
+    /// Oldest modification time first
+    Mtime,
+    /// Smallest first
+    Size,
+}
+
+/// Handling of items whose destination already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OnConflict {
+    /// Stop before anything is moved
+    Abort,
+    /// Move the item under a numbered name, e.g. `report (1).pdf`
+    Rename,
+    /// Move the existing item into the base folder's `.trash` directory first
+    Trash,
+    /// Delete the existing item first
+    Overwrite,
+}
+
+/// How `--on-conflict rename` numbers a renamed item.
+///
+/// With `append`, a number freed by a deleted version is never reused, and
+/// neither is the unnumbered name once a numbered version exists: with only
+/// `file (3).txt` left in the bucket, a new `file.txt` becomes `file (4).txt`
+/// even though `file.txt` is free, so the numbers keep following the order in
+/// which versions arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum RenameNumbering {
+    /// Take the lowest free number, filling gaps left by deleted versions
+    Fill,
+    /// Number above the highest existing version
+    Append,
+}
+
+/// Format of the run's result on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable lines
+    Text,
+    /// The planned actions as one JSON array; human output goes to stderr
+    Json,
+}
+
+/// When human output is decorated with bucket glyphs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ColorWhen {
+    /// When stdout is a terminal and `NO_COLOR` is not set
+    Auto,
+    /// Even when stdout is piped or redirected
+    Always,
+    /// Never; bucket names are shown plain
+    Never,
+}
+
+impl ColorWhen {
+    /// Returns true if human output is to be decorated.
+    fn enabled(self) -> bool {
+        match self {
+            Self::Auto => io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+/// Handling of bucket names from `--classify-command` that are not configured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum UnknownBucket {
+    /// Abort the run
+    Error,
+    /// Move the item into the catch-all bucket
+    CatchAll,
+}
+
+/// What to do with the source directories a recursive run leaves empty
+/// (`--empty-dirs`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum EmptyDirs {
+    /// Leave them in place
+    Keep,
+    /// Remove them, deepest first
+    Remove,
+    /// Bucket each by its modification time, like any other item
+    Move,
 }
 
+/// Handling of a failing `--post-move-hook`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum HookFailure {
+    /// Report the failure and carry on
+    Warn,
+    /// Stop the run, like a failed move
+    Abort,
+}
+
+/// Parses a `--bucket-glyph` value of the form `name=glyph`.
+fn parse_bucket_glyph(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, glyph)) if !name.trim().is_empty() && !glyph.trim().is_empty() => {
+            Ok((name.trim().to_string(), glyph.trim().to_string()))
+        }
+        _ => Err(format!("expected NAME=GLYPH, got '{value}'")),
+    }
+}
+
+/// Fixed point in time that all ages of a run are measured against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AgeAnchor {
+    /// The most recent midnight in the --time-zone (local by default)
+    Midnight,
+    /// The moment the run started
+    RunStart,
+}
+
+impl AgeAnchor {
+    /// Returns the anchor's point in time for a run started at `now`, with
+    /// days counted in `zone`.
+    fn resolve(self, now: SystemTime, zone: CalendarZone) -> SystemTime {
+        match self {
+            Self::Midnight => zone.last_midnight(now),
+            Self::RunStart => now,
+        }
+    }
+}
+
+/// State carried across the planning of a single run.
+#[derive(Debug, Default)]
+struct PlanState<'a> {
+    /// Per-directory entry counts, when `--bucket-max-files` is set
+    rollover: Option<BucketRollover>,
+    /// Timestamps of earlier runs, when `--source-metadata-cache` is set
+    cache: Option<&'a mut MetadataCache>,
+    /// Destinations already handed out, so no two items target the same path
+    claimed: HashSet<PathBuf>,
+    /// Reference modification times from `--newer-than` and `--older-than`
+    mtime_window: MtimeWindow,
+    /// The newest items, which stay with the new ones (`--keep-recent`)
+    recent: HashSet<PathBuf>,
+}
+
+/// Modification times items must fall between, from the reference files of
+/// `--newer-than` and `--older-than`.
+#[derive(Debug, Clone, Copy, Default)]
+struct MtimeWindow {
+    newer_than: Option<SystemTime>,
+    older_than: Option<SystemTime>,
+}
+
+impl MtimeWindow {
+    /// Reads the modification times of the reference files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the option if a reference file cannot be read.
+    fn resolve(cfg: &RefileArgs) -> io::Result<Self> {
+        let mtime = |reference: &Option<PathBuf>, option: &str| {
+            reference
+                .as_deref()
+                .map(|path| {
+                    fs::metadata(path).and_then(|m| m.modified()).map_err(|e| {
+                        io::Error::new(
+                            e.kind(),
+                            format!(
+                                "Cannot read reference file {} for {option}: {e}",
+                                path.display()
+                            ),
+                        )
+                    })
+                })
+                .transpose()
+        };
+        Ok(Self {
+            newer_than: mtime(&cfg.newer_than, "--newer-than")?,
+            older_than: mtime(&cfg.older_than, "--older-than")?,
+        })
+    }
+
+    /// Returns why an item is outside the window, if it is.
+    ///
+    /// Like `find -newer`, the bounds are exclusive: an item modified at the
+    /// same time as a reference file is outside.
+    fn skip_reason(&self, path: &Path, cfg: &RefileArgs) -> Option<String> {
+        if self.newer_than.is_none() && self.older_than.is_none() {
+            return None;
+        }
+        let modified = match fs::symlink_metadata(path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(e) => return Some(format!("cannot get modification time: {e}")),
+        };
+        if let (Some(reference), Some(path)) = (self.newer_than, &cfg.newer_than)
+            && modified <= reference
+        {
+            Some(format!("not newer than {}", path.display()))
+        } else if let (Some(reference), Some(path)) = (self.older_than, &cfg.older_than)
+            && modified >= reference
+        {
+            Some(format!("not older than {}", path.display()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Exit code of a run that moved what it could but had some moves fail.
+const PARTIAL_FAILURE_EXIT_CODE: i32 = 2;
+
+/// The error of a run in which some moves failed and the others were made.
+#[derive(Debug)]
+struct PartialFailure {
+    failed: usize,
+    total: usize,
+}
+
+impl fmt::Display for PartialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} of {} moves failed", self.failed, self.total)
+    }
+}
+
+impl std::error::Error for PartialFailure {}
+
+/// A post-move hook failure under `--hook-failure abort`: the move was made,
+/// but no further moves are started.
 #[derive(Debug)]
+struct HookAborted(io::Error);
+
+impl fmt::Display for HookAborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for HookAborted {}
+
+/// An operation that failed during execution, as recorded in the error report.
+#[derive(Debug, Serialize)]
+struct Failure {
+    path: String,
+    operation: &'static str,
+    /// The `io::ErrorKind`, e.g. `PermissionDenied`
+    kind: String,
+    error: String,
+}
+
+impl Failure {
+    fn new(path: &Path, operation: &'static str, error: &io::Error) -> Self {
+        Self {
+            path: path.display().to_string(),
+            operation,
+            kind: format!("{:?}", error.kind()),
+            error: error.to_string(),
+        }
+    }
+}
+
+/// A move whose destination was renamed to avoid a conflict (or transliterated
+/// with `--transliterate`), as recorded in the rename report.
+#[derive(Debug, Serialize)]
+struct Rename {
+    from: String,
+    /// The destination the item would have had without the conflict, under
+    /// its original name
+    intended: String,
+    to: String,
+}
+
+/// A planned action, serialized as a JSON object tagged with `"action"` by
+/// `--output json`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
 enum FileAction {
-    Move { from: PathBuf, to: PathBuf },
-    Skip { path: PathBuf, reason: String },
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+        /// The destination that was taken, if `to` is a renamed variant of it
+        #[serde(skip_serializing_if = "Option::is_none")]
+        renamed_from: Option<PathBuf>,
+        /// The bucket the item goes into
+        bucket: String,
+        /// Another hard link to the same file, in place before this item
+        /// moves: the item is recreated as a link to it (`--dedup-hardlinks`)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        link_to: Option<PathBuf>,
+    },
+    /// A move whose destination exists; the existing item is moved to
+    /// `trashed` first
+    Trash {
+        from: PathBuf,
+        to: PathBuf,
+        trashed: PathBuf,
+        bucket: String,
+    },
+    /// A move whose destination exists; the existing item is deleted first
+    Overwrite {
+        from: PathBuf,
+        to: PathBuf,
+        bucket: String,
+    },
+    Skip {
+        path: PathBuf,
+        reason: String,
+    },
+    /// The item already sits in the bucket it belongs to
+    Keep {
+        path: PathBuf,
+        bucket: String,
+    },
+}
+
+impl FileAction {
+    /// Returns the source and destination of any kind of move.
+    fn moved(&self) -> Option<(&Path, &Path)> {
+        match self {
+            Self::Move { from, to, .. }
+            | Self::Trash { from, to, .. }
+            | Self::Overwrite { from, to, .. } => Some((from, to)),
+            Self::Skip { .. } | Self::Keep { .. } => None,
+        }
+    }
 }
 
 /// Main entry point for the refile application.
@@ -104,21 +1059,464 @@ enum FileAction {
 /// - File operations fail
 fn main() -> io::Result<()> {
     let cli = Cli::parse();
+    config::pinned_now().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
 
     // Handle config subcommand
-    if let Some(Commands::Config { command }) = &cli.command {
-        return handle_config_command(command);
+    match &cli.command {
+        Some(Commands::Config { command }) => return handle_config_command(command),
+        Some(Commands::Undo {
+            source_dir,
+            target_dir,
+            base_folder,
+        }) => {
+            return undo(
+                source_dir,
+                target_dir.as_deref(),
+                base_folder.as_deref(),
+                cli.config.as_deref(),
+            );
+        }
+        Some(Commands::Stats(args)) => {
+            return print_archive_stats(args, cli.config.as_deref());
+        }
+        Some(Commands::Flatten(args)) => {
+            return flatten(args, cli.config.as_deref());
+        }
+        None => {}
+    }
+
+    // Validate the configuration without scanning anything
+    if cli.check_config {
+        return check_config(cli.config.as_deref());
+    }
+
+    // Rewrite the configuration in canonical form without scanning anything
+    if cli.normalize_config {
+        return normalize_config(cli.config.as_deref());
     }
 
     // Handle regular refile operation
-    let cfg = cli.refile.ok_or_else(|| {
+    let mut cfg = cli.refile.ok_or_else(|| {
         io::Error::new(
             io::ErrorKind::InvalidInput,
             "Missing required argument: source_dir\n\nUsage: refile <SOURCE_DIR> [TARGET_DIR]\n\nFor more information, try '--help'",
         )
     })?;
+    check_conflict_flags(&cfg)?;
 
-    run_refile(&cfg)
+    // The dry-run only shows the plan, which is executed once confirmed
+    if cfg.execute_after_review {
+        cfg.dry_run = false;
+    }
+    if let (Some(min), Some(max)) = (cfg.min_age, cfg.max_age)
+        && min > max
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--min-age {min} is greater than --max-age {max}"),
+        ));
+    }
+    if cfg.interactive && !cfg.assume_tty && !io::stdin().is_terminal() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--interactive needs a terminal on stdin to ask before each move",
+        ));
+    }
+
+    if cfg.keep_going {
+        errln!(
+            "Warning: --keep-going is deprecated; a failed move only stops the run with --fail-fast"
+        );
+    }
+
+    match run_refile(&cfg, cli.config.as_deref()) {
+        Err(e)
+            if e.get_ref()
+                .is_some_and(<dyn std::error::Error + Send + Sync>::is::<PartialFailure>) =>
+        {
+            errln!("Error: {e}");
+            std::process::exit(PARTIAL_FAILURE_EXIT_CODE);
+        }
+        result => result,
+    }
+}
+
+/// Checks that `--rename-numbering append` comes with renaming conflict
+/// handling, and warns about the deprecated `--allow-rename`.
+///
+/// # Errors
+///
+/// Returns an `InvalidInput` error if `--rename-numbering append` is used
+/// without `--on-conflict rename`.
+fn check_conflict_flags(cfg: &RefileArgs) -> io::Result<()> {
+    if cfg.allow_rename {
+        errln!("Warning: --allow-rename is deprecated; use --on-conflict rename");
+    }
+    if cfg.rename_numbering == RenameNumbering::Fill || cfg.on_conflict() == OnConflict::Rename {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--rename-numbering append requires --on-conflict rename",
+        ))
+    }
+}
+
+/// Validate the configuration file and exit non-zero if it is invalid
+fn check_config(config_path: Option<&Path>) -> io::Result<()> {
+    let config_path = match config_path {
+        Some(path) => path.to_path_buf(),
+        None => config::get_config_file_path().map_err(|e| io::Error::other(e.to_string()))?,
+    };
+
+    match config::load_config_file_at(&config_path)
+        .and_then(|c| config::check_config_file(&c).map(|()| c))
+    {
+        Ok(config_file) => {
+            println!("✓ Configuration is valid: {}", config_path.display());
+            for warning in config_file.misordered_buckets() {
+                eprintln!("Warning: {warning}");
+            }
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("✗ Configuration is invalid: {}\n{e}", config_path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Rewrite the configuration file in canonical form, refusing if it is invalid
+fn normalize_config(config_path: Option<&Path>) -> io::Result<()> {
+    let config_path = match config_path {
+        Some(path) => path.to_path_buf(),
+        None => config::get_config_file_path().map_err(|e| io::Error::other(e.to_string()))?,
+    };
+
+    let config_file = match config::load_config_file_at(&config_path)
+        .and_then(|c| config::check_config_file(&c).map(|()| c))
+    {
+        Ok(config_file) => config_file,
+        Err(e) => {
+            eprintln!(
+                "✗ Configuration is invalid, not rewriting: {}\n{e}",
+                config_path.display()
+            );
+            std::process::exit(1);
+        }
+    };
+
+    fs::write(&config_path, config::normalize_config_file(&config_file))?;
+    println!("✓ Configuration normalized: {}", config_path.display());
+    Ok(())
+}
+
+/// Finds the base folder of an earlier run for a subcommand, the way a run
+/// finds it: from the source directory's config rule, the target directory
+/// and the base folder name. `buckets` and `bucket_mode` override the
+/// configured buckets, as `--buckets` and `--bucket-mode` do for a run.
+///
+/// # Returns
+///
+/// The bucket configuration of the source and the path of its base folder
+///
+/// # Errors
+///
+/// Returns an error if the configuration cannot be read or is invalid.
+fn locate_archive(
+    source_dir: &Path,
+    target_dir: Option<&Path>,
+    base_folder: Option<&str>,
+    buckets: Option<&str>,
+    bucket_mode: Option<BucketMode>,
+    config_path: Option<&Path>,
+) -> io::Result<(BucketConfig, PathBuf)> {
+    let config_file = match config_path {
+        Some(path) => config::load_config_file_at(path).map(Some),
+        None => config::load_config_file(),
+    }
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let bucket_config = config::resolve_bucket_config(
+        source_dir,
+        &config::ResolveOptions {
+            config_file: config_file.as_ref(),
+            base_folder,
+            buckets,
+            bucket_mode,
+            ..Default::default()
+        },
+    )
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let target_dir = bucket_config
+        .target_dir()
+        .or(target_dir)
+        .unwrap_or(source_dir);
+    let refile_base = refile_base_path(target_dir, &bucket_config);
+    Ok((bucket_config, refile_base))
+}
+
+/// Moves the items of the last run in a base folder back, newest first, as
+/// recorded in its journal, then empties the journal.
+///
+/// The base folder is found as by [`locate_archive`]. Items that no longer
+/// exist are skipped, as are items whose original path has been taken since.
+///
+/// # Errors
+///
+/// Returns an error if the configuration or the journal cannot be read, or
+/// an item cannot be moved back.
+fn undo(
+    source_dir: &Path,
+    target_dir: Option<&Path>,
+    base_folder: Option<&str>,
+    config_path: Option<&Path>,
+) -> io::Result<()> {
+    let (_, refile_base) =
+        locate_archive(source_dir, target_dir, base_folder, None, None, config_path)?;
+
+    let entries = journal::read_journal(&refile_base)?;
+    if entries.is_empty() {
+        outln!("Nothing to undo in {}", refile_base.display());
+        return Ok(());
+    }
+
+    let mut skipped = 0;
+    for entry in entries.iter().rev() {
+        let Ok(metadata) = fs::symlink_metadata(&entry.to) else {
+            errln!("Skipping {}: no longer exists", entry.to.display());
+            skipped += 1;
+            continue;
+        };
+        if fs::symlink_metadata(&entry.from).is_ok() {
+            errln!(
+                "Skipping {}: {} exists again",
+                entry.to.display(),
+                entry.from.display()
+            );
+            skipped += 1;
+            continue;
+        }
+
+        if let Some(parent) = entry.from.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if let Some(target) = &entry.link_target {
+            restore_symlink(&entry.to, &entry.from, target)?;
+        } else if metadata.file_type().is_symlink() {
+            move_symlink(&entry.to, &entry.from, false)?;
+        } else {
+            move_item(&entry.to, &entry.from, false)?;
+        }
+    }
+
+    journal::clear_journal(&refile_base)?;
+    outln!(
+        "Undid {} moves ({skipped} skipped)",
+        entries.len() - skipped
+    );
+    Ok(())
+}
+
+/// Prints what the buckets of a base folder hold (`refile stats`), as a table
+/// or as JSON, without moving anything.
+///
+/// The base folder is found as by [`locate_archive`], and items are bucketed
+/// with the bucket options given, as a run with them would.
+///
+/// # Errors
+///
+/// Returns an error if the configuration or a bucket directory cannot be read.
+fn print_archive_stats(args: &StatsArgs, config_path: Option<&Path>) -> io::Result<()> {
+    let (mut bucket_config, refile_base) = locate_archive(
+        &args.source_dir,
+        args.target_dir.as_deref(),
+        args.base_folder.as_deref(),
+        args.buckets.as_deref(),
+        args.bucket_mode,
+        config_path,
+    )?;
+    bucket_config.set_timestamp(args.by);
+    bucket_config.set_time_zone(args.time_zone);
+    if !args.color.enabled() {
+        bucket_config.clear_glyphs();
+    }
+    let stats = stats::ArchiveStats::collect(&refile_base, &bucket_config)?;
+    match args.output {
+        OutputFormat::Text => stats.print(&bucket_config, io::stdout().is_terminal()),
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(&stats).map_err(io::Error::other)?;
+            println!("{json}");
+        }
+    }
+    Ok(())
+}
+
+/// Moves every item out of the buckets of a base folder back up into the
+/// source directory (`refile flatten`), then removes the emptied bucket
+/// directories and the base folder with them.
+///
+/// The base folder is found as by [`locate_archive`], and its buckets are
+/// laid out as `--classify-by` and `--bucket-max-files` say. Anything in it
+/// that is neither a bucket nor one of refile's own files, such as the
+/// directories of buckets the options don't name, aborts before anything is
+/// moved, so no items are left behind. A name already taken in the source
+/// directory, or by an item of another bucket, is handled as `on_conflict`
+/// says; with `abort`, nothing is moved. Once every bucket is empty, the
+/// journal, the manifest and the metadata cache go with the buckets, since
+/// they describe items that are no longer there. The trash is kept.
+///
+/// # Errors
+///
+/// Returns an error if the configuration or a bucket directory cannot be
+/// read, if the base folder holds anything outside the buckets, on a conflict
+/// with `--on-conflict abort`, or if an item cannot be moved.
+fn flatten(args: &FlattenArgs, config_path: Option<&Path>) -> io::Result<()> {
+    let source_dir = args.source_dir.as_path();
+    let on_conflict = args.on_conflict;
+    let (mut bucket_config, refile_base) = locate_archive(
+        source_dir,
+        args.target_dir.as_deref(),
+        args.base_folder.as_deref(),
+        args.buckets.as_deref(),
+        args.bucket_mode,
+        config_path,
+    )?;
+    if let Some(layout) = &args.classify_by {
+        bucket_config.set_layout(layout.clone());
+    }
+    bucket_config.set_max_files(args.bucket_max_files);
+    if !refile_base.is_dir() {
+        outln!("Nothing to flatten in {}", refile_base.display());
+        return Ok(());
+    }
+
+    // Flattening only the buckets the options name would leave the rest behind
+    let mut strays = collect_stray_items(&refile_base, &bucket_config)?;
+    strays.retain(|path| !is_refile_metadata(path, &refile_base));
+    if !strays.is_empty() {
+        let listing = strays
+            .iter()
+            .map(|path| format!("\n  {}", path.display()))
+            .collect::<Vec<_>>()
+            .concat();
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} holds items outside its buckets; nothing was moved:{listing}\n\
+                 Pass the --buckets, --bucket-mode, --classify-by and --bucket-max-files \
+                 of the runs that filled it",
+                refile_base.display()
+            ),
+        ));
+    }
+
+    // Settle every destination first, so an abort leaves everything in place
+    let moves = plan_flatten(source_dir, &refile_base, &bucket_config, on_conflict)?;
+    let claimed: HashSet<PathBuf> = moves.iter().map(|(_, dest)| dest.clone()).collect();
+
+    let mut displaced = 0;
+    for (item, dest) in &moves {
+        if fs::symlink_metadata(dest).is_ok() {
+            if on_conflict == OnConflict::Trash {
+                let trashed = trash_path(&refile_base, dest, config::current_time());
+                let is_dir = fs::symlink_metadata(dest).is_ok_and(|m| m.is_dir());
+                let trashed = find_unique_dest(&trashed, is_dir, &claimed)?;
+                if let Some(parent) = trashed.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                move_item(dest, &trashed, false)?;
+            } else {
+                remove_item(dest)?;
+            }
+            displaced += 1;
+        }
+        if fs::symlink_metadata(item)?.file_type().is_symlink() {
+            move_symlink(item, dest, false)?;
+        } else {
+            move_item(item, dest, false)?;
+        }
+    }
+
+    // The journal and the rest describe the buckets' items, so they go last
+    let mut emptied = true;
+    for dir in bucket_dirs(&refile_base, &bucket_config)? {
+        emptied &= is_empty_dir(&dir)?;
+    }
+    if emptied {
+        remove_item(&journal::journal_path(&refile_base))?;
+        remove_item(&manifest::manifest_path(&refile_base))?;
+        remove_item(&cache::cache_path(&refile_base))?;
+    }
+    purge_empty_buckets(&refile_base, &bucket_config)?;
+    outln!(
+        "Flattened {} items into {} ({displaced} displaced)",
+        moves.len(),
+        source_dir.display()
+    );
+    Ok(())
+}
+
+/// Plans the moves of `refile flatten`: every item of every bucket to the
+/// source directory, under the name `on_conflict` settles on.
+///
+/// # Errors
+///
+/// Returns an error if a bucket directory cannot be read, or on a conflict
+/// that `on_conflict` doesn't resolve.
+fn plan_flatten(
+    source_dir: &Path,
+    refile_base: &Path,
+    bucket_config: &BucketConfig,
+    on_conflict: OnConflict,
+) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut claimed = HashSet::new();
+    let mut moves = Vec::new();
+    for dir in bucket_dirs(refile_base, bucket_config)? {
+        let mut items = fs::read_dir(&dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        items.sort();
+        for item in items {
+            let Some(name) = item.file_name() else {
+                continue;
+            };
+            let dest = source_dir.join(name);
+            let taken = fs::symlink_metadata(&dest).is_ok() || claimed.contains(&dest);
+            let dest = match on_conflict {
+                _ if !taken => dest,
+                OnConflict::Abort => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!(
+                            "Conflict: {} already exists (from {})\n\
+                             Use --on-conflict rename to move it back under a numbered name",
+                            dest.display(),
+                            item.display()
+                        ),
+                    ));
+                }
+                OnConflict::Rename => {
+                    let is_dir = fs::symlink_metadata(&item).is_ok_and(|m| m.is_dir());
+                    find_unique_dest(&dest, is_dir, &claimed)?
+                }
+                // Displacing another item of the archive would lose it
+                OnConflict::Trash | OnConflict::Overwrite if claimed.contains(&dest) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!(
+                            "Conflict: {} is in more than one bucket\n\
+                             Use --on-conflict rename to move both back",
+                            name.to_string_lossy()
+                        ),
+                    ));
+                }
+                OnConflict::Trash | OnConflict::Overwrite => dest,
+            };
+            claimed.insert(dest.clone());
+            moves.push((item, dest));
+        }
+    }
+    Ok(moves)
 }
 
 /// Handle config subcommands
@@ -168,210 +1566,2501 @@ fn handle_config_command(command: &ConfigCommand) -> io::Result<()> {
 }
 
 /// Run the regular refile operation
-fn run_refile(cfg: &RefileArgs) -> io::Result<()> {
-    let target_dir = cfg.target_dir.as_ref().unwrap_or(&cfg.source_dir);
+fn run_refile(cfg: &RefileArgs, config_path: Option<&Path>) -> io::Result<()> {
+    init_output(cfg)?;
 
     // Warn about dangerous directories flag
     if cfg.allow_dangerous_directories {
-        println!("WARNING: --allow-dangerous-directories is enabled!");
-        println!("This allows moving protected directories including:");
-        println!("  - Root directory (/)");
-        println!("  - Your home directory");
-        println!("  - Top-level system directories (/tmp, /var, /usr, etc.)");
-        println!("This can cause SEVERE SYSTEM DAMAGE. Use with extreme caution!");
-        println!();
+        warn_dangerous_directories();
     }
 
-    // Load configuration file
-    let config_file = config::load_config_file()
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let bucket_config = load_bucket_config(cfg, config_path)?;
+    let mtime_window = MtimeWindow::resolve(cfg)?;
+    let target_dir = &cfg.run_target_dir(&bucket_config);
+    let refile_base = refile_base_path(target_dir, &bucket_config);
+    check_not_nested(&cfg.source_dir, &refile_base)?;
+    if cfg.source_scan_exclude_base_always {
+        check_base_is_archive(&cfg.source_dir, &refile_base, &bucket_config)?;
+    }
 
-    // Resolve bucket configuration
-    let bucket_config = config::resolve_bucket_config(
-        &cfg.source_dir,
-        config_file.as_ref(),
-        cfg.base_folder.as_deref(),
-        cfg.buckets.as_deref(),
-    )
-    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    // Read-only stats mode: categorize the source and report, without moving
+    if cfg.stats_json {
+        return print_source_stats(cfg, &refile_base, &bucket_config);
+    }
 
-    let refile_base = refile_base_path(target_dir, &bucket_config);
+    // Read-only orphan report: list what sits loose in the base folder
+    if cfg.report_orphans {
+        return report_orphans(&refile_base, &bucket_config);
+    }
+
+    // Let the pre-run hook prepare the source and target before anything is
+    // planned, or, when the plan is held back for review, once it passed
+    let reviewed = cfg.apply_if_under.is_some() || cfg.execute_after_review;
+    if !reviewed {
+        run_pre_run_hook(cfg, target_dir)?;
+    }
 
-    // Ensure destination directories exist
     if cfg.dry_run {
         print_dry_run_dirs(&refile_base, &bucket_config);
-    } else {
-        create_bucket_dirs(&refile_base, &bucket_config)?;
     }
 
     // Collect all items to process
-    let items = collect_items_to_process(&cfg.source_dir, &refile_base, &bucket_config)?;
+    let items = collect_items(cfg, &refile_base, &bucket_config)?;
+
+    // Plan actions for each item, reusing timestamps of unchanged items
+    let mut cache = cfg
+        .source_metadata_cache
+        .then(|| MetadataCache::load(&refile_base))
+        .transpose()?;
+    let (mut actions, mut exploded_dirs) = plan_actions(
+        items,
+        target_dir,
+        cfg,
+        &bucket_config,
+        cache.as_mut(),
+        mtime_window,
+    )?;
+
+    // Hold the plan back for review, with --apply-if-under or --execute-after-review
+    if !review_plan(&actions, cfg)? {
+        outln!("Plan not executed; nothing was changed");
+        return Ok(());
+    }
+    if reviewed {
+        run_pre_run_hook(cfg, target_dir)?;
+    }
+
+    // Ensure destination directories exist, once a user folder named like the
+    // base folder made room for them
+    if !cfg.dry_run {
+        if cfg.source_scan_exclude_base_always {
+            set_aside_user_base(
+                &refile_base,
+                &bucket_config,
+                &mut actions,
+                &mut exploded_dirs,
+            )?;
+        }
+        create_bucket_dirs(&refile_base, &bucket_config)?;
+    }
+
+    // Whatever becomes of the run, the post-run hook tears down what the
+    // pre-run hook set up
+    let mut summary = hook::RunSummary::default();
+    let plan = ReviewedPlan {
+        actions,
+        exploded_dirs,
+        cache,
+    };
+    let result = apply_plan(
+        plan,
+        target_dir,
+        &refile_base,
+        cfg,
+        &bucket_config,
+        mtime_window,
+        &mut summary,
+    );
+    let Some(hook) = &cfg.post_run_hook else {
+        return result;
+    };
+    let hooked = hook::run_post_run_hook(hook, &cfg.source_dir, target_dir, cfg.dry_run, summary);
+    match (result, hooked) {
+        (Err(e), Err(hook_error)) => {
+            errln!("Error: {hook_error}");
+            Err(e)
+        }
+        (Ok(()), hooked) => hooked,
+        (result, Ok(())) => result,
+    }
+}
+
+/// Runs the pre-run hook, if there is one.
+///
+/// # Errors
+///
+/// Returns an error if the hook fails.
+fn run_pre_run_hook(cfg: &RefileArgs, target_dir: &Path) -> io::Result<()> {
+    match &cfg.pre_run_hook {
+        Some(hook) => hook::run_pre_run_hook(hook, &cfg.source_dir, target_dir, cfg.dry_run),
+        None => Ok(()),
+    }
+}
+
+/// A plan that passed its review, with what planning left for executing it.
+struct ReviewedPlan {
+    actions: Vec<FileAction>,
+    /// Directories whose files were planned one by one, to prune afterwards
+    exploded_dirs: Vec<PathBuf>,
+    cache: Option<MetadataCache>,
+}
+
+/// Executes a reviewed plan and everything that follows the moves: reports,
+/// the metadata cache, clean-up and the idempotency check.
+///
+/// `summary` is filled in with the number of moves actually made (planned,
+/// in a dry run) and of skips, even if the run fails.
+///
+/// # Errors
+///
+/// Returns an error if a step of the run fails, or a [`PartialFailure`] once
+/// the run is done if some moves failed.
+fn apply_plan(
+    plan: ReviewedPlan,
+    target_dir: &Path,
+    refile_base: &Path,
+    cfg: &RefileArgs,
+    bucket_config: &BucketConfig,
+    mtime_window: MtimeWindow,
+    summary: &mut hook::RunSummary,
+) -> io::Result<()> {
+    let ReviewedPlan {
+        actions,
+        exploded_dirs,
+        mut cache,
+    } = plan;
+    check_plan(&actions, cfg)?;
+    // With --interactive, every move still has to be answered for
+    let mut actions = confirm_each_move(actions, cfg)?;
+    // Only the moves that are left are paired up, so no link is made to an
+    // item that stays where it is
+    if cfg.dedup_hardlinks {
+        link_hard_links(&mut actions);
+    }
+    if let Some(report) = &cfg.report {
+        write_action_report(report, &actions, cfg, bucket_config)?;
+    }
+
+    // Snapshot both sides of the run to report what changed
+    let listing_before = cfg
+        .move_report
+        .then(|| move_report_listing(cfg, refile_base, bucket_config))
+        .transpose()?;
+
+    // Note the source directories the moves will leave empty, before they do
+    let emptied = (cfg.empty_dirs != EmptyDirs::Keep)
+        .then(|| dirs_emptied_by(&actions, cfg, refile_base))
+        .transpose()?;
+
+    summary.skipped = count_actions(&actions).1;
+    let moved = AtomicUsize::new(0);
+    let outcome = execute_plan(actions, cfg, refile_base, cache.as_mut(), &moved);
+    summary.moved = moved.into_inner();
+    let outcome = outcome?;
+    if let Some(cache) = &cache
+        && !cfg.dry_run
+    {
+        cache.save(refile_base)?;
+        outln!("Reused {} cached timestamps", cache.hits());
+    }
+
+    // Prune directory shells left empty by exploding them
+    if !cfg.dry_run {
+        for dir in &exploded_dirs {
+            remove_empty_dirs(dir)?;
+        }
+    }
+    if let Some(emptied) = emptied {
+        tidy_empty_dirs(
+            emptied,
+            target_dir,
+            cfg,
+            bucket_config,
+            outcome.journal.as_ref(),
+        )?;
+    }
+    if let Some(before) = listing_before {
+        let after = move_report_listing(cfg, refile_base, bucket_config)?;
+        print_move_report(&before, &after, &cfg.source_dir);
+    }
+
+    // Compare the archive against the previous run's manifest
+    if cfg.report_growth {
+        report_growth(refile_base, bucket_config, cfg.humanize())?;
+    }
+
+    // Clean up buckets left empty by this run
+    if cfg.purge_empty_buckets && !cfg.dry_run {
+        purge_empty_buckets(refile_base, bucket_config)?;
+    }
+
+    // Plan once more against the result, which should have nothing left to do
+    // (unless moves failed, which would be planned again)
+    if cfg.verify_idempotent && outcome.failed.is_none() {
+        verify_idempotent(target_dir, refile_base, cfg, bucket_config, mtime_window)?;
+    }
+
+    if let Some(tally) = outcome.tally {
+        tally.print(cfg, bucket_config);
+    }
 
-    // Plan actions for each item
-    let actions: Vec<_> = items
+    // Moves that failed fail the run, once everything else is done
+    outcome
+        .failed
+        .map_or(Ok(()), |partial| Err(io::Error::other(partial)))
+}
+
+/// Finds the source directories the planned moves leave empty (see
+/// [`dirs_left_empty`]), with their modification times before the moves.
+///
+/// # Errors
+///
+/// Returns an error if a source directory cannot be read.
+fn dirs_emptied_by(
+    actions: &[FileAction],
+    cfg: &RefileArgs,
+    refile_base: &Path,
+) -> io::Result<Vec<(PathBuf, SystemTime)>> {
+    let moved: HashSet<&Path> = actions
+        .iter()
+        .filter_map(|action| action.moved().map(|(from, _)| from))
+        .collect();
+    dirs_left_empty(&cfg.source_dir, refile_base, &moved)?
         .into_iter()
-        .filter_map(|path| plan_action(&path, target_dir, cfg, &bucket_config).transpose())
-        .collect::<io::Result<_>>()?;
+        .map(|dir| {
+            let modified = fs::metadata(&dir)?.modified()?;
+            Ok((dir, modified))
+        })
+        .collect()
+}
 
-    // Execute actions
-    for action in actions {
-        execute_action(action, cfg.dry_run)?;
+/// Removes or buckets the source directories the run left empty
+/// (`--empty-dirs`).
+///
+/// A directory that is no longer empty, say because a move into the buckets
+/// failed, is left alone. A directory is bucketed by the modification time
+/// it had before its contents moved out, which it gets back; if its
+/// destination is a directory already, merging the two only removes it.
+/// Directories that are moved are recorded in the run's `journal`, so
+/// `refile undo` moves them back too.
+///
+/// # Errors
+///
+/// Returns an error if a directory cannot be removed or moved.
+fn tidy_empty_dirs(
+    emptied: Vec<(PathBuf, SystemTime)>,
+    target_dir: &Path,
+    cfg: &RefileArgs,
+    bucket_config: &BucketConfig,
+    journal: Option<&Journal>,
+) -> io::Result<()> {
+    let remove = |dir: &Path| {
+        if cfg.dry_run {
+            outln!("[dry-run] REMOVE {}", dir.display());
+            Ok(())
+        } else {
+            remove_empty_dirs(dir).map(drop)
+        }
+    };
+
+    for (dir, modified) in emptied {
+        if !cfg.dry_run {
+            if !dir.is_dir() || !is_empty_tree(&dir)? {
+                continue;
+            }
+            filetime::set_file_mtime(&dir, filetime::FileTime::from_system_time(modified))?;
+        }
+        if cfg.empty_dirs == EmptyDirs::Remove {
+            remove(&dir)?;
+            continue;
+        }
+
+        let age = age_since(modified, bucket_config).unwrap_or_default();
+        let Some(dest) = pick_bucket_for(&dir, true, age, 0, bucket_config).and_then(|bucket| {
+            compute_dest_path(
+                &dir,
+                target_dir,
+                bucket,
+                bucket_config,
+                None,
+                cfg.preserve_structure.then_some(cfg.source_dir.as_path()),
+            )
+        }) else {
+            continue;
+        };
+        if dest.is_dir() {
+            remove(&dir)?;
+        } else if fs::symlink_metadata(&dest).is_ok() {
+            errln!("Skipping {}: {} exists", dir.display(), dest.display());
+        } else if cfg.dry_run {
+            outln!("[dry-run] MOVE {} -> {}", dir.display(), dest.display());
+        } else {
+            move_into_place(&dir, &dest, None, cfg, journal)?;
+        }
+    }
+    Ok(())
+}
+
+/// Executes a reviewed plan and reports on it.
+///
+/// Cached timestamps move along with the moved items. With JSON output the
+/// plan is printed once it has been executed.
+///
+/// # Returns
+///
+/// The plan's tally, after a dry-run or with `--summary`
+///
+/// # Errors
+///
+/// Returns an error if an action fails or the rename report cannot be
+/// written.
+fn execute_plan(
+    actions: Vec<FileAction>,
+    cfg: &RefileArgs,
+    refile_base: &Path,
+    cache: Option<&mut MetadataCache>,
+    made: &AtomicUsize,
+) -> io::Result<PlanOutcome> {
+    if let Some(cache) = cache {
+        for (from, to) in actions.iter().filter_map(FileAction::moved) {
+            cache.rename(from, to);
+        }
+    }
+    let (moves, skipped) = count_actions(&actions);
+    let mut renames = collect_renames(&actions);
+    let tally = (cfg.dry_run || cfg.summary).then(|| RunTally::of(&actions, cfg));
+    let plan_json = (cfg.output == OutputFormat::Json)
+        .then(|| serde_json::to_string_pretty(&actions).map_err(io::Error::other))
+        .transpose()?;
+    let (failures, journal) = execute_actions(actions, cfg, refile_base, made)?;
+    if let Some(json) = plan_json {
+        println!("{json}");
+    }
+    if moves == 0 {
+        outln!("No files to organize (0 moved, {skipped} skipped)");
+    }
+    if !cfg.dry_run {
+        // A failed move was not renamed after all
+        renames.retain(|rename| !failures.iter().any(|f| f.path == rename.from));
+        report_renames(&renames, cfg)?;
+    }
+    Ok(PlanOutcome {
+        tally,
+        failed: report_failures(&failures, moves),
+        journal,
+    })
+}
+
+/// What became of an executed plan.
+struct PlanOutcome {
+    tally: Option<RunTally>,
+    /// The moves that failed, if any did
+    failed: Option<PartialFailure>,
+    /// The journal the moves were recorded in, for moves made after the plan
+    journal: Option<Journal>,
+}
+
+/// Tally of a plan: its moves per bucket, skips and conflicts.
+#[derive(Debug, Default)]
+struct RunTally {
+    /// Number and total size of the moves into each bucket
+    per_bucket: BTreeMap<String, (usize, u64)>,
+    skipped: usize,
+    /// Moves whose destination was taken: renamed, trashed or overwritten
+    conflicts: usize,
+}
+
+impl RunTally {
+    /// Tallies planned actions, reading the size of every item to be moved.
+    fn of(actions: &[FileAction], cfg: &RefileArgs) -> Self {
+        let mut tally = Self::default();
+        for action in actions {
+            let (from, bucket, conflict) = match action {
+                FileAction::Move {
+                    from,
+                    to,
+                    renamed_from,
+                    bucket,
+                    ..
+                } => {
+                    // Transliteration renames too, but only numbering resolves a conflict
+                    let conflict = renamed_from
+                        .as_deref()
+                        .is_some_and(|intended| ascii_dest(intended, cfg).as_ref() != Some(to));
+                    (from, bucket, conflict)
+                }
+                FileAction::Trash { from, bucket, .. }
+                | FileAction::Overwrite { from, bucket, .. } => (from, bucket, true),
+                FileAction::Skip { .. } => {
+                    tally.skipped += 1;
+                    continue;
+                }
+                FileAction::Keep { .. } => continue,
+            };
+            let (count, bytes) = tally.per_bucket.entry(bucket.clone()).or_default();
+            *count += 1;
+            *bytes += item_size(from).unwrap_or(0);
+            tally.conflicts += usize::from(conflict);
+        }
+        tally
+    }
+
+    /// Prints the tally as a table, one row per bucket and a total.
+    ///
+    /// Requested with `--summary`, it is printed even with `--quiet`.
+    fn print(&self, cfg: &RefileArgs, bucket_config: &BucketConfig) {
+        let humanize = cfg.humanize();
+        let mut rows: Vec<(String, usize, u64)> = bucket_config
+            .all_buckets()
+            .into_iter()
+            .map(|bucket| {
+                let (count, bytes) = self
+                    .per_bucket
+                    .get(bucket.name())
+                    .copied()
+                    .unwrap_or_default();
+                (bucket_config.label(bucket.name()), count, bytes)
+            })
+            .collect();
+        let (moves, bytes) = self
+            .per_bucket
+            .values()
+            .fold((0, 0), |(n, b), (count, bytes)| (n + count, b + bytes));
+        rows.push(("Total".to_string(), moves, bytes));
+        let width = rows
+            .iter()
+            .map(|(label, ..)| label.chars().count())
+            .max()
+            .unwrap_or(0);
+
+        let mut lines = vec![if cfg.dry_run {
+            "Dry-run summary:".to_string()
+        } else {
+            "Summary:".to_string()
+        }];
+        lines.extend(rows.iter().map(|(label, count, bytes)| {
+            format!(
+                "  {label:<width$}  {count:>6}  {}",
+                format_size(*bytes, humanize)
+            )
+        }));
+        lines.push(format!("  Skipped: {}", self.skipped));
+        lines.push(format!("  Conflicts: {}", self.conflicts));
+        for line in lines {
+            if cfg.summary {
+                reportln!("{line}");
+            } else {
+                outln!("{line}");
+            }
+        }
     }
+}
+
+/// Runs the checks a plan has to pass before anything is moved.
+///
+/// # Errors
+///
+/// Returns an error if a protected directory move is not confirmed, a move
+/// crosses devices without `--allow-cross-device`, or a destination is not
+/// writable.
+fn check_plan(actions: &[FileAction], cfg: &RefileArgs) -> io::Result<()> {
+    // Require typed confirmation before moving any protected directory
+    if !cfg.yes && !cfg.dry_run {
+        confirm_protected_moves(actions)?;
+    }
+
+    // Copy+delete is slower and not atomic, so it has to be asked for (a copy
+    // deletes nothing, so it may go anywhere)
+    if !cfg.allow_cross_device && !cfg.copy {
+        check_same_device(actions)?;
+    }
+
+    // Make sure every destination is writable before moving anything
+    if !cfg.dry_run && !cfg.no_preflight {
+        preflight_destinations(actions)?;
+    }
+    Ok(())
+}
+
+/// Sets up where the run's output goes: the log file, the system logger and,
+/// with `--output json`, stderr instead of stdout for human output.
+///
+/// An unreachable system logger is only warned about.
+///
+/// # Errors
+///
+/// Returns an error if the log file cannot be opened.
+fn init_output(cfg: &RefileArgs) -> io::Result<()> {
+    if let Some(path) = &cfg.output_file {
+        output::init_log_file(path, cfg.log_max_size, cfg.log_keep)?;
+    }
+    if cfg.syslog
+        && let Err(e) = output::init_syslog()
+    {
+        errln!("Warning: Failed to connect to the system logger: {e}; continuing without it");
+    }
+    if cfg.output == OutputFormat::Json {
+        output::reserve_stdout();
+    }
+    output::set_verbosity(cfg.verbosity());
+    Ok(())
+}
+
+/// Refuses to run if the base folder is the source directory or contains it.
+///
+/// Items would then be moved into the directory they are collected from, or
+/// the source moved into itself.
+///
+/// # Errors
+///
+/// Returns an `InvalidInput` error if the base folder is the source directory
+/// or one of its ancestors.
+fn check_not_nested(source_dir: &Path, refile_base: &Path) -> io::Result<()> {
+    let source = resolve_path(source_dir)?;
+    let base = resolve_path(refile_base)?;
+    if source.starts_with(&base) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Base folder {} is the source directory or contains it ({}); \
+                 refusing to move the source into itself. \
+                 Choose a different --base-folder or target directory.",
+                refile_base.display(),
+                source_dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Refuses to use an existing, non-empty base folder outside the source that
+/// holds no bucket directories (`--source-scan-exclude-base-always`).
+///
+/// Such a folder is the user's own data that happens to share the base
+/// folder's name; refiling into it would dismantle it into strays. Inside the
+/// source, it is refiled whole like any other item instead.
+///
+/// # Errors
+///
+/// Returns an `InvalidInput` error if the base folder is not an archive, or
+/// an error if it cannot be read.
+fn check_base_is_archive(
+    source_dir: &Path,
+    refile_base: &Path,
+    bucket_config: &BucketConfig,
+) -> io::Result<()> {
+    if refile_base.parent() != Some(source_dir) && is_user_base(refile_base, bucket_config)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is named like the base folder but holds no bucket directories; \
+                 refusing to treat it as an archive. \
+                 Choose a different --base-folder or target directory.",
+                refile_base.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns true if the base folder is a non-empty folder without bucket
+/// directories: the user's own data rather than an archive.
+///
+/// # Errors
+///
+/// Returns an error if the base folder cannot be read.
+fn is_user_base(refile_base: &Path, bucket_config: &BucketConfig) -> io::Result<bool> {
+    Ok(refile_base.is_dir()
+        && !is_empty_dir(refile_base)?
+        && !is_archive(refile_base, bucket_config)?)
+}
+
+/// Renames a user folder named like the base folder to a numbered variant, so
+/// the archive can be created in its place, and points the planned moves of
+/// it and its contents there (`--source-scan-exclude-base-always`).
+///
+/// # Errors
+///
+/// Returns an error if the base folder cannot be read or renamed.
+fn set_aside_user_base(
+    refile_base: &Path,
+    bucket_config: &BucketConfig,
+    actions: &mut [FileAction],
+    exploded_dirs: &mut [PathBuf],
+) -> io::Result<()> {
+    if !is_user_base(refile_base, bucket_config)? {
+        return Ok(());
+    }
+    let aside = find_unique_dest(refile_base, true, &HashSet::new())?;
+    fs::rename(refile_base, &aside)?;
+    let relocate = |path: &mut PathBuf| {
+        if let Ok(rest) = path.strip_prefix(refile_base) {
+            *path = aside.join(rest);
+        }
+    };
+    let mut moved = false;
+    for action in actions {
+        if let FileAction::Move { from, .. }
+        | FileAction::Trash { from, .. }
+        | FileAction::Overwrite { from, .. } = action
+        {
+            moved |= from.as_path() == refile_base;
+            relocate(from);
+        }
+    }
+    exploded_dirs.iter_mut().for_each(relocate);
+    if !moved {
+        outln!(
+            "Renamed {} to {} to make room for the archive",
+            refile_base.display(),
+            aside.display()
+        );
+    }
+    Ok(())
+}
+
+/// Loads the configuration file and any config layer, and resolves the
+/// bucket configuration for this run's source directory.
+///
+/// # Errors
+///
+/// Returns an `InvalidData` error if a configuration file cannot be read or
+/// the resulting configuration is invalid.
+fn load_bucket_config(cfg: &RefileArgs, config_path: Option<&Path>) -> io::Result<BucketConfig> {
+    // Load configuration file
+    let config_file = match config_path {
+        Some(path) => config::load_config_file_at(path).map(Some),
+        None => config::load_config_file(),
+    }
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let config_layer = cfg
+        .config_layer
+        .as_deref()
+        .map(config::load_config_layer)
+        .transpose()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    for warning in config_file
+        .iter()
+        .flat_map(RefileConfigFile::misordered_buckets)
+        .chain(
+            config_layer
+                .iter()
+                .filter_map(ConfigLayer::misordered_buckets),
+        )
+    {
+        errln!("Warning: {warning}");
+    }
+
+    let env_buckets = if cfg.config_env_overlay {
+        std::env::var(config::BUCKETS_ENV).ok()
+    } else {
+        None
+    };
+
+    // Resolve bucket configuration
+    let mut bucket_config = config::resolve_bucket_config(
+        &cfg.source_dir,
+        &config::ResolveOptions {
+            config_file: config_file.as_ref(),
+            layer: config_layer.as_ref(),
+            env_buckets: env_buckets.as_deref(),
+            base_folder: cfg.base_folder.as_deref(),
+            buckets: cfg.buckets.as_deref(),
+            reverse_age: cfg.reverse_age,
+            bucket_mode: cfg.bucket_mode,
+            leave_unmatched: cfg.leave_unmatched,
+        },
+    )
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    if let Some(date_format) = &cfg.date_format {
+        bucket_config.set_date_format(date_format.clone());
+    }
+    for ext in &cfg.bucket_from_frontmatter {
+        bucket_config.set_age_source(ext, AgeSource::Frontmatter);
+    }
+    if let Some(layout) = &cfg.classify_by {
+        bucket_config.set_layout(layout.clone());
+    }
+    if let Some(anchor) = cfg.anchor_to {
+        bucket_config.set_age_anchor(anchor.resolve(config::current_time(), cfg.time_zone));
+    }
+    bucket_config.set_half_open(cfg.half_open);
+    bucket_config.set_time_zone(cfg.time_zone);
+    bucket_config.set_max_files(cfg.bucket_max_files);
+    for (name, glyph) in &cfg.bucket_glyph {
+        bucket_config.set_glyph(name.clone(), glyph.clone());
+    }
+    if !cfg.color.enabled() {
+        bucket_config.clear_glyphs();
+    }
+    if cfg.by == Timestamp::Atime && mounted_noatime(&cfg.source_dir) {
+        errln!(
+            "Warning: {} is mounted noatime, so access times are not kept; using mtime instead",
+            cfg.source_dir.display()
+        );
+    } else {
+        bucket_config.set_timestamp(cfg.by);
+    }
+
+    Ok(bucket_config)
+}
+
+// ============================================================================
+// Application logic
+// ============================================================================
+
+/// Collects the items this run should consider.
+///
+/// The log file, the growth manifest and the metadata cache are never included.
+///
+/// # Errors
+///
+/// Returns an error if the source, the base folder or the path list cannot be read.
+fn collect_items(
+    cfg: &RefileArgs,
+    refile_base: &Path,
+    bucket_config: &BucketConfig,
+) -> io::Result<Vec<PathBuf>> {
+    let mut items = if let Some(list) = &cfg.from_file {
+        read_path_list(list, &cfg.source_dir)?
+    } else if cfg.reconcile {
+        collect_bucket_items(refile_base, bucket_config)?
+    } else {
+        collect_items_to_process(
+            &cfg.source_dir,
+            refile_base,
+            bucket_config,
+            cfg.reconciles_existing(),
+            cfg.recursion_depth(),
+            cfg.hidden,
+            cfg.preserve_structure,
+        )?
+    };
+
+    // Never move the log file we are writing to, nor its rotations
+    if let Some(log) = &cfg.output_file {
+        items.retain(|path| !output::is_log_file(path, log));
+    }
+
+    // Nor the reports of an earlier run
+    for report in [&cfg.error_report, &cfg.rename_report, &cfg.report]
+        .into_iter()
+        .flatten()
+    {
+        items.retain(|path| !paths_equal(path, report));
+    }
+
+    // Nor other archives found in the source, while a folder in the source
+    // merely named like the base folder is refiled whole, like any other item
+    if cfg.source_scan_exclude_base_always {
+        let user_base = cfg.from_file.is_none()
+            && refile_base.parent() == Some(cfg.source_dir.as_path())
+            && is_user_base(refile_base, bucket_config)?;
+        let mut kept = Vec::with_capacity(items.len());
+        for path in items {
+            if user_base && path.starts_with(refile_base) {
+                continue;
+            }
+            if path == refile_base || !is_archive(&path, bucket_config)? {
+                kept.push(path);
+            }
+        }
+        if user_base {
+            kept.push(refile_base.to_path_buf());
+        }
+        items = kept;
+    }
+
+    // Never move the growth manifest nor the metadata cache
+    items.retain(|path| !is_refile_metadata(path, refile_base));
+
+    // Nor the dated snapshots of earlier runs
+    items.retain(|path| !cfg.is_date_snapshot(path));
+
+    Ok(items)
+}
+
+/// Returns true if `path` is one of the files refile itself keeps in the base
+/// folder (the growth manifest, the metadata cache, the journal or the trash
+/// directory).
+fn is_refile_metadata(path: &Path, refile_base: &Path) -> bool {
+    manifest::is_manifest(path, refile_base)
+        || cache::is_cache(path, refile_base)
+        || journal::is_journal(path, refile_base)
+        || path == refile_base.join(TRASH_DIR)
+}
+
+/// Prints per-bucket counts and sizes of the source as JSON (`--stats-json`).
+///
+/// # Errors
+///
+/// Returns an error if the source directory cannot be read.
+fn print_source_stats(
+    cfg: &RefileArgs,
+    refile_base: &Path,
+    bucket_config: &BucketConfig,
+) -> io::Result<()> {
+    let mut items = collect_items_to_process(
+        &cfg.source_dir,
+        refile_base,
+        bucket_config,
+        cfg.reconciles_existing(),
+        cfg.recursion_depth(),
+        cfg.hidden,
+        cfg.preserve_structure,
+    )?;
+    items.retain(|path| !is_refile_metadata(path, refile_base) && !cfg.is_date_snapshot(path));
+    let stats = compute_source_stats(&items, bucket_config, cfg.include_empty_in_stats);
+    let json = serde_json::to_string_pretty(&stats).map_err(io::Error::other)?;
+    println!("{json}");
+    Ok(())
+}
+
+/// Lists the stray items directly under the base folder.
+///
+/// # Errors
+///
+/// Returns an error if the base folder cannot be read.
+fn report_orphans(refile_base: &Path, bucket_config: &BucketConfig) -> io::Result<()> {
+    let mut orphans = collect_stray_items(refile_base, bucket_config)?;
+    orphans.retain(|path| !is_refile_metadata(path, refile_base));
+
+    if orphans.is_empty() {
+        outln!("No stray items under {}", refile_base.display());
+        return Ok(());
+    }
+    outln!(
+        "{} stray items under {} (not in a bucket):",
+        orphans.len(),
+        refile_base.display()
+    );
+    for path in &orphans {
+        outln!("  {}", path.display());
+    }
+    Ok(())
+}
+
+/// Lists the source and the buckets for `--move-report`, leaving out refile's
+/// own metadata files.
+///
+/// # Errors
+///
+/// Returns an error if the source directory or the base folder cannot be read.
+fn move_report_listing(
+    cfg: &RefileArgs,
+    refile_base: &Path,
+    bucket_config: &BucketConfig,
+) -> io::Result<BTreeSet<PathBuf>> {
+    let mut listing = list_source_and_buckets(&cfg.source_dir, refile_base, bucket_config)?;
+    listing.retain(|path| !is_refile_metadata(path, refile_base));
+    Ok(listing)
+}
+
+/// Prints the difference between the listings taken before and after the run.
+///
+/// Paths under the source directory are shown relative to it.
+fn print_move_report(before: &BTreeSet<PathBuf>, after: &BTreeSet<PathBuf>, source_dir: &Path) {
+    let removed: Vec<_> = before.difference(after).collect();
+    let added: Vec<_> = after.difference(before).collect();
+    if removed.is_empty() && added.is_empty() {
+        outln!("Move report: no changes");
+        return;
+    }
+
+    let shown = |path: &Path| {
+        path.strip_prefix(source_dir)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
+    outln!("Move report:");
+    outln!("  Removed ({}):", removed.len());
+    for path in removed {
+        outln!("    - {}", shown(path));
+    }
+    outln!("  Added ({}):", added.len());
+    for path in added {
+        outln!("    + {}", shown(path));
+    }
+}
+
+/// Checks that planning again after the run would move nothing.
+///
+/// A configuration that keeps reshuffling files on every run (for example
+/// because renamed items no longer match the layout they were renamed into)
+/// shows up as moves in this second plan. Nothing is moved by the check.
+///
+/// # Errors
+///
+/// Returns an error listing the second plan's moves if there are any, or an
+/// error if the items cannot be collected or planned.
+fn verify_idempotent(
+    target_dir: &Path,
+    refile_base: &Path,
+    cfg: &RefileArgs,
+    bucket_config: &BucketConfig,
+    mtime_window: MtimeWindow,
+) -> io::Result<()> {
+    let items = collect_items(cfg, refile_base, bucket_config)?;
+    let (actions, _) = plan_actions(items, target_dir, cfg, bucket_config, None, mtime_window)?;
+
+    let mut moves = 0;
+    for (from, to) in actions.iter().filter_map(FileAction::moved) {
+        outln!("[second plan] MOVE {} -> {}", from.display(), to.display());
+        moves += 1;
+    }
+    if moves > 0 {
+        return Err(io::Error::other(format!(
+            "Not idempotent: planning again would make {moves} more moves"
+        )));
+    }
+
+    outln!("Idempotent: planning again would move nothing");
+    Ok(())
+}
+
+/// Plans the actions for every collected item.
+///
+/// Items protected by git (with `--respect-gitignore`) become skips,
+/// directories are expanded into their files with `--explode-dirs`, and hard
+/// links are tied together with `--dedup-hardlinks`.
+///
+/// # Returns
+///
+/// The planned actions, and the directories that were exploded
+///
+/// # Errors
+///
+/// Returns an error if git state cannot be loaded, an exploded directory
+/// cannot be read, or planning an item fails.
+fn plan_actions(
+    mut items: Vec<PathBuf>,
+    target_dir: &Path,
+    cfg: &RefileArgs,
+    bucket_config: &BucketConfig,
+    cache: Option<&mut MetadataCache>,
+    mtime_window: MtimeWindow,
+) -> io::Result<(Vec<FileAction>, Vec<PathBuf>)> {
+    // Load git state so repositories are left intact
+    let git_filter = if cfg.respect_gitignore {
+        GitFilter::load(&cfg.source_dir)?
+    } else {
+        None
+    };
+
+    // Stay on the source's device, leaving other mounts alone
+    let source_device = if cfg.same_device_only {
+        device_id(&cfg.source_dir)
+    } else {
+        None
+    };
+    let pattern_filter = PatternFilter::build(&cfg.source_dir, &cfg.include, &cfg.exclude)?;
+    let refile_base = refile_base_path(target_dir, bucket_config);
+
+    sort_items(&mut items, cfg.scan_order);
+
+    let mut state = PlanState {
+        rollover: cfg.bucket_max_files.map(BucketRollover::new),
+        cache,
+        mtime_window,
+        recent: cfg
+            .keep_recent
+            .map(|count| newest_items(&items, count, bucket_config))
+            .unwrap_or_default(),
+        ..PlanState::default()
+    };
+    let mut actions = Vec::new();
+    let mut exploded_dirs = Vec::new();
+    for path in items {
+        if let Some(reason) = pattern_filter.as_ref().and_then(|f| f.skip_reason(&path)) {
+            actions.extend(pattern_skip(&path, reason, cfg));
+            continue;
+        }
+        if let Some(reason) = git_filter.as_ref().and_then(|g| g.skip_reason(&path)) {
+            actions.push(FileAction::Skip {
+                path,
+                reason: reason.to_string(),
+            });
+            continue;
+        }
+        if source_device.is_some()
+            && !path.starts_with(&refile_base)
+            && item_device_id(&path) != source_device
+        {
+            actions.push(FileAction::Skip {
+                path,
+                reason: "on a different device than the source".to_string(),
+            });
+            continue;
+        }
+
+        // Explode directories: plan their files individually instead
+        let exploded = match files_to_explode(&path, cfg, source_device) {
+            Err(e) if cfg.skip_unreadable && is_unreadable(&e) => {
+                actions.push(unreadable_skip(&path));
+                continue;
+            }
+            exploded => exploded?,
+        };
+        if let Some(mut files) = exploded {
+            sort_items(&mut files, cfg.scan_order);
+            for file in files {
+                if let Some(reason) = pattern_filter.as_ref().and_then(|f| f.skip_reason(&file)) {
+                    actions.extend(pattern_skip(&file, reason, cfg));
+                    continue;
+                }
+                if let Some(skip) = name_filter_skip(&file, cfg) {
+                    actions.push(skip);
+                    continue;
+                }
+                let planned = plan_action(&file, target_dir, cfg, bucket_config, &mut state);
+                actions.extend(reported(skip_if_unreadable(&file, planned, cfg)?, cfg));
+            }
+            exploded_dirs.push(path);
+            continue;
+        }
+
+        if let Some(skip) = name_filter_skip(&path, cfg) {
+            actions.push(skip);
+            continue;
+        }
+        let planned = plan_action(&path, target_dir, cfg, bucket_config, &mut state);
+        actions.extend(reported(skip_if_unreadable(&path, planned, cfg)?, cfg));
+    }
+    Ok((actions, exploded_dirs))
+}
+
+/// Turns the moves of every hard link to a file but the first into links to
+/// where the first ends up (`--dedup-hardlinks`), so the links stay links and
+/// a move across filesystems copies the data only once. A link kept in place
+/// counts as the first.
+///
+/// Does nothing on platforms without inode numbers.
+fn link_hard_links(actions: &mut [FileAction]) {
+    let mut placed: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    for action in actions {
+        match action {
+            FileAction::Move {
+                from, to, link_to, ..
+            } => {
+                let Some(id) = hard_link_id(from) else {
+                    continue;
+                };
+                match placed.get(&id) {
+                    Some(original) => *link_to = Some(original.clone()),
+                    None => {
+                        placed.insert(id, to.clone());
+                    }
+                }
+            }
+            FileAction::Trash { from, to, .. } | FileAction::Overwrite { from, to, .. } => {
+                if let Some(id) = hard_link_id(from) {
+                    placed.entry(id).or_insert_with(|| to.clone());
+                }
+            }
+            FileAction::Keep { path, .. } => {
+                if let Some(id) = hard_link_id(path) {
+                    placed.entry(id).or_insert_with(|| path.clone());
+                }
+            }
+            FileAction::Skip { .. } => {}
+        }
+    }
+}
+
+/// Returns the skip for an item left alone by the `--include`/`--exclude`
+/// patterns, which is only reported with `--verbose`.
+fn pattern_skip(path: &Path, reason: &str, cfg: &RefileArgs) -> Option<FileAction> {
+    (cfg.verbosity() == Verbosity::Verbose).then(|| FileAction::Skip {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    })
+}
+
+/// Returns the skip for an item left out by its name: an operating system
+/// clutter file, or (with `--ext`) an item without one of the listed
+/// extensions.
+fn name_filter_skip(path: &Path, cfg: &RefileArgs) -> Option<FileAction> {
+    let reason = if cfg.skip_system_files && is_system_file(path) {
+        "system file"
+    } else if !cfg.ext.is_empty() && !has_extension(path, &cfg.ext) {
+        "extension not included"
+    } else {
+        return None;
+    };
+    Some(FileAction::Skip {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    })
+}
+
+/// Returns true for an operating system permission error, such as reading a
+/// file or directory the user has no access to.
+fn is_unreadable(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::PermissionDenied && e.raw_os_error().is_some()
+}
+
+/// Returns the skip recorded for an item refile has no permission to read.
+fn unreadable_skip(path: &Path) -> FileAction {
+    FileAction::Skip {
+        path: path.to_path_buf(),
+        reason: "permission denied".to_string(),
+    }
+}
+
+/// Reason of the skip recorded for an item that matches no bucket.
+const UNMATCHED_REASON: &str = "matches no bucket";
+
+/// Returns the skip recorded for an item that matches no bucket.
+fn unmatched_skip(path: &Path) -> FileAction {
+    FileAction::Skip {
+        path: path.to_path_buf(),
+        reason: UNMATCHED_REASON.to_string(),
+    }
+}
+
+/// Drops the skip of an item that matches no bucket unless `--verbose`: with
+/// `--leave-unmatched` such items stay where they are on purpose, so like
+/// pattern skips they are not reported on every run.
+fn reported(action: FileAction, cfg: &RefileArgs) -> Option<FileAction> {
+    let unmatched =
+        matches!(&action, FileAction::Skip { reason, .. } if reason == UNMATCHED_REASON);
+    (!unmatched || cfg.verbosity() == Verbosity::Verbose).then_some(action)
+}
+
+/// With `--skip-unreadable`, turns a permission error hit while planning an
+/// item into a skip, so the rest of the sweep proceeds.
+///
+/// Refusals to move protected directories are not permission errors of the
+/// operating system and still abort the run.
+///
+/// # Errors
+///
+/// Returns any other planning error unchanged.
+fn skip_if_unreadable(
+    path: &Path,
+    planned: io::Result<FileAction>,
+    cfg: &RefileArgs,
+) -> io::Result<FileAction> {
+    match planned {
+        Err(e) if cfg.skip_unreadable && is_unreadable(&e) => Ok(unreadable_skip(path)),
+        planned => planned,
+    }
+}
+
+/// Returns the files of a directory that should be exploded, if any.
+///
+/// With `--explode-dirs` every directory is exploded. With
+/// `--dir-move-threshold N` only directories holding at most N files are;
+/// larger ones are moved whole, bucketed by the directory's own age.
+/// With `device` set, files on other devices are left out, and so are hidden
+/// files (without `--hidden`) and what `.refileignore` files exclude, as in a
+/// `--recursive` scan.
+///
+/// # Errors
+///
+/// Returns an error if the directory or an ignore file cannot be read.
+fn files_to_explode(
+    path: &Path,
+    cfg: &RefileArgs,
+    device: Option<u64>,
+) -> io::Result<Option<Vec<PathBuf>>> {
+    if !fs::symlink_metadata(path).is_ok_and(|m| m.is_dir())
+        || !(cfg.explode_dirs || cfg.dir_move_threshold.is_some())
+    {
+        return Ok(None);
+    }
+    // The source's ignore file applies to its own entries, not to archived ones
+    let ignore_files = if path.parent() == Some(cfg.source_dir.as_path()) {
+        IgnoreFiles::default().enter(&cfg.source_dir)?
+    } else {
+        IgnoreFiles::default()
+    };
+    let files = collect_files_recursive(path, device, &ignore_files.enter(path)?, cfg.hidden)?;
+    match cfg.dir_move_threshold {
+        Some(threshold) if !cfg.explode_dirs => Ok((files.len() <= threshold).then_some(files)),
+        _ => Ok(Some(files)),
+    }
+}
+
+/// Sorts items into the requested scan order.
+///
+/// `fs::read_dir` order depends on the filesystem, so sorting makes conflict
+/// outcomes reproducible. Ties (and unreadable metadata) fall back to the path.
+fn sort_items(items: &mut [PathBuf], order: ScanOrder) {
+    match order {
+        ScanOrder::Name => items.sort(),
+        ScanOrder::Mtime => items.sort_by_cached_key(|path| {
+            let mtime = fs::symlink_metadata(path).and_then(|m| m.modified()).ok();
+            (mtime, path.clone())
+        }),
+        ScanOrder::Size => {
+            items.sort_by_cached_key(|path| (item_size(path).unwrap_or(0), path.clone()));
+        }
+    }
+}
+
+/// Reads a list of paths to process from a file.
+///
+/// One path per line. Blank lines and lines starting with `#` (after leading
+/// whitespace) are ignored; surrounding whitespace is trimmed.
+///
+/// # Arguments
+///
+/// * `list` - The file containing the paths
+/// * `base` - Directory that relative paths are resolved against
+///
+/// # Errors
+///
+/// Returns an error if the list file cannot be read.
+fn read_path_list(list: &Path, base: &Path) -> io::Result<Vec<PathBuf>> {
+    let contents = fs::read_to_string(list).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to read path list {}: {e}", list.display()),
+        )
+    })?;
+    Ok(parse_path_list(&contents, base))
+}
+
+/// Parses the contents of a path list (see `read_path_list`).
+fn parse_path_list(contents: &str, base: &Path) -> Vec<PathBuf> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line))
+        .collect()
+}
+
+/// Plans the appropriate action for a single file or directory.
+///
+/// This function:
+/// 1. Checks if the path is a protected directory
+/// 2. Reads the item's age from its metadata
+/// 3. Determines the appropriate bucket
+/// 4. Computes the destination path, spilling over into an overflow directory
+///    when the bucket is full
+/// 5. Checks for conflicts and handles them based on configuration
+/// 6. Returns a `FileAction` describing what should be done
+///
+/// # Arguments
+///
+/// * `path` - Path to the item to plan an action for
+/// * `target_dir` - Target directory for refile structure
+/// * `cfg` - Configuration including target directory and conflict handling
+/// * `bucket_config` - The bucket configuration to use
+/// * `state` - Overflow counts and destinations claimed by earlier items
+///
+/// # Returns
+///
+/// - `Ok(FileAction::Move)` if the item should be moved (or `Trash` or
+///   `Overwrite` if that displaces an existing item)
+/// - `Ok(FileAction::Skip)` if the item should be skipped (with reason)
+/// - `Ok(FileAction::Keep)` if the item is already in the correct location
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - The path is a protected directory (root or home) and `allow_dangerous_directories` is false
+/// - File metadata cannot be read
+/// - A conflict exists and `--on-conflict` is `abort`
+/// - No unique destination can be found when renaming conflicting items
+fn plan_action(
+    path: &Path,
+    target_dir: &Path,
+    cfg: &RefileArgs,
+    bucket_config: &BucketConfig,
+    state: &mut PlanState,
+) -> io::Result<FileAction> {
+    // Check if this is a protected directory
+    if is_protected_directory(path) && !cfg.allow_dangerous_directories {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "Refusing to move protected directory: {}. \
+                 Protected directories include: root (/), user home, and top-level directories (/tmp, /var, /usr, etc.).",
+                path.display()
+            ),
+        ));
+    }
+
+    // Get file age
+    let age = match &mut state.cache {
+        Some(cache) => cache
+            .timestamp(path, &timestamp_source(path, bucket_config), || {
+                get_file_timestamp(path, bucket_config)
+            })
+            .and_then(|timestamp| age_since(timestamp, bucket_config)),
+        None => get_file_age(path, bucket_config),
+    };
+    let age = match age {
+        Ok(a) => a,
+        Err(e) if cfg.skip_unreadable && is_unreadable(&e) => {
+            return Ok(unreadable_skip(path));
+        }
+        Err(e) => {
+            return Ok(FileAction::Skip {
+                path: path.to_path_buf(),
+                reason: format!("cannot get age: {e}"),
+            });
+        }
+    };
+
+    // Leave items outside the age window alone, before they get a bucket
+    if let Some(reason) =
+        age_window_skip_reason(age, cfg).or_else(|| state.mtime_window.skip_reason(path, cfg))
+    {
+        return Ok(FileAction::Skip {
+            path: path.to_path_buf(),
+            reason,
+        });
+    }
+
+    let bucket = if state.recent.contains(path) {
+        newest_bucket(path, bucket_config)
+    } else {
+        choose_bucket(path, age, cfg, bucket_config)?
+    };
+    let Some(bucket) = bucket else {
+        return Ok(unmatched_skip(path));
+    };
+
+    // Date-suffix directories by their age-derived year and month
+    let dir_suffix = (cfg.date_suffix_dirs && path.is_dir()).then(|| {
+        let modified = bucket_config
+            .age_reference()
+            .checked_sub(age)
+            .unwrap_or(UNIX_EPOCH);
+        let date = bucket_config.time_zone().date_of(modified);
+        date.format("%Y-%m").to_string()
+    });
+
+    // Compute destination path
+    let Some(dest_path) = compute_dest_path(
+        path,
+        target_dir,
+        bucket,
+        bucket_config,
+        dir_suffix.as_deref(),
+        cfg.preserve_structure.then_some(cfg.source_dir.as_path()),
+    ) else {
+        return Ok(FileAction::Skip {
+            path: path.to_path_buf(),
+            reason: "no file name".to_string(),
+        });
+    };
+
+    // A directory cannot be moved into its own subtree (a target inside it),
+    // except for a user folder named like the base folder, which is set aside
+    // before the archive is created
+    let refile_base = refile_base_path(target_dir, bucket_config);
+    let user_base = cfg.source_scan_exclude_base_always && path == refile_base;
+    if !user_base && is_inside_dir(&dest_path, path)? {
+        return Ok(FileAction::Skip {
+            path: path.to_path_buf(),
+            reason: "destination is inside source".to_string(),
+        });
+    }
+
+    // Check if source and destination are the same
+    // (without following a symlink to its target, which may be the destination)
+    if same_entry(path, &dest_path) {
+        return Ok(keep(path, bucket));
+    }
+
+    // Leave duplicates of already archived files where they are
+    if cfg.dedupe_bucket
+        && let Some(bucket_dir) = dest_path.parent()
+        && let Some(existing) = find_duplicate_in_bucket(path, bucket_dir)?
+    {
+        return Ok(FileAction::Skip {
+            path: path.to_path_buf(),
+            reason: format!("duplicate of {}", existing.display()),
+        });
+    }
+
+    // Spill over into the first overflow directory with room
+    let dest_path = match &mut state.rollover {
+        Some(rollover) => match spill_over(path, &dest_path, rollover)? {
+            Some(dest_path) => dest_path,
+            None => return Ok(keep(path, bucket)), // Already in an overflow directory
+        },
+        None => dest_path,
+    };
+
+    place_item(path, dest_path, bucket.name(), &refile_base, cfg, state)
+}
+
+/// Plans the move of an item to its destination, handling a conflict with an
+/// existing item (or one planned earlier) as `--on-conflict` says.
+///
+/// # Errors
+///
+/// Returns an `AlreadyExists` error on a conflict with `--on-conflict abort`,
+/// or an error if no unique name can be found when renaming.
+fn place_item(
+    path: &Path,
+    dest_path: PathBuf,
+    bucket: &str,
+    refile_base: &Path,
+    cfg: &RefileArgs,
+    state: &mut PlanState,
+) -> io::Result<FileAction> {
+    let intended = dest_path;
+    let Some(dest_path) = ascii_dest(&intended, cfg) else {
+        return Ok(FileAction::Skip {
+            path: path.to_path_buf(),
+            reason: "non-ASCII name (--ascii-only)".to_string(),
+        });
+    };
+    let is_dir = || fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+    let conflict = dest_path.exists() || state.claimed.contains(&dest_path);
+    let final_dest = if cfg.rename_numbering == RenameNumbering::Append {
+        // Number above every existing version, even if the name itself is free
+        find_appended_dest(&dest_path, is_dir(), &state.claimed)?
+    } else if !conflict {
+        dest_path.clone()
+    } else {
+        match cfg.on_conflict() {
+            OnConflict::Abort => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "Conflict: destination path already exists: {} (source: {})\n\
+                         Use --on-conflict rename to automatically rename conflicting files",
+                        dest_path.display(),
+                        path.display()
+                    ),
+                ));
+            }
+            OnConflict::Rename => find_unique_dest(&dest_path, is_dir(), &state.claimed)?,
+            // Only items that were there before the run are displaced: an item
+            // planned earlier in the run would be lost, so the later one is renamed
+            OnConflict::Trash | OnConflict::Overwrite if state.claimed.contains(&dest_path) => {
+                find_unique_dest(&dest_path, is_dir(), &state.claimed)?
+            }
+            OnConflict::Trash => {
+                // Unique per run, even if several items displace the same name
+                let trashed = trash_path(refile_base, &dest_path, config::current_time());
+                let is_dir = fs::symlink_metadata(&dest_path).is_ok_and(|m| m.is_dir());
+                let trashed = find_unique_dest(&trashed, is_dir, &state.claimed)?;
+                state.claimed.insert(trashed.clone());
+                state.claimed.insert(dest_path.clone());
+                return Ok(FileAction::Trash {
+                    from: path.to_path_buf(),
+                    to: dest_path,
+                    trashed,
+                    bucket: bucket.to_string(),
+                });
+            }
+            OnConflict::Overwrite => {
+                state.claimed.insert(dest_path.clone());
+                return Ok(FileAction::Overwrite {
+                    from: path.to_path_buf(),
+                    to: dest_path,
+                    bucket: bucket.to_string(),
+                });
+            }
+        }
+    };
+
+    state.claimed.insert(final_dest.clone());
+    Ok(FileAction::Move {
+        from: path.to_path_buf(),
+        renamed_from: (final_dest != intended).then_some(intended),
+        to: final_dest,
+        bucket: bucket.to_string(),
+        link_to: None,
+    })
+}
+
+/// Applies `--ascii-only` to a destination path.
+///
+/// # Returns
+///
+/// The destination unchanged if its name is ASCII (or `--ascii-only` is off),
+/// with a transliterated name under `--transliterate`, or `None` if the item
+/// is to be skipped.
+fn ascii_dest(dest_path: &Path, cfg: &RefileArgs) -> Option<PathBuf> {
+    let Some(name) = dest_path.file_name() else {
+        return Some(dest_path.to_path_buf());
+    };
+    if !cfg.ascii_only || name.is_ascii() {
+        return Some(dest_path.to_path_buf());
+    }
+    cfg.transliterate
+        .then(|| dest_path.with_file_name(transliterate_to_ascii(&name.to_string_lossy())))
+}
+
+/// Returns why an item of the given age is outside the `--min-age` and
+/// `--max-age` window, if it is.
+fn age_window_skip_reason(age: Duration, cfg: &RefileArgs) -> Option<String> {
+    let days = |days: u64| Duration::from_secs(days.saturating_mul(24 * 3600));
+    if let Some(min) = cfg.min_age
+        && age < days(min)
+    {
+        Some(format!("younger than --min-age {min} days"))
+    } else if let Some(max) = cfg.max_age
+        && age > days(max)
+    {
+        Some(format!("older than --max-age {max} days"))
+    } else {
+        None
+    }
+}
+
+/// Determines an item's bucket, by age or size or by the classify command.
+///
+/// Returns `None` if the item matches no bucket and is to be left in place
+/// (see [`BucketConfig::leave_unmatched`]).
+///
+/// # Errors
+///
+/// Returns an error if the classify command fails, or if the size of an item
+/// cannot be read in size mode.
+fn choose_bucket<'a>(
+    path: &Path,
+    age: Duration,
+    cfg: &RefileArgs,
+    bucket_config: &'a BucketConfig,
+) -> io::Result<Option<&'a BucketDef>> {
+    if let Some(program) = &cfg.classify_command {
+        return classify::classify(
+            program,
+            path,
+            age,
+            bucket_config,
+            cfg.unknown_bucket == UnknownBucket::CatchAll,
+        );
+    }
+
+    // Sizes are only needed for size buckets
+    let size = match bucket_config.bucket_mode() {
+        BucketMode::Age | BucketMode::Calendar => 0,
+        BucketMode::Size => item_size(path)?,
+    };
+    let is_dir = fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+    Ok(pick_bucket_for(path, is_dir, age, size, bucket_config))
+}
+
+/// Returns the `count` most recently changed items (`--keep-recent`), by the
+/// timestamp their ages are counted from, ties broken by name.
+///
+/// Items whose timestamp cannot be read are never among them.
+fn newest_items(items: &[PathBuf], count: usize, bucket_config: &BucketConfig) -> HashSet<PathBuf> {
+    let mut stamped: Vec<(SystemTime, &PathBuf)> = items
+        .iter()
+        .filter_map(|path| {
+            get_file_timestamp(path, bucket_config)
+                .ok()
+                .map(|timestamp| (timestamp, path))
+        })
+        .collect();
+    stamped.sort_by(|(a_time, a), (b_time, b)| {
+        b_time
+            .cmp(a_time)
+            .then_with(|| a.file_name().cmp(&b.file_name()))
+            .then_with(|| a.cmp(b))
+    });
+    stamped
+        .into_iter()
+        .take(count)
+        .map(|(_, path)| path.clone())
+        .collect()
+}
+
+/// Returns the bucket a brand new item would go into, where `--keep-recent`
+/// keeps the newest items.
+fn newest_bucket<'a>(path: &Path, bucket_config: &'a BucketConfig) -> Option<&'a BucketDef> {
+    let is_dir = fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+    pick_bucket_for(path, is_dir, Duration::ZERO, 0, bucket_config)
+}
+
+/// Returns the action for an item already in its bucket.
+fn keep(path: &Path, bucket: &BucketDef) -> FileAction {
+    FileAction::Keep {
+        path: path.to_path_buf(),
+        bucket: bucket.name().to_string(),
+    }
+}
+
+/// Redirects a destination into the first directory of its bucket with room.
+///
+/// # Returns
+///
+/// The destination inside the bucket directory or one of its overflow
+/// directories, or `None` if the item already sits in one of them
+///
+/// # Errors
+///
+/// Returns an error if an existing overflow directory cannot be read.
+fn spill_over(
+    path: &Path,
+    dest_path: &Path,
+    rollover: &mut BucketRollover,
+) -> io::Result<Option<PathBuf>> {
+    let (Some(bucket_dir), Some(file_name)) = (dest_path.parent(), dest_path.file_name()) else {
+        return Ok(None);
+    };
+    let parent = path.parent();
+    let bucket_dir_name = bucket_dir.file_name().and_then(|n| n.to_str());
+    let in_overflow_dir = parent.and_then(Path::parent) == bucket_dir.parent()
+        && parent
+            .and_then(Path::file_name)
+            .and_then(|n| n.to_str())
+            .zip(bucket_dir_name)
+            .is_some_and(|(n, bucket_name)| overflow_index(n, bucket_name).is_some());
+    if in_overflow_dir && path.file_name() == Some(file_name) {
+        return Ok(None);
+    }
+    Ok(Some(rollover.reserve(bucket_dir)?.join(file_name)))
+}
+
+/// Prints per-bucket growth since the stored manifest, then stores a new one.
+///
+/// The first run has nothing to compare against and only records a baseline.
+///
+/// # Errors
+///
+/// Returns an error if the buckets cannot be read or the manifest cannot be
+/// loaded or written.
+fn report_growth(
+    refile_base: &Path,
+    bucket_config: &BucketConfig,
+    humanize: bool,
+) -> io::Result<()> {
+    let current = Manifest::snapshot(refile_base, bucket_config)?;
+    match Manifest::load(refile_base)? {
+        Some(previous) => {
+            outln!();
+            let report = manifest::growth_report(&previous, &current, bucket_config, humanize);
+            for line in report.lines() {
+                outln!("{line}");
+            }
+        }
+        None => outln!("No previous manifest, recording a baseline for --report-growth"),
+    }
+    current.save(refile_base)
+}
+
+/// Computes per-bucket counts and total sizes for a set of items.
+///
+/// Every configured bucket appears in the result (in configuration order),
+/// even if no item falls into it. Items whose age or size cannot be read, or
+/// that match no bucket, are counted as skipped.
+///
+/// # Arguments
+///
+/// * `items` - The items to categorize
+/// * `bucket_config` - The bucket configuration to use
+/// * `include_empty` - Whether empty files are counted; otherwise they are
+///   left out entirely, neither counted nor skipped
+fn compute_source_stats(
+    items: &[PathBuf],
+    bucket_config: &BucketConfig,
+    include_empty: bool,
+) -> SourceStats {
+    let all_buckets = bucket_config.all_buckets();
+    let mut per_bucket: Vec<BucketStats> =
+        all_buckets.iter().map(|_| BucketStats::default()).collect();
+    let mut skipped = 0;
+
+    for path in items {
+        if !include_empty && is_empty_file(path) {
+            continue;
+        }
+        let (Ok(age), Ok(size)) = (get_file_age(path, bucket_config), item_size(path)) else {
+            skipped += 1;
+            continue;
+        };
+        let is_dir = fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+        let Some(bucket) = pick_bucket_for(path, is_dir, age, size, bucket_config) else {
+            skipped += 1;
+            continue;
+        };
+        if let Some(index) = all_buckets.iter().position(|b| b.name() == bucket.name()) {
+            per_bucket[index].count += 1;
+            per_bucket[index].bytes += size;
+        }
+    }
+
+    let buckets = all_buckets
+        .iter()
+        .zip(per_bucket)
+        .map(|(bucket, stats)| {
+            (
+                bucket.name().to_string(),
+                serde_json::to_value(stats).expect("BucketStats is always serializable"),
+            )
+        })
+        .collect();
+
+    SourceStats { buckets, skipped }
+}
+
+/// Aborts with the plan printed if it has more moves than `threshold`.
+///
+/// Planning and executing happen in one invocation, so there is no window for
+/// the directory to change between a reviewed dry-run and the real run.
+///
+/// # Errors
+///
+/// Returns an error after printing the plan if more than `threshold` moves
+/// are planned.
+fn check_apply_threshold(actions: &[FileAction], threshold: usize) -> io::Result<()> {
+    let (moves, _) = count_actions(actions);
+    if moves <= threshold {
+        return Ok(());
+    }
+
+    print_plan(actions);
+    Err(io::Error::other(format!(
+        "{moves} moves planned, more than --apply-if-under {threshold}; \
+         nothing was changed. Review the plan above and rerun."
+    )))
+}
+
+/// Reviews the plan before anything is changed.
+///
+/// With `--apply-if-under`, the plan passes if it is small enough. With
+/// `--execute-after-review`, it is printed and passes if the user confirms
+/// it.
+///
+/// # Returns
+///
+/// `Ok(false)` if the user declined to execute the plan
+///
+/// # Errors
+///
+/// Returns an error if the plan is too large for `--apply-if-under` or the
+/// answer cannot be read.
+fn review_plan(actions: &[FileAction], cfg: &RefileArgs) -> io::Result<bool> {
+    if let Some(threshold) = cfg.apply_if_under {
+        check_apply_threshold(actions, threshold)?;
+    } else if cfg.execute_after_review && count_actions(actions).0 > 0 {
+        return confirm_plan(actions);
+    }
+    Ok(true)
+}
+
+/// Prints the plan and asks whether to execute it.
+///
+/// The question goes to stderr and the answer is read from stdin; only `y`
+/// or `yes` confirms, so an empty answer or the end of input (say, when
+/// stdin is not a terminal) declines.
+///
+/// # Errors
+///
+/// Returns an error if stdin cannot be read.
+fn confirm_plan(actions: &[FileAction]) -> io::Result<bool> {
+    print_plan(actions);
+    eprint!("Execute this plan? [y/N] ");
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// An answer to an `--interactive` question.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MoveAnswer {
+    Yes,
+    No,
+    All,
+    Quit,
+}
+
+/// With `--interactive`, asks about each planned move, like `rm -i`.
+///
+/// `y` keeps the move, `n` turns it into a skip, `a` keeps it and every
+/// remaining move without asking again, and `q` aborts the run. All questions
+/// are asked before anything is moved. They go to stderr and the answers are
+/// read from stdin; anything other than the four answers declines, as does
+/// the end of input.
+///
+/// # Errors
+///
+/// Returns an `Interrupted` error on `q`, with nothing moved, or an error if
+/// stdin cannot be read.
+fn confirm_each_move(actions: Vec<FileAction>, cfg: &RefileArgs) -> io::Result<Vec<FileAction>> {
+    if !cfg.interactive {
+        return Ok(actions);
+    }
+
+    let mut ask = true;
+    let mut confirmed = Vec::with_capacity(actions.len());
+    for action in actions {
+        let Some((from, to)) = action.moved().filter(|_| ask) else {
+            confirmed.push(action);
+            continue;
+        };
+        eprint!("Move {} -> {}? [y,n,a,q] ", from.display(), to.display());
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        match parse_move_answer(&answer) {
+            MoveAnswer::Yes => confirmed.push(action),
+            MoveAnswer::All => {
+                ask = false;
+                confirmed.push(action);
+            }
+            MoveAnswer::No => confirmed.push(FileAction::Skip {
+                path: from.to_path_buf(),
+                reason: "declined".to_string(),
+            }),
+            MoveAnswer::Quit => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    "Quit at user request; nothing was moved",
+                ));
+            }
+        }
+    }
+    Ok(confirmed)
+}
+
+/// Reads an answer to an `--interactive` question; anything unrecognized,
+/// including no answer at all, is a no.
+fn parse_move_answer(answer: &str) -> MoveAnswer {
+    match answer.trim().to_ascii_lowercase().as_str() {
+        "y" | "yes" => MoveAnswer::Yes,
+        "a" | "all" => MoveAnswer::All,
+        "q" | "quit" => MoveAnswer::Quit,
+        _ => MoveAnswer::No,
+    }
+}
+
+/// Prints the planned moves and skips.
+fn print_plan(actions: &[FileAction]) {
+    for action in actions {
+        match action {
+            FileAction::Move { from, to, .. } => {
+                outln!("[plan] MOVE {} -> {}", from.display(), to.display());
+            }
+            FileAction::Trash {
+                from, to, trashed, ..
+            } => {
+                outln!("[plan] TRASH {} -> {}", to.display(), trashed.display());
+                outln!("[plan] MOVE {} -> {}", from.display(), to.display());
+            }
+            FileAction::Overwrite { from, to, .. } => {
+                outln!("[plan] OVERWRITE {} -> {}", from.display(), to.display());
+            }
+            FileAction::Skip { path, reason } => {
+                outln!("[plan] SKIP {}: {reason}", path.display());
+            }
+            FileAction::Keep { .. } => {}
+        }
+    }
+}
+
+/// Checks that no planned move crosses devices, where a rename is replaced by
+/// a slower, non-atomic copy and delete.
+///
+/// # Errors
+///
+/// Returns a `CrossesDevices` error naming the first such move.
+fn check_same_device(actions: &[FileAction]) -> io::Result<()> {
+    for (from, to) in actions.iter().filter_map(FileAction::moved) {
+        if let (Some(from_device), Some(to_device)) = (item_device_id(from), device_id(to))
+            && from_device != to_device
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::CrossesDevices,
+                format!(
+                    "Moving {} to {} would cross devices (copy and delete instead of an \
+                     atomic rename); nothing was moved (use --allow-cross-device to allow it)",
+                    from.display(),
+                    to.display()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every destination directory of the plan is writable, so an
+/// unwritable bucket aborts the run before the first move rather than partway
+/// through.
+///
+/// # Errors
+///
+/// Returns an error naming the first directory that cannot be written to.
+fn preflight_destinations(actions: &[FileAction]) -> io::Result<()> {
+    let dirs: BTreeSet<&Path> = actions
+        .iter()
+        .filter_map(|action| action.moved().and_then(|(_, to)| to.parent()))
+        .collect();
+    for dir in dirs {
+        probe_writable(dir).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "Destination {} is not writable: {e}; nothing was moved \
+                     (use --no-preflight to skip this check)",
+                    dir.display()
+                ),
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Prints the warning shown when `--allow-dangerous-directories` is enabled.
+fn warn_dangerous_directories() {
+    outln!("WARNING: --allow-dangerous-directories is enabled!");
+    outln!("This allows moving protected directories including:");
+    outln!("  - Root directory (/)");
+    outln!("  - Your home directory");
+    outln!("  - Top-level system directories (/tmp, /var, /usr, etc.)");
+    outln!("This can cause SEVERE SYSTEM DAMAGE. Use with extreme caution!");
+    outln!();
+}
+
+/// Asks for confirmation of every planned move of a protected directory.
+///
+/// # Errors
+///
+/// Returns the first refused confirmation, see [`confirm_protected_move`].
+fn confirm_protected_moves(actions: &[FileAction]) -> io::Result<()> {
+    for (from, _) in actions.iter().filter_map(FileAction::moved) {
+        if is_protected_directory(from) {
+            confirm_protected_move(from)?;
+        }
+    }
+    Ok(())
+}
+
+/// Asks the user to type a protected directory's name before it is moved.
+///
+/// The prompt goes to stderr and the answer is read from stdin. The directory
+/// name (or the full path, for directories without a name like `/`) must be
+/// typed exactly.
+///
+/// # Errors
+///
+/// Returns a `PermissionDenied` error if the answer doesn't match, or an
+/// error if stdin cannot be read.
+fn confirm_protected_move(path: &Path) -> io::Result<()> {
+    let expected = path.file_name().map_or_else(
+        || path.display().to_string(),
+        |n| n.to_string_lossy().into_owned(),
+    );
+
+    eprint!(
+        "About to move protected directory {}.\nType '{expected}' to confirm: ",
+        path.display()
+    );
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if answer.trim_end_matches(['\r', '\n']) == expected {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "Confirmation failed, refusing to move protected directory: {}",
+                path.display()
+            ),
+        ))
+    }
+}
+
+/// Executes a planned file action.
+///
+/// For `FileAction::Skip`, prints a message to stderr.
+/// For `FileAction::Keep`, prints a message with `--verbose`.
+/// For `FileAction::Move`, attempts to move the file:
+/// - In dry-run mode, only prints what would be done
+/// - Symlinks are recreated at the destination with their target rewritten
+///   so it still resolves (relative links stay relative unless
+///   `--absolute-symlinks` is set)
+/// - Otherwise, attempts atomic rename first
+/// - Falls back to copy+delete for cross-filesystem moves
+/// - With `--copy`, copies the item instead, leaving the original in place
+///
+/// # Arguments
+///
+/// * `action` - The action to execute
+/// * `cfg` - Configuration (dry-run and symlink handling)
+/// * `journal` - The journal successful moves are recorded in, if any
+///
+/// # Errors
+///
+/// Returns an error if the file operation fails.
+fn execute_action(
+    action: FileAction,
+    cfg: &RefileArgs,
+    journal: Option<&Journal>,
+) -> io::Result<()> {
+    match action {
+        FileAction::Skip { path, reason } => {
+            errln!("Skipping {}: {}", path.display(), reason);
+            Ok(())
+        }
+        FileAction::Keep { path, bucket } => {
+            if cfg.verbosity() < Verbosity::Verbose {
+                // Nothing to report
+            } else if cfg.dry_run {
+                outln!("[dry-run] KEEP {} (already in {bucket})", path.display());
+            } else {
+                outln!("Kept {} (already in {bucket})", path.display());
+            }
+            Ok(())
+        }
+        FileAction::Move {
+            from, to, link_to, ..
+        } => {
+            if cfg.dry_run {
+                let link = link_to
+                    .as_ref()
+                    .map(|original| format!(" (hard link to {})", original.display()))
+                    .unwrap_or_default();
+                outln!(
+                    "[dry-run] {} {} -> {}{link}",
+                    cfg.verb(),
+                    from.display(),
+                    to.display()
+                );
+                return Ok(());
+            }
+            move_into_place(&from, &to, link_to.as_deref(), cfg, journal)
+        }
+        FileAction::Trash {
+            from, to, trashed, ..
+        } => {
+            if cfg.dry_run {
+                outln!("[dry-run] TRASH {} -> {}", to.display(), trashed.display());
+                outln!(
+                    "[dry-run] {} {} -> {}",
+                    cfg.verb(),
+                    from.display(),
+                    to.display()
+                );
+                return Ok(());
+            }
+
+            // The conflict may be with an item moved earlier in this run
+            if fs::symlink_metadata(&to).is_ok() {
+                if let Some(parent) = trashed.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                move_item(&to, &trashed, false)?;
+                if let Some(journal) = journal {
+                    journal.record(&to, &trashed, None)?;
+                }
+            }
+            move_into_place(&from, &to, None, cfg, journal)
+        }
+        FileAction::Overwrite { from, to, .. } => {
+            if cfg.dry_run {
+                outln!("[dry-run] OVERWRITE {} -> {}", from.display(), to.display());
+                return Ok(());
+            }
+            remove_item(&to)?;
+            move_into_place(&from, &to, None, cfg, journal)
+        }
+    }
+}
+
+/// Moves an item to a destination that is free, recording the move in the
+/// journal and running the post-move hook once it has succeeded.
+///
+/// With `link_to`, the item is a hard link to that path, which is already in
+/// place, and is recreated as a link to it instead. If that path is not the
+/// same file (its move failed, say), the item is moved like any other.
+///
+/// # Errors
+///
+/// Returns an error if the destination directory cannot be created, the
+/// item cannot be moved or the journal cannot be written, or if the post-move
+/// hook fails with `--hook-failure abort`.
+fn move_into_place(
+    from: &Path,
+    to: &Path,
+    link_to: Option<&Path>,
+    cfg: &RefileArgs,
+    journal: Option<&Journal>,
+) -> io::Result<()> {
+    // Ensure parent directory exists
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // A hard link is only recreated if what it links to made it into place
+    let link_to = link_to
+        .filter(|original| hard_link_id(original).is_some_and(|id| hard_link_id(from) == Some(id)));
+
+    // Symlinks are relinked rather than renamed so relative targets keep resolving,
+    // unless what they point at is to be moved instead (dangling links stay links)
+    let mut link_target = None;
+    if let Some(original) = link_to {
+        move_hard_link(from, to, original)?;
+    } else if cfg.copy {
+        copy_item(from, to, cfg.follow_symlinks, cfg.absolute_symlinks)?;
+    } else if fs::symlink_metadata(from)?.file_type().is_symlink() {
+        if cfg.follow_symlinks && from.exists() {
+            link_target = Some(fs::read_link(from)?);
+            move_symlink_target(from, to)?;
+        } else {
+            move_symlink(from, to, cfg.absolute_symlinks)?;
+        }
+    } else {
+        // Atomic rename, or copy+delete across filesystems
+        move_item(from, to, cfg.follow_symlinks)?;
+    }
+
+    if let Some(journal) = journal {
+        journal.record(from, to, link_target.as_deref())?;
+    }
+
+    if let Some(hook) = &cfg.post_move_hook
+        && let Err(e) = hook::run_post_move_hook(hook, from, to)
+    {
+        match cfg.hook_failure {
+            HookFailure::Warn => errln!("Warning: {e}"),
+            HookFailure::Abort => return Err(io::Error::other(HookAborted(e))),
+        }
+    }
+    Ok(())
+}
 
-    Ok(())
+/// Counts the planned moves and skips.
+fn count_actions(actions: &[FileAction]) -> (usize, usize) {
+    let count = |f: fn(&FileAction) -> bool| actions.iter().filter(|a| f(a)).count();
+    (
+        count(|a| a.moved().is_some()),
+        count(|a| matches!(a, FileAction::Skip { .. })),
+    )
 }
 
-// ============================================================================
-// Application logic
-// ============================================================================
+/// Returns true if no two of the actions move an item to the same path.
+fn destinations_unique<'a>(actions: impl IntoIterator<Item = &'a FileAction>) -> bool {
+    let mut seen = HashSet::new();
+    actions
+        .into_iter()
+        .filter_map(FileAction::moved)
+        .all(|(_, to)| seen.insert(to))
+}
 
-/// Plans the appropriate action for a single file or directory.
+/// Executes the planned actions, reporting progress if requested.
 ///
-/// This function:
-/// 1. Checks if the path is a protected directory
-/// 2. Reads the item's age from its metadata
-/// 3. Determines the appropriate bucket
-/// 4. Computes the destination path
-/// 5. Checks for conflicts and handles them based on configuration
-/// 6. Returns a `FileAction` describing what should be done
-///
-/// # Arguments
+/// Moves run sequentially, `--jobs` at a time, or in parallel throttled per
+/// destination device with `--max-concurrency-per-device`: moves are grouped
+/// by the device of their destination (or its nearest existing ancestor) so
+/// each disk sees at most that many concurrent moves while different disks
+/// work in parallel. Planning claims every destination for a single action,
+/// since `--on-conflict trash` and `overwrite` only displace items that were
+/// there before the run and rename an item that would displace another one of
+/// the run, so concurrent moves never race for the same path. Hard links
+/// recreated with `--dedup-hardlinks` come last, one at a time, once every
+/// other move is done.
 ///
-/// * `path` - Path to the item to plan an action for
-/// * `target_dir` - Target directory for refile structure
-/// * `cfg` - Configuration including target directory and conflict handling
-/// * `bucket_config` - The bucket configuration to use
+/// A real run with moves starts a fresh journal in the base folder for
+/// `refile undo`. Each move made (or shown, in a dry run) is counted in
+/// `made`.
 ///
 /// # Returns
 ///
-/// - `Ok(Some(FileAction::Move))` if the item should be moved
-/// - `Ok(Some(FileAction::Skip))` if the item should be skipped (with reason)
-/// - `Ok(None)` if the item is already in the correct location
+/// The moves that failed, and the journal if one was started. A failed move
+/// does not stop the others, so the rest of the run can go on and report them
+/// at the end.
 ///
 /// # Errors
 ///
-/// Returns an error if:
-/// - The path is a protected directory (root or home) and `allow_dangerous_directories` is false
-/// - File metadata cannot be read
-/// - A conflict exists and `allow_rename` is false
-/// - No unique destination can be found when `allow_rename` is true
-fn plan_action(
-    path: &Path,
-    target_dir: &Path,
+/// With `--fail-fast`, returns the first error from any action, and the error
+/// of a post-move hook under `--hook-failure abort`; with parallel moves,
+/// moves already running finish, but no new ones are started.
+fn execute_actions(
+    actions: Vec<FileAction>,
     cfg: &RefileArgs,
-    bucket_config: &BucketConfig,
-) -> io::Result<Option<FileAction>> {
-    // Check if this is a protected directory
-    if is_protected_directory(path) && !cfg.allow_dangerous_directories {
-        return Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            format!(
-                "Refusing to move protected directory: {}. \
-                 Protected directories include: root (/), user home, and top-level directories (/tmp, /var, /usr, etc.).",
-                path.display()
-            ),
-        ));
+    refile_base: &Path,
+    made: &AtomicUsize,
+) -> io::Result<(Vec<Failure>, Option<Journal>)> {
+    debug_assert!(
+        destinations_unique(&actions),
+        "two moves share a destination"
+    );
+    if cfg.dry_run {
+        made.store(count_actions(&actions).0, Ordering::Relaxed);
+        for action in actions {
+            execute_action(action, cfg, None)?;
+        }
+        return Ok((Vec::new(), None));
     }
 
-    // Get file age
-    let age = match get_file_age(path) {
-        Ok(a) => a,
-        Err(e) => {
-            return Ok(Some(FileAction::Skip {
-                path: path.to_path_buf(),
-                reason: format!("cannot get age: {e}"),
-            }));
+    // Sizes are only needed for progress reporting
+    let mut moves = Vec::new();
+    for action in actions {
+        if let Some((from, _)) = action.moved() {
+            let size = match cfg.progress_interval {
+                Some(_) => item_size(from).unwrap_or(0),
+                None => 0,
+            };
+            moves.push((action, size));
+        } else {
+            execute_action(action, cfg, None)?;
         }
-    };
+    }
 
-    // Determine bucket
-    let bucket = pick_bucket(age, bucket_config);
+    // A run without moves keeps the previous run's journal for undo, and
+    // copies leave nothing to undo
+    let journal = if moves.is_empty() || cfg.copy {
+        None
+    } else {
+        Some(Journal::create(refile_base)?)
+    };
 
-    // Compute destination path
-    let Some(dest_path) = compute_dest_path(path, target_dir, bucket, bucket_config) else {
-        return Ok(Some(FileAction::Skip {
-            path: path.to_path_buf(),
-            reason: "no file name".to_string(),
-        }));
+    let progress = cfg.progress_interval.map(|secs| {
+        let total_bytes = moves.iter().map(|(_, size)| size).sum();
+        Mutex::new(Progress::new(
+            Duration::from_secs(secs),
+            moves.len(),
+            total_bytes,
+        ))
+    });
+    let failures = Mutex::new(Vec::new());
+    let run = |(action, size): (FileAction, u64)| {
+        let path = action
+            .moved()
+            .map(|(from, _)| from.to_path_buf())
+            .unwrap_or_default();
+        if let Err(e) = execute_action(action, cfg, journal.as_ref()) {
+            // The move itself was made, but the run is to stop
+            if e.get_ref()
+                .is_some_and(<dyn std::error::Error + Send + Sync>::is::<HookAborted>)
+            {
+                return Err(e);
+            }
+            let failure = Failure::new(&path, if cfg.copy { "copy" } else { "move" }, &e);
+            failures
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(failure);
+            if cfg.fail_fast {
+                return Err(e);
+            }
+            return Ok(());
+        }
+        made.fetch_add(1, Ordering::Relaxed);
+        if let Some(progress) = &progress {
+            progress
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .record(size);
+        }
+        Ok(())
     };
 
-    // Check if source and destination are the same
-    if paths_equal(path, &dest_path) {
-        return Ok(None); // Skip silently - already in correct location
+    // Hard links are recreated once what they link to is in place
+    let (linked, moves): (Vec<_>, Vec<_>) = moves.into_iter().partition(|(action, _)| {
+        matches!(
+            action,
+            FileAction::Move {
+                link_to: Some(_),
+                ..
+            }
+        )
+    });
+
+    let result = match cfg.max_concurrency_per_device {
+        Some(per_device) => {
+            let moves = moves
+                .into_iter()
+                .map(|planned| (planned.0.moved().and_then(|(_, to)| device_id(to)), planned))
+                .collect();
+            schedule::run_grouped(moves, per_device.get(), run)
+        }
+        None if cfg.jobs.get() > 1 => {
+            let moves = moves.into_iter().map(|planned| ((), planned)).collect();
+            schedule::run_grouped(moves, cfg.jobs.get(), run)
+        }
+        None => moves.into_iter().try_for_each(&run),
     }
+    .and_then(|()| linked.into_iter().try_for_each(&run));
 
-    // Handle conflicts based on configuration
-    let final_dest = if dest_path.exists() {
-        if cfg.allow_rename {
-            // Find a unique destination by renaming
-            find_unique_dest(&dest_path)?
+    let failures = failures
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(path) = &cfg.error_report {
+        write_error_report(path, &failures)?;
+    }
+    result?;
+    Ok((failures, journal))
+}
+
+/// Lists the moves that failed, if any.
+///
+/// # Returns
+///
+/// A [`PartialFailure`] if any of the `total` moves failed.
+fn report_failures(failures: &[Failure], total: usize) -> Option<PartialFailure> {
+    if failures.is_empty() {
+        return None;
+    }
+    errln!("Failed moves:");
+    for failure in failures {
+        errln!("  {}: {}", failure.path, failure.error);
+    }
+    Some(PartialFailure {
+        failed: failures.len(),
+        total,
+    })
+}
+
+/// Lists the planned moves that were renamed to avoid a conflict.
+fn collect_renames(actions: &[FileAction]) -> Vec<Rename> {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            FileAction::Move {
+                from,
+                to,
+                renamed_from: Some(intended),
+                ..
+            } => Some(Rename {
+                from: from.display().to_string(),
+                intended: intended.display().to_string(),
+                to: to.display().to_string(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Summarizes the renames of a run and writes them to the rename report, if
+/// one was requested.
+///
+/// # Errors
+///
+/// Returns an error if the report cannot be written.
+fn report_renames(renames: &[Rename], cfg: &RefileArgs) -> io::Result<()> {
+    if !renames.is_empty() {
+        let why = if cfg.transliterate {
+            "conflicts or non-ASCII names"
         } else {
-            // Abort on conflict
-            return Err(io::Error::new(
-                io::ErrorKind::AlreadyExists,
-                format!(
-                    "Conflict: destination path already exists: {} (source: {})\n\
-                     Use --allow-rename to automatically rename conflicting files",
-                    dest_path.display(),
-                    path.display()
-                ),
-            ));
+            "conflicts"
+        };
+        outln!("Renamed {} items to avoid {why}:", renames.len());
+        for rename in renames {
+            outln!("  {} -> {}", rename.intended, rename.to);
         }
-    } else {
-        dest_path
+    }
+    let Some(path) = &cfg.rename_report else {
+        return Ok(());
     };
-
-    Ok(Some(FileAction::Move {
-        from: path.to_path_buf(),
-        to: final_dest,
-    }))
+    let mut lines = String::new();
+    for rename in renames {
+        lines.push_str(&serde_json::to_string(rename).map_err(io::Error::other)?);
+        lines.push('\n');
+    }
+    fs::write(path, lines)
 }
 
-/// Executes a planned file action.
+/// Writes failures as JSON lines, replacing any previous report.
 ///
-/// For `FileAction::Skip`, prints a message to stderr.
-/// For `FileAction::Move`, attempts to move the file:
-/// - In dry-run mode, only prints what would be done
-/// - Otherwise, attempts atomic rename first
-/// - Falls back to copy+delete for cross-filesystem moves
+/// # Errors
 ///
-/// # Arguments
+/// Returns an error if the report cannot be written.
+fn write_error_report(path: &Path, failures: &[Failure]) -> io::Result<()> {
+    let mut report = String::new();
+    for failure in failures {
+        report.push_str(&serde_json::to_string(failure).map_err(io::Error::other)?);
+        report.push('\n');
+    }
+    fs::write(path, report)
+}
+
+/// Columns of the `--report` CSV file.
+const ACTION_REPORT_HEADER: &str = "source,destination,bucket,age_days,bytes,action,note";
+
+/// Appends a CSV row for every planned action to the `--report` file,
+/// starting a new file with a header row.
 ///
-/// * `action` - The action to execute
-/// * `dry_run` - If true, only prints actions without performing them
+/// Ages and sizes are those of the source items before anything moves; they
+/// are left empty for items that cannot be read. Skips have no destination
+/// and give their reason as the note.
 ///
 /// # Errors
 ///
-/// Returns an error if the file operation fails.
-fn execute_action(action: FileAction, dry_run: bool) -> io::Result<()> {
-    match action {
-        FileAction::Skip { path, reason } => {
-            eprintln!("Skipping {}: {}", path.display(), reason);
-            Ok(())
-        }
-        FileAction::Move { from, to } => {
-            if dry_run {
-                println!("[dry-run] MOVE {} -> {}", from.display(), to.display());
-                return Ok(());
+/// Returns an error if the report cannot be opened or written.
+fn write_action_report(
+    path: &Path,
+    actions: &[FileAction],
+    cfg: &RefileArgs,
+    bucket_config: &BucketConfig,
+) -> io::Result<()> {
+    let mut rows = String::new();
+    for action in actions {
+        let (source, destination, bucket, kind, note) = match action {
+            FileAction::Move {
+                from,
+                to,
+                renamed_from,
+                bucket,
+                link_to,
+            } => {
+                let note = match (renamed_from, link_to) {
+                    (_, Some(original)) => format!("hard link to {}", original.display()),
+                    (Some(intended), None) => format!("renamed from {}", intended.display()),
+                    (None, None) => String::new(),
+                };
+                let kind = if cfg.copy { "copy" } else { "move" };
+                (from, Some(to), bucket.as_str(), kind, note)
             }
-
-            // Ensure parent directory exists
-            if let Some(parent) = to.parent() {
-                fs::create_dir_all(parent)?;
+            FileAction::Trash {
+                from,
+                to,
+                trashed,
+                bucket,
+            } => {
+                let note = format!("existing item trashed to {}", trashed.display());
+                (from, Some(to), bucket.as_str(), "trash", note)
             }
-
-            // Try atomic rename first
-            match fs::rename(&from, &to) {
-                Ok(()) => {
-                    println!("Moved {} -> {}", from.display(), to.display());
-                    Ok(())
-                }
-                Err(rename_err) => {
-                    // Cross-filesystem move: copy then delete
-                    move_cross_filesystem(&from, &to, &rename_err)
-                }
+            FileAction::Overwrite { from, to, bucket } => {
+                let note = "existing item deleted".to_string();
+                (from, Some(to), bucket.as_str(), "overwrite", note)
             }
-        }
+            FileAction::Skip { path, reason } => (path, None, "", "skip", reason.clone()),
+            FileAction::Keep { path, bucket } => {
+                (path, Some(path), bucket.as_str(), "keep", String::new())
+            }
+        };
+        let age_days = get_file_age(source, bucket_config)
+            .map(|age| (age.as_secs() / (24 * 3600)).to_string())
+            .unwrap_or_default();
+        let bytes = item_size(source)
+            .map(|bytes| bytes.to_string())
+            .unwrap_or_default();
+        let fields = [
+            source.display().to_string(),
+            destination
+                .map(|d| d.display().to_string())
+                .unwrap_or_default(),
+            bucket.to_string(),
+            age_days,
+            bytes,
+            kind.to_string(),
+            note,
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        rows.push_str(&row.join(","));
+        rows.push('\n');
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{ACTION_REPORT_HEADER}")?;
+    }
+    file.write_all(rows.as_bytes())
+}
+
+/// Quotes a CSV field if it holds a comma, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
     }
 }
 
@@ -385,8 +4074,11 @@ mod tests {
     use crate::config::BucketDef;
     use crate::core::{
         bucket_dest_dir, compute_dest_path, generate_unique_name, is_bucket_dir,
-        is_protected_directory, paths_equal, pick_bucket, refile_base_path,
+        is_protected_directory, is_system_file, normalize_lexically, overflow_dir,
+        parse_date_from_name, paths_equal, pick_bucket, pick_bucket_calendar, refile_base_path,
+        relative_path, same_entry,
     };
+    use crate::filesystem::{copy_dir_recursive, directory_size, move_item_with};
     use std::env;
     use std::time::Duration;
 
@@ -394,44 +4086,102 @@ mod tests {
         BucketConfig::default()
     }
 
+    fn test_args(source_dir: &str, allow_dangerous_directories: bool) -> RefileArgs {
+        let mut args = Cli::parse_from(["refile", source_dir])
+            .refile
+            .expect("source_dir should produce refile args");
+        args.allow_dangerous_directories = allow_dangerous_directories;
+        args
+    }
+
+    #[test]
+    fn test_pick_bucket_calendar() {
+        use chrono::{FixedOffset, TimeZone};
+        use config::CalendarPeriod;
+
+        let tz = FixedOffset::east_opt(3600).unwrap();
+        let at = |y, m, d, h| SystemTime::from(tz.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap());
+        let buckets = vec![
+            BucketDef::with_period("this-week".to_string(), Some(CalendarPeriod::ThisWeek)),
+            BucketDef::with_period("last-week".to_string(), Some(CalendarPeriod::LastWeek)),
+            BucketDef::with_period("this-month".to_string(), Some(CalendarPeriod::ThisMonth)),
+            BucketDef::with_period("last-month".to_string(), Some(CalendarPeriod::LastMonth)),
+            BucketDef::with_period("older".to_string(), None),
+        ];
+        // Wednesday, 13 March 2024, 10:00 at UTC+1
+        let now = tz.with_ymd_and_hms(2024, 3, 13, 10, 0, 0).unwrap();
+        let pick = |modified| {
+            pick_bucket_calendar(&buckets, modified, &now)
+                .unwrap()
+                .name()
+        };
+
+        assert_eq!(pick(at(2024, 3, 11, 9)), "this-week");
+        assert_eq!(pick(at(2024, 3, 10, 23)), "last-week");
+        assert_eq!(pick(at(2024, 3, 4, 0)), "last-week");
+        assert_eq!(pick(at(2024, 3, 3, 12)), "this-month");
+        assert_eq!(pick(at(2024, 2, 29, 12)), "last-month");
+        assert_eq!(pick(at(2024, 1, 31, 12)), "older");
+
+        // Days are counted in local time: 23:30 UTC on Sunday is Monday at UTC+1
+        let utc_sunday_night = SystemTime::from(
+            chrono::Utc
+                .with_ymd_and_hms(2024, 3, 10, 23, 30, 0)
+                .unwrap(),
+        );
+        assert_eq!(pick(utc_sunday_night), "this-week");
+
+        // Files from the future land in the newest bucket
+        assert_eq!(pick(at(2024, 4, 1, 12)), "this-week");
+
+        // Last month wraps around the year
+        let january = tz.with_ymd_and_hms(2024, 1, 10, 10, 0, 0).unwrap();
+        let picked = pick_bucket_calendar(&buckets, at(2023, 12, 20, 12), &january);
+        assert_eq!(picked.unwrap().name(), "last-month");
+
+        // Without a catch-all, nothing matches an older file
+        let picked = pick_bucket_calendar(&buckets[..4], at(2023, 6, 1, 12), &now);
+        assert!(picked.is_none());
+    }
+
     #[test]
     fn test_pick_bucket_with_default_config() {
         let config = default_config();
 
         // 0 days -> last-week
-        let bucket = pick_bucket(Duration::from_secs(0), &config);
+        let bucket = pick_bucket(Duration::from_secs(0), 0, &config).unwrap();
         assert_eq!(bucket.name(), "last-week");
 
         // 3 days -> last-week
-        let bucket = pick_bucket(Duration::from_secs(3 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(3 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "last-week");
 
         // 7 days -> last-week
-        let bucket = pick_bucket(Duration::from_secs(7 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(7 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "last-week");
 
         // 8 days -> current-month
-        let bucket = pick_bucket(Duration::from_secs(8 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(8 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "current-month");
 
         // 28 days -> current-month
-        let bucket = pick_bucket(Duration::from_secs(28 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(28 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "current-month");
 
         // 29 days -> last-months
-        let bucket = pick_bucket(Duration::from_secs(29 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(29 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "last-months");
 
         // 92 days -> last-months
-        let bucket = pick_bucket(Duration::from_secs(92 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(92 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "last-months");
 
         // 93 days -> old-stuff
-        let bucket = pick_bucket(Duration::from_secs(93 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(93 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "old-stuff");
 
         // 365 days -> old-stuff
-        let bucket = pick_bucket(Duration::from_secs(365 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(365 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "old-stuff");
     }
 
@@ -447,30 +4197,183 @@ mod tests {
         );
 
         // 0 days -> today
-        let bucket = pick_bucket(Duration::from_secs(0), &config);
+        let bucket = pick_bucket(Duration::from_secs(0), 0, &config).unwrap();
         assert_eq!(bucket.name(), "today");
 
         // 1 day -> today
-        let bucket = pick_bucket(Duration::from_secs(24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "today");
 
         // 2 days -> week
-        let bucket = pick_bucket(Duration::from_secs(2 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(2 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "week");
 
         // 7 days -> week
-        let bucket = pick_bucket(Duration::from_secs(7 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(7 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "week");
 
         // 8 days -> old
-        let bucket = pick_bucket(Duration::from_secs(8 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(8 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "old");
 
         // 100 days -> old
-        let bucket = pick_bucket(Duration::from_secs(100 * 24 * 3600), &config);
+        let bucket = pick_bucket(Duration::from_hours(100 * 24), 0, &config).unwrap();
         assert_eq!(bucket.name(), "old");
     }
 
+    #[test]
+    fn test_pick_bucket_with_reverse_age() {
+        // Lower bounds, oldest first: everything a week or older is lumped
+        // together, the newest items are split out
+        let config = config::resolve_bucket_config(
+            Path::new("/nonexistent"),
+            &config::ResolveOptions {
+                buckets: Some("rest=7,yesterday=1,today=null"),
+                reverse_age: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let bucket = pick_bucket(Duration::from_hours(1), 0, &config).unwrap();
+        assert_eq!(bucket.name(), "today");
+        let bucket = pick_bucket(Duration::from_hours(3 * 24), 0, &config).unwrap();
+        assert_eq!(bucket.name(), "yesterday");
+        let bucket = pick_bucket(Duration::from_hours(7 * 24), 0, &config).unwrap();
+        assert_eq!(bucket.name(), "rest");
+        let bucket = pick_bucket(Duration::from_hours(365 * 24), 0, &config).unwrap();
+        assert_eq!(bucket.name(), "rest");
+    }
+
+    #[test]
+    fn test_pick_bucket_half_open_boundaries() {
+        let mut config = default_config();
+        config.set_half_open(true);
+        let days = |n: u64| Duration::from_secs(n * 24 * 3600);
+
+        // [0, 7), [7, 28), [28, 92), [92, ...)
+        let cases = [
+            (0, "last-week"),
+            (6, "last-week"),
+            (7, "current-month"),
+            (27, "current-month"),
+            (28, "last-months"),
+            (91, "last-months"),
+            (92, "old-stuff"),
+            (365, "old-stuff"),
+        ];
+        for (age, expected) in cases {
+            assert_eq!(
+                pick_bucket(days(age), 0, &config).unwrap().name(),
+                expected,
+                "{age} days"
+            );
+        }
+
+        // Just short of a boundary is still inside the bucket
+        let almost_week = Duration::from_secs(7 * 24 * 3600 - 1);
+        assert_eq!(
+            pick_bucket(almost_week, 0, &config).unwrap().name(),
+            "last-week"
+        );
+
+        // Inclusive by default: an item exactly at a limit stays in the bucket
+        config.set_half_open(false);
+        for (age, expected) in [(7, "last-week"), (28, "current-month"), (92, "last-months")] {
+            assert_eq!(
+                pick_bucket(days(age), 0, &config).unwrap().name(),
+                expected,
+                "{age} days"
+            );
+        }
+    }
+
+    #[test]
+    fn test_pick_bucket_leave_unmatched() {
+        let resolve = |leave_unmatched| {
+            config::resolve_bucket_config(
+                Path::new("/nonexistent"),
+                &config::ResolveOptions {
+                    buckets: Some("week=7,month=30"),
+                    leave_unmatched,
+                    ..Default::default()
+                },
+            )
+        };
+        let days = |n: u64| Duration::from_secs(n * 24 * 3600);
+
+        // Strict: a catch-all bucket is required
+        assert!(resolve(false).is_err());
+
+        let config = resolve(true).unwrap();
+        assert_eq!(pick_bucket(days(3), 0, &config).unwrap().name(), "week");
+        assert_eq!(pick_bucket(days(30), 0, &config).unwrap().name(), "month");
+        assert!(pick_bucket(days(31), 0, &config).is_none());
+
+        // With a catch-all, nothing is left unmatched
+        let mut config = default_config();
+        config.validate().unwrap();
+        assert_eq!(
+            pick_bucket(days(365), 0, &config).unwrap().name(),
+            "old-stuff"
+        );
+        config.set_half_open(true);
+        assert!(pick_bucket(Duration::MAX, 0, &config).is_some());
+    }
+
+    #[test]
+    fn test_pick_bucket_half_open_sizes() {
+        let mut config = config::resolve_bucket_config(
+            Path::new("/nonexistent"),
+            &config::ResolveOptions {
+                buckets: Some("small=1KB,large=null"),
+                bucket_mode: Some(BucketMode::Size),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        config.set_half_open(true);
+
+        assert_eq!(
+            pick_bucket(Duration::ZERO, 1023, &config).unwrap().name(),
+            "small"
+        );
+        assert_eq!(
+            pick_bucket(Duration::ZERO, 1024, &config).unwrap().name(),
+            "large"
+        );
+    }
+
+    #[test]
+    fn test_pick_bucket_by_size() {
+        let config = config::resolve_bucket_config(
+            Path::new("/nonexistent"),
+            &config::ResolveOptions {
+                buckets: Some("small=1KB,medium=1MB,large=null"),
+                bucket_mode: Some(BucketMode::Size),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // Age plays no part
+        let old = Duration::from_hours(365 * 24);
+        assert_eq!(pick_bucket(old, 0, &config).unwrap().name(), "small");
+        assert_eq!(pick_bucket(old, 1024, &config).unwrap().name(), "small");
+        assert_eq!(
+            pick_bucket(Duration::ZERO, 1025, &config).unwrap().name(),
+            "medium"
+        );
+        assert_eq!(
+            pick_bucket(old, 1024 * 1024, &config).unwrap().name(),
+            "medium"
+        );
+        assert_eq!(
+            pick_bucket(old, 1024 * 1024 + 1, &config).unwrap().name(),
+            "large"
+        );
+    }
+
     #[test]
     fn test_refile_base_path() {
         let config = default_config();
@@ -492,53 +4395,205 @@ mod tests {
 
         let bucket = &config.buckets()[0]; // last-week
         assert_eq!(
-            bucket_dest_dir(target, bucket, &config),
+            bucket_dest_dir(Path::new("/src/a.txt"), target, bucket, &config),
             PathBuf::from("/home/user/documents/refile/last-week")
         );
 
-        let bucket = &config.buckets()[1]; // current-month
+        let bucket = &config.buckets()[1]; // current-month
+        assert_eq!(
+            bucket_dest_dir(Path::new("/src/a.txt"), target, bucket, &config),
+            PathBuf::from("/home/user/documents/refile/current-month")
+        );
+    }
+
+    #[test]
+    fn test_bucket_dest_dir_with_layout() {
+        let mut config = default_config();
+        let target = Path::new("/home/user/documents");
+        let bucket = &config.buckets()[3].clone(); // old-stuff
+
+        config.set_layout("type,age".parse().unwrap());
+        assert_eq!(
+            bucket_dest_dir(Path::new("/src/photo.JPG"), target, bucket, &config),
+            PathBuf::from("/home/user/documents/refile/images/old-stuff")
+        );
+        assert!(is_bucket_dir(
+            "/home/user/documents/refile/images/old-stuff",
+            &config
+        ));
+        assert!(!is_bucket_dir(
+            "/home/user/documents/refile/old-stuff",
+            &config
+        ));
+
+        config.set_layout("age,type".parse().unwrap());
+        assert_eq!(
+            bucket_dest_dir(Path::new("/src/notes"), target, bucket, &config),
+            PathBuf::from("/home/user/documents/refile/old-stuff/other")
+        );
+
+        assert!("age,age".parse::<Layout>().is_err());
+        assert!("size".parse::<Layout>().is_err());
+    }
+
+    #[test]
+    fn test_compute_dest_path() {
+        let config = default_config();
+        let source = Path::new("/home/user/documents/file.txt");
+        let target = Path::new("/home/user/archive");
+
+        let bucket = &config.buckets()[0]; // last-week
+        let dest = compute_dest_path(source, target, bucket, &config, None, None);
+        assert_eq!(
+            dest,
+            Some(PathBuf::from(
+                "/home/user/archive/refile/last-week/file.txt"
+            ))
+        );
+
+        let bucket = &config.buckets()[3]; // old-stuff
+        let dest = compute_dest_path(source, target, bucket, &config, None, None);
+        assert_eq!(
+            dest,
+            Some(PathBuf::from(
+                "/home/user/archive/refile/old-stuff/file.txt"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_compute_dest_path_no_filename() {
+        let config = default_config();
+        let bucket = &config.buckets()[0];
+        let dest = compute_dest_path(
+            Path::new("/"),
+            Path::new("/home/user/archive"),
+            bucket,
+            &config,
+            None,
+            None,
+        );
+        assert_eq!(dest, None);
+    }
+
+    #[test]
+    fn test_compute_dest_path_preserving_structure() {
+        let config = default_config();
+        let bucket = &config.buckets()[3]; // old-stuff
+        let source_dir = Path::new("/home/user/documents");
+
+        let dest = compute_dest_path(
+            Path::new("/home/user/documents/a/b/c.txt"),
+            source_dir,
+            bucket,
+            &config,
+            None,
+            Some(source_dir),
+        );
+        assert_eq!(
+            dest,
+            Some(PathBuf::from(
+                "/home/user/documents/refile/old-stuff/a/b/c.txt"
+            ))
+        );
+
+        // Filed files keep the directories they have below their bucket
+        let dest = compute_dest_path(
+            Path::new("/home/user/documents/refile/last-week/a/b/c.txt"),
+            source_dir,
+            bucket,
+            &config,
+            None,
+            Some(source_dir),
+        );
         assert_eq!(
-            bucket_dest_dir(target, bucket, &config),
-            PathBuf::from("/home/user/documents/refile/current-month")
+            dest,
+            Some(PathBuf::from(
+                "/home/user/documents/refile/old-stuff/a/b/c.txt"
+            ))
         );
     }
 
     #[test]
-    fn test_compute_dest_path() {
+    fn test_compute_dest_path_with_suffix() {
         let config = default_config();
-        let source = Path::new("/home/user/documents/file.txt");
+        let bucket = &config.buckets()[3]; // old-stuff
         let target = Path::new("/home/user/archive");
 
-        let bucket = &config.buckets()[0]; // last-week
-        let dest = compute_dest_path(source, target, bucket, &config);
+        let dest = compute_dest_path(
+            Path::new("/home/user/documents/project"),
+            target,
+            bucket,
+            &config,
+            Some("2023-11"),
+            None,
+        );
         assert_eq!(
             dest,
             Some(PathBuf::from(
-                "/home/user/archive/refile/last-week/file.txt"
+                "/home/user/archive/refile/old-stuff/project_2023-11"
             ))
         );
 
-        let bucket = &config.buckets()[3]; // old-stuff
-        let dest = compute_dest_path(source, target, bucket, &config);
+        // Already suffixed names are not suffixed twice, whatever their date
+        // and whether or not a conflict gave them a counter
+        for name in ["project_2023-11", "project_2023-10", "project_2023-11 (1)"] {
+            let dest = compute_dest_path(
+                &Path::new("/home/user/archive/refile/old-stuff").join(name),
+                target,
+                bucket,
+                &config,
+                Some("2023-11"),
+                None,
+            );
+            assert_eq!(
+                dest,
+                Some(Path::new("/home/user/archive/refile/old-stuff").join(name))
+            );
+        }
+        let dest = compute_dest_path(
+            Path::new("/home/user/documents/project_v2 (1)"),
+            target,
+            bucket,
+            &config,
+            Some("2023-11"),
+            None,
+        );
         assert_eq!(
             dest,
             Some(PathBuf::from(
-                "/home/user/archive/refile/old-stuff/file.txt"
+                "/home/user/archive/refile/old-stuff/project_v2 (1)_2023-11"
             ))
         );
     }
 
     #[test]
-    fn test_compute_dest_path_no_filename() {
-        let config = default_config();
-        let bucket = &config.buckets()[0];
-        let dest = compute_dest_path(
-            Path::new("/"),
-            Path::new("/home/user/archive"),
-            bucket,
-            &config,
+    fn test_trash_path() {
+        let t = chrono::NaiveDate::from_ymd_opt(2024, 3, 15)
+            .and_then(|date| date.and_hms_opt(13, 5, 9))
+            .unwrap()
+            .and_utc()
+            .into();
+        assert_eq!(
+            trash_path(
+                Path::new("/home/user/refile"),
+                Path::new("/x/report.pdf"),
+                t
+            ),
+            PathBuf::from("/home/user/refile/.trash/report.pdf.20240315T130509")
         );
-        assert_eq!(dest, None);
+    }
+
+    #[test]
+    fn test_transliterate_to_ascii() {
+        assert_eq!(transliterate_to_ascii("Café Müller.pdf"), "Cafe Muller.pdf");
+        assert_eq!(
+            transliterate_to_ascii("Straße Æsir Œuvre"),
+            "Strasse AEsir OEuvre"
+        );
+        assert_eq!(transliterate_to_ascii("Łódź-2023.txt"), "Lodz-2023.txt");
+        assert_eq!(transliterate_to_ascii("日記.md"), "__.md");
+        assert_eq!(transliterate_to_ascii("plain.txt"), "plain.txt");
     }
 
     #[test]
@@ -546,15 +4601,15 @@ mod tests {
         let base = Path::new("/home/user/documents/file.txt");
 
         assert_eq!(
-            generate_unique_name(base, 1),
+            generate_unique_name(base, 1, false),
             PathBuf::from("/home/user/documents/file (1).txt")
         );
         assert_eq!(
-            generate_unique_name(base, 2),
+            generate_unique_name(base, 2, false),
             PathBuf::from("/home/user/documents/file (2).txt")
         );
         assert_eq!(
-            generate_unique_name(base, 42),
+            generate_unique_name(base, 42, false),
             PathBuf::from("/home/user/documents/file (42).txt")
         );
     }
@@ -564,11 +4619,11 @@ mod tests {
         let base = Path::new("/home/user/documents/my-directory");
 
         assert_eq!(
-            generate_unique_name(base, 1),
+            generate_unique_name(base, 1, false),
             PathBuf::from("/home/user/documents/my-directory (1)")
         );
         assert_eq!(
-            generate_unique_name(base, 5),
+            generate_unique_name(base, 5, false),
             PathBuf::from("/home/user/documents/my-directory (5)")
         );
     }
@@ -579,11 +4634,78 @@ mod tests {
 
         // Should only use the last extension
         assert_eq!(
-            generate_unique_name(base, 1),
+            generate_unique_name(base, 1, false),
             PathBuf::from("/home/user/archive.tar (1).gz")
         );
     }
 
+    #[test]
+    fn test_generate_unique_name_dotted_directory() {
+        let base = Path::new("/home/user/my.project.v2");
+
+        // Directories never split on dots, files keep their extension last
+        assert_eq!(
+            generate_unique_name(base, 1, true),
+            PathBuf::from("/home/user/my.project.v2 (1)")
+        );
+        assert_eq!(
+            generate_unique_name(base, 1, false),
+            PathBuf::from("/home/user/my.project (1).v2")
+        );
+    }
+
+    #[test]
+    fn test_parse_date_from_name() {
+        let iso = DateFormat::default();
+        assert_eq!(
+            parse_date_from_name("scan-2023-11-05.pdf", &iso),
+            Some((2023, 11, 5))
+        );
+        assert_eq!(
+            parse_date_from_name("2024-01-31", &iso),
+            Some((2024, 1, 31))
+        );
+        // First valid date wins, invalid months are skipped
+        assert_eq!(
+            parse_date_from_name("2023-13-01_2022-02-03.pdf", &iso),
+            Some((2022, 2, 3))
+        );
+        assert_eq!(parse_date_from_name("report.pdf", &iso), None);
+        assert_eq!(parse_date_from_name("20231105.pdf", &iso), None);
+        // Day-first dates are not guessed at without a format
+        assert_eq!(parse_date_from_name("scan-05-11-2023.pdf", &iso), None);
+    }
+
+    #[test]
+    fn test_parse_date_from_name_with_format() {
+        let dmy: DateFormat = "%d-%m-%Y".parse().unwrap();
+        assert_eq!(
+            parse_date_from_name("scan-05-11-2023.pdf", &dmy),
+            Some((2023, 11, 5))
+        );
+        let mdy: DateFormat = "%m-%d-%Y".parse().unwrap();
+        assert_eq!(
+            parse_date_from_name("scan-05-11-2023.pdf", &mdy),
+            Some((2023, 5, 11))
+        );
+        let compact: DateFormat = "%Y%m%d".parse().unwrap();
+        assert_eq!(
+            parse_date_from_name("IMG_20231105_1200.jpg", &compact),
+            Some((2023, 11, 5))
+        );
+        let short: DateFormat = "%d.%m.%y".parse().unwrap();
+        assert_eq!(parse_date_from_name("5.11.23", &short), None);
+        assert_eq!(
+            parse_date_from_name("05.11.23", &short),
+            Some((2023, 11, 5))
+        );
+
+        assert!("%Y-%m".parse::<DateFormat>().is_err());
+        assert!("%Y-%m-%d-%d".parse::<DateFormat>().is_err());
+        assert!("%Y-%b-%d".parse::<DateFormat>().is_err());
+        assert!("%Y-%m-%d%".parse::<DateFormat>().is_err());
+    }
+
     #[test]
     fn test_is_bucket_dir() {
         let config = default_config();
@@ -665,6 +4787,282 @@ mod tests {
         assert!(!paths_equal(path1, path3));
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_same_entry_does_not_follow_symlinks() {
+        let dir = env::temp_dir().join(format!("refile-same-entry-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        fs::write(&target, "content").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        assert!(same_entry(&target, &target));
+        assert!(same_entry(&link, &dir.join(".").join("link.txt")));
+        assert!(!same_entry(&link, &target));
+        assert!(paths_equal(&link, &target));
+        assert!(!same_entry(&target, &dir.join("missing.txt")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_dir_recursive_symlinks() {
+        let dir = env::temp_dir().join(format!("refile-copy-links-{}", std::process::id()));
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("file.txt"), "content").unwrap();
+        std::os::unix::fs::symlink("file.txt", src.join("link")).unwrap();
+        // Points back up the tree: copied as a link, it must not be traversed
+        std::os::unix::fs::symlink("..", src.join("sub/up")).unwrap();
+
+        let kept = dir.join("kept");
+        copy_dir_recursive(&src, &kept, false).unwrap();
+        assert_eq!(
+            fs::read_link(kept.join("link")).unwrap(),
+            Path::new("file.txt")
+        );
+        assert_eq!(fs::read_link(kept.join("sub/up")).unwrap(), Path::new(".."));
+
+        // Followed, the file link becomes a copy and the loop is refused,
+        // without leaving the partial copy behind
+        let followed = dir.join("followed");
+        let err = copy_dir_recursive(&src, &followed, true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!followed.exists());
+        fs::remove_file(src.join("sub/up")).unwrap();
+        copy_dir_recursive(&src, &followed, true).unwrap();
+        let link = fs::symlink_metadata(followed.join("link")).unwrap();
+        assert!(link.is_file());
+        assert_eq!(
+            fs::read_to_string(followed.join("link")).unwrap(),
+            "content"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_move_item_retries_transient_rename_errors() {
+        let dir = env::temp_dir().join(format!("refile-move-retry-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let from = dir.join("from.txt");
+        let to = dir.join("to.txt");
+        fs::write(&from, "content").unwrap();
+
+        // A busy destination is retried, not copied
+        let mut calls = 0;
+        let result = move_item_with(&from, &to, false, |from, to| {
+            calls += 1;
+            if calls < 3 {
+                Err(io::Error::from(io::ErrorKind::ResourceBusy))
+            } else {
+                fs::rename(from, to)
+            }
+        });
+        assert!(result.is_ok());
+        assert_eq!(calls, 3);
+        assert!(!from.exists() && to.exists());
+
+        // Other failures are returned without falling back to copy+delete
+        let mut calls = 0;
+        let result = move_item_with(&to, &from, false, |_, _| {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(calls, 1);
+        assert!(to.exists() && !from.exists());
+
+        // Only a cross-device failure copies
+        let result = move_item_with(&to, &from, false, |_, _| {
+            Err(io::Error::from(io::ErrorKind::CrossesDevices))
+        });
+        assert!(result.is_ok());
+        assert!(from.exists() && !to.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cross_device_move_keeps_metadata() {
+        let dir = env::temp_dir().join(format!("refile-move-metadata-{}", std::process::id()));
+        let from = dir.join("project");
+        let to = dir.join("moved");
+        fs::create_dir_all(from.join("sub")).unwrap();
+        fs::write(from.join("sub/notes.txt"), "content").unwrap();
+
+        let old = filetime::FileTime::from_unix_time(1_500_000_000, 0);
+        for path in [from.join("sub/notes.txt"), from.join("sub"), from.clone()] {
+            filetime::set_file_times(&path, old, old).unwrap();
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(from.join("sub"), fs::Permissions::from_mode(0o750)).unwrap();
+        }
+
+        let result = move_item_with(&from, &to, false, |_, _| {
+            Err(io::Error::from(io::ErrorKind::CrossesDevices))
+        });
+        assert!(result.is_ok());
+        assert!(!from.exists());
+
+        for path in [to.join("sub/notes.txt"), to.join("sub"), to.clone()] {
+            let meta = fs::metadata(&path).unwrap();
+            assert_eq!(filetime::FileTime::from_last_modification_time(&meta), old);
+            assert_eq!(filetime::FileTime::from_last_access_time(&meta), old);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(to.join("sub")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o750);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_newest_items_breaks_ties_by_name() {
+        let dir = env::temp_dir().join(format!("refile-newest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let older = filetime::FileTime::from_unix_time(1_500_000_000, 0);
+        let newer = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+        let mut items = Vec::new();
+        for (name, mtime) in [("c.txt", newer), ("b.txt", newer), ("a.txt", older)] {
+            let path = dir.join(name);
+            fs::write(&path, "content").unwrap();
+            filetime::set_file_mtime(&path, mtime).unwrap();
+            items.push(path);
+        }
+        items.push(dir.join("missing.txt"));
+
+        let config = default_config();
+        assert_eq!(
+            newest_items(&items, 1, &config),
+            HashSet::from([dir.join("b.txt")])
+        );
+        assert_eq!(
+            newest_items(&items, 2, &config),
+            HashSet::from([dir.join("b.txt"), dir.join("c.txt")])
+        );
+        assert_eq!(newest_items(&items, 10, &config).len(), 3);
+        assert!(newest_items(&items, 0, &config).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("plain.txt"), "plain.txt");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b.txt"), "\"a,b.txt\"");
+        assert_eq!(csv_field("say \"hi\".txt"), "\"say \"\"hi\"\".txt\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_timestamp_selects_bucket() {
+        let dir = env::temp_dir().join(format!("refile-timestamp-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file.txt");
+        fs::write(&file, "content").unwrap();
+
+        // Modified long ago, read yesterday
+        let day = 24 * 3600;
+        let now = SystemTime::now();
+        filetime::set_file_times(
+            &file,
+            filetime::FileTime::from_system_time(now - Duration::from_secs(day)),
+            filetime::FileTime::from_system_time(now - Duration::from_secs(100 * day)),
+        )
+        .unwrap();
+
+        let bucket_for = |timestamp| {
+            let mut config = default_config();
+            config.set_timestamp(timestamp);
+            let age = get_file_age(&file, &config).unwrap();
+            pick_bucket(age, 0, &config).unwrap().name().to_string()
+        };
+        assert_eq!(bucket_for(Timestamp::Mtime), "old-stuff");
+        assert_eq!(bucket_for(Timestamp::Atime), "last-week");
+        // Setting the times just changed the inode
+        assert_eq!(bucket_for(Timestamp::Ctime), "last-week");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_directory_size_nested() {
+        let dir = env::temp_dir().join(format!("refile-dir-size-{}", std::process::id()));
+        fs::create_dir_all(dir.join("a/b")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::write(dir.join("top.txt"), "12345").unwrap();
+        fs::write(dir.join("a/mid.txt"), "123").unwrap();
+        fs::write(dir.join("a/b/deep.txt"), "1234567").unwrap();
+
+        // Only file contents count, not the directories themselves
+        assert_eq!(directory_size(&dir).unwrap(), 15);
+        assert_eq!(item_size(&dir.join("a")).unwrap(), 10);
+        assert_eq!(directory_size(&dir.join("empty")).unwrap(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_source_stats_empty_files() {
+        let dir = env::temp_dir().join(format!("refile-empty-stats-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let items = vec![dir.join("full.txt"), dir.join("empty.txt")];
+        fs::write(&items[0], "content").unwrap();
+        fs::write(&items[1], "").unwrap();
+
+        let config = default_config();
+        let count = |include_empty| {
+            let stats = compute_source_stats(&items, &config, include_empty);
+            (stats.buckets["last-week"]["count"].clone(), stats.skipped)
+        };
+        assert_eq!(count(false), (1.into(), 0));
+        assert_eq!(count(true), (2.into(), 0));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recursion_depth_limits_collected_files() {
+        let dir = env::temp_dir().join(format!("refile-depth-{}", std::process::id()));
+        fs::create_dir_all(dir.join("a/b/c")).unwrap();
+        for file in ["top.txt", "a/one.txt", "a/b/two.txt", "a/b/c/three.txt"] {
+            fs::write(dir.join(file), "content").unwrap();
+        }
+
+        let collected = |depth| {
+            let mut items = collect_items_to_process(
+                &dir,
+                &dir.join("refile"),
+                &default_config(),
+                true,
+                Some(depth),
+                false,
+                false,
+            )
+            .unwrap();
+            items.sort();
+            items
+                .iter()
+                .map(|p| p.strip_prefix(&dir).unwrap().to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(collected(0), ["top.txt"]);
+        assert_eq!(collected(1), ["a/one.txt", "top.txt"]);
+        assert_eq!(collected(2), ["a/b/two.txt", "a/one.txt", "top.txt"]);
+        assert_eq!(collected(usize::MAX).len(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_is_protected_directory_root() {
         // Root directory should be protected
@@ -673,12 +5071,31 @@ mod tests {
 
     #[test]
     fn test_is_protected_directory_home() {
-        // Home directory should be protected if HOME is set
-        if let Ok(home) = env::var("HOME") {
-            assert!(is_protected_directory(Path::new(&home)));
+        // Home directory should be protected if there is one
+        if let Some(home) = dirs::home_dir() {
+            assert!(is_protected_directory(&home));
         }
     }
 
+    #[test]
+    #[cfg(windows)]
+    fn test_is_protected_directory_windows_drive_root() {
+        // Drive roots and the directories directly in them are protected
+        assert!(is_protected_directory(Path::new("C:\\")));
+        assert!(is_protected_directory(Path::new("C:\\Windows")));
+        assert!(is_protected_directory(Path::new("C:\\Users")));
+        assert!(!is_protected_directory(Path::new("C:\\Windows\\Temp")));
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn test_is_protected_directory_windows_profile() {
+        // The user profile is the home directory, wherever USERPROFILE points
+        let profile = dirs::home_dir().expect("Windows always has a user profile");
+        assert!(is_protected_directory(&profile));
+        assert!(!is_protected_directory(&profile.join("Downloads")));
+    }
+
     #[test]
     fn test_is_protected_directory_top_level() {
         // Top-level directories (direct children of root) should be protected
@@ -696,25 +5113,107 @@ mod tests {
         assert!(!is_protected_directory(Path::new("/usr/local")));
     }
 
+    #[test]
+    fn test_is_protected_directory_relative_not_protected() {
+        // A relative path that can't be resolved is not mistaken for a root
+        assert!(!is_protected_directory(Path::new("no-such-dir")));
+        assert!(!is_protected_directory(Path::new("no-such-dir/child")));
+    }
+
+    #[test]
+    fn test_is_system_file() {
+        assert!(is_system_file(Path::new("/src/.DS_Store")));
+        assert!(is_system_file(Path::new("/src/Thumbs.db")));
+        assert!(is_system_file(Path::new("/src/THUMBS.DB")));
+        assert!(is_system_file(Path::new("desktop.ini")));
+        assert!(!is_system_file(Path::new("/src/notes.DS_Store")));
+        assert!(!is_system_file(Path::new("/src/report.pdf")));
+    }
+
+    #[test]
+    fn test_has_extension() {
+        let extensions = vec!["pdf".to_string(), ".JPG".to_string()];
+        assert!(has_extension(Path::new("/src/report.pdf"), &extensions));
+        assert!(has_extension(Path::new("/src/report.PDF"), &extensions));
+        assert!(has_extension(Path::new("/src/photo.jpg"), &extensions));
+        assert!(!has_extension(Path::new("/src/notes.txt"), &extensions));
+        assert!(!has_extension(Path::new("/src/pdf"), &extensions));
+    }
+
+    #[test]
+    fn test_parse_path_list() {
+        let contents = "# curated cleanup\n\n  a.txt  \n/abs/b.txt\n   # indented comment\n";
+        assert_eq!(
+            parse_path_list(contents, Path::new("/src")),
+            vec![PathBuf::from("/src/a.txt"), PathBuf::from("/abs/b.txt")]
+        );
+    }
+
+    #[test]
+    fn test_parse_move_answer() {
+        assert_eq!(parse_move_answer("y\n"), MoveAnswer::Yes);
+        assert_eq!(parse_move_answer("Yes\n"), MoveAnswer::Yes);
+        assert_eq!(parse_move_answer("a\n"), MoveAnswer::All);
+        assert_eq!(parse_move_answer("q\n"), MoveAnswer::Quit);
+        assert_eq!(parse_move_answer("n\n"), MoveAnswer::No);
+        assert_eq!(parse_move_answer("maybe\n"), MoveAnswer::No);
+        // End of input
+        assert_eq!(parse_move_answer(""), MoveAnswer::No);
+    }
+
+    #[test]
+    fn test_plan_action_skips_move_into_own_subtree() {
+        let dir = env::temp_dir().join(format!("refile-subtree-{}", std::process::id()));
+        let docs = dir.join("docs");
+        fs::create_dir_all(&docs).unwrap();
+        let cfg = test_args(dir.to_str().unwrap(), false);
+
+        // With the target inside docs, docs would land in docs/refile/<bucket>/docs
+        let action = plan_action(
+            &docs,
+            &docs,
+            &cfg,
+            &default_config(),
+            &mut PlanState::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            action,
+            FileAction::Skip { ref reason, .. } if reason == "destination is inside source"
+        ));
+
+        // A file next to it is planned as usual
+        fs::write(dir.join("file.txt"), "content").unwrap();
+        let action = plan_action(
+            &dir.join("file.txt"),
+            &docs,
+            &cfg,
+            &default_config(),
+            &mut PlanState::default(),
+        )
+        .unwrap();
+        assert!(matches!(action, FileAction::Move { .. }));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn test_plan_action_rejects_protected_dir_by_default() {
         // Test that protected directories are rejected when allow_dangerous_directories is false
-        let cfg = RefileArgs {
-            source_dir: PathBuf::from("/tmp"),
-            target_dir: None,
-            dry_run: false,
-            allow_rename: false,
-            allow_dangerous_directories: false,
-            base_folder: None,
-            buckets: None,
-        };
+        let cfg = test_args("/tmp", false);
 
         let bucket_config = default_config();
         let target = Path::new("/tmp");
         let protected_path = Path::new("/tmp"); // /tmp is a protected top-level directory
 
         // This should return an error because /tmp is protected and flag is false
-        let result = plan_action(protected_path, target, &cfg, &bucket_config);
+        let result = plan_action(
+            protected_path,
+            target,
+            &cfg,
+            &bucket_config,
+            &mut PlanState::default(),
+        );
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
     }
@@ -722,15 +5221,7 @@ mod tests {
     #[test]
     fn test_plan_action_allows_protected_dir_with_flag() {
         // Test that protected directories are allowed when allow_dangerous_directories is true
-        let cfg = RefileArgs {
-            source_dir: PathBuf::from("/tmp"),
-            target_dir: None,
-            dry_run: false,
-            allow_rename: false,
-            allow_dangerous_directories: true,
-            base_folder: None,
-            buckets: None,
-        };
+        let cfg = test_args("/tmp", true);
 
         let bucket_config = default_config();
         let target = Path::new("/tmp");
@@ -738,7 +5229,13 @@ mod tests {
 
         // This should NOT return a permission denied error because the flag is true
         // It may return other errors or succeed, but NOT PermissionDenied for protected dir
-        let result = plan_action(protected_path, target, &cfg, &bucket_config);
+        let result = plan_action(
+            protected_path,
+            target,
+            &cfg,
+            &bucket_config,
+            &mut PlanState::default(),
+        );
 
         // If there's an error, it should not be PermissionDenied
         if let Err(e) = result {
@@ -753,25 +5250,9 @@ mod tests {
     #[test]
     fn test_plan_action_allows_nonprotected_dirs_regardless_of_flag() {
         // Test that non-protected directories work with both flag values
-        let cfg_false = RefileArgs {
-            source_dir: PathBuf::from("/tmp/test"),
-            target_dir: None,
-            dry_run: false,
-            allow_rename: false,
-            allow_dangerous_directories: false,
-            base_folder: None,
-            buckets: None,
-        };
+        let cfg_false = test_args("/tmp/test", false);
 
-        let cfg_true = RefileArgs {
-            source_dir: PathBuf::from("/tmp/test"),
-            target_dir: None,
-            dry_run: false,
-            allow_rename: false,
-            allow_dangerous_directories: true,
-            base_folder: None,
-            buckets: None,
-        };
+        let cfg_true = test_args("/tmp/test", true);
 
         let bucket_config = default_config();
 
@@ -781,8 +5262,20 @@ mod tests {
 
         // Both should NOT return PermissionDenied for protected directories
         // (they may fail for other reasons like file not found, but not for being protected)
-        let result_false = plan_action(non_protected, target, &cfg_false, &bucket_config);
-        let result_true = plan_action(non_protected, target, &cfg_true, &bucket_config);
+        let result_false = plan_action(
+            non_protected,
+            target,
+            &cfg_false,
+            &bucket_config,
+            &mut PlanState::default(),
+        );
+        let result_true = plan_action(
+            non_protected,
+            target,
+            &cfg_true,
+            &bucket_config,
+            &mut PlanState::default(),
+        );
 
         // Neither should fail with PermissionDenied for protected directory
         if let Err(e) = result_false {
@@ -801,4 +5294,185 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_normalize_lexically() {
+        assert_eq!(
+            normalize_lexically(Path::new("/a/b/./c/../d")),
+            PathBuf::from("/a/b/d")
+        );
+        assert_eq!(
+            normalize_lexically(Path::new("../a/../../b")),
+            PathBuf::from("../../b")
+        );
+        assert_eq!(normalize_lexically(Path::new("/..")), PathBuf::from("/"));
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("/a/b"), Path::new("/a/b/c.txt")),
+            PathBuf::from("c.txt")
+        );
+        assert_eq!(
+            relative_path(Path::new("/a/b/refile/old-stuff"), Path::new("/a/b/c.txt")),
+            PathBuf::from("../../c.txt")
+        );
+        assert_eq!(
+            relative_path(Path::new("/a/x"), Path::new("/a/y/z")),
+            PathBuf::from("../y/z")
+        );
+        assert_eq!(
+            relative_path(Path::new("/a"), Path::new("/a")),
+            PathBuf::from(".")
+        );
+    }
+
+    #[test]
+    fn test_overflow_dirs() {
+        let bucket = Path::new("/src/refile/old-stuff");
+        assert_eq!(overflow_dir(bucket, 1), bucket);
+        assert_eq!(
+            overflow_dir(bucket, 3),
+            PathBuf::from("/src/refile/old-stuff-3")
+        );
+
+        assert_eq!(overflow_index("old-stuff", "old-stuff"), Some(1));
+        assert_eq!(overflow_index("old-stuff-2", "old-stuff"), Some(2));
+        assert_eq!(overflow_index("old-stuff-1", "old-stuff"), None);
+        assert_eq!(overflow_index("old-stuff-x", "old-stuff"), None);
+        assert_eq!(overflow_index("old-stuff-+2", "old-stuff"), None);
+
+        // Overflow directories are only buckets when rolling over
+        let mut config = default_config();
+        assert!(!is_bucket_dir("/src/refile/old-stuff-2", &config));
+        config.set_max_files(NonZeroUsize::new(2));
+        assert!(is_bucket_dir("/src/refile/old-stuff-2", &config));
+        assert!(!is_bucket_dir("/src/refile/old-stuffing", &config));
+    }
+
+    #[test]
+    fn test_run_grouped_respects_per_group_cap() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let active = [AtomicUsize::new(0), AtomicUsize::new(0)];
+        let peak = [AtomicUsize::new(0), AtomicUsize::new(0)];
+        let done = AtomicUsize::new(0);
+        let items: Vec<_> = (0..24).map(|i| (i % 2, i % 2)).collect();
+
+        schedule::run_grouped(items, 3, |group| {
+            let now = active[group].fetch_add(1, Ordering::SeqCst) + 1;
+            peak[group].fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(5));
+            active[group].fetch_sub(1, Ordering::SeqCst);
+            done.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(done.load(Ordering::SeqCst), 24);
+        for peak in &peak {
+            let peak = peak.load(Ordering::SeqCst);
+            assert!(
+                (1..=3).contains(&peak),
+                "peak concurrency {peak} exceeds cap"
+            );
+        }
+    }
+
+    #[test]
+    fn test_run_grouped_returns_first_error() {
+        let items: Vec<_> = (0..10).map(|i| ((), i)).collect();
+        let err = schedule::run_grouped(items, 1, |i| {
+            if i == 3 {
+                Err(io::Error::other("boom"))
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "boom");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_device_id_uses_nearest_existing_ancestor() {
+        let dir = env::temp_dir();
+        let id = device_id(&dir);
+        assert!(id.is_some());
+        assert_eq!(
+            device_id(&dir.join("refile-missing/old-stuff/file.txt")),
+            id
+        );
+    }
+
+    #[test]
+    fn test_age_anchor_resolve() {
+        let now = UNIX_EPOCH + Duration::from_secs(19_000 * 24 * 3600 + 3723);
+        let utc: CalendarZone = "utc".parse().unwrap();
+        let ahead: CalendarZone = "+02:00".parse().unwrap();
+        let behind: CalendarZone = "-05:00".parse().unwrap();
+        assert_eq!(
+            AgeAnchor::Midnight.resolve(now, utc),
+            UNIX_EPOCH + Duration::from_hours(19_000 * 24)
+        );
+        assert_eq!(
+            AgeAnchor::Midnight.resolve(now, ahead),
+            UNIX_EPOCH + Duration::from_hours(19_000 * 24 - 2)
+        );
+        assert_eq!(
+            AgeAnchor::Midnight.resolve(now, behind),
+            UNIX_EPOCH + Duration::from_hours(18_999 * 24 + 5)
+        );
+        assert_eq!(AgeAnchor::RunStart.resolve(now, utc), now);
+    }
+
+    #[test]
+    fn test_growth_report_deltas() {
+        let stats = |count, bytes| BucketStats { count, bytes };
+        let previous = Manifest {
+            created: 100,
+            buckets: [
+                ("last-week".to_string(), stats(3, 300)),
+                ("old-stuff".to_string(), stats(1, 50)),
+            ]
+            .into(),
+        };
+        let current = Manifest {
+            created: 160,
+            buckets: [
+                ("last-week".to_string(), stats(1, 100)),
+                ("current-month".to_string(), stats(2, 20)),
+                ("old-stuff".to_string(), stats(1, 50)),
+            ]
+            .into(),
+        };
+
+        let report = manifest::growth_report(&previous, &current, &default_config(), false);
+        assert!(report.contains("(60s ago)"));
+        assert!(report.contains("last-week: -2 files, -200 bytes (1 files, 100 bytes)"));
+        assert!(report.contains("current-month: +2 files, +20 bytes (2 files, 20 bytes)"));
+        assert!(report.contains("last-months: +0 files, +0 bytes"));
+        assert!(report.contains("old-stuff: +0 files, +0 bytes (1 files, 50 bytes)"));
+
+        let report = manifest::growth_report(&previous, &current, &default_config(), true);
+        assert!(report.contains("last-week: -2 files, -200 B (1 files, 100 B)"));
+    }
+
+    #[test]
+    fn test_progress_formatting() {
+        use output::{format_bytes, format_size, format_size_delta};
+        use progress::format_eta;
+
+        assert_eq!(format_bytes(12), "12 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+        assert_eq!(format_size(1536, true), "1.5 KiB");
+        assert_eq!(format_size(1536, false), "1536 bytes");
+        assert_eq!(format_size_delta(-1536, true), "-1.5 KiB");
+        assert_eq!(format_size_delta(0, false), "+0 bytes");
+        assert_eq!(format_eta(Duration::from_secs(7)), "7s");
+        assert_eq!(format_eta(Duration::from_secs(185)), "3m05s");
+        assert_eq!(format_eta(Duration::from_mins(62)), "1h02m");
+    }
 }
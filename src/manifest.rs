@@ -0,0 +1,173 @@
+//! Per-bucket archive manifest.
+//!
+//! With `--report-growth`, a snapshot of every bucket's file count and size is
+//! stored in the base folder at the end of the run. The next run compares its
+//! own snapshot against the stored one to report how each bucket grew.
+
+use crate::config::{self, BucketConfig};
+use crate::core::bucket_of_dir;
+use crate::filesystem::{bucket_dirs, item_size};
+use crate::output::{format_size, format_size_delta};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// File name of the manifest inside the base folder.
+pub const MANIFEST_NAME: &str = ".refile-manifest.json";
+
+/// Aggregate count and size of the items falling into one bucket.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BucketStats {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+/// Snapshot of the archived contents of every bucket.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// When the snapshot was taken, in seconds since the Unix epoch
+    pub created: u64,
+    /// Totals per bucket name, overflow directories (and file types) included
+    pub buckets: BTreeMap<String, BucketStats>,
+}
+
+impl Manifest {
+    /// Takes a snapshot of the bucket directories under the base folder.
+    ///
+    /// # Arguments
+    ///
+    /// * `refile_base` - Path to the refile base directory
+    /// * `bucket_config` - The bucket configuration to use
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a bucket directory or one of its items cannot be read.
+    pub fn snapshot(refile_base: &Path, bucket_config: &BucketConfig) -> io::Result<Self> {
+        let mut buckets: BTreeMap<String, BucketStats> = bucket_config
+            .all_buckets()
+            .into_iter()
+            .map(|bucket| (bucket.name().to_string(), BucketStats::default()))
+            .collect();
+
+        for dir in bucket_dirs(refile_base, bucket_config)? {
+            let Some(stats) =
+                bucket_of_dir(&dir, bucket_config).and_then(|b| buckets.get_mut(b.name()))
+            else {
+                continue;
+            };
+            for entry in fs::read_dir(&dir)? {
+                stats.count += 1;
+                stats.bytes += item_size(&entry?.path())?;
+            }
+        }
+
+        let created = config::current_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Ok(Self { created, buckets })
+    }
+
+    /// Loads the manifest stored in a base folder.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(None)` if no manifest has been stored yet
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the manifest exists but cannot be read or parsed.
+    pub fn load(refile_base: &Path) -> io::Result<Option<Self>> {
+        let path = manifest_path(refile_base);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_str(&contents).map(Some).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse manifest {}: {e}", path.display()),
+            )
+        })
+    }
+
+    /// Stores the manifest in a base folder, replacing any previous one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the manifest cannot be written.
+    pub fn save(&self, refile_base: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(manifest_path(refile_base), json + "\n")
+    }
+}
+
+/// Returns the path of the manifest inside a base folder.
+pub fn manifest_path(refile_base: &Path) -> PathBuf {
+    refile_base.join(MANIFEST_NAME)
+}
+
+/// Returns true if `path` is the manifest of the given base folder.
+pub fn is_manifest(path: &Path, refile_base: &Path) -> bool {
+    path == manifest_path(refile_base)
+}
+
+/// Formats the per-bucket change between two snapshots.
+///
+/// Buckets are listed in configuration order. Buckets missing from the
+/// previous snapshot count as empty.
+///
+/// # Arguments
+///
+/// * `previous` - The stored snapshot
+/// * `current` - The snapshot taken at the end of this run
+/// * `bucket_config` - The bucket configuration (for ordering)
+/// * `humanize` - Whether to format sizes with binary units instead of bytes
+pub fn growth_report(
+    previous: &Manifest,
+    current: &Manifest,
+    bucket_config: &BucketConfig,
+    humanize: bool,
+) -> String {
+    let mut report = String::new();
+    writeln!(
+        report,
+        "Bucket growth since last run ({}s ago):",
+        current.created.saturating_sub(previous.created)
+    )
+    .expect("Writing to String should not fail");
+
+    for bucket in bucket_config.all_buckets() {
+        let before = previous
+            .buckets
+            .get(bucket.name())
+            .copied()
+            .unwrap_or_default();
+        let after = current
+            .buckets
+            .get(bucket.name())
+            .copied()
+            .unwrap_or_default();
+        writeln!(
+            report,
+            "  {}: {:+} files, {} ({} files, {})",
+            bucket_config.label(bucket.name()),
+            delta(before.count, after.count),
+            format_size_delta(delta(before.bytes, after.bytes), humanize),
+            after.count,
+            format_size(after.bytes, humanize)
+        )
+        .expect("Writing to String should not fail");
+    }
+
+    report
+}
+
+/// Returns `after - before` as a signed difference.
+fn delta(before: u64, after: u64) -> i128 {
+    i128::from(after) - i128::from(before)
+}
@@ -0,0 +1,374 @@
+//! Run output routing.
+//!
+//! Human-readable run output goes through the `outln!` and `errln!` macros
+//! rather than raw `println!`/`eprintln!`, so every line can also be appended
+//! to a log file (`--output-file`) that is rotated by size.
+//!
+//! With `--output json`, stdout is reserved for the JSON result and `outln!`
+//! lines go to stderr instead.
+//!
+//! With `--syslog`, every line is also sent to the system logger: `outln!`
+//! lines at info level and `errln!` lines at error level.
+//!
+//! With `--quiet`, `outln!` lines are kept off the terminal (they still go to
+//! the log file and the system logger), so only errors and warnings show.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// The log file shared by all output, if `--output-file` was given.
+static LOG_FILE: OnceLock<Mutex<RotatingWriter>> = OnceLock::new();
+
+/// Whether stdout is kept free of human output.
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// The run's `Verbosity`, as its discriminant.
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// How much human output a run prints.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only errors and warnings (`--quiet`)
+    Quiet,
+    /// Moves, summaries, errors and warnings
+    #[default]
+    Normal,
+    /// Also the items left in place and why (`--verbose`)
+    Verbose,
+}
+
+/// The system logger shared by all output, if `--syslog` was given.
+#[cfg(unix)]
+static SYSLOG: OnceLock<Mutex<syslog::Logger<syslog::LoggerBackend, syslog::Formatter3164>>> =
+    OnceLock::new();
+
+/// Prints a line to stdout and appends it to the log file.
+macro_rules! outln {
+    () => {
+        $crate::output::write_stdout(format_args!(""))
+    };
+    ($($arg:tt)*) => {
+        $crate::output::write_stdout(format_args!($($arg)*))
+    };
+}
+
+/// Prints a line to stderr and appends it to the log file.
+macro_rules! errln {
+    ($($arg:tt)*) => {
+        $crate::output::write_stderr(format_args!($($arg)*))
+    };
+}
+
+/// Like `outln!`, but also printed with `--quiet`, for output asked for
+/// explicitly (such as the `--summary` tally).
+macro_rules! reportln {
+    ($($arg:tt)*) => {
+        $crate::output::write_report(format_args!($($arg)*))
+    };
+}
+
+pub(crate) use {errln, outln, reportln};
+
+/// Opens the log file that all subsequent output is appended to.
+///
+/// # Arguments
+///
+/// * `path` - The log file path
+/// * `max_size` - Rotate once the file would grow beyond this many bytes
+/// * `keep` - Number of rotated files to keep (`<path>.1` is the newest)
+///
+/// # Errors
+///
+/// Returns an error if the log file cannot be opened, or if a log file was
+/// already initialized.
+pub fn init_log_file(path: &Path, max_size: Option<u64>, keep: usize) -> io::Result<()> {
+    let writer = RotatingWriter::open(path, max_size, keep)?;
+    LOG_FILE
+        .set(Mutex::new(writer))
+        .map_err(|_| io::Error::other("Log file already initialized"))
+}
+
+/// Connects to the system logger that all subsequent output is sent to.
+///
+/// # Errors
+///
+/// Returns an error if no system logger can be reached, or if the system
+/// logger was already initialized.
+#[cfg(unix)]
+pub fn init_syslog() -> io::Result<()> {
+    let formatter = syslog::Formatter3164 {
+        facility: syslog::Facility::LOG_USER,
+        hostname: None,
+        process: env!("CARGO_PKG_NAME").to_string(),
+        pid: std::process::id(),
+    };
+    let logger = syslog::unix(formatter)
+        .map_err(|e| io::Error::other(format!("no system logger reachable ({e})")))?;
+    SYSLOG
+        .set(Mutex::new(logger))
+        .map_err(|_| io::Error::other("System logger already initialized"))
+}
+
+/// Connects to the system logger that all subsequent output is sent to.
+///
+/// # Errors
+///
+/// Always: there is no system logger to send to on this platform.
+#[cfg(not(unix))]
+pub fn init_syslog() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--syslog is only supported on Unix",
+    ))
+}
+
+/// Sets how much human output all subsequent output prints.
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+/// Sends all subsequent `outln!` lines to stderr, keeping stdout for
+/// machine-readable output.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+/// Returns true if `path` is the log file or one of its rotations.
+///
+/// Directories are compared after resolving symlinks and `..`, so a relative
+/// `--output-file` is recognised too.
+///
+/// # Arguments
+///
+/// * `path` - The path to check
+/// * `log` - The configured `--output-file` path
+pub fn is_log_file(path: &Path, log: &Path) -> bool {
+    let (Some(name), Some(log_name)) = (
+        path.file_name().and_then(|n| n.to_str()),
+        log.file_name().and_then(|n| n.to_str()),
+    ) else {
+        return false;
+    };
+    if canonical_parent(path) != canonical_parent(log) {
+        return false;
+    }
+
+    name == log_name
+        || name
+            .strip_prefix(log_name)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Returns the canonicalized directory containing `path`, or the lexical
+/// parent if it cannot be resolved.
+fn canonical_parent(path: &Path) -> PathBuf {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::canonicalize(parent).unwrap_or_else(|_| parent.to_path_buf())
+}
+
+/// Formats a size for human output: `1.5 KiB` when humanized, otherwise
+/// the raw `1536 bytes`.
+pub fn format_size(bytes: u64, humanize: bool) -> String {
+    if humanize {
+        format_bytes(bytes)
+    } else {
+        format!("{bytes} bytes")
+    }
+}
+
+/// Formats a signed size difference, e.g. `+1.5 KiB` or `-200 bytes`.
+pub fn format_size_delta(delta: i128, humanize: bool) -> String {
+    let sign = if delta < 0 { '-' } else { '+' };
+    let magnitude = u64::try_from(delta.unsigned_abs()).unwrap_or(u64::MAX);
+    format!("{sign}{}", format_size(magnitude, humanize))
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 MiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    #[allow(clippy::cast_precision_loss)]
+    format_bytes_f64(bytes as f64)
+}
+
+/// Formats a (possibly fractional) byte count, such as a rate.
+pub fn format_bytes_f64(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{value:.0} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Backs `outln!`; use the macro instead.
+pub fn write_stdout(args: fmt::Arguments) {
+    if VERBOSITY.load(Ordering::Relaxed) == Verbosity::Quiet as u8 {
+        // Kept off the terminal only
+        log_line(args);
+        syslog_line(args, false);
+    } else {
+        write_report(args);
+    }
+}
+
+/// Backs `reportln!`; use the macro instead.
+pub fn write_report(args: fmt::Arguments) {
+    if STDOUT_RESERVED.load(Ordering::Relaxed) {
+        eprintln!("{args}");
+    } else {
+        println!("{args}");
+    }
+    log_line(args);
+    syslog_line(args, false);
+}
+
+/// Backs `errln!`; use the macro instead.
+pub fn write_stderr(args: fmt::Arguments) {
+    eprintln!("{args}");
+    log_line(args);
+    syslog_line(args, true);
+}
+
+/// Appends a line to the log file, if any.
+///
+/// Logging failures are reported on stderr but never abort the run: the log
+/// is a copy of the output, not the source of truth.
+fn log_line(args: fmt::Arguments) {
+    let Some(log) = LOG_FILE.get() else {
+        return;
+    };
+    let mut writer = log
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    if let Err(e) = writer.write_line(&args.to_string()) {
+        eprintln!("Failed to write log file {}: {e}", writer.path.display());
+    }
+}
+
+/// Sends a line to the system logger, if any, at error or info level.
+///
+/// Like the log file, failures are reported on stderr but never abort the run.
+#[cfg(unix)]
+fn syslog_line(args: fmt::Arguments, error: bool) {
+    let Some(logger) = SYSLOG.get() else {
+        return;
+    };
+    let mut logger = logger
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let line = args.to_string();
+    let result = if error {
+        logger.err(line)
+    } else {
+        logger.info(line)
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to write to the system logger: {e}");
+    }
+}
+
+#[cfg(not(unix))]
+fn syslog_line(_args: fmt::Arguments, _error: bool) {}
+
+/// Line-oriented file writer with size-based rotation.
+///
+/// When appending a line would push the file past `max_size`, the file is
+/// renamed to `<path>.1` (shifting older rotations up to `<path>.<keep>`,
+/// dropping the oldest) and a fresh file is started.
+#[derive(Debug)]
+pub struct RotatingWriter {
+    path: PathBuf,
+    max_size: Option<u64>,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingWriter {
+    /// Opens (or creates) the log file in append mode.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub fn open(path: &Path, max_size: Option<u64>, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    /// Appends a line, rotating first if it would exceed the size limit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if rotating or writing fails.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if let Some(max) = self.max_size
+            && self.size > 0
+            && self.size + len > max
+        {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{line}")?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Shifts `<path>.N` to `<path>.N+1`, moves the current file to `<path>.1`,
+    /// and reopens an empty file.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+            self.size = 0;
+            return Ok(());
+        }
+
+        let oldest = self.rotated_path(self.keep);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for n in (1..self.keep).rev() {
+            let from = self.rotated_path(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Returns `<path>.<n>`.
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+}
@@ -0,0 +1,98 @@
+//! Glob pattern filters.
+//!
+//! `--exclude` patterns leave matching items where they are, and with
+//! `--include` patterns only matching items are organized. An item must match
+//! an include pattern (if there are any) and no exclude pattern to be moved.
+//!
+//! A pattern is matched against an item's file name and against its path
+//! relative to the source directory, and also covers everything inside a
+//! matching directory.
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Filter built from the `--include` and `--exclude` patterns of a run.
+#[derive(Debug)]
+pub struct PatternFilter {
+    root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PatternFilter {
+    /// Builds the filter for the given patterns.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The directory relative paths are taken from (usually the
+    ///   source directory)
+    /// * `include` - The `--include` patterns
+    /// * `exclude` - The `--exclude` patterns
+    ///
+    /// # Returns
+    ///
+    /// `Ok(None)` if there are no patterns
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first invalid pattern.
+    pub fn build(root: &Path, include: &[String], exclude: &[String]) -> io::Result<Option<Self>> {
+        if include.is_empty() && exclude.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            root: root.to_path_buf(),
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(glob_set(include, "--include")?)
+            },
+            exclude: glob_set(exclude, "--exclude")?,
+        }))
+    }
+
+    /// Returns why the patterns leave an item alone, if they do.
+    ///
+    /// Exclusion wins: an item matching both an include and an exclude
+    /// pattern is excluded.
+    pub fn skip_reason(&self, path: &Path) -> Option<&'static str> {
+        if self.matches(&self.exclude, path) {
+            Some("excluded by pattern")
+        } else if self
+            .include
+            .as_ref()
+            .is_some_and(|set| !self.matches(set, path))
+        {
+            Some("not included by pattern")
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the path, or a directory it is in below the root,
+    /// matches a pattern of the set.
+    fn matches(&self, set: &GlobSet, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        relative
+            .ancestors()
+            .filter(|p| !p.as_os_str().is_empty())
+            .any(|p| set.is_match(p) || p.file_name().is_some_and(|n| set.is_match(n)))
+    }
+}
+
+/// Compiles a list of glob patterns into one set.
+fn glob_set(patterns: &[String], flag: &str) -> io::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid {flag} pattern '{pattern}': {e}"),
+            )
+        })?;
+        builder.add(glob);
+    }
+    builder.build().map_err(io::Error::other)
+}
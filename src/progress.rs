@@ -0,0 +1,90 @@
+//! Progress reporting for long runs.
+//!
+//! With `--progress-interval`, the execute phase prints a status line to
+//! stderr at most once per interval: moves and bytes done, the throughput so
+//! far, and an ETA for the remaining bytes at that throughput.
+
+use crate::output::{errln, format_bytes, format_bytes_f64};
+use std::time::{Duration, Instant};
+
+/// Running totals of the execute phase.
+#[derive(Debug)]
+pub struct Progress {
+    interval: Duration,
+    started: Instant,
+    last_report: Option<Instant>,
+    total_moves: usize,
+    total_bytes: u64,
+    done_moves: usize,
+    done_bytes: u64,
+}
+
+impl Progress {
+    /// Starts tracking a run of `total_moves` moves totalling `total_bytes`.
+    pub fn new(interval: Duration, total_moves: usize, total_bytes: u64) -> Self {
+        Self {
+            interval,
+            started: Instant::now(),
+            last_report: None,
+            total_moves,
+            total_bytes,
+            done_moves: 0,
+            done_bytes: 0,
+        }
+    }
+
+    /// Records a completed move of `bytes` bytes.
+    ///
+    /// Reports after the first move, whenever `interval` has passed since the
+    /// last report, and after the last move.
+    pub fn record(&mut self, bytes: u64) {
+        self.done_moves += 1;
+        self.done_bytes += bytes;
+
+        let now = Instant::now();
+        let due = self
+            .last_report
+            .is_none_or(|last| now.duration_since(last) >= self.interval);
+        if due || self.done_moves == self.total_moves {
+            self.last_report = Some(now);
+            errln!("{}", self.status(now.duration_since(self.started)));
+        }
+    }
+
+    /// Formats the status line for the given elapsed time.
+    fn status(&self, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs_f64();
+        let (rate, eta) = if self.done_bytes > 0 && secs > 0.0 {
+            #[allow(clippy::cast_precision_loss)]
+            let rate = self.done_bytes as f64 / secs;
+            #[allow(clippy::cast_precision_loss)]
+            let remaining = self.total_bytes.saturating_sub(self.done_bytes) as f64;
+            (
+                format!("{}/s", format_bytes_f64(rate)),
+                format_eta(Duration::from_secs_f64(remaining / rate)),
+            )
+        } else {
+            ("-".to_string(), "unknown".to_string())
+        };
+
+        format!(
+            "Progress: {}/{} moves, {}/{} ({rate}), ETA {eta}",
+            self.done_moves,
+            self.total_moves,
+            format_bytes(self.done_bytes),
+            format_bytes(self.total_bytes),
+        )
+    }
+}
+
+/// Formats a remaining duration, e.g. `1h02m`, `3m05s` or `7s`.
+pub fn format_eta(eta: Duration) -> String {
+    let secs = eta.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
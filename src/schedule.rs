@@ -0,0 +1,79 @@
+//! Concurrent execution of planned work, throttled per group.
+//!
+//! Moves are grouped by the device they write to: a spinning disk slows down
+//! when hammered by many concurrent moves, while moves to different devices do
+//! not compete with each other. Each group gets its own small pool of workers.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+/// Runs `task` on every item, at most `per_group` at a time within a group.
+///
+/// Groups run concurrently with each other. Within a group, items are started
+/// in the order given. Once a task fails, no further items are started and the
+/// first error is returned after running tasks finish.
+///
+/// # Arguments
+///
+/// * `items` - The work items, each tagged with its group key
+/// * `per_group` - Maximum number of concurrent tasks per group (at least 1)
+/// * `task` - The work to perform on each item
+///
+/// # Errors
+///
+/// Returns the first error returned by `task`.
+pub fn run_grouped<K, T, F>(items: Vec<(K, T)>, per_group: usize, task: F) -> io::Result<()>
+where
+    K: Eq + Hash,
+    T: Send,
+    F: Fn(T) -> io::Result<()> + Sync,
+{
+    let mut groups: HashMap<K, VecDeque<T>> = HashMap::new();
+    for (key, item) in items {
+        groups.entry(key).or_default().push_back(item);
+    }
+
+    let queues: Vec<Mutex<VecDeque<T>>> = groups.into_values().map(Mutex::new).collect();
+    let failed = AtomicBool::new(false);
+    let first_error: Mutex<Option<io::Error>> = Mutex::new(None);
+
+    let (failed, first_error, task) = (&failed, &first_error, &task);
+    thread::scope(|scope| {
+        for queue in &queues {
+            let len = queue.lock().unwrap_or_else(PoisonError::into_inner).len();
+            for _ in 0..per_group.max(1).min(len) {
+                scope.spawn(move || {
+                    while !failed.load(Ordering::SeqCst) {
+                        let next = queue
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .pop_front();
+                        let Some(item) = next else {
+                            break;
+                        };
+                        if let Err(e) = task(item) {
+                            failed.store(true, Ordering::SeqCst);
+                            first_error
+                                .lock()
+                                .unwrap_or_else(PoisonError::into_inner)
+                                .get_or_insert(e);
+                        }
+                    }
+                });
+            }
+        }
+    });
+
+    match first_error
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+    {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,196 @@
+//! Bucket statistics.
+//!
+//! `refile stats` audits a base folder without moving anything: for every
+//! bucket, how many items it holds and how big they are, its oldest and its
+//! newest item, and the items whose current age puts them in another bucket.
+//! Those are what the next run with `--reconcile-existing` would move.
+
+use crate::config::BucketConfig;
+use crate::core::{bucket_of_dir, pick_bucket_for};
+use crate::filesystem::{bucket_dirs, get_file_age, item_size};
+use crate::output::{format_size, outln};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const SECS_PER_DAY: u64 = 24 * 3600;
+
+/// An item and its age in whole days.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AgedItem {
+    pub path: PathBuf,
+    pub age_days: u64,
+}
+
+/// An item filed in a bucket its age no longer fits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Mislocated {
+    pub path: PathBuf,
+    /// The bucket the item belongs in now
+    pub belongs_in: String,
+}
+
+/// What one bucket holds, overflow directories included.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct BucketContents {
+    pub count: u64,
+    pub bytes: u64,
+    pub oldest: Option<AgedItem>,
+    pub newest: Option<AgedItem>,
+    pub mislocated: Vec<Mislocated>,
+}
+
+/// What every bucket under a base folder holds.
+#[derive(Debug, Default, Serialize)]
+pub struct ArchiveStats {
+    pub base: PathBuf,
+    pub buckets: BTreeMap<String, BucketContents>,
+}
+
+impl ArchiveStats {
+    /// Scans the bucket directories under the base folder.
+    ///
+    /// Every configured bucket is listed, empty or not. An item's age and size
+    /// are measured the way a run measures them, and it is mislocated if a run
+    /// would pick another bucket for it. An item that matches no bucket under
+    /// `leave_unmatched` stays where it is, so it is not mislocated.
+    ///
+    /// # Arguments
+    ///
+    /// * `refile_base` - Path to the refile base directory
+    /// * `bucket_config` - The bucket configuration to use
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a bucket directory or one of its items cannot be read.
+    pub fn collect(refile_base: &Path, bucket_config: &BucketConfig) -> io::Result<Self> {
+        let mut buckets: BTreeMap<String, BucketContents> = bucket_config
+            .all_buckets()
+            .into_iter()
+            .map(|bucket| (bucket.name().to_string(), BucketContents::default()))
+            .collect();
+
+        for dir in bucket_dirs(refile_base, bucket_config)? {
+            let Some(name) = bucket_of_dir(&dir, bucket_config).map(|b| b.name().to_string())
+            else {
+                continue;
+            };
+            let Some(contents) = buckets.get_mut(&name) else {
+                continue;
+            };
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                let age = get_file_age(&path, bucket_config)?;
+                let size = item_size(&path)?;
+                contents.count += 1;
+                contents.bytes += size;
+
+                let item = AgedItem {
+                    path: path.clone(),
+                    age_days: age.as_secs() / SECS_PER_DAY,
+                };
+                if contents
+                    .oldest
+                    .as_ref()
+                    .is_none_or(|oldest| item.age_days > oldest.age_days)
+                {
+                    contents.oldest = Some(item.clone());
+                }
+                if contents
+                    .newest
+                    .as_ref()
+                    .is_none_or(|newest| item.age_days < newest.age_days)
+                {
+                    contents.newest = Some(item);
+                }
+
+                let is_dir = entry.file_type()?.is_dir();
+                if let Some(bucket) = pick_bucket_for(&path, is_dir, age, size, bucket_config)
+                    && bucket.name() != name
+                {
+                    contents.mislocated.push(Mislocated {
+                        path,
+                        belongs_in: bucket.name().to_string(),
+                    });
+                }
+            }
+        }
+
+        for contents in buckets.values_mut() {
+            contents.mislocated.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+        Ok(Self {
+            base: refile_base.to_path_buf(),
+            buckets,
+        })
+    }
+
+    /// Returns the number of mislocated items across all buckets.
+    pub fn mislocated_count(&self) -> usize {
+        self.buckets.values().map(|b| b.mislocated.len()).sum()
+    }
+
+    /// Prints the statistics as a table, buckets in configuration order and
+    /// labelled with their glyphs, followed by the mislocated items.
+    pub fn print(&self, bucket_config: &BucketConfig, humanize: bool) {
+        let width = self
+            .buckets
+            .keys()
+            .map(|name| bucket_config.label(name).chars().count())
+            .chain(["Bucket".len(), "Total".len()])
+            .max()
+            .unwrap_or_default();
+        let days = |item: &Option<AgedItem>| {
+            item.as_ref()
+                .map_or_else(|| "-".to_string(), |item| format!("{}d", item.age_days))
+        };
+
+        outln!("Buckets in {}:", self.base.display());
+        outln!(
+            "  {:<width$}  {:>6}  {:>12}  {:>7}  {:>7}  {:>10}",
+            "Bucket",
+            "Items",
+            "Size",
+            "Oldest",
+            "Newest",
+            "Mislocated"
+        );
+        let (mut count, mut bytes) = (0, 0);
+        for bucket in bucket_config.all_buckets() {
+            let Some(contents) = self.buckets.get(bucket.name()) else {
+                continue;
+            };
+            count += contents.count;
+            bytes += contents.bytes;
+            outln!(
+                "  {:<width$}  {:>6}  {:>12}  {:>7}  {:>7}  {:>10}",
+                bucket_config.label(bucket.name()),
+                contents.count,
+                format_size(contents.bytes, humanize),
+                days(&contents.oldest),
+                days(&contents.newest),
+                contents.mislocated.len()
+            );
+        }
+        outln!(
+            "  {:<width$}  {count:>6}  {:>12}  {:>7}  {:>7}  {:>10}",
+            "Total",
+            format_size(bytes, humanize),
+            "",
+            "",
+            self.mislocated_count()
+        );
+
+        if self.mislocated_count() > 0 {
+            outln!("Mislocated (would move on the next run with --reconcile-existing):");
+            for contents in self.buckets.values() {
+                for item in &contents.mislocated {
+                    outln!("  {} -> {}", item.path.display(), item.belongs_in);
+                }
+            }
+        }
+    }
+}
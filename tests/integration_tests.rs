@@ -16,6 +16,8 @@
 use assert_cmd::Command;
 use assert_fs::TempDir;
 use assert_fs::prelude::*;
+use chrono::Datelike;
+use predicates::prelude::*;
 use std::fs;
 use std::path::Path;
 use std::time::{Duration, SystemTime};
@@ -48,6 +50,78 @@ fn create_file_with_age(dir: &Path, name: &str, days_old: u64) -> std::io::Resul
     Ok(())
 }
 
+/// Helper to write a config file into a fake config home.
+///
+/// Point the command at it with `.env("XDG_CONFIG_HOME", config_home)`.
+fn write_config(config_home: &Path, contents: &str) -> std::io::Result<()> {
+    let dir = config_home.join("refile");
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("config.toml"), contents)
+}
+
+/// Helper to set up a conflict in the old-stuff bucket.
+///
+/// The bucket holds an old `file.txt` reading "archived", and the source an
+/// old `file.txt` reading "test content".
+fn create_conflict(source: &Path) -> std::io::Result<()> {
+    let bucket = source.join(OLD_STUFF_BUCKET);
+    fs::create_dir_all(&bucket)?;
+    fs::write(bucket.join("file.txt"), "archived")?;
+    let age = SystemTime::now() - Duration::from_secs(OLD_FILE_AGE * SECONDS_PER_DAY);
+    filetime::set_file_mtime(
+        bucket.join("file.txt"),
+        filetime::FileTime::from_system_time(age),
+    )?;
+    create_file_with_age(source, "file.txt", OLD_FILE_AGE)
+}
+
+/// Helper to compute the UTC calendar date `days_ago` days before today.
+///
+/// Returns `(year, month, day)`.
+fn date_days_ago(days_ago: u64) -> (i32, u32, u32) {
+    let date = (chrono::Utc::now() - chrono::Days::new(days_ago)).date_naive();
+    (date.year(), date.month(), date.day())
+}
+
+/// Helper to build a minimal JPEG carrying an EXIF `DateTimeOriginal` tag.
+///
+/// `datetime` must be in EXIF format: `YYYY:MM:DD HH:MM:SS`.
+fn jpeg_with_exif_date(datetime: &str) -> Vec<u8> {
+    let mut value = datetime.as_bytes().to_vec();
+    value.push(0);
+    assert_eq!(value.len(), 20, "EXIF datetime must be 19 characters");
+
+    // Little-endian TIFF structure: header, IFD0 -> Exif IFD -> DateTimeOriginal
+    let mut tiff = Vec::new();
+    tiff.extend_from_slice(b"II*\0");
+    tiff.extend_from_slice(&8u32.to_le_bytes()); // IFD0 offset
+    // IFD0: one entry, ExifIFDPointer
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&0x8769u16.to_le_bytes());
+    tiff.extend_from_slice(&4u16.to_le_bytes()); // LONG
+    tiff.extend_from_slice(&1u32.to_le_bytes());
+    tiff.extend_from_slice(&26u32.to_le_bytes()); // Exif IFD offset
+    tiff.extend_from_slice(&0u32.to_le_bytes()); // no next IFD
+    // Exif IFD: one entry, DateTimeOriginal
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&0x9003u16.to_le_bytes());
+    tiff.extend_from_slice(&2u16.to_le_bytes()); // ASCII
+    tiff.extend_from_slice(&20u32.to_le_bytes());
+    tiff.extend_from_slice(&44u32.to_le_bytes()); // value offset
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    tiff.extend_from_slice(&value);
+
+    let mut app1 = b"Exif\0\0".to_vec();
+    app1.extend_from_slice(&tiff);
+    let len = u16::try_from(app1.len() + 2).expect("APP1 segment too large");
+
+    let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+    jpeg.extend_from_slice(&len.to_be_bytes());
+    jpeg.extend_from_slice(&app1);
+    jpeg.extend_from_slice(&[0xFF, 0xD9]);
+    jpeg
+}
+
 /// Helper to create a refile command
 #[must_use]
 fn refile_cmd() -> Command {
@@ -251,6 +325,242 @@ fn test_allow_rename_handles_conflicts() {
     );
 }
 
+/// Tests that `--on-conflict abort` stops before anything is moved.
+///
+/// **User Story**: User wants to be told about conflicts rather than have
+/// refile resolve them.
+///
+/// **Scenario**: The old-stuff bucket already holds `file.txt` and another
+/// old `file.txt` plus an unrelated `other.txt` are refiled with
+/// `--on-conflict abort`.
+///
+/// **Expected**:
+/// - The run fails naming the conflict
+/// - Nothing is moved, not even the unrelated file
+#[test]
+fn test_on_conflict_abort() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    create_conflict(source).expect("Failed to create conflicting files");
+    create_file_with_age(source, "other.txt", OLD_FILE_AGE).expect("Failed to create other.txt");
+
+    refile_cmd()
+        .args(["--on-conflict", "abort"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Conflict"));
+
+    temp_dir
+        .child("file.txt")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("other.txt")
+        .assert(predicates::path::exists());
+}
+
+/// Tests that `--on-conflict rename` numbers the incoming item.
+///
+/// **User Story**: User wants both versions of a file kept side by side.
+///
+/// **Scenario**: The old-stuff bucket already holds `file.txt` and another
+/// old `file.txt` is refiled with `--on-conflict rename`.
+///
+/// **Expected**:
+/// - The existing file is untouched and the new one becomes `file (1).txt`
+/// - No deprecation warning is printed
+#[test]
+fn test_on_conflict_rename() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    create_conflict(source).expect("Failed to create conflicting files");
+
+    refile_cmd()
+        .args(["--on-conflict", "rename"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("deprecated").not());
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/file.txt"))
+        .assert("archived");
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/file (1).txt"))
+        .assert("test content");
+}
+
+/// Tests that `--on-conflict trash` moves the existing item aside.
+///
+/// **User Story**: User wants the newest version under the plain name, with
+/// the one it replaces kept around just in case.
+///
+/// **Scenario**: The old-stuff bucket already holds `file.txt` and another
+/// old `file.txt` is refiled with `--on-conflict trash`.
+///
+/// **Expected**:
+/// - The new file takes the plain name
+/// - The existing file is in `refile/.trash` under a timestamped name
+/// - A second run leaves the trash directory alone
+#[test]
+fn test_on_conflict_trash() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    create_conflict(source).expect("Failed to create conflicting files");
+
+    refile_cmd()
+        .args(["--on-conflict", "trash"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/file.txt"))
+        .assert("test content");
+    let trash = source.join(REFILE_BASE).join(".trash");
+    let trashed: Vec<_> = fs::read_dir(&trash)
+        .expect("Failed to read trash directory")
+        .map(|e| e.expect("Failed to read trash entry").path())
+        .collect();
+    assert_eq!(trashed.len(), 1);
+    let name = trashed[0]
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned();
+    assert!(
+        name.starts_with("file.txt."),
+        "Unexpected trash name {name}"
+    );
+    assert_eq!(fs::read_to_string(&trashed[0]).unwrap(), "archived");
+
+    refile_cmd()
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    assert!(trashed[0].exists());
+}
+
+/// Tests that `--on-conflict overwrite` replaces the existing item.
+///
+/// **User Story**: User only cares about the latest version of a file.
+///
+/// **Scenario**: The old-stuff bucket already holds `file.txt` and another
+/// old `file.txt` is refiled with `--on-conflict overwrite`, first as a
+/// dry-run.
+///
+/// **Expected**:
+/// - The dry-run reports the overwrite and changes nothing
+/// - The real run leaves only the new file, under the plain name
+#[test]
+fn test_on_conflict_overwrite() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    create_conflict(source).expect("Failed to create conflicting files");
+
+    refile_cmd()
+        .args(["--on-conflict", "overwrite", "--dry-run"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[dry-run] OVERWRITE"));
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/file.txt"))
+        .assert("archived");
+
+    refile_cmd()
+        .args(["--on-conflict", "overwrite"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/file.txt"))
+        .assert("test content");
+    temp_dir
+        .child("file.txt")
+        .assert(predicates::path::missing());
+    let entries = fs::read_dir(source.join(OLD_STUFF_BUCKET))
+        .expect("Failed to read bucket")
+        .count();
+    assert_eq!(entries, 1);
+}
+
+/// Tests that `--on-conflict overwrite` never replaces an item of the same run.
+///
+/// **User Story**: User overwrites stale archived copies, but two files of
+/// the same name in different subdirectories must both survive the run.
+///
+/// **Scenario**: The old-stuff bucket already holds `x.txt`. `a/x.txt` and
+/// `b/x.txt`, both old and with different contents, are refiled with
+/// `--recursive --on-conflict overwrite`.
+///
+/// **Expected**:
+/// - The archived `x.txt` is replaced by one of them
+/// - The other one is renamed, so both end up in the old-stuff bucket
+#[test]
+fn test_on_conflict_overwrite_keeps_items_of_the_run() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    for (dir, contents) in [(OLD_STUFF_BUCKET, "archived"), ("a", "A"), ("b", "B")] {
+        fs::create_dir_all(source.join(dir)).expect("Failed to create directory");
+        let path = source.join(dir).join("x.txt");
+        fs::write(&path, contents).expect("Failed to create x.txt");
+        let age = SystemTime::now() - Duration::from_secs(OLD_FILE_AGE * SECONDS_PER_DAY);
+        filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(age))
+            .expect("Failed to set mtime");
+    }
+
+    refile_cmd()
+        .args(["--recursive", "--on-conflict", "overwrite"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    let bucket = source.join(OLD_STUFF_BUCKET);
+    let mut contents: Vec<String> = ["x.txt", "x (1).txt"]
+        .iter()
+        .map(|name| fs::read_to_string(bucket.join(name)).expect("Failed to read moved file"))
+        .collect();
+    contents.sort();
+    assert_eq!(contents, ["A", "B"]);
+    assert!(!bucket.join("x (2).txt").exists());
+}
+
+/// Tests that `--allow-rename` still works as a deprecated alias.
+///
+/// **User Story**: User has scripts that pass `--allow-rename`.
+///
+/// **Scenario**: A conflicting file is refiled with `--allow-rename`, and
+/// again with both `--allow-rename` and `--on-conflict`.
+///
+/// **Expected**:
+/// - The first run renames the file and warns that the flag is deprecated
+/// - Combining the alias with `--on-conflict` is rejected
+#[test]
+fn test_allow_rename_is_deprecated_alias() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    create_conflict(source).expect("Failed to create conflicting files");
+
+    refile_cmd()
+        .args(["--allow-rename", "--on-conflict", "trash"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure();
+
+    refile_cmd()
+        .arg("--allow-rename")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("--allow-rename is deprecated"));
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/file (1).txt"))
+        .assert(predicates::path::exists());
+}
+
 /// Tests custom base folder configuration.
 ///
 /// **User Story**: User wants to organize files into a custom directory name
@@ -519,3 +829,5837 @@ fn test_repeated_refiling() {
         "File not moved to last-months"
     );
 }
+
+/// Tests per-extension age sources from the config file.
+///
+/// **User Story**: User has a mixed folder where photos should be organized by
+/// when they were taken (EXIF), not when they were last copied around.
+///
+/// **Scenario**: `[age_sources]` maps `jpg` to `exif`. A freshly written JPEG
+/// carries an old EXIF date; a fresh text file has no mapping.
+///
+/// **Expected**:
+/// - The JPEG is bucketed by its EXIF date → old-stuff/
+/// - The text file falls back to mtime → last-week/
+#[test]
+fn test_age_source_per_extension() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let config_home = TempDir::new().expect("Failed to create config directory");
+    let source = temp_dir.path();
+
+    write_config(config_home.path(), "[age_sources]\njpg = \"exif\"\n")
+        .expect("Failed to write config");
+
+    fs::write(
+        source.join("photo.jpg"),
+        jpeg_with_exif_date("2001:02:03 04:05:06"),
+    )
+    .expect("Failed to create photo.jpg");
+    create_file_with_age(source, "notes.txt", 0).expect("Failed to create notes.txt");
+
+    refile_cmd()
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/photo.jpg"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/notes.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --purge-empty-buckets removes buckets emptied by a run.
+///
+/// **User Story**: As files age out of a bucket, its directory is left behind
+/// empty and clutters the base folder.
+///
+/// **Scenario**:
+/// 1. Refile a recent file (goes to last-week)
+/// 2. Age the file and refile with --purge-empty-buckets
+///
+/// **Expected**:
+/// - The file moves to last-months
+/// - The now-empty last-week bucket is removed
+/// - Stray user directories under the base folder are never purged, even if empty
+#[test]
+fn test_purge_empty_buckets() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "file.txt", RECENT_FILE_AGE).expect("Failed to create file.txt");
+    refile_cmd()
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/file.txt"))
+        .assert(predicates::path::exists());
+
+    // Age the file and add an empty stray directory under the base folder
+    let moved = source.join(LAST_WEEK_BUCKET).join("file.txt");
+    let age = SystemTime::now() - Duration::from_secs(LAST_MONTHS_AGE * SECONDS_PER_DAY);
+    filetime::set_file_mtime(&moved, filetime::FileTime::from_system_time(age))
+        .expect("Failed to set mtime to simulate aging");
+    let stray = source.join(REFILE_BASE).join("keep-me");
+    fs::create_dir(&stray).expect("Failed to create stray directory");
+    filetime::set_file_mtime(&stray, filetime::FileTime::from_system_time(age))
+        .expect("Failed to set mtime on stray directory");
+
+    refile_cmd()
+        .arg("--purge-empty-buckets")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{LAST_MONTHS_BUCKET}/file.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(LAST_WEEK_BUCKET)
+        .assert(predicates::path::missing());
+    temp_dir
+        .child(CURRENT_MONTH_BUCKET)
+        .assert(predicates::path::missing());
+    // The stray directory was refiled as an item, not purged
+    temp_dir
+        .child(format!("{LAST_MONTHS_BUCKET}/keep-me"))
+        .assert(predicates::path::is_dir());
+}
+
+/// Tests the read-only --stats-json snapshot of the source.
+///
+/// **User Story**: User feeds refile data into a monitoring dashboard and
+/// wants an aggregate view of the source without moving anything.
+///
+/// **Expected**:
+/// - Output is a JSON object with a count and total size per bucket
+/// - Files are grouped into the buckets matching their age
+/// - Nothing is moved and no bucket directories are created
+#[test]
+fn test_stats_json_groups_files_by_bucket() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "a.txt", RECENT_FILE_AGE).expect("Failed to create a.txt");
+    create_file_with_age(source, "b.txt", RECENT_FILE_AGE).expect("Failed to create b.txt");
+    create_file_with_age(source, "c.txt", OLD_FILE_AGE).expect("Failed to create c.txt");
+
+    let output = refile_cmd()
+        .arg("--stats-json")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .output()
+        .expect("Failed to run refile");
+    assert!(output.status.success());
+
+    let stats: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("Output is not valid JSON");
+    let buckets = &stats["buckets"];
+    let content_len = u64::try_from(b"test content".len()).expect("length fits in u64");
+
+    assert_eq!(buckets["last-week"]["count"], 2);
+    assert_eq!(buckets["last-week"]["bytes"], 2 * content_len);
+    assert_eq!(buckets["current-month"]["count"], 0);
+    assert_eq!(buckets["last-months"]["count"], 0);
+    assert_eq!(buckets["old-stuff"]["count"], 1);
+    assert_eq!(buckets["old-stuff"]["bytes"], content_len);
+
+    temp_dir.child("a.txt").assert(predicates::path::exists());
+    temp_dir
+        .child(REFILE_BASE)
+        .assert(predicates::path::missing());
+}
+
+/// Tests that --respect-gitignore leaves a git working tree intact.
+///
+/// **User Story**: User refiles a directory that is a git repository and must
+/// not wreck the working tree.
+///
+/// **Scenario**: A repo with a tracked file, a gitignored file, and an
+/// untracked stray file, all old.
+///
+/// **Expected**:
+/// - Tracked file, ignored file, `.gitignore`, and `.git` stay in place
+/// - Only the untracked, non-ignored file is refiled
+#[test]
+fn test_respect_gitignore_leaves_repo_intact() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(source)
+            .args(args)
+            .status()
+            .expect("Failed to run git");
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "--quiet"]);
+
+    fs::write(source.join(".gitignore"), "*.log\n").expect("Failed to write .gitignore");
+    create_file_with_age(source, "tracked.txt", OLD_FILE_AGE).expect("Failed to create file");
+    create_file_with_age(source, "debug.log", OLD_FILE_AGE).expect("Failed to create file");
+    create_file_with_age(source, "stray.txt", OLD_FILE_AGE).expect("Failed to create file");
+    git(&["add", ".gitignore", "tracked.txt"]);
+
+    refile_cmd()
+        .arg("--respect-gitignore")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("tracked by git"))
+        .stderr(predicates::str::contains("ignored by git"));
+
+    temp_dir
+        .child("tracked.txt")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("debug.log")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(".gitignore")
+        .assert(predicates::path::exists());
+    temp_dir.child(".git").assert(predicates::path::is_dir());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/stray.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --date-suffix-dirs names moved directories after their age.
+///
+/// **User Story**: User archives dated project dumps that all share the same
+/// directory name, and wants the month in the name for context.
+///
+/// **Scenario**: A directory named `project` is refiled, then a second, older
+/// `project` directory is refiled into the same bucket. A third `project` from
+/// the same month as the first is then refiled with `--allow-rename` and
+/// `--verify-idempotent`.
+///
+/// **Expected**:
+/// - Each directory gets a `_YYYY-MM` suffix derived from its mtime
+/// - The two directories don't collide, so no `--allow-rename` is needed
+/// - Files are never suffixed
+/// - A directory renamed after its suffix (`project_YYYY-MM (1)`) keeps its
+///   name on later runs instead of being suffixed again
+#[test]
+fn test_date_suffix_dirs() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let make_project = |days_old: u64| -> String {
+        let dir = source.join("project");
+        fs::create_dir(&dir).expect("Failed to create project directory");
+        let mtime = SystemTime::now() - Duration::from_secs(days_old * SECONDS_PER_DAY);
+        filetime::set_file_mtime(&dir, filetime::FileTime::from_system_time(mtime))
+            .expect("Failed to set mtime on project directory");
+
+        let secs = mtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("mtime before epoch")
+            .as_secs();
+        let output = std::process::Command::new("date")
+            .args(["-u", "-d", &format!("@{secs}"), "+%Y-%m"])
+            .output()
+            .expect("Failed to run date");
+        format!("project_{}", String::from_utf8_lossy(&output.stdout).trim())
+    };
+
+    let first = make_project(OLD_FILE_AGE);
+    create_file_with_age(source, "file.txt", OLD_FILE_AGE).expect("Failed to create file.txt");
+    refile_cmd()
+        .arg("--date-suffix-dirs")
+        .args(["--time-zone", "utc"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    let second = make_project(OLD_FILE_AGE + 60);
+    refile_cmd()
+        .arg("--date-suffix-dirs")
+        .args(["--time-zone", "utc"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    assert_ne!(first, second);
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/{first}"))
+        .assert(predicates::path::is_dir());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/{second}"))
+        .assert(predicates::path::is_dir());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/file.txt"))
+        .assert(predicates::path::exists());
+
+    make_project(OLD_FILE_AGE);
+    refile_cmd()
+        .arg("--date-suffix-dirs")
+        .args(["--time-zone", "utc"])
+        .arg("--allow-rename")
+        .arg("--verify-idempotent")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "Idempotent: planning again would move nothing",
+        ));
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/{first} (1)"))
+        .assert(predicates::path::is_dir());
+}
+
+/// Tests that --date-suffix-dirs takes the month in the --time-zone.
+///
+/// **User Story**: User in Tokyo archives a project created early on the
+/// first of the month, and expects that month in its name.
+///
+/// **Scenario**: A `project` directory modified at 2024-03-01 08:30 JST
+/// (2024-02-29 23:30 UTC) is refiled with `--time-zone +09:00`, then another
+/// one with the same time with `--time-zone utc`.
+///
+/// **Expected**:
+/// - The first is suffixed `_2024-03`, the month in Tokyo
+/// - The second is suffixed `_2024-02`, the month in UTC
+#[test]
+fn test_date_suffix_dirs_time_zone() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_mins(28_487_490);
+
+    for (zone, month) in [("+09:00", "2024-03"), ("utc", "2024-02")] {
+        let dir = source.join("project");
+        fs::create_dir(&dir).expect("Failed to create project directory");
+        filetime::set_file_mtime(&dir, filetime::FileTime::from_system_time(modified))
+            .expect("Failed to set mtime on project directory");
+        refile_cmd()
+            .env("REFILE_NOW", "1710459000")
+            .arg("--date-suffix-dirs")
+            .args(["--time-zone", zone])
+            .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+            .assert()
+            .success();
+        temp_dir
+            .child(format!("{CURRENT_MONTH_BUCKET}/project_{month}"))
+            .assert(predicates::path::is_dir());
+    }
+}
+
+/// Tests that --check-config accepts a valid config without a source directory.
+///
+/// **User Story**: User lints their refile config in CI, where there is no
+/// real directory to scan.
+///
+/// **Expected**: Exit code 0 and a confirmation message.
+#[test]
+fn test_check_config_valid() {
+    let config_dir = TempDir::new().expect("Failed to create temporary directory");
+    let config_path = config_dir.child("config.toml");
+    config_path
+        .write_str(
+            r#"
+[default]
+base_folder = "archive"
+
+[default.buckets]
+a-recent = 7
+b-old = "null"
+
+[[rules]]
+path = "/nonexistent/downloads"
+
+[rules.buckets]
+today = 1
+zz-rest = "null"
+"#,
+        )
+        .expect("Failed to write config");
+
+    refile_cmd()
+        .arg("--check-config")
+        .arg("--config")
+        .arg(config_path.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Configuration is valid"));
+}
+
+/// Tests that --check-config rejects an invalid rule, even one matching no source.
+///
+/// **Expected**: Non-zero exit code and an error naming the broken rule.
+#[test]
+fn test_check_config_invalid_rule() {
+    let config_dir = TempDir::new().expect("Failed to create temporary directory");
+    let config_path = config_dir.child("config.toml");
+    config_path
+        .write_str(
+            r#"
+[[rules]]
+path = "/nonexistent/downloads"
+
+[rules.buckets]
+today = 1
+week = 7
+"#,
+        )
+        .expect("Failed to write config");
+
+    refile_cmd()
+        .arg("--check-config")
+        .arg("--config")
+        .arg(config_path.path())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("rule 1 (/nonexistent/downloads)"))
+        .stderr(predicates::str::contains(
+            "At least one bucket must have no age limit",
+        ));
+}
+
+/// Tests that --reconcile moves every misplaced archived file to its correct bucket.
+///
+/// **User Story**: User changed bucket thresholds (or files were filed by hand)
+/// and wants the existing archive self-healed in one run.
+///
+/// **Scenario**: A separate target archive has files sitting in the wrong
+/// buckets, and the source has a new file.
+///
+/// **Expected**:
+/// - Every archived file ends up in the bucket matching its age
+/// - Correctly placed files stay put
+/// - The source is not scanned, so the new file is left alone
+#[test]
+fn test_reconcile_fixes_misplaced_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source_dir = temp_dir.child("source");
+    let target_dir = temp_dir.child("target");
+    source_dir
+        .create_dir_all()
+        .expect("Failed to create source directory");
+
+    for bucket in [
+        LAST_WEEK_BUCKET,
+        CURRENT_MONTH_BUCKET,
+        LAST_MONTHS_BUCKET,
+        OLD_STUFF_BUCKET,
+    ] {
+        target_dir
+            .child(bucket)
+            .create_dir_all()
+            .expect("Failed to create bucket directory");
+    }
+    let bucket = |name: &str| target_dir.path().join(name);
+    create_file_with_age(&bucket(LAST_WEEK_BUCKET), "old.txt", OLD_FILE_AGE)
+        .expect("Failed to create old.txt");
+    create_file_with_age(&bucket(OLD_STUFF_BUCKET), "recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create recent.txt");
+    create_file_with_age(&bucket(CURRENT_MONTH_BUCKET), "months.txt", LAST_MONTHS_AGE)
+        .expect("Failed to create months.txt");
+    create_file_with_age(&bucket(CURRENT_MONTH_BUCKET), "medium.txt", MEDIUM_FILE_AGE)
+        .expect("Failed to create medium.txt");
+    create_file_with_age(source_dir.path(), "new.txt", RECENT_FILE_AGE)
+        .expect("Failed to create new.txt");
+
+    refile_cmd()
+        .arg("--reconcile")
+        .arg(source_dir.path())
+        .arg(target_dir.path())
+        .assert()
+        .success();
+
+    target_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+    target_dir
+        .child(format!("{LAST_WEEK_BUCKET}/recent.txt"))
+        .assert(predicates::path::exists());
+    target_dir
+        .child(format!("{LAST_MONTHS_BUCKET}/months.txt"))
+        .assert(predicates::path::exists());
+    target_dir
+        .child(format!("{CURRENT_MONTH_BUCKET}/medium.txt"))
+        .assert(predicates::path::exists());
+    source_dir
+        .child("new.txt")
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --output-file rotates the log once it exceeds --log-max-size.
+///
+/// **User Story**: User runs refile unattended for weeks, appending to a log
+/// file that must not grow unbounded.
+///
+/// **Expected**:
+/// - Run output is appended to the log file
+/// - Once the size threshold is exceeded, the log is rotated to `<log>.1`
+/// - The log file inside the source directory is never refiled itself, also
+///   when given as a relative path
+#[test]
+fn test_output_file_rotation() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    let log = source.join("refile.log");
+
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        create_file_with_age(source, name, RECENT_FILE_AGE).expect("Failed to create file");
+    }
+
+    // Each "Moved ..." line is far longer than 40 bytes, forcing rotations
+    refile_cmd()
+        .arg("--output-file")
+        .arg(&log)
+        .arg("--log-max-size")
+        .arg("40")
+        .arg("--log-keep")
+        .arg("2")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    let current = fs::read_to_string(&log).expect("Log file missing");
+    let rotated = fs::read_to_string(source.join("refile.log.1")).expect("Rotated log missing");
+    assert!(current.starts_with("Moved "));
+    assert!(rotated.starts_with("Moved "));
+    assert!(source.join("refile.log.2").exists());
+    assert!(!source.join("refile.log.3").exists());
+    assert!(!source.join(LAST_WEEK_BUCKET).join("refile.log").exists());
+
+    // A second run leaves the log and its rotations in place
+    refile_cmd()
+        .arg("--output-file")
+        .arg(&log)
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    assert!(log.exists());
+    assert!(source.join("refile.log.1").exists());
+
+    // Nor does a run naming the log relative to the working directory
+    refile_cmd()
+        .current_dir(source)
+        .arg("--output-file")
+        .arg("refile.log")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    assert!(log.exists());
+    assert!(source.join("refile.log.1").exists());
+}
+
+/// Tests that --explode-dirs buckets a directory's files individually.
+///
+/// **User Story**: User wants every file inside a dump directory bucketed by
+/// its own age rather than moving the whole directory by the directory's age.
+///
+/// **Scenario**: A directory containing a recent file and a nested old file.
+///
+/// **Expected**:
+/// - Each file lands in the bucket matching its own age
+/// - The emptied directory shell (including nested directories) is removed
+#[test]
+fn test_explode_dirs() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let dump = source.join("dump");
+    fs::create_dir_all(dump.join("nested")).expect("Failed to create dump directories");
+    create_file_with_age(&dump, "recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create recent.txt");
+    create_file_with_age(&dump.join("nested"), "old.txt", OLD_FILE_AGE)
+        .expect("Failed to create old.txt");
+
+    refile_cmd()
+        .arg("--explode-dirs")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/recent.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+    temp_dir.child("dump").assert(predicates::path::missing());
+}
+
+/// Tests that exploded files never overwrite files already in a bucket.
+///
+/// **User Story**: Exploding a folder must not clobber an earlier file of the
+/// same name that was refiled before.
+///
+/// **Scenario**: A directory holds a recent `report.txt` while last-week
+/// already contains a `report.txt`. The source is refiled with
+/// `--explode-dirs`, then again with `--on-conflict rename`.
+///
+/// **Expected**:
+/// - The first run aborts with a conflict, and nothing is moved
+/// - The second run moves the file to `report (1).txt`
+/// - The existing bucket file keeps its content in both runs
+#[test]
+fn test_explode_dirs_conflicts() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let dump = source.join("dump");
+    fs::create_dir_all(&dump).expect("Failed to create dump directory");
+    create_file_with_age(&dump, "report.txt", RECENT_FILE_AGE)
+        .expect("Failed to create report.txt");
+    let bucket = source.join(LAST_WEEK_BUCKET);
+    fs::create_dir_all(&bucket).expect("Failed to create bucket");
+    fs::write(bucket.join("report.txt"), "existing").expect("Failed to write existing file");
+
+    refile_cmd()
+        .arg("--explode-dirs")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("destination path already exists"));
+    temp_dir
+        .child("dump/report.txt")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/report.txt"))
+        .assert("existing");
+
+    refile_cmd()
+        .arg("--explode-dirs")
+        .args(["--on-conflict", "rename"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/report (1).txt"))
+        .assert("test content");
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/report.txt"))
+        .assert("existing");
+    temp_dir.child("dump").assert(predicates::path::missing());
+}
+
+/// Tests that exploding a directory honours hidden files and `.refileignore`.
+///
+/// **User Story**: Exploding a folder should not drag out the hidden files
+/// and ignored files a `--recursive` scan of the same folder leaves alone.
+///
+/// **Scenario**: A directory holding a hidden file, a file inside a hidden
+/// directory, a `.refileignore` excluding `*.log`, a log file and a normal
+/// file, run with `--explode-dirs` and then with `--dir-move-threshold`.
+///
+/// **Expected**:
+/// - Only the normal file is moved; the rest stays in the directory
+/// - Ignored files do not count towards the threshold
+#[test]
+fn test_explode_dirs_respects_hidden_and_ignore_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    for (dir, options) in [
+        ("dump", &["--explode-dirs"][..]),
+        ("pile", &["--dir-move-threshold", "1"]),
+    ] {
+        let dump = source.join(dir);
+        fs::create_dir_all(dump.join(".git")).expect("Failed to create dump directories");
+        fs::write(dump.join(".refileignore"), "*.log\n").expect("Failed to write .refileignore");
+        create_file_with_age(&dump.join(".git"), "config", OLD_FILE_AGE)
+            .expect("Failed to create .git/config");
+        create_file_with_age(&dump, ".hidden", OLD_FILE_AGE).expect("Failed to create .hidden");
+        create_file_with_age(&dump, "x.log", OLD_FILE_AGE).expect("Failed to create x.log");
+        create_file_with_age(&dump, &format!("{dir}.txt"), OLD_FILE_AGE)
+            .expect("Failed to create normal file");
+
+        refile_cmd()
+            .args(options)
+            .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+            .assert()
+            .success();
+
+        temp_dir
+            .child(format!("{OLD_STUFF_BUCKET}/{dir}.txt"))
+            .assert(predicates::path::exists());
+        for kept in [".git/config", ".hidden", "x.log", ".refileignore"] {
+            temp_dir
+                .child(format!("{dir}/{kept}"))
+                .assert(predicates::path::exists());
+        }
+        temp_dir
+            .child(format!("{dir}/{dir}.txt"))
+            .assert(predicates::path::missing());
+    }
+}
+
+/// Tests that --dir-move-threshold explodes only small directories.
+///
+/// **User Story**: Large project folders should stay intact, while a folder
+/// holding a couple of stray files should not keep them all in one bucket.
+///
+/// **Scenario**: A small directory with 2 files of different ages and a large
+/// directory with 4 recent files, run with `--dir-move-threshold 3`.
+///
+/// **Expected**:
+/// - The small directory's files are bucketed individually and it is removed
+/// - The large directory is moved whole, by its own age
+#[test]
+fn test_dir_move_threshold() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let small = source.join("small");
+    fs::create_dir(&small).expect("Failed to create small directory");
+    create_file_with_age(&small, "recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create recent.txt");
+    create_file_with_age(&small, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+
+    let large = source.join("large");
+    fs::create_dir(&large).expect("Failed to create large directory");
+    for i in 0..4 {
+        create_file_with_age(&large, &format!("file{i}.txt"), RECENT_FILE_AGE)
+            .expect("Failed to create large directory file");
+    }
+
+    refile_cmd()
+        .arg("--dir-move-threshold")
+        .arg("3")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/recent.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+    temp_dir.child("small").assert(predicates::path::missing());
+
+    for i in 0..4 {
+        temp_dir
+            .child(format!("{LAST_WEEK_BUCKET}/large/file{i}.txt"))
+            .assert(predicates::path::exists());
+    }
+    temp_dir.child("large").assert(predicates::path::missing());
+}
+
+/// Tests the typed confirmation required to move a protected directory.
+///
+/// **User Story**: Even with --allow-dangerous-directories, a protected
+/// directory should only move once the user deliberately types its name.
+///
+/// **Scenario**: HOME points at a directory inside the source, making it a
+/// protected item that would be moved.
+///
+/// **Expected**:
+/// - A wrong answer aborts the run before anything is moved
+/// - Typing the directory name lets the move proceed
+#[test]
+fn test_confirm_protected_directory_move() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    let home = source.join("home");
+    fs::create_dir(&home).expect("Failed to create fake home directory");
+    create_file_with_age(source, "file.txt", RECENT_FILE_AGE).expect("Failed to create file.txt");
+
+    refile_cmd()
+        .env("HOME", &home)
+        .arg("--allow-dangerous-directories")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .write_stdin("nope\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Type 'home' to confirm"));
+    temp_dir.child("home").assert(predicates::path::is_dir());
+    temp_dir
+        .child("file.txt")
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .env("HOME", &home)
+        .arg("--allow-dangerous-directories")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .write_stdin("home\n")
+        .assert()
+        .success();
+    temp_dir.child("home").assert(predicates::path::missing());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/home"))
+        .assert(predicates::path::is_dir());
+}
+
+/// Tests that --from-file processes only the listed paths.
+///
+/// **User Story**: User maintains a curated cleanup list and wants refile to
+/// organize exactly those items instead of scanning the whole directory.
+///
+/// **Scenario**: A list file with comments, a blank line, a relative path,
+/// and an absolute path; the source also contains an unlisted file.
+///
+/// **Expected**: Only the two listed files are organized.
+#[test]
+fn test_from_file_processes_listed_paths() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let list_dir = TempDir::new().expect("Failed to create list directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "one.txt", RECENT_FILE_AGE).expect("Failed to create one.txt");
+    create_file_with_age(source, "two.txt", OLD_FILE_AGE).expect("Failed to create two.txt");
+    create_file_with_age(source, "unlisted.txt", OLD_FILE_AGE)
+        .expect("Failed to create unlisted.txt");
+
+    let list = list_dir.child("list.txt");
+    list.write_str(&format!(
+        "# files to clean up\none.txt\n\n  # the old one, by absolute path\n{}\n",
+        source.join("two.txt").display()
+    ))
+    .expect("Failed to write list file");
+
+    refile_cmd()
+        .arg("--from-file")
+        .arg(list.path())
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/one.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/two.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("unlisted.txt")
+        .assert(predicates::path::exists());
+}
+
+/// Tests that relative symlinks keep pointing at the same file after a move.
+///
+/// **User Story**: User keeps relative symlinks to files outside the source
+/// directory and expects them to still work once refiled into a bucket.
+///
+/// **Scenario**: Two symlinks to an old file in a sibling directory, refiled
+/// once with the default behavior and once with --absolute-symlinks.
+///
+/// **Expected**: The moved links resolve to the original file; the first is
+/// rewritten relative to its new location, the second becomes absolute.
+#[cfg(unix)]
+#[test]
+fn test_relative_symlinks_are_rewritten() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.child("source");
+    let elsewhere = temp_dir.child("elsewhere");
+    source.create_dir_all().expect("Failed to create source");
+    elsewhere
+        .create_dir_all()
+        .expect("Failed to create elsewhere");
+    create_file_with_age(elsewhere.path(), "data.txt", OLD_FILE_AGE)
+        .expect("Failed to create data.txt");
+    std::os::unix::fs::symlink("../elsewhere/data.txt", source.child("relative").path())
+        .expect("Failed to create relative symlink");
+
+    refile_cmd()
+        .arg(
+            source
+                .path()
+                .to_str()
+                .expect("Test path contains invalid UTF-8"),
+        )
+        .assert()
+        .success();
+
+    let moved = source.child(format!("{OLD_STUFF_BUCKET}/relative"));
+    assert_eq!(
+        fs::read_link(moved.path()).expect("Moved link should be a symlink"),
+        Path::new("../../../elsewhere/data.txt")
+    );
+    assert_eq!(
+        fs::read_to_string(moved.path()).expect("Moved link should resolve"),
+        "test content"
+    );
+
+    std::os::unix::fs::symlink("../elsewhere/data.txt", source.child("absolute").path())
+        .expect("Failed to create second symlink");
+
+    refile_cmd()
+        .arg("--absolute-symlinks")
+        .arg(
+            source
+                .path()
+                .to_str()
+                .expect("Test path contains invalid UTF-8"),
+        )
+        .assert()
+        .success();
+
+    let moved = source.child(format!("{OLD_STUFF_BUCKET}/absolute"));
+    let target = fs::read_link(moved.path()).expect("Moved link should be a symlink");
+    assert!(
+        target.is_absolute(),
+        "Expected absolute target, got {target:?}"
+    );
+    assert_eq!(
+        fs::read_to_string(moved.path()).expect("Moved link should resolve"),
+        "test content"
+    );
+}
+
+/// Tests that --bucket-max-files spills over into numbered overflow buckets.
+///
+/// **User Story**: User wants to cap bucket sizes so no single archive
+/// directory grows unmanageably large.
+///
+/// **Scenario**: Five old files refiled with a cap of two, followed by a
+/// second run that adds one more file. A recent file is then put in
+/// `old-stuff-2` and refiled without the cap.
+///
+/// **Expected**:
+/// - The files fill `old-stuff`, then `old-stuff-2`, then `old-stuff-3` in
+///   order; the second run leaves placed files alone and fills the remaining
+///   slot in `old-stuff-3`
+/// - Without the cap `old-stuff-2` is not a bucket but a folder like any
+///   other in the base folder, and is refiled whole
+#[test]
+fn test_bucket_max_files_rollover() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    for name in ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"] {
+        create_file_with_age(source, name, OLD_FILE_AGE).expect("Failed to create file");
+    }
+
+    let count = |dir: &str| {
+        fs::read_dir(source.join(dir))
+            .expect("Bucket directory should exist")
+            .count()
+    };
+
+    refile_cmd()
+        .arg("--bucket-max-files")
+        .arg("2")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    assert_eq!(count(OLD_STUFF_BUCKET), 2);
+    assert_eq!(count(&format!("{OLD_STUFF_BUCKET}-2")), 2);
+    assert_eq!(count(&format!("{OLD_STUFF_BUCKET}-3")), 1);
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}-4"))
+        .assert(predicates::path::missing());
+
+    create_file_with_age(source, "f.txt", OLD_FILE_AGE).expect("Failed to create f.txt");
+
+    refile_cmd()
+        .arg("--bucket-max-files")
+        .arg("2")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    assert_eq!(count(OLD_STUFF_BUCKET), 2);
+    assert_eq!(count(&format!("{OLD_STUFF_BUCKET}-2")), 2);
+    assert_eq!(count(&format!("{OLD_STUFF_BUCKET}-3")), 2);
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}-3/f.txt"))
+        .assert(predicates::path::exists());
+
+    create_file_with_age(
+        &source.join(format!("{OLD_STUFF_BUCKET}-2")),
+        "new.txt",
+        RECENT_FILE_AGE,
+    )
+    .expect("Failed to create new.txt");
+    refile_cmd()
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/old-stuff-2/new.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --normalize-config rewrites the config in canonical form.
+///
+/// **User Story**: User hand-edited their config and wants it rewritten in a
+/// consistent layout, with buckets listed in the order they apply.
+///
+/// **Scenario**: A valid config whose buckets are written in name order
+/// rather than age order is checked, normalized and checked again, followed
+/// by an invalid config.
+///
+/// **Expected**:
+/// - Checking the config warns that its buckets are out of order
+/// - The valid config is rewritten with buckets sorted by age and the
+///   catch-all last, and is no longer warned about
+/// - The invalid config is left untouched and the command fails
+#[test]
+fn test_normalize_config_sorts_buckets_by_age() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let config = temp_dir.child("config.toml");
+    config
+        .write_str("[default.buckets]\narchive = \"null\"\nmonth = 30\nweek = 7\n")
+        .expect("Failed to write config");
+
+    let check = || {
+        refile_cmd()
+            .arg("--config")
+            .arg(config.path())
+            .arg("--check-config")
+            .assert()
+            .success()
+    };
+    check().stderr(predicates::str::contains(
+        "[default.buckets]: 'week' applies before 'archive' but is written after it",
+    ));
+
+    refile_cmd()
+        .arg("--config")
+        .arg(config.path())
+        .arg("--normalize-config")
+        .assert()
+        .success();
+
+    let normalized = fs::read_to_string(config.path()).expect("Failed to read config");
+    let week = normalized.find("week = 7").expect("week bucket missing");
+    let month = normalized.find("month = 30").expect("month bucket missing");
+    let archive = normalized
+        .find("archive = \"null\"")
+        .expect("archive bucket missing");
+    assert!(
+        week < month && month < archive,
+        "Unexpected order:\n{normalized}"
+    );
+    check().stderr(predicates::str::contains("Warning").not());
+
+    let invalid = "[default.buckets]\nweek = 7\n";
+    config.write_str(invalid).expect("Failed to write config");
+
+    refile_cmd()
+        .arg("--config")
+        .arg(config.path())
+        .arg("--normalize-config")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("not rewriting"));
+    assert_eq!(
+        fs::read_to_string(config.path()).expect("Failed to read config"),
+        invalid
+    );
+}
+
+/// Tests that --allow-rename never splits dotted directory names.
+///
+/// **User Story**: User refiles a directory like `my.project.v2` that collides
+/// with an archived one and expects the copy to be recognizable.
+///
+/// **Scenario**: A dotted directory and a dotted file each collide with an
+/// item already in the bucket.
+///
+/// **Expected**: The directory becomes `my.project.v2 (1)`, while the file
+/// keeps its extension last: `report.final (1).txt`.
+#[test]
+fn test_allow_rename_dotted_directory() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    let bucket = temp_dir.child(LAST_WEEK_BUCKET);
+
+    bucket
+        .child("my.project.v2")
+        .create_dir_all()
+        .expect("Failed to create archived directory");
+    bucket
+        .child("report.final.txt")
+        .write_str("archived")
+        .expect("Failed to create archived file");
+
+    temp_dir
+        .child("my.project.v2/main.rs")
+        .write_str("fn main() {}")
+        .expect("Failed to create directory");
+    create_file_with_age(source, "report.final.txt", RECENT_FILE_AGE)
+        .expect("Failed to create report.final.txt");
+
+    refile_cmd()
+        .arg("--allow-rename")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    bucket
+        .child("my.project.v2 (1)/main.rs")
+        .assert(predicates::path::exists());
+    bucket
+        .child("report.final (1).txt")
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --scan-order name makes rename conflicts deterministic.
+///
+/// **User Story**: User wants the same file to keep the original name on
+/// every run and platform, regardless of directory listing order.
+///
+/// **Scenario**: Two exploded directories each contain `report.txt`, which
+/// land in the same bucket, refiled with --allow-rename.
+///
+/// **Expected**: The file from the directory that sorts first keeps
+/// `report.txt`; the other becomes `report (1).txt`. Neither is overwritten.
+#[test]
+fn test_scan_order_name_decides_conflict_winner() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    for dir in ["b-second", "a-first"] {
+        let file = temp_dir.child(format!("{dir}/report.txt"));
+        file.write_str(dir).expect("Failed to create report.txt");
+    }
+
+    refile_cmd()
+        .arg("--scan-order")
+        .arg("name")
+        .arg("--explode-dirs")
+        .arg("--allow-rename")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    let bucket = source.join(LAST_WEEK_BUCKET);
+    assert_eq!(
+        fs::read_to_string(bucket.join("report.txt")).expect("report.txt missing"),
+        "a-first"
+    );
+    assert_eq!(
+        fs::read_to_string(bucket.join("report (1).txt")).expect("report (1).txt missing"),
+        "b-second"
+    );
+}
+
+/// Tests that parallel moves with --max-concurrency-per-device land correctly.
+///
+/// **User Story**: User enables parallel moves on a large directory and
+/// expects the same result as a sequential run.
+///
+/// **Scenario**: Twenty files of mixed ages refiled with a per-device cap of 4.
+///
+/// **Expected**: Every file ends up in its bucket and the source is emptied.
+#[test]
+fn test_max_concurrency_per_device_moves_all_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    for i in 0..20 {
+        let age = if i % 2 == 0 {
+            RECENT_FILE_AGE
+        } else {
+            OLD_FILE_AGE
+        };
+        create_file_with_age(source, &format!("file-{i:02}.txt"), age)
+            .expect("Failed to create file");
+    }
+
+    refile_cmd()
+        .arg("--max-concurrency-per-device")
+        .arg("4")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    for i in 0..20 {
+        let bucket = if i % 2 == 0 {
+            LAST_WEEK_BUCKET
+        } else {
+            OLD_STUFF_BUCKET
+        };
+        temp_dir
+            .child(format!("{bucket}/file-{i:02}.txt"))
+            .assert(predicates::path::exists());
+        temp_dir
+            .child(format!("file-{i:02}.txt"))
+            .assert(predicates::path::missing());
+    }
+}
+
+/// Tests that --report-growth reports per-bucket deltas between runs.
+///
+/// **User Story**: User tracks how their archive grows over time.
+///
+/// **Scenario**: A first run records a baseline manifest; two old files and
+/// one recent file are added before a second run.
+///
+/// **Expected**: The first run reports a baseline; the second reports the
+/// file and byte deltas per bucket, and the manifest stays in the base folder.
+#[test]
+fn test_report_growth_against_previous_manifest() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "first.txt", OLD_FILE_AGE).expect("Failed to create first.txt");
+
+    refile_cmd()
+        .arg("--report-growth")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("recording a baseline"));
+    temp_dir
+        .child(format!("{REFILE_BASE}/.refile-manifest.json"))
+        .assert(predicates::path::is_file());
+
+    create_file_with_age(source, "second.txt", OLD_FILE_AGE).expect("Failed to create second.txt");
+    create_file_with_age(source, "third.txt", OLD_FILE_AGE).expect("Failed to create third.txt");
+    create_file_with_age(source, "recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create recent.txt");
+
+    // Each test file holds 12 bytes ("test content")
+    refile_cmd()
+        .arg("--report-growth")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "old-stuff: +2 files, +24 bytes (3 files, 36 bytes)",
+        ))
+        .stdout(predicates::str::contains(
+            "last-week: +1 files, +12 bytes (1 files, 12 bytes)",
+        ))
+        .stdout(predicates::str::contains(
+            "current-month: +0 files, +0 bytes",
+        ));
+    temp_dir
+        .child(format!("{REFILE_BASE}/.refile-manifest.json"))
+        .assert(predicates::path::is_file());
+}
+
+/// Tests that --humanize only affects human output.
+///
+/// **User Story**: User reads growth reports in binary units but pipes the
+/// JSON stats into tooling that expects raw byte counts.
+///
+/// **Scenario**: An old file is refiled with `--report-growth --humanize` twice,
+/// then `--stats-json --humanize` is run.
+///
+/// **Expected**:
+/// - The growth report shows sizes with units (`12 B`)
+/// - The JSON stats keep numeric byte counts
+#[test]
+fn test_humanize_keeps_json_numeric() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "first.txt", OLD_FILE_AGE).expect("Failed to create first.txt");
+
+    for _ in 0..2 {
+        refile_cmd()
+            .arg("--report-growth")
+            .arg("--humanize")
+            .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+            .assert()
+            .success();
+    }
+    create_file_with_age(source, "second.txt", OLD_FILE_AGE).expect("Failed to create second.txt");
+    refile_cmd()
+        .arg("--report-growth")
+        .arg("--humanize")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "old-stuff: +1 files, +12 B (2 files, 24 B)",
+        ));
+
+    let output = refile_cmd()
+        .arg("--stats-json")
+        .arg("--humanize")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .output()
+        .expect("Failed to run refile");
+    assert!(output.status.success());
+    let stats: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("Output is not valid JSON");
+    assert!(stats["buckets"]["old-stuff"]["bytes"].is_u64());
+}
+
+/// Tests that EXIF and file name dates are read in the --time-zone.
+///
+/// **User Story**: User's camera stamps photos in Tokyo time and scans are
+/// named after the local date; neither should be read as UTC.
+///
+/// **Scenario**: A JPEG taken at 2024-03-07 00:00 and a PDF named
+/// `scan-2024-03-07.pdf` are refiled with the clock pinned to 2024-03-14
+/// 20:00 UTC, once with `--time-zone utc` and once with `--time-zone +09:00`.
+///
+/// **Expected**:
+/// - Read as UTC both are 7 days old → last-week/
+/// - Read in Tokyo time both are 8 days old → current-month/
+#[test]
+fn test_zoneless_dates_use_time_zone() {
+    let config_home = TempDir::new().expect("Failed to create config directory");
+    write_config(
+        config_home.path(),
+        "[age_sources]\njpg = \"exif\"\npdf = \"filename\"\n",
+    )
+    .expect("Failed to write config");
+
+    for (zone, bucket) in [("utc", LAST_WEEK_BUCKET), ("+09:00", CURRENT_MONTH_BUCKET)] {
+        let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+        temp_dir
+            .child("photo.jpg")
+            .write_binary(&jpeg_with_exif_date("2024:03:07 00:00:00"))
+            .expect("Failed to write photo.jpg");
+        temp_dir
+            .child("scan-2024-03-07.pdf")
+            .write_str("test content")
+            .expect("Failed to write scan");
+
+        refile_cmd()
+            .env("XDG_CONFIG_HOME", config_home.path())
+            .env("REFILE_NOW", "1710446400")
+            .args(["--time-zone", zone])
+            .arg(temp_dir.path())
+            .assert()
+            .success();
+        for name in ["photo.jpg", "scan-2024-03-07.pdf"] {
+            temp_dir
+                .child(format!("{bucket}/{name}"))
+                .assert(predicates::path::exists());
+        }
+    }
+}
+
+/// Tests that --date-format controls how dates in file names are read.
+///
+/// **User Story**: User's scanner names files `scan-DD-MM-YYYY.pdf` and wants
+/// them bucketed by the scan date, not by when they were copied.
+///
+/// **Scenario**: `[age_sources]` maps `pdf` to `filename`. A freshly written
+/// PDF is named with a day-first date from 50 days ago; it is refiled once
+/// without and once with `--date-format %d-%m-%Y`.
+///
+/// **Expected**:
+/// - Without a format the day-first date is not guessed at → mtime → last-week/
+/// - With the format the name date is used → last-months/
+#[test]
+fn test_date_format_for_filename_dates() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let config_home = TempDir::new().expect("Failed to create config directory");
+    let source = temp_dir.path();
+
+    write_config(config_home.path(), "[age_sources]\npdf = \"filename\"\n")
+        .expect("Failed to write config");
+
+    let (year, month, day) = date_days_ago(LAST_MONTHS_AGE);
+    let name = format!("scan-{day:02}-{month:02}-{year}.pdf");
+    create_file_with_age(source, &name, 0).expect("Failed to create scan");
+
+    refile_cmd()
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/{name}"))
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .arg("--date-format")
+        .arg("%d-%m-%Y")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{LAST_MONTHS_BUCKET}/{name}"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --apply-if-under executes plans at or below the threshold.
+///
+/// **User Story**: User wants small cleanups applied in one step, without a
+/// separate dry-run that could go stale before the real run.
+///
+/// **Scenario**: Two files refiled with --apply-if-under 2.
+///
+/// **Expected**: Both files are moved.
+#[test]
+fn test_apply_if_under_applies_small_plans() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "a.txt", RECENT_FILE_AGE).expect("Failed to create a.txt");
+    create_file_with_age(source, "b.txt", OLD_FILE_AGE).expect("Failed to create b.txt");
+
+    refile_cmd()
+        .arg("--apply-if-under")
+        .arg("2")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/a.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/b.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --apply-if-under aborts larger plans for review.
+///
+/// **User Story**: User wants a safety net so a surprisingly large cleanup is
+/// shown before anything happens.
+///
+/// **Scenario**: Three files refiled with --apply-if-under 2.
+///
+/// **Expected**: The plan is printed, the command fails, and nothing is
+/// created or moved.
+#[test]
+fn test_apply_if_under_aborts_large_plans() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        create_file_with_age(source, name, RECENT_FILE_AGE).expect("Failed to create file");
+    }
+
+    refile_cmd()
+        .arg("--apply-if-under")
+        .arg("2")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("[plan] MOVE"))
+        .stderr(predicates::str::contains("3 moves planned"));
+
+    temp_dir
+        .child(REFILE_BASE)
+        .assert(predicates::path::missing());
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        temp_dir.child(name).assert(predicates::path::exists());
+    }
+}
+
+/// Tests that --reconcile-existing false sweeps only new top-level items.
+///
+/// **User Story**: User with a huge archive only wants to file new downloads
+/// quickly, without re-checking everything already in the buckets.
+///
+/// **Scenario**: An old file sits in last-week (it has aged since it was
+/// filed) and a new file sits in the source. Refile once with
+/// `--reconcile-existing false`, then again with the default.
+///
+/// **Expected**:
+/// - Toggle off: the new file is filed, the archived file is not re-scanned
+/// - Toggle on (default): the archived file moves on to old-stuff
+#[test]
+fn test_reconcile_existing_toggle() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    let last_week = source.join(LAST_WEEK_BUCKET);
+    fs::create_dir_all(&last_week).expect("Failed to create last-week bucket");
+
+    create_file_with_age(&last_week, "archived.txt", OLD_FILE_AGE)
+        .expect("Failed to create archived.txt");
+    create_file_with_age(source, "new.txt", RECENT_FILE_AGE).expect("Failed to create new.txt");
+
+    refile_cmd()
+        .arg("--reconcile-existing")
+        .arg("false")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/new.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/archived.txt"))
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/archived.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/new.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --dedupe-bucket skips files identical to an archived file.
+///
+/// **User Story**: User keeps downloading the same file and wants refile to
+/// stop piling up renamed copies of it.
+///
+/// **Scenario**: last-week already holds `file (1).txt`; the source has a
+/// byte-identical `file.txt` and a different `other.txt` of the same size.
+///
+/// **Expected**: `file.txt` is skipped as a duplicate and stays in the
+/// source; `other.txt` is filed normally.
+#[test]
+fn test_dedupe_bucket_skips_identical_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/file (1).txt"))
+        .write_str("test content")
+        .expect("Failed to create archived file");
+    create_file_with_age(source, "file.txt", RECENT_FILE_AGE).expect("Failed to create file.txt");
+    temp_dir
+        .child("other.txt")
+        .write_str("diff content")
+        .expect("Failed to create other.txt");
+
+    refile_cmd()
+        .arg("--dedupe-bucket")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("duplicate of"));
+
+    temp_dir
+        .child("file.txt")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/file.txt"))
+        .assert(predicates::path::missing());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/other.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --progress-interval reports progress with an ETA on stderr.
+///
+/// **User Story**: User refiling a large directory across filesystems wants
+/// to know how long the run will take.
+///
+/// **Scenario**: Three files refiled with `--progress-interval 1`.
+///
+/// **Expected**: The run succeeds, every file is moved, and a progress line
+/// with an ETA covering all moves appears on stderr.
+#[test]
+fn test_progress_interval_reports_eta() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        create_file_with_age(source, name, RECENT_FILE_AGE).expect("Failed to create file");
+    }
+
+    refile_cmd()
+        .arg("--progress-interval")
+        .arg("1")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Progress: 3/3 moves, 36 B/36 B"))
+        .stderr(predicates::str::contains("ETA"));
+
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        temp_dir
+            .child(format!("{LAST_WEEK_BUCKET}/{name}"))
+            .assert(predicates::path::exists());
+    }
+}
+
+/// Tests that --config-layer overrides the config file but not --buckets.
+///
+/// **User Story**: User wants to try an alternate bucket layout for one run
+/// without editing their main config.
+///
+/// **Scenario**: The config file's default section defines `cfg-*` buckets;
+/// a layer file defines `layer-*` buckets. A file is refiled with the layer,
+/// then another with the layer and `--buckets`.
+///
+/// **Expected**:
+/// - With the layer alone, the layer buckets win over the default section
+/// - With `--buckets` too, the CLI buckets win over the layer
+#[test]
+fn test_config_layer_precedence() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let config_home = TempDir::new().expect("Failed to create config directory");
+    let source = temp_dir.path();
+
+    write_config(
+        config_home.path(),
+        "[default.buckets]\ncfg-recent = 7\ncfg-old = \"null\"\n",
+    )
+    .expect("Failed to write config");
+    let layer = config_home.child("experiment.toml");
+    layer
+        .write_str("[buckets]\nlayer-recent = 7\nlayer-old = \"null\"\n")
+        .expect("Failed to write layer");
+
+    create_file_with_age(source, "first.txt", RECENT_FILE_AGE).expect("Failed to create file");
+    refile_cmd()
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .arg("--config-layer")
+        .arg(layer.path())
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{REFILE_BASE}/layer-recent/first.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{REFILE_BASE}/cfg-recent"))
+        .assert(predicates::path::missing());
+
+    create_file_with_age(source, "second.txt", RECENT_FILE_AGE).expect("Failed to create file");
+    refile_cmd()
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .arg("--config-layer")
+        .arg(layer.path())
+        .arg("--buckets")
+        .arg("cli-recent=7,cli-old=null")
+        .arg("--reconcile-existing")
+        .arg("false")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{REFILE_BASE}/cli-recent/second.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// **User Story**: As a user, I want my PDFs kept in buckets of their own,
+/// on a longer time scale than everything else.
+///
+/// **Scenario**: The config file's default section defines `recent` and `old`
+/// buckets, and `[default.extensions.pdf]` defines `papers` (up to a year)
+/// and `old-papers`. A PDF and a text file of the same age are refiled along
+/// with a fresh directory whose name ends in `.pdf`, then refile runs again.
+///
+/// **Expected**:
+/// - The text file lands in `old`, the PDF in `papers`
+/// - The directory has no extension and lands in the default `recent` bucket
+/// - The second run leaves everything in place
+#[test]
+fn test_extension_buckets() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let config_home = TempDir::new().expect("Failed to create config directory");
+    let source = temp_dir.path();
+
+    write_config(
+        config_home.path(),
+        "[default.buckets]\nrecent = 7\nold = \"null\"\n\n\
+         [default.extensions.pdf]\npapers = 365\nold-papers = \"null\"\n",
+    )
+    .expect("Failed to write config");
+    create_file_with_age(source, "paper.pdf", LAST_MONTHS_AGE).expect("Failed to create file");
+    create_file_with_age(source, "notes.txt", LAST_MONTHS_AGE).expect("Failed to create file");
+    temp_dir
+        .child("bundle.pdf")
+        .create_dir_all()
+        .expect("Failed to create directory");
+
+    for _ in 0..2 {
+        refile_cmd()
+            .env("XDG_CONFIG_HOME", config_home.path())
+            .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+            .assert()
+            .success();
+        temp_dir
+            .child(format!("{REFILE_BASE}/papers/paper.pdf"))
+            .assert(predicates::path::exists());
+        temp_dir
+            .child(format!("{REFILE_BASE}/old/notes.txt"))
+            .assert(predicates::path::exists());
+        temp_dir
+            .child(format!("{REFILE_BASE}/recent/bundle.pdf"))
+            .assert(predicates::path::is_dir());
+    }
+}
+
+/// Tests that --verify-idempotent confirms a stable layout.
+///
+/// **User Story**: A maintainer wants to check that a configuration settles
+/// after one run instead of reshuffling files forever.
+///
+/// **Scenario**: Files of every age are refiled with the default buckets and
+/// `--verify-idempotent`.
+///
+/// **Expected**:
+/// - The run succeeds and the files are moved
+/// - The second plan is reported as empty
+#[test]
+fn test_verify_idempotent_stable() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create recent.txt");
+    create_file_with_age(source, "medium.txt", MEDIUM_FILE_AGE)
+        .expect("Failed to create medium.txt");
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+
+    refile_cmd()
+        .arg("--verify-idempotent")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "Idempotent: planning again would move nothing",
+        ));
+
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/recent.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{CURRENT_MONTH_BUCKET}/medium.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --verify-idempotent catches a layout that keeps reshuffling.
+///
+/// **User Story**: A maintainer wants combinations of options that move the
+/// same items again on the next run to be reported, not silently applied.
+///
+/// **Scenario**: An old file is refiled with a post-move hook that touches
+/// each moved item, so it is recent again once it lands in old-stuff.
+///
+/// **Expected**:
+/// - The run fails, reporting the move the second plan would make
+/// - The first plan's moves were still performed
+#[cfg(unix)]
+#[test]
+fn test_verify_idempotent_reshuffling() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let scripts = TempDir::new().expect("Failed to create script directory");
+    let source = temp_dir.path();
+    let hook = write_script(scripts.path(), "touch.sh", "touch \"$2\"");
+
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+    refile_cmd()
+        .arg("--post-move-hook")
+        .arg(&hook)
+        .arg("--verify-idempotent")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("[second plan] MOVE"))
+        .stderr(predicates::str::contains(
+            "Not idempotent: planning again would make 1 more moves",
+        ));
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Mounts a fresh tmpfs on `dir`, unmounting it again when dropped.
+#[cfg(unix)]
+struct TmpfsMount(std::path::PathBuf);
+
+#[cfg(unix)]
+impl TmpfsMount {
+    /// Returns `None` if mounting isn't possible here (e.g. not running as root).
+    fn new(dir: &Path) -> Option<Self> {
+        Self::with_options(dir, "defaults")
+    }
+
+    /// Mounts with the given `-o` options, e.g. `noatime`.
+    fn with_options(dir: &Path, options: &str) -> Option<Self> {
+        fs::create_dir_all(dir).expect("Failed to create mount point");
+        let mounted = std::process::Command::new("mount")
+            .args(["-t", "tmpfs", "-o", options, "tmpfs"])
+            .arg(dir)
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success());
+        mounted.then(|| Self(dir.to_path_buf()))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for TmpfsMount {
+    fn drop(&mut self) {
+        let _ = std::process::Command::new("umount").arg(&self.0).status();
+    }
+}
+
+/// Tests that --same-device-only leaves other filesystems alone.
+///
+/// **User Story**: User's source directory contains mount points of other
+/// filesystems, which refile should neither move nor descend into.
+///
+/// **Scenario**: The source holds a local file, a mount point, and a directory
+/// that is exploded and has a mount point nested inside it. Skipped when no
+/// tmpfs can be mounted.
+///
+/// **Expected**:
+/// - Local files are refiled
+/// - The mount point and everything below both mounts stay in place
+#[cfg(unix)]
+#[test]
+fn test_same_device_only() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let Some(media) = TmpfsMount::new(&source.join("media")) else {
+        eprintln!("Skipping test_same_device_only: cannot mount a tmpfs");
+        return;
+    };
+    let dump = source.join("dump");
+    let Some(nested) = TmpfsMount::new(&dump.join("mnt")) else {
+        eprintln!("Skipping test_same_device_only: cannot mount a tmpfs");
+        return;
+    };
+
+    create_file_with_age(source, "local.txt", OLD_FILE_AGE).expect("Failed to create local.txt");
+    create_file_with_age(&dump, "dumped.txt", OLD_FILE_AGE).expect("Failed to create dumped.txt");
+    create_file_with_age(&media.0, "photo.jpg", OLD_FILE_AGE).expect("Failed to create photo.jpg");
+    create_file_with_age(&nested.0, "backup.tar", OLD_FILE_AGE)
+        .expect("Failed to create backup.tar");
+
+    refile_cmd()
+        .arg("--same-device-only")
+        .arg("--explode-dirs")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "media: on a different device than the source",
+        ));
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/local.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/dumped.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("media/photo.jpg")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("dump/mnt/backup.tar")
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --anchor-to midnight ages every item against the same midnight.
+///
+/// **User Story**: A nightly cron job should classify a file the same way no
+/// matter at what time of day it happens to run.
+///
+/// **Scenario**: Relative to today's midnight in `--time-zone utc`, one file is
+/// a minute short of 8 days old and another is a minute past 8 days old; a
+/// third file was modified after midnight.
+///
+/// **Expected**:
+/// - Ages are measured from midnight, so the first file stays in last-week
+///   even though it is more than 8 days old by the wall clock
+/// - The second file lands in current-month
+/// - The file modified after midnight counts as brand new
+#[test]
+fn test_anchor_to_midnight() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Clock before epoch")
+        .as_secs();
+    let midnight = SystemTime::UNIX_EPOCH + Duration::from_secs(now - now % SECONDS_PER_DAY);
+
+    let create_file_at = |name: &str, mtime: SystemTime| {
+        let path = source.join(name);
+        fs::write(&path, "test content").expect("Failed to write file");
+        filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(mtime))
+            .expect("Failed to set mtime");
+    };
+    let eight_days = Duration::from_secs(8 * SECONDS_PER_DAY);
+    create_file_at("short.txt", midnight - eight_days + Duration::from_mins(1));
+    create_file_at("past.txt", midnight - eight_days - Duration::from_mins(1));
+    create_file_at("today.txt", SystemTime::now());
+
+    refile_cmd()
+        .arg("--anchor-to")
+        .arg("midnight")
+        .args(["--time-zone", "utc"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/short.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{CURRENT_MONTH_BUCKET}/past.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/today.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --rename-numbering append never reuses freed version numbers.
+///
+/// **User Story**: User keeps numbered versions of a file in a bucket and
+/// deletes some of them; new versions should still sort after the old ones.
+///
+/// **Scenario**: The bucket only holds `file (3).txt`; `file.txt` and its
+/// earlier versions were deleted. A new old `file.txt` is refiled with
+/// `--allow-rename --rename-numbering append`.
+///
+/// **Expected**:
+/// - The file becomes `file (4).txt`
+/// - Neither the free base name nor a lower free number is used
+/// - Without `--on-conflict rename` the numbering mode is refused
+#[test]
+fn test_rename_numbering_append() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let bucket = source.join(OLD_STUFF_BUCKET);
+    fs::create_dir_all(&bucket).expect("Failed to create bucket directory");
+    create_file_with_age(&bucket, "file (3).txt", OLD_FILE_AGE)
+        .expect("Failed to create file (3).txt");
+    create_file_with_age(source, "file.txt", OLD_FILE_AGE).expect("Failed to create file.txt");
+
+    refile_cmd()
+        .args(["--rename-numbering", "append"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("requires --on-conflict rename"));
+
+    refile_cmd()
+        .arg("--allow-rename")
+        .args(["--rename-numbering", "append"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/file (4).txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/file (3).txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/file.txt"))
+        .assert(predicates::path::missing());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/file (1).txt"))
+        .assert(predicates::path::missing());
+}
+
+/// Tests that --rename-numbering append continues a series with gaps.
+///
+/// **User Story**: User keeps versioned documents in a bucket and wants a new
+/// version to be numbered after all existing ones, even if one was deleted.
+///
+/// **Scenario**: The bucket holds `report.pdf`, `report (1).pdf` and
+/// `report (3).pdf`, leaving a gap at 2. A new old `report.pdf` is refiled
+/// with `--allow-rename --rename-numbering append`.
+///
+/// **Expected**:
+/// - The file becomes `report (4).pdf`
+/// - The gap at 2 is not filled
+#[test]
+fn test_rename_numbering_append_fills_no_gaps() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let bucket = source.join(OLD_STUFF_BUCKET);
+    fs::create_dir_all(&bucket).expect("Failed to create bucket directory");
+    for name in ["report.pdf", "report (1).pdf", "report (3).pdf"] {
+        create_file_with_age(&bucket, name, OLD_FILE_AGE)
+            .unwrap_or_else(|_| panic!("Failed to create {name}"));
+    }
+    create_file_with_age(source, "report.pdf", OLD_FILE_AGE).expect("Failed to create report.pdf");
+
+    refile_cmd()
+        .arg("--allow-rename")
+        .args(["--rename-numbering", "append"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/report (4).pdf"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/report (2).pdf"))
+        .assert(predicates::path::missing());
+}
+
+/// Tests that --source-metadata-cache stores timestamps and reuses them.
+///
+/// **User Story**: User refiles a huge directory every night and wants
+/// unchanged items to skip the timestamp lookup on later runs.
+///
+/// **Scenario**: Files of different ages are refiled twice with
+/// `--source-metadata-cache`, then a third time with `--by atime`, the old
+/// file having just been read.
+///
+/// **Expected**:
+/// - The first run writes the cache to the base folder, keyed by the moved paths
+/// - The second run reuses every cached timestamp
+/// - Both runs leave the files in the same buckets, and the cache itself is never moved
+/// - The third run reuses nothing, and moves the old file by its access time
+#[test]
+fn test_source_metadata_cache() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create recent.txt");
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+
+    let run = || {
+        refile_cmd()
+            .arg("--source-metadata-cache")
+            .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+            .assert()
+            .success()
+    };
+    let assert_layout = || {
+        temp_dir
+            .child(format!("{LAST_WEEK_BUCKET}/recent.txt"))
+            .assert(predicates::path::exists());
+        temp_dir
+            .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+            .assert(predicates::path::exists());
+    };
+
+    run().stdout(predicates::str::contains("Reused 0 cached timestamps"));
+    assert_layout();
+
+    let cache = fs::read_to_string(source.join(REFILE_BASE).join(".refile-cache.json"))
+        .expect("Failed to read metadata cache");
+    assert!(cache.contains("last-week/recent.txt"));
+    assert!(cache.contains("old-stuff/old.txt"));
+
+    run().stdout(predicates::str::contains("Reused 2 cached timestamps"));
+    assert_layout();
+    temp_dir
+        .child(format!("{REFILE_BASE}/.refile-cache.json"))
+        .assert(predicates::path::exists());
+
+    let old = source.join(OLD_STUFF_BUCKET).join("old.txt");
+    filetime::set_file_atime(&old, filetime::FileTime::now()).expect("Failed to set atime");
+    refile_cmd()
+        .args(["--source-metadata-cache", "--by", "atime"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Reused 0 cached timestamps"));
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --report-orphans lists stray items under the base folder.
+///
+/// **User Story**: User wants to spot files accidentally dropped directly into
+/// `refile/` instead of into one of its buckets.
+///
+/// **Scenario**: The base folder holds a bucket with a file, a stray file and
+/// a stray directory, and the source holds an unrelated file.
+///
+/// **Expected**:
+/// - The stray file and directory are reported
+/// - Bucket directories and their contents are not
+/// - Nothing is moved
+#[test]
+fn test_report_orphans() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let bucket = source.join(OLD_STUFF_BUCKET);
+    fs::create_dir_all(&bucket).expect("Failed to create bucket directory");
+    create_file_with_age(&bucket, "archived.txt", OLD_FILE_AGE)
+        .expect("Failed to create archived.txt");
+    create_file_with_age(&source.join(REFILE_BASE), "stray.txt", OLD_FILE_AGE)
+        .expect("Failed to create stray.txt");
+    fs::create_dir(source.join(REFILE_BASE).join("misc")).expect("Failed to create misc");
+    create_file_with_age(source, "source.txt", OLD_FILE_AGE).expect("Failed to create source.txt");
+
+    let output = refile_cmd()
+        .arg("--report-orphans")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let stdout = String::from_utf8_lossy(&output);
+    assert!(stdout.contains("2 stray items under"));
+    assert!(stdout.contains("stray.txt"));
+    assert!(stdout.contains("misc"));
+    assert!(!stdout.contains("old-stuff"));
+    assert!(!stdout.contains("source.txt"));
+
+    temp_dir
+        .child(format!("{REFILE_BASE}/stray.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("source.txt")
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --classify-by type,age nests age buckets inside file types.
+///
+/// **User Story**: User wants photos and documents kept apart first, and
+/// each sorted by age within.
+///
+/// **Scenario**: An old image and a recent document are refiled with
+/// `--classify-by type,age`, then the same run is repeated.
+///
+/// **Expected**:
+/// - The image lands in `images/old-stuff/` and the document in
+///   `documents/last-week/`
+/// - The repeated run recognizes the nested layout and moves nothing
+#[test]
+fn test_classify_by_type_and_age() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "photo.jpg", OLD_FILE_AGE).expect("Failed to create photo.jpg");
+    create_file_with_age(source, "notes.pdf", RECENT_FILE_AGE).expect("Failed to create notes.pdf");
+
+    for _ in 0..2 {
+        refile_cmd()
+            .arg("--classify-by")
+            .arg("type,age")
+            .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("refile/images ->").not());
+
+        temp_dir
+            .child(format!("{REFILE_BASE}/images/old-stuff/photo.jpg"))
+            .assert(predicates::path::exists());
+        temp_dir
+            .child(format!("{REFILE_BASE}/documents/last-week/notes.pdf"))
+            .assert(predicates::path::exists());
+    }
+    temp_dir
+        .child(OLD_STUFF_BUCKET)
+        .assert(predicates::path::missing());
+}
+
+/// Tests that --error-report records failed moves.
+///
+/// **User Story**: User runs a large sweep unattended and wants every failed
+/// move captured in a machine-readable file, while the rest still moves.
+///
+/// **Scenario**: With `--classify-by type,age`, a regular file named `images`
+/// sits where the images directory would be created, so moving an image
+/// fails. A document moves normally.
+///
+/// **Expected**:
+/// - The run fails at the end with exit code 2, after moving the document
+/// - The report is a JSON line with the image's path, the operation and the
+///   error kind
+#[test]
+fn test_error_report() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    let report = temp_dir.path().join("errors.jsonl");
+
+    fs::create_dir(source.join(REFILE_BASE)).expect("Failed to create base folder");
+    fs::write(source.join(REFILE_BASE).join("images"), "not a directory")
+        .expect("Failed to create images file");
+    create_file_with_age(source, "photo.jpg", OLD_FILE_AGE).expect("Failed to create photo.jpg");
+    create_file_with_age(source, "notes.pdf", OLD_FILE_AGE).expect("Failed to create notes.pdf");
+
+    refile_cmd()
+        .arg("--classify-by")
+        .arg("type,age")
+        .arg("--reconcile-existing")
+        .arg("false")
+        .arg("--error-report")
+        .arg(&report)
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("1 of 2 moves failed"));
+
+    temp_dir
+        .child(format!("{REFILE_BASE}/documents/old-stuff/notes.pdf"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("photo.jpg")
+        .assert(predicates::path::exists());
+
+    let contents = fs::read_to_string(&report).expect("Failed to read error report");
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 1);
+    let record: serde_json::Value =
+        serde_json::from_str(lines[0]).expect("Error report line is not JSON");
+    assert!(
+        record["path"]
+            .as_str()
+            .is_some_and(|p| p.ends_with("photo.jpg"))
+    );
+    assert_eq!(record["operation"], "move");
+    assert_eq!(record["kind"], "NotADirectory");
+    assert!(record["error"].as_str().is_some_and(|e| !e.is_empty()));
+}
+
+/// Tests that a run with failed moves still finishes before failing.
+///
+/// **User Story**: User relies on the rename report and the post-run hook,
+/// and needs them even when one move out of many fails.
+///
+/// **Scenario**: As in `test_error_report`, moving `photo.jpg` fails while
+/// `notes.pdf` is renamed past an archived file of the same name, with
+/// `--rename-report` and a post-run hook.
+///
+/// **Expected**:
+/// - The run exits with code 2
+/// - The rename report lists the rename of `notes.pdf`
+/// - The post-run hook ran, counting the one move made
+#[cfg(unix)]
+#[test]
+fn test_partial_failure_finishes_run() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let scripts = TempDir::new().expect("Failed to create script directory");
+    let source = temp_dir.path();
+    let renames = scripts.path().join("renames.jsonl");
+    let record = scripts.path().join("record.txt");
+    let hook = write_script(
+        scripts.path(),
+        "hook.sh",
+        &format!("echo \"$REFILE_MOVED\" > '{}'", record.display()),
+    );
+
+    let documents = source.join(REFILE_BASE).join("documents/old-stuff");
+    fs::create_dir_all(&documents).expect("Failed to create bucket");
+    fs::write(source.join(REFILE_BASE).join("images"), "not a directory")
+        .expect("Failed to create images file");
+    create_file_with_age(&documents, "notes.pdf", OLD_FILE_AGE)
+        .expect("Failed to create archived notes.pdf");
+    create_file_with_age(source, "photo.jpg", OLD_FILE_AGE).expect("Failed to create photo.jpg");
+    create_file_with_age(source, "notes.pdf", OLD_FILE_AGE).expect("Failed to create notes.pdf");
+
+    refile_cmd()
+        .args(["--classify-by", "type,age", "--on-conflict", "rename"])
+        .args(["--reconcile-existing", "false"])
+        .arg("--rename-report")
+        .arg(&renames)
+        .arg("--post-run-hook")
+        .arg(&hook)
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("1 of 2 moves failed"));
+
+    temp_dir
+        .child(format!("{REFILE_BASE}/documents/old-stuff/notes (1).pdf"))
+        .assert(predicates::path::exists());
+    let report = fs::read_to_string(&renames).expect("Failed to read rename report");
+    assert_eq!(report.lines().count(), 1);
+    assert!(report.contains("notes (1).pdf"));
+    assert_eq!(
+        fs::read_to_string(&record).expect("post-run hook did not run"),
+        "1\n"
+    );
+}
+
+/// Tests that --skip-unreadable turns permission errors into skips.
+///
+/// **User Story**: User sweeps a directory holding a few files owned by
+/// another user, and wants the rest refiled instead of the run aborting.
+///
+/// **Scenario**: With `--dedupe-bucket`, a file the user may not read has the
+/// same size as an archived file, so planning needs to read it. Skipped when
+/// running as root, where the file stays readable.
+///
+/// **Expected**:
+/// - Without the flag the run aborts and nothing is moved
+/// - With the flag the file is skipped with reason "permission denied" and
+///   the other file is moved
+#[cfg(unix)]
+#[test]
+fn test_skip_unreadable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let bucket = source.join(OLD_STUFF_BUCKET);
+    fs::create_dir_all(&bucket).expect("Failed to create bucket directory");
+    create_file_with_age(&bucket, "archived.txt", OLD_FILE_AGE)
+        .expect("Failed to create archived.txt");
+    create_file_with_age(source, "secret.txt", OLD_FILE_AGE).expect("Failed to create secret.txt");
+    let secret = source.join("secret.txt");
+    fs::set_permissions(&secret, fs::Permissions::from_mode(0o000))
+        .expect("Failed to make secret.txt unreadable");
+    if fs::File::open(&secret).is_ok() {
+        eprintln!("Skipping test_skip_unreadable: permissions are not enforced (root?)");
+        return;
+    }
+    let other = source.join("other.txt");
+    fs::write(&other, "other content, other size").expect("Failed to write other.txt");
+    let mtime = SystemTime::now() - Duration::from_secs(OLD_FILE_AGE * SECONDS_PER_DAY);
+    filetime::set_file_mtime(&other, filetime::FileTime::from_system_time(mtime))
+        .expect("Failed to set mtime on other.txt");
+
+    refile_cmd()
+        .arg("--dedupe-bucket")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure();
+    temp_dir
+        .child("other.txt")
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("--dedupe-bucket")
+        .arg("--skip-unreadable")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("secret.txt: permission denied"));
+
+    temp_dir
+        .child("secret.txt")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/other.txt"))
+        .assert(predicates::path::exists());
+
+    fs::set_permissions(&secret, fs::Permissions::from_mode(0o644))
+        .expect("Failed to restore permissions");
+}
+
+/// Tests that a failed move does not stop the others.
+///
+/// **User Story**: User sweeps a directory holding an item they may not
+/// touch, and wants everything else refiled, with a distinct exit code
+/// telling a script that the run only partly succeeded.
+///
+/// **Scenario**: Among several old files sits an old directory with no
+/// permissions at all; moving a directory to another parent needs write
+/// access to it, so its move fails. The sweep runs once with `--fail-fast`,
+/// then without. Skipped when running as root, where the move succeeds.
+///
+/// **Expected**:
+/// - With `--fail-fast` the run stops at the directory with exit code 1,
+///   leaving the files after it in place
+/// - Without it every file is moved, the failure is listed, and the run
+///   exits with code 2
+#[cfg(unix)]
+#[test]
+fn test_partial_failure_exit_code() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    for name in ["a.txt", "b.txt", "m.txt", "z.txt"] {
+        create_file_with_age(source, name, OLD_FILE_AGE).expect("Failed to create file");
+    }
+    let locked = source.join("locked");
+    fs::create_dir(&locked).expect("Failed to create locked");
+    let mtime = SystemTime::now() - Duration::from_secs(OLD_FILE_AGE * SECONDS_PER_DAY);
+    filetime::set_file_mtime(&locked, filetime::FileTime::from_system_time(mtime))
+        .expect("Failed to set mtime on locked");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000))
+        .expect("Failed to make locked unreadable");
+    if fs::read_dir(&locked).is_ok() {
+        eprintln!("Skipping test_partial_failure_exit_code: permissions are not enforced (root?)");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))
+            .expect("Failed to restore permissions");
+        return;
+    }
+
+    refile_cmd()
+        .arg("--fail-fast")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .code(1);
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/b.txt"))
+        .assert(predicates::path::exists());
+    temp_dir.child("z.txt").assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .code(2)
+        .stderr(predicates::str::contains("1 of 3 moves failed"))
+        .stderr(predicates::str::contains("locked: Permission denied"));
+    for name in ["a.txt", "b.txt", "m.txt", "z.txt"] {
+        temp_dir
+            .child(format!("{OLD_STUFF_BUCKET}/{name}"))
+            .assert(predicates::path::exists());
+    }
+    temp_dir.child("locked").assert(predicates::path::exists());
+
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755))
+        .expect("Failed to restore permissions");
+}
+
+/// Tests that refile refuses to nest the source inside its own base folder.
+///
+/// **User Story**: User passes a target or base folder that makes the base
+/// folder resolve to the source directory (or above it), and expects a clear
+/// error instead of the source being shuffled into itself.
+///
+/// **Scenario**: The source is `<dir>/refile` with `<dir>` as target, and
+/// separately a source with `--base-folder ..`.
+///
+/// **Expected**:
+/// - Both runs abort with an error naming the problem
+/// - Nothing is moved
+#[test]
+fn test_base_folder_containing_source_is_refused() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+
+    let nested = temp_dir.path().join(REFILE_BASE);
+    fs::create_dir(&nested).expect("Failed to create source directory");
+    create_file_with_age(&nested, "file.txt", OLD_FILE_AGE).expect("Failed to create file.txt");
+
+    refile_cmd()
+        .arg(nested.to_str().expect("Test path contains invalid UTF-8"))
+        .arg(
+            temp_dir
+                .path()
+                .to_str()
+                .expect("Test path contains invalid UTF-8"),
+        )
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "refusing to move the source into itself",
+        ));
+
+    refile_cmd()
+        .arg("--base-folder")
+        .arg("..")
+        .arg(nested.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "refusing to move the source into itself",
+        ));
+
+    temp_dir
+        .child(format!("{REFILE_BASE}/file.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Helper to write an executable shell script
+#[cfg(unix)]
+fn write_script(dir: &Path, name: &str, body: &str) -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{body}\n")).expect("Failed to write script");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+        .expect("Failed to make script executable");
+    path
+}
+
+/// Tests that --classify-command chooses the bucket of each item.
+///
+/// **User Story**: User has their own rules for where files belong and wants
+/// refile to ask an external program instead of going by age.
+///
+/// **Scenario**: A script that always prints `old-stuff` classifies a recent
+/// file. A second script prints a name that is not a bucket.
+///
+/// **Expected**:
+/// - The recent file lands in old-stuff/ as the script chose
+/// - An unknown bucket name aborts the run, unless `--unknown-bucket
+///   catch-all` routes the item to the catch-all bucket
+#[cfg(unix)]
+#[test]
+fn test_classify_command_chooses_bucket() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let scripts = TempDir::new().expect("Failed to create script directory");
+    let source = temp_dir.path();
+
+    let fixed = write_script(scripts.path(), "fixed.sh", "echo old-stuff");
+    let unknown = write_script(scripts.path(), "unknown.sh", "echo no-such-bucket");
+
+    create_file_with_age(source, "recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create recent.txt");
+    refile_cmd()
+        .arg("--classify-command")
+        .arg(&fixed)
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/recent.txt"))
+        .assert(predicates::path::exists());
+
+    create_file_with_age(source, "other.txt", RECENT_FILE_AGE).expect("Failed to create other.txt");
+    refile_cmd()
+        .arg("--classify-command")
+        .arg(&unknown)
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "chose unknown bucket \\\"no-such-bucket\\\"",
+        ));
+    temp_dir
+        .child("other.txt")
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("--classify-command")
+        .arg(&unknown)
+        .arg("--unknown-bucket")
+        .arg("catch-all")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/other.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --move-report diffs the listings taken before and after the run.
+///
+/// **User Story**: User wants a single summary confirming what left the
+/// source and what arrived in the buckets, rather than reading every move line.
+///
+/// **Scenario**: A recent and an old file are refiled with `--move-report`,
+/// then refiled again with nothing left to do.
+///
+/// **Expected**:
+/// - The first report lists both files as removed and their bucket paths as
+///   added
+/// - The second report shows no changes
+#[test]
+fn test_move_report_lists_changes() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create recent.txt");
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+
+    refile_cmd()
+        .arg("--move-report")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "  Removed (2):\n    - old.txt\n    - recent.txt\n",
+        ))
+        .stdout(predicates::str::contains(format!(
+            "  Added (2):\n    + {LAST_WEEK_BUCKET}/recent.txt\n    + {OLD_STUFF_BUCKET}/old.txt\n"
+        )));
+
+    refile_cmd()
+        .arg("--move-report")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Move report: no changes"));
+}
+
+/// Tests that --config-env-overlay reads buckets from `REFILE_BUCKETS`.
+///
+/// **User Story**: User runs refile in a container and passes the bucket
+/// layout through the environment instead of mounting a config file.
+///
+/// **Scenario**: `REFILE_BUCKETS="today=1,week=7,old=null"` is set; an old
+/// file is refiled with and without `--config-env-overlay`.
+///
+/// **Expected**:
+/// - Without the flag the variable is ignored → default old-stuff/
+/// - With the flag the file lands in the environment's catch-all old/
+#[test]
+fn test_config_env_overlay_buckets() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "first.txt", OLD_FILE_AGE).expect("Failed to create first.txt");
+    refile_cmd()
+        .env("REFILE_BUCKETS", "today=1,week=7,old=null")
+        .arg("--reconcile-existing=false")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/first.txt"))
+        .assert(predicates::path::exists());
+
+    create_file_with_age(source, "second.txt", OLD_FILE_AGE).expect("Failed to create second.txt");
+    refile_cmd()
+        .env("REFILE_BUCKETS", "today=1,week=7,old=null")
+        .arg("--config-env-overlay")
+        .arg("--reconcile-existing=false")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{REFILE_BASE}/old/second.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that operating system clutter files are left in place.
+///
+/// **User Story**: User refiles a folder shared with macOS and Windows
+/// machines and doesn't want `.DS_Store` files scattered into the buckets.
+///
+/// **Scenario**: An old `.DS_Store` next to an old normal file, refiled with
+/// the default settings and then with `--skip-system-files false`.
+///
+/// **Expected**:
+/// - By default `.DS_Store` stays in the source while the normal file moves
+/// - With the skip disabled `.DS_Store` is bucketed like any other file
+#[test]
+fn test_skip_system_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, ".DS_Store", OLD_FILE_AGE).expect("Failed to create .DS_Store");
+    create_file_with_age(source, "normal.txt", OLD_FILE_AGE).expect("Failed to create normal.txt");
+
+    // System files are dotfiles too, which are only collected with --hidden
+    refile_cmd()
+        .arg("--hidden")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(".DS_Store: system file"));
+    temp_dir
+        .child(".DS_Store")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/normal.txt"))
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("--hidden")
+        .arg("--skip-system-files")
+        .arg("false")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/.DS_Store"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --target-date-subdir archives each run into a dated snapshot.
+///
+/// **User Story**: User takes periodic snapshots and wants every run to
+/// create its own dated organization, keeping earlier ones as history.
+///
+/// **Scenario**: An old file is refiled with `--target-date-subdir` in place
+/// (target = source); a second run picks up another file.
+///
+/// **Expected**:
+/// - Files land under `<source>/<YYYY-MM-DD>/refile/<bucket>/`
+/// - The dated snapshot itself is never refiled by the next run
+#[test]
+fn test_target_date_subdir() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let (year, month, day) = date_days_ago(0);
+    let snapshot = format!("{year:04}-{month:02}-{day:02}");
+
+    create_file_with_age(source, "first.txt", OLD_FILE_AGE).expect("Failed to create first.txt");
+    refile_cmd()
+        .arg("--target-date-subdir")
+        .args(["--time-zone", "utc"])
+        .arg("--anchor-to")
+        .arg("run-start")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{snapshot}/{OLD_STUFF_BUCKET}/first.txt"))
+        .assert(predicates::path::exists());
+
+    create_file_with_age(source, "second.txt", RECENT_FILE_AGE)
+        .expect("Failed to create second.txt");
+    refile_cmd()
+        .arg("--target-date-subdir")
+        .args(["--time-zone", "utc"])
+        .arg("--anchor-to")
+        .arg("run-start")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{snapshot}/{LAST_WEEK_BUCKET}/second.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{snapshot}/{OLD_STUFF_BUCKET}/first.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that --target-date-subdir names the snapshot after the local date.
+///
+/// **User Story**: User in Tokyo runs refile in the morning and expects the
+/// snapshot to carry today's date, not yesterday's UTC date.
+///
+/// **Scenario**: The clock is pinned to 2024-03-15 08:30 in `TZ=JST-9`
+/// (2024-03-14 23:30 UTC), and an old file is refiled with
+/// `--target-date-subdir`, then again with `--time-zone utc`.
+///
+/// **Expected**:
+/// - By default the snapshot is `2024-03-15`, the local date
+/// - With `--time-zone utc` it is `2024-03-14`
+#[test]
+fn test_target_date_subdir_local_date() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+    for (zone, snapshot) in [("local", "2024-03-15"), ("utc", "2024-03-14")] {
+        let file = source.join(format!("{zone}.txt"));
+        fs::write(&file, "test content").expect("Failed to create file");
+        filetime::set_file_mtime(&file, filetime::FileTime::from_system_time(old))
+            .expect("Failed to set mtime");
+        refile_cmd()
+            .env("TZ", "JST-9")
+            .env("REFILE_NOW", "1710459000")
+            .arg("--target-date-subdir")
+            .args(["--time-zone", zone])
+            .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+            .assert()
+            .success();
+        temp_dir
+            .child(format!("{snapshot}/{OLD_STUFF_BUCKET}/{zone}.txt"))
+            .assert(predicates::path::exists());
+    }
+}
+
+/// Tests that an unwritable bucket aborts the run before anything moves.
+///
+/// **User Story**: User's archive has a bucket they can't write to, and
+/// wants the run to fail cleanly instead of leaving a half-done refile.
+///
+/// **Scenario**: old-stuff/ is read-only; a recent file (sorted first) and
+/// an old file are refiled. Skipped when running as root, where the bucket
+/// stays writable.
+///
+/// **Expected**:
+/// - The run aborts naming the unwritable bucket
+/// - Neither file is moved, not even the one bound for a writable bucket
+#[cfg(unix)]
+#[test]
+fn test_preflight_aborts_on_unwritable_bucket() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let bucket = source.join(OLD_STUFF_BUCKET);
+    fs::create_dir_all(&bucket).expect("Failed to create bucket directory");
+    fs::set_permissions(&bucket, fs::Permissions::from_mode(0o555))
+        .expect("Failed to make the bucket read-only");
+    if fs::write(bucket.join("probe"), "").is_ok() {
+        eprintln!(
+            "Skipping test_preflight_aborts_on_unwritable_bucket: permissions are not enforced (root?)"
+        );
+        return;
+    }
+
+    create_file_with_age(source, "a-recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create a-recent.txt");
+    create_file_with_age(source, "b-old.txt", OLD_FILE_AGE).expect("Failed to create b-old.txt");
+
+    refile_cmd()
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("is not writable"));
+    temp_dir
+        .child("a-recent.txt")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("b-old.txt")
+        .assert(predicates::path::exists());
+
+    fs::set_permissions(&bucket, fs::Permissions::from_mode(0o755))
+        .expect("Failed to restore permissions");
+}
+
+/// Tests that --reverse-age treats bucket ages as lower bounds.
+///
+/// **User Story**: User wants the newest files split out by day and
+/// everything older lumped into one bucket.
+///
+/// **Scenario**: Buckets `rest=7,recent=2,today=null` with `--reverse-age`;
+/// files aged 3, 15 and 100 days plus a fresh one.
+///
+/// **Expected**:
+/// - The 15- and 100-day-old files share rest/
+/// - The 3-day-old file lands in recent/, the fresh file in today/
+#[test]
+fn test_reverse_age_lumps_old_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "fresh.txt", 0).expect("Failed to create fresh.txt");
+    create_file_with_age(source, "recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create recent.txt");
+    create_file_with_age(source, "medium.txt", MEDIUM_FILE_AGE)
+        .expect("Failed to create medium.txt");
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+
+    refile_cmd()
+        .arg("--reverse-age")
+        .arg("--buckets")
+        .arg("rest=7,recent=2,today=null")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    for (bucket, name) in [
+        ("rest", "medium.txt"),
+        ("rest", "old.txt"),
+        ("recent", "recent.txt"),
+        ("today", "fresh.txt"),
+    ] {
+        temp_dir
+            .child(format!("{REFILE_BASE}/{bucket}/{name}"))
+            .assert(predicates::path::exists());
+    }
+}
+
+/// Tests that a symlink to its own destination is not taken as already in place.
+///
+/// **User Story**: User keeps a link in the source to a file that was
+/// archived earlier, and expects the link to be refiled like any other item
+/// rather than silently left behind.
+///
+/// **Scenario**: `report.txt` in the source is a symlink to the archived
+/// `refile/old-stuff/report.txt`, which is also where the link itself belongs.
+///
+/// **Expected**:
+/// - Without --allow-rename the run stops on the conflict
+/// - With --allow-rename the link moves to `report (1).txt` and still resolves
+#[cfg(unix)]
+#[test]
+fn test_symlink_to_destination_is_still_moved() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let bucket = source.join(OLD_STUFF_BUCKET);
+    fs::create_dir_all(&bucket).expect("Failed to create bucket directory");
+    create_file_with_age(&bucket, "report.txt", OLD_FILE_AGE).expect("Failed to create report.txt");
+    std::os::unix::fs::symlink(
+        format!("{OLD_STUFF_BUCKET}/report.txt"),
+        source.join("report.txt"),
+    )
+    .expect("Failed to create symlink");
+
+    refile_cmd()
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Conflict"));
+
+    refile_cmd()
+        .arg("--allow-rename")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child("report.txt")
+        .assert(predicates::path::missing());
+    let moved = temp_dir.child(format!("{OLD_STUFF_BUCKET}/report (1).txt"));
+    assert!(
+        fs::symlink_metadata(moved.path())
+            .expect("Moved link should exist")
+            .file_type()
+            .is_symlink()
+    );
+    assert_eq!(
+        fs::read_to_string(moved.path()).expect("Moved link should resolve"),
+        "test content"
+    );
+}
+
+/// Tests that a run with nothing to move says so.
+///
+/// **User Story**: User runs refile on a folder with nothing to organize and
+/// wants to know it ran rather than seeing no output at all.
+///
+/// **Scenario**: An empty source, then a source holding only a `.DS_Store`
+/// (refiled with `--hidden`).
+///
+/// **Expected**: Both runs print a summary line with the number of skips.
+#[test]
+fn test_empty_plan_prints_summary() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    refile_cmd()
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "No files to organize (0 moved, 0 skipped)",
+        ));
+
+    create_file_with_age(source, ".DS_Store", OLD_FILE_AGE).expect("Failed to create .DS_Store");
+    refile_cmd()
+        .arg("--hidden")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "No files to organize (0 moved, 1 skipped)",
+        ));
+}
+
+/// Tests that --ext restricts refiling to the listed extensions.
+///
+/// **User Story**: User only wants their PDFs organized and doesn't want to
+/// learn glob syntax to say so.
+///
+/// **Scenario**: Old `report.pdf`, `SCAN.PDF` and `notes.txt` refiled with
+/// `--ext pdf`.
+///
+/// **Expected**:
+/// - Both PDFs move, regardless of case
+/// - `notes.txt` stays with reason "extension not included"
+#[test]
+fn test_ext_filters_by_extension() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "report.pdf", OLD_FILE_AGE).expect("Failed to create report.pdf");
+    create_file_with_age(source, "SCAN.PDF", OLD_FILE_AGE).expect("Failed to create SCAN.PDF");
+    create_file_with_age(source, "notes.txt", OLD_FILE_AGE).expect("Failed to create notes.txt");
+
+    refile_cmd()
+        .arg("--ext")
+        .arg("pdf")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "notes.txt: extension not included",
+        ));
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/report.pdf"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/SCAN.PDF"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("notes.txt")
+        .assert(predicates::path::exists());
+}
+
+/// Tests that renames made to avoid conflicts are summarized and reported.
+///
+/// **User Story**: User runs with --allow-rename and wants to notice when
+/// files get suffixed, which may hint at unexpected duplication.
+///
+/// **Scenario**: An old `report.txt` is refiled while `old-stuff/report.txt`
+/// already exists, with `--rename-report` pointing outside the source.
+///
+/// **Expected**:
+/// - The run summarizes the rename with the intended and the actual name
+/// - The report holds one JSON line with the source, intended and final paths
+#[test]
+fn test_rename_report_lists_renames() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let report_dir = TempDir::new().expect("Failed to create report directory");
+    let source = temp_dir.path();
+    let report = report_dir.path().join("renames.jsonl");
+
+    let bucket = source.join(OLD_STUFF_BUCKET);
+    fs::create_dir_all(&bucket).expect("Failed to create bucket directory");
+    create_file_with_age(&bucket, "report.txt", OLD_FILE_AGE)
+        .expect("Failed to create archived report.txt");
+    create_file_with_age(source, "report.txt", OLD_FILE_AGE).expect("Failed to create report.txt");
+
+    let intended = bucket.join("report.txt");
+    let renamed = bucket.join("report (1).txt");
+    refile_cmd()
+        .arg("--allow-rename")
+        .arg("--rename-report")
+        .arg(&report)
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "Renamed 1 items to avoid conflicts:",
+        ))
+        .stdout(predicates::str::contains(format!(
+            "  {} -> {}",
+            intended.display(),
+            renamed.display()
+        )));
+
+    let contents = fs::read_to_string(&report).expect("Failed to read rename report");
+    let lines: Vec<serde_json::Value> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Report line is not valid JSON"))
+        .collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(
+        lines[0]["from"],
+        source.join("report.txt").display().to_string()
+    );
+    assert_eq!(lines[0]["intended"], intended.display().to_string());
+    assert_eq!(lines[0]["to"], renamed.display().to_string());
+}
+
+/// Tests that a config rule can send organized files to its own target.
+///
+/// **User Story**: User wants everything organized out of their downloads
+/// folder to land in a separate archive without passing it every time.
+///
+/// **Scenario**: A rule for the source sets `target` to an archive
+/// directory; refile runs once without and once with a CLI target.
+///
+/// **Expected**: Both times files land under `<archive>/refile/`; the rule's
+/// target overrides the CLI target, and nothing is created under the source.
+#[test]
+fn test_rule_target_redirects_output() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let archive = TempDir::new().expect("Failed to create archive directory");
+    let cli_target = TempDir::new().expect("Failed to create CLI target directory");
+    let config_home = TempDir::new().expect("Failed to create config directory");
+    let source = temp_dir.path();
+
+    write_config(
+        config_home.path(),
+        &format!(
+            "[[rules]]\npath = {:?}\ntarget = {:?}\n\n[rules.buckets]\nrecent = 7\nold = \"null\"\n",
+            source.to_str().expect("Test path contains invalid UTF-8"),
+            archive
+                .path()
+                .to_str()
+                .expect("Test path contains invalid UTF-8"),
+        ),
+    )
+    .expect("Failed to write config");
+
+    create_file_with_age(source, "first.txt", OLD_FILE_AGE).expect("Failed to create first.txt");
+    refile_cmd()
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    archive
+        .child(format!("{REFILE_BASE}/old/first.txt"))
+        .assert(predicates::path::exists());
+
+    create_file_with_age(source, "second.txt", RECENT_FILE_AGE)
+        .expect("Failed to create second.txt");
+    refile_cmd()
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .arg(
+            cli_target
+                .path()
+                .to_str()
+                .expect("Test path contains invalid UTF-8"),
+        )
+        .assert()
+        .success();
+    archive
+        .child(format!("{REFILE_BASE}/recent/second.txt"))
+        .assert(predicates::path::exists());
+
+    temp_dir
+        .child(REFILE_BASE)
+        .assert(predicates::path::missing());
+    cli_target
+        .child(REFILE_BASE)
+        .assert(predicates::path::missing());
+}
+
+/// Tests that ages can be counted from the access time.
+///
+/// **User Story**: User wants files they still open regularly to stay in the
+/// recent buckets, however long ago they were last modified.
+///
+/// **Scenario**: A file modified 100 days ago but read 3 days ago is refiled
+/// with `--by atime`.
+///
+/// **Expected**: The file goes to the last-week bucket
+#[test]
+fn test_by_atime() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "read.txt", OLD_FILE_AGE).expect("Failed to create read.txt");
+    let read = SystemTime::now() - Duration::from_secs(RECENT_FILE_AGE * SECONDS_PER_DAY);
+    filetime::set_file_atime(
+        source.join("read.txt"),
+        filetime::FileTime::from_system_time(read),
+    )
+    .expect("Failed to set access time");
+
+    refile_cmd()
+        .arg("--by")
+        .arg("atime")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/read.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that `--by atime` falls back to mtime on a `noatime` mount.
+///
+/// **User Story**: User asks for access times on a filesystem that doesn't
+/// keep them and should be told rather than get meaningless buckets.
+///
+/// **Scenario**: The source is a tmpfs mounted `noatime`, holding a file
+/// modified 100 days ago but read 3 days ago. Skipped when no tmpfs can be
+/// mounted.
+///
+/// **Expected**:
+/// - A one-line warning about the `noatime` mount on stderr
+/// - The file is bucketed by its modification time (old-stuff)
+#[cfg(target_os = "linux")]
+#[test]
+fn test_by_atime_falls_back_on_noatime_mount() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let Some(mount) = TmpfsMount::with_options(&temp_dir.path().join("src"), "noatime") else {
+        eprintln!("Skipping test_by_atime_falls_back_on_noatime_mount: cannot mount a tmpfs");
+        return;
+    };
+    let source = mount.0.as_path();
+
+    create_file_with_age(source, "read.txt", OLD_FILE_AGE).expect("Failed to create read.txt");
+    let read = SystemTime::now() - Duration::from_secs(RECENT_FILE_AGE * SECONDS_PER_DAY);
+    filetime::set_file_atime(
+        source.join("read.txt"),
+        filetime::FileTime::from_system_time(read),
+    )
+    .expect("Failed to set access time");
+
+    refile_cmd()
+        .arg("--by")
+        .arg("atime")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(
+            predicate::str::contains("mounted noatime")
+                .and(predicate::str::contains("using mtime")),
+        );
+
+    assert!(source.join(format!("{OLD_STUFF_BUCKET}/read.txt")).exists());
+}
+
+/// Tests that a verbose dry-run reports items that are already in place.
+///
+/// **User Story**: User previews a run and wants to see that refile looked at
+/// every item, including the ones it has nothing to do for.
+///
+/// **Scenario**: A file already sits in the bucket matching its age; refile
+/// runs with `--dry-run --verbose`, then with `--dry-run` alone.
+///
+/// **Expected**: The verbose run prints a KEEP line naming the file and its
+/// bucket; the plain dry-run does not.
+#[test]
+fn test_verbose_dry_run_reports_kept_items() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    let bucket = source.join(LAST_WEEK_BUCKET);
+    fs::create_dir_all(&bucket).expect("Failed to create bucket directory");
+    create_file_with_age(&bucket, "placed.txt", RECENT_FILE_AGE)
+        .expect("Failed to create placed.txt");
+
+    refile_cmd()
+        .arg("--dry-run")
+        .arg("--verbose")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "[dry-run] KEEP {} (already in last-week)",
+            bucket.join("placed.txt").display()
+        )));
+
+    refile_cmd()
+        .arg("--dry-run")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("KEEP").not());
+}
+
+/// Tests that `--bucket-mode size` buckets items by size instead of age.
+///
+/// **User Story**: User organizes a media folder where file size matters,
+/// not age.
+///
+/// **Scenario**: A small old file, a large new file, and a directory of small
+/// files whose total is large are refiled with
+/// `--bucket-mode size --buckets "small=1KB,large=null"`.
+///
+/// **Expected**:
+/// - The small file goes to `small/`, regardless of its age
+/// - The large file and the directory (by recursive size) go to `large/`
+#[test]
+fn test_bucket_mode_size() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "note.txt", OLD_FILE_AGE).expect("Failed to create note.txt");
+    fs::write(source.join("video.bin"), vec![0u8; 4096]).expect("Failed to create video.bin");
+    let album = source.join("album");
+    fs::create_dir(&album).expect("Failed to create album");
+    for i in 0..4 {
+        fs::write(album.join(format!("{i}.jpg")), vec![0u8; 512]).expect("Failed to create photo");
+    }
+
+    refile_cmd()
+        .arg("--bucket-mode")
+        .arg("size")
+        .arg("--buckets")
+        .arg("small=1KB,large=null")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{REFILE_BASE}/small/note.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{REFILE_BASE}/large/video.bin"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{REFILE_BASE}/large/album/0.jpg"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that `--exclude` leaves matching items in the source.
+///
+/// **User Story**: User runs refile on their home directory and never wants
+/// `node_modules` directories or `*.lock` files touched.
+///
+/// **Scenario**: The source holds an old `Cargo.lock`, an old `node_modules`
+/// directory, an old regular file, and an old directory that is exploded and
+/// contains a `node_modules` directory of its own.
+///
+/// **Expected**:
+/// - Excluded items, including those inside the exploded directory, stay put
+/// - The regular file is refiled
+/// - Exclusions are silent unless `--verbose` is given
+#[test]
+fn test_exclude_leaves_matching_items() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "Cargo.lock", OLD_FILE_AGE).expect("Failed to create Cargo.lock");
+    create_file_with_age(source, "notes.txt", OLD_FILE_AGE).expect("Failed to create notes.txt");
+    let modules = source.join("node_modules");
+    fs::create_dir(&modules).expect("Failed to create node_modules");
+    create_file_with_age(&modules, "index.js", OLD_FILE_AGE).expect("Failed to create index.js");
+    let project = source.join("project");
+    fs::create_dir_all(project.join("node_modules")).expect("Failed to create project");
+    create_file_with_age(&project, "main.js", OLD_FILE_AGE).expect("Failed to create main.js");
+    create_file_with_age(&project.join("node_modules"), "dep.js", OLD_FILE_AGE)
+        .expect("Failed to create dep.js");
+
+    refile_cmd()
+        .arg("--exclude")
+        .arg("*.lock")
+        .arg("--exclude")
+        .arg("node_modules")
+        .arg("--explode-dirs")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("excluded").not());
+
+    for kept in [
+        "Cargo.lock",
+        "node_modules/index.js",
+        "project/node_modules/dep.js",
+    ] {
+        temp_dir.child(kept).assert(predicates::path::exists());
+    }
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/notes.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/main.js"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that `--verbose` reports items left out by `--exclude`.
+///
+/// **User Story**: User wants to check which items their patterns caught.
+///
+/// **Scenario**: An old `*.lock` file is excluded in a verbose run.
+///
+/// **Expected**: The file stays put and is reported as excluded by pattern
+#[test]
+fn test_exclude_reported_when_verbose() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "yarn.lock", OLD_FILE_AGE).expect("Failed to create yarn.lock");
+
+    refile_cmd()
+        .arg("--exclude")
+        .arg("*.lock")
+        .arg("--verbose")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("yarn.lock: excluded by pattern"));
+
+    temp_dir
+        .child("yarn.lock")
+        .assert(predicates::path::exists());
+}
+
+/// Tests that bucket glyphs prefix bucket names in human output only.
+///
+/// **User Story**: User wants growth summaries that are quick to scan, with
+/// a glyph in front of each bucket, without affecting JSON consumers.
+///
+/// **Scenario**: The config file sets glyphs for two buckets, one of which
+/// `--bucket-glyph` overrides. An old file is refiled twice with
+/// `--report-growth --color always`, then `--stats-json` is run with the same
+/// glyphs. The growth report is then asked for with `--color never`, and with
+/// the default `--color auto` while stdout is not a terminal.
+///
+/// **Expected**:
+/// - The growth report shows the overriding glyph and the configured one
+/// - Bucket folders and the JSON stats use the plain bucket names
+/// - With `--color never`, or `auto` off a terminal, no glyph is shown
+#[test]
+fn test_bucket_glyphs_in_human_output_only() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let config_home = TempDir::new().expect("Failed to create config directory");
+    let source = temp_dir.path();
+
+    write_config(
+        config_home.path(),
+        "[bucket_glyphs]\nold-stuff = \"🗄\"\nlast-week = \"🗓\"\n",
+    )
+    .expect("Failed to write config");
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+
+    let run = |extra: &[&str]| {
+        refile_cmd()
+            .env("XDG_CONFIG_HOME", config_home.path())
+            .args(["--bucket-glyph", "old-stuff=📦"])
+            .args(extra)
+            .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+            .assert()
+            .success()
+    };
+    run(&["--report-growth", "--color", "always"]);
+    run(&["--report-growth", "--color", "always"])
+        .stdout(predicate::str::contains("📦 old-stuff: +0 files"))
+        .stdout(predicate::str::contains("🗓 last-week: +0 files"))
+        .stdout(predicate::str::contains("🗄").not());
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+
+    let output = run(&["--stats-json", "--color", "always"])
+        .get_output()
+        .stdout
+        .clone();
+    let stdout = String::from_utf8(output).expect("Output is not UTF-8");
+    assert!(!stdout.contains("📦") && !stdout.contains("🗓"));
+    let stats: serde_json::Value = serde_json::from_str(&stdout).expect("Invalid JSON");
+    assert!(stats["buckets"]["old-stuff"].is_object());
+
+    for color in [&["--color", "never"][..], &[]] {
+        let output = run(&[&["--report-growth"][..], color].concat())
+            .stdout(predicate::str::contains("  old-stuff: +0 files"))
+            .get_output()
+            .stdout
+            .clone();
+        let stdout = String::from_utf8(output).expect("Output is not UTF-8");
+        assert!(!stdout.contains("📦") && !stdout.contains("🗓"));
+    }
+}
+
+/// Tests that `--include` composes with `--exclude`.
+///
+/// **User Story**: User wants to refile only the PDFs and EPUBs from their
+/// downloads folder, except drafts, and leave everything else alone.
+///
+/// **Scenario**: Old files that match neither pattern (`photo.jpg`), only an
+/// include pattern (`paper.pdf`, `novel.epub`), and both an include and an
+/// exclude pattern (`draft.pdf`) are refiled with
+/// `--include '*.pdf' --include '*.epub' --exclude 'draft*'`.
+///
+/// **Expected**:
+/// - Only the files matching an include and no exclude pattern are moved
+/// - A verbose run reports the file that matches no include pattern
+#[test]
+fn test_include_and_exclude_compose() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    for name in ["photo.jpg", "paper.pdf", "novel.epub", "draft.pdf"] {
+        create_file_with_age(source, name, OLD_FILE_AGE).expect("Failed to create test file");
+    }
+
+    refile_cmd()
+        .args([
+            "--include",
+            "*.pdf",
+            "--include",
+            "*.epub",
+            "--exclude",
+            "draft*",
+        ])
+        .arg("--verbose")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "photo.jpg: not included by pattern",
+        ))
+        .stderr(predicate::str::contains("draft.pdf: excluded by pattern"));
+
+    for kept in ["photo.jpg", "draft.pdf"] {
+        temp_dir.child(kept).assert(predicates::path::exists());
+    }
+    for moved in ["paper.pdf", "novel.epub"] {
+        temp_dir
+            .child(format!("{OLD_STUFF_BUCKET}/{moved}"))
+            .assert(predicates::path::exists());
+    }
+}
+
+/// Tests that moves across devices must be allowed explicitly.
+///
+/// **User Story**: User would rather be told up front than have refile fall
+/// back to a slow, non-atomic copy and delete.
+///
+/// **Scenario**: The target is a tmpfs mount, so refiling an old file from
+/// the source crosses devices. Refile runs without, then with
+/// `--allow-cross-device`. Skipped when no tmpfs can be mounted.
+///
+/// **Expected**:
+/// - Without the flag the run aborts naming the move, and nothing is moved
+/// - With the flag the file is moved to the target
+#[cfg(unix)]
+#[test]
+fn test_cross_device_moves_need_flag() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.child("source");
+    source
+        .create_dir_all()
+        .expect("Failed to create source directory");
+    let Some(target) = TmpfsMount::new(&temp_dir.path().join("target")) else {
+        eprintln!("Skipping test_cross_device_moves_need_flag: cannot mount a tmpfs");
+        return;
+    };
+
+    create_file_with_age(source.path(), "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+
+    refile_cmd()
+        .arg(source.path())
+        .arg(&target.0)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("old.txt"))
+        .stderr(predicate::str::contains("would cross devices"))
+        .stderr(predicate::str::contains("--allow-cross-device"));
+    source.child("old.txt").assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("--allow-cross-device")
+        .arg(source.path())
+        .arg(&target.0)
+        .assert()
+        .success();
+    source.child("old.txt").assert(predicates::path::missing());
+    assert!(target.0.join(OLD_STUFF_BUCKET).join("old.txt").exists());
+}
+
+/// Tests that `--output json` prints the plan as a JSON array.
+///
+/// **User Story**: User wants to feed the plan of a dry run to a script
+/// without scraping the human-readable lines.
+///
+/// **Scenario**: A source with an old file and a recent file is refiled with
+/// `--dry-run --output json`.
+///
+/// **Expected**:
+/// - Stdout is a JSON array with a move object per file, naming its bucket
+/// - The human-readable lines go to stderr and nothing is moved
+#[test]
+fn test_output_json_dry_run() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+    create_file_with_age(source, "new.txt", RECENT_FILE_AGE).expect("Failed to create new.txt");
+
+    let assert = refile_cmd()
+        .args(["--dry-run", "--output", "json"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("[dry-run]"));
+
+    let stdout =
+        String::from_utf8(assert.get_output().stdout.clone()).expect("Output is not UTF-8");
+    let actions: serde_json::Value = serde_json::from_str(&stdout).expect("Invalid JSON");
+    let actions = actions.as_array().expect("Plan is not an array");
+    assert_eq!(actions.len(), 2);
+
+    let old = actions
+        .iter()
+        .find(|a| a["from"] == source.join("old.txt").to_str().unwrap())
+        .expect("No action for old.txt");
+    assert_eq!(old["action"], "move");
+    assert_eq!(old["bucket"], "old-stuff");
+    assert_eq!(
+        old["to"],
+        source
+            .join(OLD_STUFF_BUCKET)
+            .join("old.txt")
+            .to_str()
+            .unwrap()
+    );
+
+    temp_dir.child("old.txt").assert(predicates::path::exists());
+}
+
+/// Tests that `refile undo` moves everything back.
+///
+/// **User Story**: User refiled a directory with the wrong buckets and wants
+/// to get back to where they started.
+///
+/// **Scenario**: Files of different ages and a directory are refiled, then
+/// `refile undo` is run twice.
+///
+/// **Expected**:
+/// - After the first undo every item is back at its original path
+/// - The second undo finds nothing to undo
+#[test]
+fn test_undo_restores_layout() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "new.txt", RECENT_FILE_AGE).expect("Failed to create new.txt");
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+    fs::create_dir(source.join("project")).expect("Failed to create project");
+    create_file_with_age(&source.join("project"), "notes.txt", MEDIUM_FILE_AGE)
+        .expect("Failed to create notes.txt");
+
+    refile_cmd()
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("undo")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Undid 3 moves (0 skipped)"));
+
+    for item in ["new.txt", "old.txt", "project/notes.txt"] {
+        temp_dir.child(item).assert(predicates::path::exists());
+    }
+    for bucket in [LAST_WEEK_BUCKET, CURRENT_MONTH_BUCKET, OLD_STUFF_BUCKET] {
+        let entries = fs::read_dir(source.join(bucket))
+            .expect("Failed to read bucket")
+            .count();
+        assert_eq!(entries, 0, "{bucket} should be empty");
+    }
+
+    refile_cmd()
+        .arg("undo")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Nothing to undo"));
+}
+
+/// Tests that `--min-age` leaves younger items in place.
+///
+/// **User Story**: User only wants to archive things older than 30 days and
+/// never have recent files moved, whatever the buckets say.
+///
+/// **Scenario**: A 5-day-old and a 50-day-old file are refiled with
+/// `--min-age 30`.
+///
+/// **Expected**:
+/// - The 5-day-old file stays where it is, with the reason reported
+/// - The 50-day-old file is moved to its bucket
+#[test]
+fn test_min_age_leaves_recent_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "recent.txt", 5).expect("Failed to create recent.txt");
+    create_file_with_age(source, "older.txt", LAST_MONTHS_AGE).expect("Failed to create older.txt");
+
+    refile_cmd()
+        .args(["--min-age", "30"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("younger than --min-age 30 days"));
+
+    temp_dir
+        .child("recent.txt")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/recent.txt"))
+        .assert(predicates::path::missing());
+    temp_dir
+        .child(format!("{LAST_MONTHS_BUCKET}/older.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that `--max-age` leaves older items in place.
+///
+/// **User Story**: User wants to sort recent files but not touch old ones
+/// they handle by hand.
+///
+/// **Scenario**: A 5-day-old and a 100-day-old file are refiled with
+/// `--max-age 30`, then with `--min-age 40 --max-age 30`.
+///
+/// **Expected**:
+/// - The 100-day-old file stays where it is, the recent one is moved
+/// - An empty window is rejected
+#[test]
+fn test_max_age_leaves_old_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "recent.txt", 5).expect("Failed to create recent.txt");
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+
+    refile_cmd()
+        .args(["--max-age", "30"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir.child("old.txt").assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/recent.txt"))
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .args(["--min-age", "40", "--max-age", "30"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("greater than --max-age"));
+}
+
+/// Tests that `--execute-after-review` runs the reviewed plan once confirmed.
+///
+/// **User Story**: User wants to see the plan and run exactly that plan,
+/// without a second invocation that might plan something else.
+///
+/// **Scenario**: An old file is refiled with `--dry-run --execute-after-review`,
+/// first answering "n" and then "y" on stdin.
+///
+/// **Expected**:
+/// - Both runs print the plan and ask for confirmation
+/// - Declining changes nothing, not even the bucket directories
+/// - Confirming executes the displayed move
+#[test]
+fn test_execute_after_review() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+    let planned = format!(
+        "[plan] MOVE {} -> {}",
+        source.join("old.txt").display(),
+        source.join(OLD_STUFF_BUCKET).join("old.txt").display()
+    );
+
+    refile_cmd()
+        .args(["--dry-run", "--execute-after-review"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .write_stdin("n\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(planned.as_str()))
+        .stderr(predicate::str::contains("Execute this plan? [y/N]"));
+    temp_dir.child("old.txt").assert(predicates::path::exists());
+    temp_dir
+        .child(REFILE_BASE)
+        .assert(predicates::path::missing());
+
+    refile_cmd()
+        .args(["--dry-run", "--execute-after-review"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .write_stdin("y\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(planned.as_str()));
+    temp_dir
+        .child("old.txt")
+        .assert(predicates::path::missing());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that `--recursive` buckets nested files individually.
+///
+/// **User Story**: User keeps a flat dump folder with nested subfolders and
+/// wants every file sorted by its own age, whatever folder it is in.
+///
+/// **Scenario**: A top-level file, a file one level down in `a/` and a file
+/// two levels down in `a/b/`, all of different ages, are refiled with
+/// `--recursive`; then a new file is added and the source refiled again.
+///
+/// **Expected**:
+/// - Each file lands in the bucket for its own age, without its directories
+/// - The directories stay in place, now empty
+/// - The second run only moves the new file, not the archived ones
+#[test]
+fn test_recursive_buckets_nested_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let nested = source.join("a/b");
+    fs::create_dir_all(&nested).expect("Failed to create a/b");
+    create_file_with_age(source, "top.txt", MEDIUM_FILE_AGE).expect("Failed to create top.txt");
+    create_file_with_age(&source.join("a"), "one.txt", OLD_FILE_AGE)
+        .expect("Failed to create one.txt");
+    create_file_with_age(&nested, "two.txt", RECENT_FILE_AGE).expect("Failed to create two.txt");
+
+    refile_cmd()
+        .arg("--recursive")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{CURRENT_MONTH_BUCKET}/top.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/one.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/two.txt"))
+        .assert(predicates::path::exists());
+    temp_dir.child("a/b").assert(predicates::path::is_dir());
+    temp_dir
+        .child("a/one.txt")
+        .assert(predicates::path::missing());
+
+    create_file_with_age(&nested, "three.txt", RECENT_FILE_AGE)
+        .expect("Failed to create three.txt");
+    refile_cmd()
+        .arg("--recursive")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Moved").count(1));
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/three.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that `--depth` leaves deeper files untouched.
+///
+/// **User Story**: User has folders with deeply nested caches that must never
+/// be flattened into the buckets.
+///
+/// **Scenario**: Old files one and two levels down are refiled with
+/// `--recursive --depth 1`.
+///
+/// **Expected**:
+/// - The file one level down is moved to its bucket
+/// - The file two levels down and all directories stay where they are
+#[test]
+fn test_depth_limits_recursion() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let cache = source.join("app/cache");
+    fs::create_dir_all(&cache).expect("Failed to create app/cache");
+    create_file_with_age(&source.join("app"), "notes.txt", OLD_FILE_AGE)
+        .expect("Failed to create notes.txt");
+    create_file_with_age(&cache, "blob.bin", OLD_FILE_AGE).expect("Failed to create blob.bin");
+
+    refile_cmd()
+        .args(["--recursive", "--depth", "1"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/notes.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("app/cache/blob.bin")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/blob.bin"))
+        .assert(predicates::path::missing());
+}
+
+/// Tests that `--source-scan-exclude-base-always` keeps a user folder named
+/// like the base folder intact.
+///
+/// **User Story**: User has their own `refile` folder of notes that has
+/// nothing to do with the archive and must not be taken apart.
+///
+/// **Scenario**: A `refile` folder without bucket directories sits in the
+/// source. It is refiled into a separate target with the flag, and then the
+/// source is refiled in place with the flag, first as a dry run.
+///
+/// **Expected**:
+/// - With a separate target, the folder is moved whole into a bucket
+/// - In place, the dry run leaves everything alone, and the real run moves the
+///   folder whole into a bucket of the archive created in its place
+#[test]
+fn test_source_scan_exclude_base_always_keeps_user_folder_whole() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source_dir = temp_dir.child("source");
+    let target_dir = temp_dir.child("target");
+    let notes = source_dir.child(REFILE_BASE);
+    notes
+        .create_dir_all()
+        .expect("Failed to create refile folder");
+    target_dir
+        .create_dir_all()
+        .expect("Failed to create target directory");
+    create_file_with_age(notes.path(), "ideas.txt", OLD_FILE_AGE)
+        .expect("Failed to create ideas.txt");
+    create_file_with_age(notes.path(), "todo.txt", RECENT_FILE_AGE)
+        .expect("Failed to create todo.txt");
+
+    refile_cmd()
+        .arg("--source-scan-exclude-base-always")
+        .arg(source_dir.path())
+        .arg(target_dir.path())
+        .assert()
+        .success();
+
+    let moved = target_dir.child(format!("{LAST_WEEK_BUCKET}/{REFILE_BASE}"));
+    moved.child("ideas.txt").assert(predicates::path::exists());
+    moved.child("todo.txt").assert(predicates::path::exists());
+    notes.assert(predicates::path::missing());
+
+    // In place, the folder must not be dismantled as a broken archive
+    let in_place = temp_dir.child("in-place");
+    let notes = in_place.child(REFILE_BASE);
+    notes
+        .create_dir_all()
+        .expect("Failed to create refile folder");
+    create_file_with_age(notes.path(), "ideas.txt", OLD_FILE_AGE)
+        .expect("Failed to create ideas.txt");
+    create_file_with_age(notes.path(), "todo.txt", RECENT_FILE_AGE)
+        .expect("Failed to create todo.txt");
+
+    refile_cmd()
+        .args(["--source-scan-exclude-base-always", "--dry-run"])
+        .arg(in_place.path())
+        .assert()
+        .success();
+    notes.child("ideas.txt").assert(predicates::path::exists());
+    notes.child("todo.txt").assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("--source-scan-exclude-base-always")
+        .arg(in_place.path())
+        .assert()
+        .success();
+
+    let moved = in_place.child(format!("{LAST_WEEK_BUCKET}/{REFILE_BASE}"));
+    moved.child("ideas.txt").assert(predicates::path::exists());
+    moved.child("todo.txt").assert(predicates::path::exists());
+    in_place
+        .child(format!("{OLD_STUFF_BUCKET}/ideas.txt"))
+        .assert(predicates::path::missing());
+    in_place
+        .child(format!("{REFILE_BASE} (1)"))
+        .assert(predicates::path::missing());
+}
+
+/// Tests that `--syslog` never gets in the way of a run.
+///
+/// **User Story**: User runs refile unattended from systemd and wants its
+/// output in the journal, whether or not a system logger is reachable.
+///
+/// **Scenario**: An old file is refiled with `--syslog` (the test machine
+/// may or may not have a syslog socket).
+///
+/// **Expected**:
+/// - The run succeeds and the file is moved to its bucket
+#[test]
+fn test_syslog_run_succeeds() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    create_file_with_age(temp_dir.path(), "old.txt", OLD_FILE_AGE)
+        .expect("Failed to create old.txt");
+
+    refile_cmd()
+        .arg("--syslog")
+        .arg(temp_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Moved"));
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that `--jobs` moves every file exactly once.
+///
+/// **User Story**: User refiles a huge download folder and wants the moves
+/// spread over several threads without losing or mangling anything.
+///
+/// **Scenario**: 200 old files are refiled with `--jobs 8`.
+///
+/// **Expected**:
+/// - Every file lands in its bucket and none is left in the source
+/// - Every move is reported on a line of its own
+#[test]
+fn test_jobs_moves_many_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let names: Vec<String> = (0..200).map(|i| format!("file-{i:03}.txt")).collect();
+    for name in &names {
+        create_file_with_age(temp_dir.path(), name, OLD_FILE_AGE)
+            .expect("Failed to create test file");
+    }
+
+    let output = refile_cmd()
+        .args(["--jobs", "8"])
+        .arg(temp_dir.path())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let stdout = String::from_utf8(output).expect("Output is not UTF-8");
+    assert_eq!(
+        stdout
+            .lines()
+            .filter(|line| {
+                line.starts_with("Moved ")
+                    && Path::new(line).extension().is_some_and(|ext| ext == "txt")
+            })
+            .count(),
+        names.len()
+    );
+
+    for name in &names {
+        temp_dir
+            .child(format!("{OLD_STUFF_BUCKET}/{name}"))
+            .assert(predicates::path::exists());
+        temp_dir.child(name).assert(predicates::path::missing());
+    }
+}
+
+/// Tests that `--bucket-from-frontmatter` ages notes by their front matter date.
+///
+/// **User Story**: User's notes carry a `date:` in their YAML front matter
+/// that is more accurate than the modification time, which changes whenever
+/// a note is synced.
+///
+/// **Scenario**: Two freshly written markdown files, one with a front matter
+/// date from 50 days ago and one without front matter, are refiled with
+/// `--bucket-from-frontmatter md`.
+///
+/// **Expected**:
+/// - The note with a front matter date is bucketed by it → last-months/
+/// - The note without front matter falls back to mtime → last-week/
+#[test]
+fn test_bucket_from_frontmatter() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let (year, month, day) = date_days_ago(LAST_MONTHS_AGE);
+    fs::write(
+        source.join("dated.md"),
+        format!("---\ntitle: Meeting\ndate: \"{year}-{month:02}-{day:02} 09:30\"\n---\n# Notes\n"),
+    )
+    .expect("Failed to create dated.md");
+    fs::write(
+        source.join("plain.md"),
+        "# No front matter\ndate: 2001-01-01\n",
+    )
+    .expect("Failed to create plain.md");
+
+    refile_cmd()
+        .args(["--bucket-from-frontmatter", "md"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{LAST_MONTHS_BUCKET}/dated.md"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/plain.md"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that `--ascii-only` leaves items with non-ASCII names in place.
+///
+/// **User Story**: User syncs the archive to a legacy system that only
+/// accepts ASCII file names.
+///
+/// **Scenario**: An old file named `Café.txt` and an old ASCII-named file are
+/// refiled with `--ascii-only`.
+///
+/// **Expected**:
+/// - The non-ASCII file stays in the source and is reported as skipped
+/// - The ASCII file is moved to its bucket
+#[test]
+fn test_ascii_only_skips_non_ascii_names() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    create_file_with_age(source, "Café.txt", OLD_FILE_AGE).expect("Failed to create Café.txt");
+    create_file_with_age(source, "plain.txt", OLD_FILE_AGE).expect("Failed to create plain.txt");
+
+    refile_cmd()
+        .arg("--ascii-only")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Café.txt: non-ASCII name"));
+
+    temp_dir
+        .child("Café.txt")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/plain.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests that `--transliterate` moves non-ASCII names under an ASCII name.
+///
+/// **User Story**: User wants everything archived for the legacy system,
+/// with names made ASCII rather than left behind.
+///
+/// **Scenario**: An old file named `Café Müller.txt` is refiled with
+/// `--ascii-only --transliterate --rename-report`.
+///
+/// **Expected**:
+/// - The file is moved to its bucket as `Cafe Muller.txt`
+/// - The rename report keeps the original name as the intended destination
+#[test]
+fn test_transliterate_non_ascii_names() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let report_dir = TempDir::new().expect("Failed to create report directory");
+    let source = temp_dir.path();
+    let report = report_dir.path().join("renames.jsonl");
+    create_file_with_age(source, "Café Müller.txt", OLD_FILE_AGE)
+        .expect("Failed to create Café Müller.txt");
+
+    refile_cmd()
+        .args(["--ascii-only", "--transliterate", "--rename-report"])
+        .arg(&report)
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/Cafe Muller.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("Café Müller.txt")
+        .assert(predicates::path::missing());
+
+    let report = fs::read_to_string(&report).expect("Failed to read rename report");
+    let entry: serde_json::Value =
+        serde_json::from_str(report.lines().next().expect("Rename report is empty"))
+            .expect("Rename report line is not JSON");
+    assert!(
+        entry["intended"]
+            .as_str()
+            .is_some_and(|intended| intended.ends_with("Café Müller.txt"))
+    );
+}
+
+/// Tests that a `.refileignore` file keeps matching items in place.
+///
+/// **User Story**: User drops a `.refileignore` into their downloads folder,
+/// like a `.gitignore`, to list what refile must never move.
+///
+/// **Scenario**: The source's `.refileignore` excludes the `private/` folder
+/// and `*.log` files, but re-includes `keep.log` with a negation. Old files
+/// in both folders are refiled with `--recursive`.
+///
+/// **Expected**:
+/// - Files in `private/` and the excluded log stay where they are
+/// - The re-included log and other files are moved to their bucket
+/// - The `.refileignore` file itself is never moved
+#[test]
+fn test_refileignore_excludes_and_reincludes() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    fs::write(source.join(".refileignore"), "private/\n*.log\n!keep.log\n")
+        .expect("Failed to write .refileignore");
+    let private = source.join("private");
+    let notes = source.join("notes");
+    fs::create_dir_all(&private).expect("Failed to create private");
+    fs::create_dir_all(&notes).expect("Failed to create notes");
+    create_file_with_age(&private, "secret.txt", OLD_FILE_AGE)
+        .expect("Failed to create secret.txt");
+    for name in ["todo.txt", "debug.log", "keep.log"] {
+        create_file_with_age(&notes, name, OLD_FILE_AGE).expect("Failed to create note");
+    }
+
+    refile_cmd()
+        .arg("--recursive")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    temp_dir
+        .child("private/secret.txt")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("notes/debug.log")
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(".refileignore")
+        .assert(predicates::path::exists());
+    for name in ["todo.txt", "keep.log"] {
+        temp_dir
+            .child(format!("{OLD_STUFF_BUCKET}/{name}"))
+            .assert(predicates::path::exists());
+    }
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/debug.log"))
+        .assert(predicates::path::missing());
+}
+
+/// Tests that `--post-move-hook` runs once per successful move.
+///
+/// **User Story**: User keeps a search index of their archive and wants it
+/// updated whenever refile moves something.
+///
+/// **Scenario**: A hook script that appends its arguments and environment to
+/// a record file runs while three old files are refiled. A failing hook is
+/// then used with the default `--hook-failure warn` and with `abort`.
+///
+/// **Expected**:
+/// - The hook ran once per move, with the old and new path
+/// - A failing hook is only warned about by default
+/// - With `--hook-failure abort` the run fails after the first move, which
+///   is not reported as failed
+#[cfg(unix)]
+#[test]
+fn test_post_move_hook_runs_per_move() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let scripts = TempDir::new().expect("Failed to create script directory");
+    let source = temp_dir.path();
+    let record = scripts.path().join("record.txt");
+
+    let hook = write_script(
+        scripts.path(),
+        "hook.sh",
+        &format!(
+            "echo \"$1|$2|$REFILE_FROM|$REFILE_TO\" >> '{}'",
+            record.display()
+        ),
+    );
+    let failing = write_script(scripts.path(), "failing.sh", "echo oops >&2; exit 1");
+
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        create_file_with_age(source, name, OLD_FILE_AGE).expect("Failed to create test file");
+    }
+    refile_cmd()
+        .arg("--post-move-hook")
+        .arg(&hook)
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    let record = fs::read_to_string(&record).expect("Failed to read hook record");
+    let mut lines: Vec<&str> = record.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(lines.len(), 3);
+    for (line, name) in lines.iter().zip(["a.txt", "b.txt", "c.txt"]) {
+        let from = source.join(name).display().to_string();
+        let to = source
+            .join(OLD_STUFF_BUCKET)
+            .join(name)
+            .display()
+            .to_string();
+        assert_eq!(*line, format!("{from}|{to}|{from}|{to}"));
+    }
+
+    create_file_with_age(source, "d.txt", OLD_FILE_AGE).expect("Failed to create d.txt");
+    refile_cmd()
+        .arg("--post-move-hook")
+        .arg(&failing)
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("oops"));
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/d.txt"))
+        .assert(predicates::path::exists());
+
+    create_file_with_age(source, "e.txt", OLD_FILE_AGE).expect("Failed to create e.txt");
+    create_file_with_age(source, "f.txt", OLD_FILE_AGE).expect("Failed to create f.txt");
+    refile_cmd()
+        .arg("--post-move-hook")
+        .arg(&failing)
+        .args(["--hook-failure", "abort"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Post-move hook"))
+        .stderr(predicate::str::contains("moves failed").not());
+    let left = ["e.txt", "f.txt"]
+        .iter()
+        .filter(|name| source.join(name).exists())
+        .count();
+    assert_eq!(left, 1, "the run should stop after the first move");
+}
+
+/// Tests that symlinks are moved as links unless `--follow-symlinks` is set.
+///
+/// **User Story**: User has symlinks in their downloads folder, some to files
+/// and some to directories elsewhere, and must never have the targets pulled
+/// into the archive by accident; sometimes they do want a copy archived.
+///
+/// **Scenario**: Symlinks to an old file and to a directory outside the
+/// source are refiled, once by default and once with `--follow-symlinks`.
+///
+/// **Expected**:
+/// - By default both moved items are still symlinks to the same targets
+/// - With `--follow-symlinks` the moved items are copies of the file and of
+///   the directory, and the link targets stay where they are
+/// - Undoing that run puts the symlinks back and removes the copies
+#[cfg(unix)]
+#[test]
+fn test_follow_symlinks() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.child("source");
+    let elsewhere = temp_dir.child("elsewhere");
+    let shared = elsewhere.child("shared");
+    source.create_dir_all().expect("Failed to create source");
+    shared.create_dir_all().expect("Failed to create shared");
+    create_file_with_age(elsewhere.path(), "data.txt", OLD_FILE_AGE)
+        .expect("Failed to create data.txt");
+    create_file_with_age(shared.path(), "inner.txt", OLD_FILE_AGE)
+        .expect("Failed to create inner.txt");
+
+    let link_both = |suffix: &str| {
+        std::os::unix::fs::symlink(
+            elsewhere.child("data.txt").path(),
+            source.child(format!("file-link{suffix}")).path(),
+        )
+        .expect("Failed to create file symlink");
+        std::os::unix::fs::symlink(
+            shared.path(),
+            source.child(format!("dir-link{suffix}")).path(),
+        )
+        .expect("Failed to create directory symlink");
+    };
+    // Where an item ended up, whichever bucket its age put it in
+    let moved = |name: &str| {
+        [
+            LAST_WEEK_BUCKET,
+            CURRENT_MONTH_BUCKET,
+            LAST_MONTHS_BUCKET,
+            OLD_STUFF_BUCKET,
+        ]
+        .iter()
+        .map(|bucket| source.path().join(bucket).join(name))
+        .find(|path| fs::symlink_metadata(path).is_ok())
+        .unwrap_or_else(|| panic!("{name} was not moved into a bucket"))
+    };
+
+    link_both("");
+    refile_cmd().arg(source.path()).assert().success();
+    for (name, target) in [("file-link", "data.txt"), ("dir-link", "shared")] {
+        assert_eq!(
+            fs::read_link(moved(name)).expect("Moved item should be a symlink"),
+            elsewhere.path().join(target)
+        );
+    }
+
+    link_both("-followed");
+    refile_cmd()
+        .arg("--follow-symlinks")
+        .arg(source.path())
+        .assert()
+        .success();
+    let file = moved("file-link-followed");
+    let dir = moved("dir-link-followed");
+    for path in [&file, &dir] {
+        assert!(
+            !fs::symlink_metadata(path)
+                .expect("Moved item should exist")
+                .is_symlink()
+        );
+    }
+    assert_eq!(
+        fs::read_to_string(&file).expect("Failed to read copied file"),
+        "test content"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("inner.txt")).expect("Failed to read copied directory"),
+        "test content"
+    );
+    elsewhere
+        .child("data.txt")
+        .assert(predicates::path::exists());
+    shared.child("inner.txt").assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("undo")
+        .arg(source.path())
+        .assert()
+        .success();
+    for (name, target) in [
+        ("file-link-followed", "data.txt"),
+        ("dir-link-followed", "shared"),
+    ] {
+        assert_eq!(
+            fs::read_link(source.path().join(name)).expect("Undo should restore the symlink"),
+            elsewhere.path().join(target)
+        );
+    }
+    assert!(fs::symlink_metadata(&file).is_err());
+    assert!(fs::symlink_metadata(&dir).is_err());
+}
+
+/// Tests that `--follow-symlinks` refuses a link into a symlink loop.
+///
+/// **User Story**: User follows symlinks while refiling, and one of them
+/// points into a directory that links back to itself.
+///
+/// **Scenario**: A symlink to a directory containing a link to itself is
+/// refiled with `--follow-symlinks`.
+///
+/// **Expected**:
+/// - The run reports the symlink loop
+/// - The link is left in place and no partial copy is left in any bucket
+#[cfg(unix)]
+#[test]
+fn test_follow_symlinks_refuses_loops() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.child("source");
+    let looped = temp_dir.child("elsewhere/looped");
+    source.create_dir_all().expect("Failed to create source");
+    looped.create_dir_all().expect("Failed to create looped");
+    create_file_with_age(looped.path(), "inner.txt", OLD_FILE_AGE)
+        .expect("Failed to create inner.txt");
+    std::os::unix::fs::symlink(looped.path(), looped.child("back").path())
+        .expect("Failed to create looping symlink");
+    std::os::unix::fs::symlink(looped.path(), source.child("loop-link").path())
+        .expect("Failed to create symlink");
+    refile_cmd()
+        .arg("--follow-symlinks")
+        .arg(source.path())
+        .assert()
+        .stderr(predicate::str::contains("Symlink loop"));
+    assert!(
+        fs::symlink_metadata(source.child("loop-link").path())
+            .expect("The refused link should stay")
+            .is_symlink()
+    );
+    for bucket in [
+        LAST_WEEK_BUCKET,
+        CURRENT_MONTH_BUCKET,
+        LAST_MONTHS_BUCKET,
+        OLD_STUFF_BUCKET,
+    ] {
+        assert!(fs::symlink_metadata(source.path().join(bucket).join("loop-link")).is_err());
+    }
+}
+
+/// Tests that the run hooks bracket the moves.
+///
+/// **User Story**: User mounts their archive disk before refile runs and
+/// unmounts it afterwards, and needs both to happen at the right time.
+///
+/// **Scenario**: A pre-run and a post-run hook each append a line to a shared
+/// log, noting whether the old file is still in the source. A failing pre-run
+/// hook is then used on another old file.
+///
+/// **Expected**:
+/// - The pre-run hook ran first, while the file was still in the source
+/// - The post-run hook ran after the move, with the run summary
+/// - A failing pre-run hook aborts the run before anything is moved
+#[cfg(unix)]
+#[test]
+fn test_run_hooks_order() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let scripts = TempDir::new().expect("Failed to create script directory");
+    let source = temp_dir.path();
+    let log = scripts.path().join("hooks.log");
+
+    let record = |stage: &str| {
+        format!(
+            "if [ -e \"$1/old.txt\" ]; then where=source; else where=moved; fi\n\
+             echo \"{stage} $where $REFILE_MOVED\" >> '{}'",
+            log.display()
+        )
+    };
+    let pre = write_script(scripts.path(), "pre.sh", &record("pre"));
+    let post = write_script(scripts.path(), "post.sh", &record("post"));
+    let failing = write_script(scripts.path(), "failing.sh", "exit 1");
+
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+    refile_cmd()
+        .arg("--pre-run-hook")
+        .arg(&pre)
+        .arg("--post-run-hook")
+        .arg(&post)
+        .arg(source)
+        .assert()
+        .success();
+
+    let lines = fs::read_to_string(&log).expect("Failed to read hook log");
+    assert_eq!(
+        lines.lines().collect::<Vec<_>>(),
+        ["pre source ", "post moved 1"]
+    );
+
+    create_file_with_age(source, "new.txt", OLD_FILE_AGE).expect("Failed to create new.txt");
+    refile_cmd()
+        .arg("--pre-run-hook")
+        .arg(&failing)
+        .arg(source)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Pre-run hook"));
+    temp_dir.child("new.txt").assert(predicates::path::exists());
+}
+
+/// Tests that the run hooks bracket a reviewed plan and a failed run.
+///
+/// **User Story**: User mounts their archive disk in the pre-run hook and
+/// unmounts it in the post-run hook, and reviews plans before they run.
+///
+/// **Scenario**: Run hooks that log the dry-run flag and the number of moves
+/// are used with `--execute-after-review`, declining and then confirming the
+/// plan, and then on a run whose only move fails with `--fail-fast`.
+///
+/// **Expected**:
+/// - A declined plan runs neither hook
+/// - A confirmed plan runs the pre-run hook for real, after the review, and
+///   the post-run hook with the one move made
+/// - A failed run still runs the post-run hook, counting no moves
+#[cfg(unix)]
+#[test]
+fn test_run_hooks_bracket_review_and_failures() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let scripts = TempDir::new().expect("Failed to create script directory");
+    let source = temp_dir.path();
+    let log = scripts.path().join("hooks.log");
+
+    let record = |stage: &str| {
+        format!(
+            "echo \"{stage} $REFILE_DRY_RUN $REFILE_MOVED\" >> '{}'",
+            log.display()
+        )
+    };
+    let pre = write_script(scripts.path(), "pre.sh", &record("pre"));
+    let post = write_script(scripts.path(), "post.sh", &record("post"));
+    let hooks = |cmd: &mut Command| {
+        cmd.arg("--pre-run-hook")
+            .arg(&pre)
+            .arg("--post-run-hook")
+            .arg(&post);
+    };
+
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+    for answer in ["n\n", "y\n"] {
+        let mut cmd = refile_cmd();
+        hooks(&mut cmd);
+        cmd.args(["--dry-run", "--execute-after-review"])
+            .arg(source)
+            .write_stdin(answer)
+            .assert()
+            .success();
+        if answer == "n\n" {
+            assert!(!log.exists(), "a declined plan ran a hook");
+            temp_dir.child("old.txt").assert(predicates::path::exists());
+        }
+    }
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+
+    fs::write(source.join(REFILE_BASE).join("images"), "not a directory")
+        .expect("Failed to create images file");
+    create_file_with_age(source, "photo.jpg", OLD_FILE_AGE).expect("Failed to create photo.jpg");
+    let mut cmd = refile_cmd();
+    hooks(&mut cmd);
+    cmd.args(["--classify-by", "type,age", "--reconcile-existing", "false"])
+        .arg("--fail-fast")
+        .arg(source)
+        .assert()
+        .code(1);
+
+    let lines = fs::read_to_string(&log).expect("Failed to read hook log");
+    assert_eq!(
+        lines.lines().collect::<Vec<_>>(),
+        ["pre false ", "post false 1", "pre false ", "post false 0"]
+    );
+}
+
+/// Tests that `--quiet` prints nothing on a successful run.
+///
+/// **User Story**: User runs refile from cron and only wants mail when
+/// something goes wrong.
+///
+/// **Scenario**: Two old files are refiled with `--quiet`.
+///
+/// **Expected**:
+/// - Both files are moved
+/// - Nothing is printed on stdout or stderr
+#[test]
+fn test_quiet_prints_nothing_on_success() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    for name in ["a.txt", "b.txt"] {
+        create_file_with_age(source, name, OLD_FILE_AGE).expect("Failed to create test file");
+    }
+
+    refile_cmd()
+        .arg("--quiet")
+        .arg(source)
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::is_empty());
+
+    for name in ["a.txt", "b.txt"] {
+        temp_dir
+            .child(format!("{OLD_STUFF_BUCKET}/{name}"))
+            .assert(predicates::path::exists());
+    }
+}
+
+/// Tests that `--verbose` reports why items stay in place in a real run.
+///
+/// **User Story**: User wonders why refile did not touch some files and wants
+/// it to say so.
+///
+/// **Scenario**: An old file already in its bucket and an excluded file are
+/// refiled with `--verbose`, then without it.
+///
+/// **Expected**:
+/// - The verbose run names the kept file and the bucket it is already in,
+///   and the excluded file with its reason
+/// - The normal run mentions neither
+#[test]
+fn test_verbose_reports_skip_reasons() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    let bucket = source.join(OLD_STUFF_BUCKET);
+    fs::create_dir_all(&bucket).expect("Failed to create bucket");
+    create_file_with_age(&bucket, "placed.txt", OLD_FILE_AGE).expect("Failed to create placed.txt");
+    create_file_with_age(source, "cargo.lock", OLD_FILE_AGE).expect("Failed to create cargo.lock");
+
+    refile_cmd()
+        .args(["--verbose", "--exclude", "*.lock"])
+        .arg(source)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "placed.txt (already in old-stuff)",
+        ))
+        .stderr(predicate::str::contains("cargo.lock: excluded by pattern"));
+
+    refile_cmd()
+        .args(["--exclude", "*.lock"])
+        .arg(source)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("placed.txt").not())
+        .stderr(predicate::str::contains("cargo.lock").not());
+}
+
+/// Tests that a glob rule covers every directory it matches.
+///
+/// **User Story**: User keeps many project folders under one directory and
+/// wants a single rule for all of them.
+///
+/// **Scenario**: The config has a rule for `<work>/*` with its own buckets;
+/// refile runs on two project folders below `<work>`.
+///
+/// **Expected**: Both projects are organized into the rule's buckets.
+#[test]
+fn test_glob_rule_matches_each_project() {
+    let work = TempDir::new().expect("Failed to create temporary directory");
+    let config_home = TempDir::new().expect("Failed to create config directory");
+
+    write_config(
+        config_home.path(),
+        &format!(
+            "[[rules]]\npath = {:?}\n\n[rules.buckets]\nrecent = 7\nold = \"null\"\n",
+            format!(
+                "{}/*",
+                work.path()
+                    .to_str()
+                    .expect("Test path contains invalid UTF-8")
+            ),
+        ),
+    )
+    .expect("Failed to write config");
+
+    for project in ["alpha", "beta"] {
+        let source = work.child(project);
+        source.create_dir_all().expect("Failed to create project");
+        create_file_with_age(source.path(), "notes.txt", OLD_FILE_AGE)
+            .expect("Failed to create file");
+        refile_cmd()
+            .env("XDG_CONFIG_HOME", config_home.path())
+            .arg(source.path())
+            .assert()
+            .success();
+        source
+            .child(format!("{REFILE_BASE}/old/notes.txt"))
+            .assert(predicates::path::exists());
+    }
+}
+
+/// Tests that a project-local `.refile.toml` is found above the source.
+///
+/// **User Story**: User wants a project to be organized the same way on
+/// every machine, with the settings checked in next to it.
+///
+/// **Scenario**: A `.refile.toml` two levels above the source sets a base
+/// folder and buckets; refile runs on the source. Then another `.refile.toml`
+/// is put into the source itself and refile runs again.
+///
+/// **Expected**:
+/// - The first run uses the project config's base folder and buckets
+/// - The second run uses the nearer config, and leaves that file in place
+#[test]
+fn test_project_config_found_above_source() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let config_home = TempDir::new().expect("Failed to create config directory");
+    let source = temp_dir.child("notes/inbox");
+    source.create_dir_all().expect("Failed to create source");
+    temp_dir
+        .child(".refile.toml")
+        .write_str("base_folder = \"sorted\"\n[buckets]\nfresh = 7\nstale = \"null\"\n")
+        .expect("Failed to write project config");
+
+    create_file_with_age(source.path(), "first.txt", OLD_FILE_AGE).expect("Failed to create file");
+    refile_cmd()
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .arg(source.path())
+        .assert()
+        .success();
+    source
+        .child("sorted/stale/first.txt")
+        .assert(predicates::path::exists());
+
+    source
+        .child(".refile.toml")
+        .write_str("[buckets]\nnear = \"null\"\n")
+        .expect("Failed to write project config");
+    create_file_with_age(source.path(), "second.txt", OLD_FILE_AGE).expect("Failed to create file");
+    refile_cmd()
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .arg("--reconcile-existing")
+        .arg("false")
+        .arg(source.path())
+        .assert()
+        .success();
+    source
+        .child(format!("{REFILE_BASE}/near/second.txt"))
+        .assert(predicates::path::exists());
+    source
+        .child(".refile.toml")
+        .assert(predicates::path::is_file());
+}
+
+/// Tests that `--leave-unmatched` leaves items older than every bucket alone.
+///
+/// **User Story**: User wants recent files sorted, but old ones left where
+/// they are rather than swept into a catch-all bin.
+///
+/// **Scenario**: Buckets `week=7,month=30` (no catch-all) with a recent and
+/// an old file; refile runs without, then with `--leave-unmatched`.
+///
+/// **Expected**:
+/// - Without the flag, refile refuses the buckets and moves nothing
+/// - With it, the recent file goes to `week` and the old one stays put,
+///   which is only reported with `--verbose`
+#[test]
+fn test_leave_unmatched() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    create_file_with_age(source, "recent.txt", RECENT_FILE_AGE).expect("Failed to create file");
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create file");
+
+    refile_cmd()
+        .arg("--buckets")
+        .arg("week=7,month=30")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("no age limit"));
+    temp_dir
+        .child("recent.txt")
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("--buckets")
+        .arg("week=7,month=30")
+        .arg("--leave-unmatched")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("matches no bucket").not());
+    temp_dir
+        .child(format!("{REFILE_BASE}/week/recent.txt"))
+        .assert(predicates::path::exists());
+    temp_dir.child("old.txt").assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("--buckets")
+        .arg("week=7,month=30")
+        .arg("--leave-unmatched")
+        .arg("--verbose")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("old.txt: matches no bucket"));
+    temp_dir.child("old.txt").assert(predicates::path::exists());
+}
+
+/// Tests `--newer-than` and `--older-than` with a reference file.
+///
+/// **User Story**: User wants to organize only what changed since a marker
+/// file was touched (or only what is older), like `find -newer`.
+///
+/// **Scenario**: A reference file outside the source is 50 days old; the
+/// source holds a 3-day-old and a 100-day-old file. refile runs with
+/// `--newer-than`, then with `--older-than`, then with a missing reference.
+///
+/// **Expected**:
+/// - `--newer-than` moves only the recent file, into its age bucket
+/// - `--older-than` moves only the old file, into its age bucket
+/// - A missing reference file aborts the run with a clear error
+#[test]
+fn test_reference_file_filters() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let reference_dir = TempDir::new().expect("Failed to create reference directory");
+    let source = temp_dir.path();
+    create_file_with_age(reference_dir.path(), "marker", LAST_MONTHS_AGE)
+        .expect("Failed to create reference file");
+    let marker = reference_dir.child("marker");
+    create_file_with_age(source, "recent.txt", RECENT_FILE_AGE).expect("Failed to create file");
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create file");
+
+    refile_cmd()
+        .arg("--newer-than")
+        .arg(marker.path())
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/recent.txt"))
+        .assert(predicates::path::exists());
+    temp_dir.child("old.txt").assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("--older-than")
+        .arg(marker.path())
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/recent.txt"))
+        .assert(predicates::path::exists());
+
+    create_file_with_age(source, "new.txt", RECENT_FILE_AGE).expect("Failed to create file");
+    refile_cmd()
+        .arg("--newer-than")
+        .arg(reference_dir.child("missing").path())
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Cannot read reference file"))
+        .stderr(predicates::str::contains("--newer-than"));
+    temp_dir.child("new.txt").assert(predicates::path::exists());
+}
+
+/// Tests the tally printed after a dry-run and with `--summary`.
+///
+/// **User Story**: User wants a quick overview of what a run does instead of
+/// reading every "would move" line.
+///
+/// **Scenario**: Three recent and two old files are refiled with `--dry-run`,
+/// then for real with `--summary --quiet`.
+///
+/// **Expected**:
+/// - The dry-run lists the moves and a tally: 3 into `last-week`, 2 into
+///   `old-stuff`, 5 in total (60 bytes), no skips or conflicts
+/// - The real run prints the same tally but no per-item lines
+#[test]
+fn test_run_summary_counts() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        create_file_with_age(source, name, RECENT_FILE_AGE).expect("Failed to create file");
+    }
+    for name in ["d.txt", "e.txt"] {
+        create_file_with_age(source, name, OLD_FILE_AGE).expect("Failed to create file");
+    }
+    let tally = |output: &[u8]| {
+        let stdout = String::from_utf8_lossy(output).to_string();
+        let row = |label: &str| {
+            stdout
+                .lines()
+                .find(|line| line.trim_start().starts_with(label))
+                .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        };
+        (
+            row("last-week"),
+            row("old-stuff"),
+            row("Total"),
+            row("Skipped:"),
+            row("Conflicts:"),
+        )
+    };
+    let expected = (
+        Some("last-week 3 36 bytes".to_string()),
+        Some("old-stuff 2 24 bytes".to_string()),
+        Some("Total 5 60 bytes".to_string()),
+        Some("Skipped: 0".to_string()),
+        Some("Conflicts: 0".to_string()),
+    );
+
+    let dry_run = refile_cmd()
+        .arg("--dry-run")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Dry-run summary:"))
+        .stdout(predicates::str::contains("[dry-run] MOVE"));
+    assert_eq!(tally(&dry_run.get_output().stdout), expected);
+
+    let real = refile_cmd()
+        .arg("--summary")
+        .arg("--quiet")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("MOVE").not());
+    assert_eq!(tally(&real.get_output().stdout), expected);
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/e.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests asking before each move with `--interactive`.
+///
+/// **User Story**: User wants to approve moves one at a time, like `rm -i`,
+/// before trusting refile with a directory.
+///
+/// **Scenario**: Four old files are refiled with `--interactive`, answering
+/// `y`, `n` and `a` from a pipe; then without the pipe override, and with
+/// `--dry-run`.
+///
+/// **Expected**:
+/// - `a.txt` is moved, `b.txt` is declined and left in place, and `c.txt` and
+///   `d.txt` are moved without further questions
+/// - Without a terminal on stdin, `--interactive` fails and moves nothing
+/// - `--interactive` cannot be combined with `--dry-run`
+#[test]
+fn test_interactive_confirms_each_move() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    for name in ["a.txt", "b.txt", "c.txt", "d.txt"] {
+        create_file_with_age(source, name, OLD_FILE_AGE).expect("Failed to create file");
+    }
+
+    refile_cmd()
+        .arg("--interactive")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--interactive needs a terminal"));
+    temp_dir.child("a.txt").assert(predicates::path::exists());
+
+    refile_cmd()
+        .args(["--interactive", "--dry-run"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure();
+
+    let prompts = refile_cmd()
+        .args(["--interactive", "--assume-tty"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .write_stdin("y\nn\na\n")
+        .assert()
+        .success()
+        .get_output()
+        .stderr
+        .clone();
+    assert_eq!(
+        String::from_utf8_lossy(&prompts)
+            .matches("[y,n,a,q]")
+            .count(),
+        3
+    );
+    temp_dir.child("b.txt").assert(predicates::path::exists());
+    for name in ["a.txt", "c.txt", "d.txt"] {
+        temp_dir.child(name).assert(predicates::path::missing());
+        temp_dir
+            .child(format!("{OLD_STUFF_BUCKET}/{name}"))
+            .assert(predicates::path::exists());
+    }
+}
+
+/// Tests that hidden items are only refiled with --hidden.
+///
+/// **User Story**: User keeps dotfiles with configuration and state in their
+/// home directory and doesn't want them archived with everything else.
+///
+/// **Scenario**: An old `.bashrc`, an old `.config/` directory and an old
+/// `notes.txt` are refiled, first as usual and then with `--hidden`.
+///
+/// **Expected**:
+/// - The first run only moves `notes.txt`
+/// - With `--hidden`, `.bashrc` and `.config/` are moved out of the source as well
+#[test]
+fn test_hidden_items_need_hidden_flag() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    create_file_with_age(source, ".bashrc", OLD_FILE_AGE).expect("Failed to create .bashrc");
+    create_file_with_age(source, "notes.txt", OLD_FILE_AGE).expect("Failed to create notes.txt");
+    fs::create_dir(source.join(".config")).expect("Failed to create .config");
+    create_file_with_age(&source.join(".config"), "app.toml", OLD_FILE_AGE)
+        .expect("Failed to create app.toml");
+
+    refile_cmd()
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/notes.txt"))
+        .assert(predicates::path::exists());
+    temp_dir.child(".bashrc").assert(predicates::path::exists());
+    temp_dir.child(".config").assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("--hidden")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(".bashrc")
+        .assert(predicates::path::missing());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/.bashrc"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(".config")
+        .assert(predicates::path::missing());
+}
+
+/// Tests that `refile stats` reports the buckets without moving anything.
+///
+/// **User Story**: User periodically audits what is already organized and
+/// wants to see which files have aged out of their bucket.
+///
+/// **Scenario**: `last-week` holds a recent and an old file, `old-stuff` holds
+/// an old file; `refile stats` is run as text, with a glyph configured for
+/// `old-stuff` and `--color always`, and as JSON, then with the buckets of a run that keeps files
+/// in `last-week` for 200 days.
+///
+/// **Expected**:
+/// - The table lists each bucket's items under its glyph label, with one
+///   mislocated item in total
+/// - The JSON has per-bucket counts, sizes, oldest and newest items, and
+///   names `old-stuff` as where the old file in `last-week` belongs
+/// - With `--buckets`, no item is mislocated
+/// - Nothing is moved
+#[test]
+fn test_stats_subcommand_counts_mislocated() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    let last_week = source.join(LAST_WEEK_BUCKET);
+    let old_stuff = source.join(OLD_STUFF_BUCKET);
+    fs::create_dir_all(&last_week).expect("Failed to create last-week");
+    fs::create_dir_all(&old_stuff).expect("Failed to create old-stuff");
+    create_file_with_age(&last_week, "new.txt", RECENT_FILE_AGE).expect("Failed to create file");
+    create_file_with_age(&last_week, "stale.txt", OLD_FILE_AGE).expect("Failed to create file");
+    create_file_with_age(&old_stuff, "old.txt", OLD_FILE_AGE).expect("Failed to create file");
+    let config_home = TempDir::new().expect("Failed to create config directory");
+    write_config(config_home.path(), "[bucket_glyphs]\nold-stuff = \"🗄\"\n")
+        .expect("Failed to write config");
+
+    let text = refile_cmd()
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .args(["stats", "--color", "always"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Mislocated"))
+        .stdout(predicate::str::contains("🗄 old-stuff"))
+        .get_output()
+        .stdout
+        .clone();
+    let total = String::from_utf8_lossy(&text)
+        .lines()
+        .find(|line| line.trim_start().starts_with("Total"))
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "));
+    assert_eq!(total.as_deref(), Some("Total 3 36 bytes 1"));
+
+    let output = refile_cmd()
+        .args(["stats", "--output", "json"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .output()
+        .expect("Failed to run refile stats");
+    assert!(output.status.success());
+    let stats: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("Output is not valid JSON");
+    let last_week_stats = &stats["buckets"]["last-week"];
+    assert_eq!(last_week_stats["count"], 2);
+    assert_eq!(last_week_stats["bytes"], 24);
+    assert_eq!(last_week_stats["oldest"]["age_days"], OLD_FILE_AGE);
+    assert_eq!(last_week_stats["newest"]["age_days"], RECENT_FILE_AGE);
+    assert_eq!(last_week_stats["mislocated"][0]["belongs_in"], "old-stuff");
+    assert_eq!(stats["buckets"]["old-stuff"]["count"], 1);
+    assert_eq!(
+        stats["buckets"]["old-stuff"]["mislocated"]
+            .as_array()
+            .map(Vec::len),
+        Some(0)
+    );
+
+    let output = refile_cmd()
+        .args([
+            "stats",
+            "--output",
+            "json",
+            "--buckets",
+            "last-week=200,old-stuff=null",
+        ])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .output()
+        .expect("Failed to run refile stats");
+    assert!(output.status.success());
+    let stats: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("Output is not valid JSON");
+    assert_eq!(
+        stats["buckets"]["last-week"]["mislocated"]
+            .as_array()
+            .map(Vec::len),
+        Some(0)
+    );
+
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/stale.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// Tests calendar buckets from the config file and from the command line.
+///
+/// **User Story**: User thinks in calendar periods ("this week") rather than
+/// rolling windows ("the last 7 days") and wants buckets to match.
+///
+/// **Scenario**: A config with `mode = "calendar"` and `today`, `this-year`
+/// and catch-all buckets; a file modified now, one 400 days ago and one two
+/// days in the future are refiled. Then a file modified half an hour before
+/// midnight UTC is refiled with the clock pinned to midnight, in UTC and in
+/// a time zone two hours behind. Then `--bucket-mode calendar` with an age
+/// bucket spec.
+///
+/// **Expected**:
+/// - The new and the future file land in `today/`, the old one in `older/`
+/// - The late file was modified yesterday in UTC, but today two hours behind
+/// - A spec with a number of days instead of a period is rejected
+#[test]
+fn test_calendar_buckets() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let config_home = TempDir::new().expect("Failed to create config directory");
+    let source = temp_dir.path();
+    write_config(
+        config_home.path(),
+        "[default]\nmode = \"calendar\"\n\n[default.buckets]\n\
+         today = \"today\"\nyear = \"this-year\"\nolder = \"null\"\n",
+    )
+    .expect("Failed to write config");
+    create_file_with_age(source, "new.txt", 0).expect("Failed to create new.txt");
+    create_file_with_age(source, "ancient.txt", 400).expect("Failed to create ancient.txt");
+    let future = source.join("future.txt");
+    fs::write(&future, "test content").expect("Failed to create future.txt");
+    let in_two_days = SystemTime::now() + Duration::from_secs(2 * SECONDS_PER_DAY);
+    filetime::set_file_mtime(&future, filetime::FileTime::from_system_time(in_two_days))
+        .expect("Failed to set mtime");
+
+    refile_cmd()
+        .env("XDG_CONFIG_HOME", config_home.path())
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    for (bucket, name) in [
+        ("today", "new.txt"),
+        ("older", "ancient.txt"),
+        ("today", "future.txt"),
+    ] {
+        temp_dir
+            .child(format!("{REFILE_BASE}/{bucket}/{name}"))
+            .assert(predicates::path::exists());
+    }
+
+    // 2020-09-13 23:30 UTC, with the clock pinned to 2020-09-14 00:00 UTC
+    let late = source.join("late.txt");
+    for (zone, bucket) in [("utc", "year"), ("-02:00", "today")] {
+        fs::write(&late, "test content").expect("Failed to create late.txt");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_mins(26_667_330);
+        filetime::set_file_mtime(&late, filetime::FileTime::from_system_time(modified))
+            .expect("Failed to set mtime");
+
+        refile_cmd()
+            .env("XDG_CONFIG_HOME", config_home.path())
+            .env("REFILE_NOW", "1600041600")
+            .args(["--time-zone", zone, "--reconcile-existing", "false"])
+            .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+            .assert()
+            .success();
+        let moved = temp_dir.child(format!("{REFILE_BASE}/{bucket}/late.txt"));
+        moved.assert(predicates::path::exists());
+        fs::remove_file(moved.path()).expect("Failed to remove late.txt");
+    }
+
+    refile_cmd()
+        .args([
+            "--bucket-mode",
+            "calendar",
+            "--buckets",
+            "week=7,older=null",
+        ])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid calendar period '7'"));
+}
+
+/// Tests that --preserve-structure recreates source subdirectories in buckets.
+///
+/// **User Story**: User refiles a project tree with `--recursive` and wants
+/// to keep each file's context instead of flattening same-named files into
+/// one bucket directory.
+///
+/// **Scenario**: Old files `a/b/c.txt` and `d/c.txt` are refiled with
+/// `--recursive --preserve-structure`; then a new old `a/b/c.txt` is refiled
+/// the same way with `--allow-rename`.
+///
+/// **Expected**:
+/// - The files land at `old-stuff/a/b/c.txt` and `old-stuff/d/c.txt`
+/// - The second `a/b/c.txt` is renamed within `old-stuff/a/b/`, and the
+///   filed files stay where they are
+#[test]
+fn test_preserve_structure_mirrors_subdirectories() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    fs::create_dir_all(source.join("a/b")).expect("Failed to create a/b");
+    fs::create_dir_all(source.join("d")).expect("Failed to create d");
+    create_file_with_age(&source.join("a/b"), "c.txt", OLD_FILE_AGE)
+        .expect("Failed to create file");
+    create_file_with_age(&source.join("d"), "c.txt", OLD_FILE_AGE).expect("Failed to create file");
+
+    refile_cmd()
+        .args(["--recursive", "--preserve-structure"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/a/b/c.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/d/c.txt"))
+        .assert(predicates::path::exists());
+    temp_dir.child("a/b").assert(predicates::path::exists());
+
+    create_file_with_age(&source.join("a/b"), "c.txt", OLD_FILE_AGE)
+        .expect("Failed to create file");
+    refile_cmd()
+        .args(["--recursive", "--preserve-structure", "--allow-rename"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/a/b/c (1).txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/a/b/c.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("a/b/c.txt")
+        .assert(predicates::path::missing());
+    // Filed files are checked one by one, not moved as directories
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/d/c.txt"))
+        .assert(predicates::path::exists());
+}
+
+/// **User Story**: As a developer writing tests, I want to pin the current
+/// time so that bucket assignment does not depend on the day the tests run.
+///
+/// **Scenario**: A file with a fixed modification time is refiled twice with
+/// `REFILE_NOW` set, once 3 days after that time and once 100 days after.
+///
+/// **Expected**: The file lands in the last-week bucket and then in the
+/// old-stuff bucket, whatever the real clock says. A file modified after the
+/// pinned time is in the future and skipped. An invalid `REFILE_NOW` is
+/// rejected.
+#[test]
+fn test_refile_now_pins_the_clock() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    let file = temp_dir.child("report.txt");
+    file.write_str("test content")
+        .expect("Failed to create file");
+    filetime::set_file_mtime(file.path(), filetime::FileTime::from_system_time(modified))
+        .expect("Failed to set mtime");
+    let now_after = |days: u64| (1_600_000_000 + days * SECONDS_PER_DAY).to_string();
+
+    refile_cmd()
+        .env("REFILE_NOW", now_after(RECENT_FILE_AGE))
+        .arg(temp_dir.path())
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/report.txt"))
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .env("REFILE_NOW", now_after(OLD_FILE_AGE))
+        .arg(temp_dir.path())
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/report.txt"))
+        .assert(predicates::path::exists());
+
+    let draft = temp_dir.child("draft.txt");
+    draft
+        .write_str("test content")
+        .expect("Failed to create file");
+    filetime::set_file_mtime(draft.path(), filetime::FileTime::from_system_time(modified))
+        .expect("Failed to set mtime");
+    refile_cmd()
+        .env("REFILE_NOW", "1599000000")
+        .arg(temp_dir.path())
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("draft.txt: cannot get age"));
+    draft.assert(predicates::path::exists());
+
+    refile_cmd()
+        .env("REFILE_NOW", "yesterday")
+        .arg(temp_dir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("REFILE_NOW"));
+}
+
+/// Tests that --copy fills the buckets and leaves the originals in place.
+///
+/// **User Story**: User stages a backup of an aged snapshot in the refile
+/// structure and wants the source untouched.
+///
+/// **Scenario**: A recent file, an old file and an old directory are refiled
+/// with `--copy`.
+///
+/// **Expected**:
+/// - Each item is copied, contents included, into the bucket its age picks
+/// - The copies keep the modification times of the originals
+/// - The originals are all still in the source
+#[test]
+fn test_copy_leaves_originals() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create recent.txt");
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+    fs::create_dir(source.join("project")).expect("Failed to create project");
+    create_file_with_age(&source.join("project"), "notes.txt", OLD_FILE_AGE)
+        .expect("Failed to create notes.txt");
+    let mtime = SystemTime::now() - Duration::from_secs(OLD_FILE_AGE * SECONDS_PER_DAY);
+    filetime::set_file_mtime(
+        source.join("project"),
+        filetime::FileTime::from_system_time(mtime),
+    )
+    .expect("Failed to set mtime on project");
+
+    refile_cmd()
+        .arg("--copy")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Copied"));
+
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/recent.txt"))
+        .assert("test content");
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert("test content");
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/project/notes.txt"))
+        .assert("test content");
+    temp_dir.child("recent.txt").assert("test content");
+    temp_dir.child("old.txt").assert("test content");
+    temp_dir.child("project/notes.txt").assert("test content");
+
+    // Copies are as old as their originals, so later runs file them alike
+    for name in ["old.txt", "project"] {
+        let modified = |path: &Path| {
+            fs::metadata(path)
+                .and_then(|m| m.modified())
+                .expect("Failed to read mtime")
+        };
+        assert_eq!(
+            modified(&source.join(OLD_STUFF_BUCKET).join(name)),
+            modified(&source.join(name))
+        );
+    }
+}
+
+/// Tests that conflict handling applies to the destination of a copy.
+///
+/// **User Story**: User stages a backup again after the first one, and wants
+/// the existing copies treated like any other conflicting destination.
+///
+/// **Scenario**: An old file is copied twice: the second time with the
+/// default conflict handling, then with `--on-conflict rename`.
+///
+/// **Expected**:
+/// - The second run aborts on the existing copy
+/// - With `--on-conflict rename` a numbered copy is made next to it, and the
+///   original is still in the source
+/// - The copies in the base folder are not copied again
+#[test]
+fn test_copy_conflict_handling() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+
+    refile_cmd()
+        .arg("--copy")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    refile_cmd()
+        .arg("--copy")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure();
+
+    refile_cmd()
+        .args(["--copy", "--on-conflict", "rename"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old (1).txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old (2).txt"))
+        .assert(predicates::path::missing());
+    temp_dir.child("old.txt").assert(predicates::path::exists());
+}
+
+/// Tests that --keep-recent keeps the newest items out of the archive.
+///
+/// **User Story**: User wants the few files they touched last at hand,
+/// however long ago that was, and everything else archived by age.
+///
+/// **Scenario**: Five old files, each a day older than the one before, are
+/// refiled with `--keep-recent 2`.
+///
+/// **Expected**:
+/// - Exactly the two newest go into the first bucket
+/// - The other three go into the bucket their age picks
+#[test]
+fn test_keep_recent() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    let names = ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"];
+    for (days, name) in (OLD_FILE_AGE..).zip(names) {
+        create_file_with_age(source, name, days).expect("Failed to create file");
+    }
+
+    refile_cmd()
+        .args(["--keep-recent", "2"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+
+    let in_first: Vec<_> = fs::read_dir(source.join(LAST_WEEK_BUCKET))
+        .expect("Failed to read first bucket")
+        .map(|entry| entry.expect("Failed to read entry").file_name())
+        .collect();
+    assert_eq!(in_first.len(), 2);
+    for name in &names[..2] {
+        temp_dir
+            .child(format!("{LAST_WEEK_BUCKET}/{name}"))
+            .assert(predicates::path::exists());
+    }
+    for name in &names[2..] {
+        temp_dir
+            .child(format!("{OLD_STUFF_BUCKET}/{name}"))
+            .assert(predicates::path::exists());
+    }
+}
+
+/// Tests that --dedup-hardlinks keeps hard links linked.
+///
+/// **User Story**: User keeps several hard links to the same file in the
+/// source, and wants them archived as links to one file, not as copies.
+///
+/// **Scenario**: An old file and a second hard link to it are refiled with
+/// `--dedup-hardlinks`. Then another pair is refiled with `--interactive`,
+/// declining the move of the first link.
+///
+/// **Expected**:
+/// - The first link is moved into its bucket and the second is recreated
+///   next to it as a link to it
+/// - Both destinations are the same file, still with two links
+/// - When the first link stays, the second is moved on its own
+#[cfg(unix)]
+#[test]
+fn test_dedup_hardlinks() {
+    use std::os::unix::fs::MetadataExt;
+
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "a.txt", OLD_FILE_AGE).expect("Failed to create a.txt");
+    fs::hard_link(source.join("a.txt"), source.join("b.txt")).expect("Failed to link b.txt");
+
+    refile_cmd()
+        .arg("--dedup-hardlinks")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("(hard link to"));
+
+    temp_dir.child("a.txt").assert(predicates::path::missing());
+    temp_dir.child("b.txt").assert(predicates::path::missing());
+    let a = fs::metadata(source.join(OLD_STUFF_BUCKET).join("a.txt"))
+        .expect("Failed to read a.txt in its bucket");
+    let b = fs::metadata(source.join(OLD_STUFF_BUCKET).join("b.txt"))
+        .expect("Failed to read b.txt in its bucket");
+    assert_eq!((a.dev(), a.ino()), (b.dev(), b.ino()));
+    assert_eq!(a.nlink(), 2);
+
+    create_file_with_age(source, "c.txt", OLD_FILE_AGE).expect("Failed to create c.txt");
+    fs::hard_link(source.join("c.txt"), source.join("d.txt")).expect("Failed to link d.txt");
+    refile_cmd()
+        .args(["--dedup-hardlinks", "--interactive", "--assume-tty"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .write_stdin("n\ny\n")
+        .assert()
+        .success();
+    temp_dir.child("c.txt").assert(predicates::path::exists());
+    let d = fs::metadata(source.join(OLD_STUFF_BUCKET).join("d.txt"))
+        .expect("Failed to read d.txt in its bucket");
+    assert_eq!(d.ino(), fs::metadata(source.join("c.txt")).unwrap().ino());
+}
+
+/// Tests that --report appends a CSV row for every planned action.
+///
+/// **User Story**: User audits runs in a spreadsheet, and wants every action
+/// of every run, dry or real, in one growing CSV file.
+///
+/// **Scenario**: Two old files and a recent one are refiled with
+/// `--min-age 30`, first with `--dry-run`, then for real, with the same
+/// report file.
+///
+/// **Expected**:
+/// - The report starts with one header row, and each run adds three rows
+/// - The old files are moves into the old-stuff bucket
+/// - The recent file is a skip, with no destination nor bucket and its reason
+///   as the note
+#[test]
+fn test_action_report_csv() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    let report = source.join("report.csv");
+
+    create_file_with_age(source, "old1.txt", OLD_FILE_AGE).expect("Failed to create old1.txt");
+    create_file_with_age(source, "old2.txt", OLD_FILE_AGE).expect("Failed to create old2.txt");
+    create_file_with_age(source, "recent.txt", RECENT_FILE_AGE)
+        .expect("Failed to create recent.txt");
+
+    for dry_run in [true, false] {
+        let mut cmd = refile_cmd();
+        if dry_run {
+            cmd.arg("--dry-run");
+        }
+        cmd.args(["--min-age", "30", "--report"])
+            .arg(&report)
+            .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+            .assert()
+            .success();
+    }
+
+    let contents = fs::read_to_string(&report).expect("Failed to read report");
+    let mut lines = contents.lines();
+    assert_eq!(
+        lines.next(),
+        Some("source,destination,bucket,age_days,bytes,action,note")
+    );
+    let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+    assert_eq!(rows.len(), 6);
+    for row in &rows {
+        assert_eq!(row.len(), 7);
+        if row[0].ends_with("recent.txt") {
+            assert_eq!(row[1..3], ["", ""]);
+            assert_eq!(row[5], "skip");
+            assert!(!row[6].is_empty());
+        } else {
+            let name = Path::new(row[0])
+                .file_name()
+                .expect("Source has no file name");
+            assert!(Path::new(row[1]).ends_with(Path::new(OLD_STUFF_BUCKET).join(name)));
+            assert_eq!(row[2], "old-stuff");
+            assert_eq!(row[3], OLD_FILE_AGE.to_string());
+            assert_eq!(row[4], "12");
+            assert_eq!(row[5], "move");
+        }
+    }
+}
+
+/// Tests the --empty-dirs policies for source directories a recursive run
+/// leaves empty.
+///
+/// **User Story**: User sweeps a tree of old folders file by file, and wants
+/// to choose whether the folders left behind stay, go, or are archived too.
+///
+/// **Scenario**: An old folder `a` holds only `a/b/c.txt`, a folder `d` holds
+/// an old file and a hidden file, and a folder `empty` was empty before. The
+/// tree is refiled with `--recursive` under each policy.
+///
+/// **Expected**:
+/// - `keep`: `a/b` stays in the source, empty
+/// - `remove`: `a` is removed; `d` (still holding the hidden file) and
+///   `empty` (empty before the run) stay
+/// - `move`: `a` is moved whole into the bucket its old modification time
+///   picks, keeping that time; `d` and `empty` stay
+#[test]
+fn test_empty_dirs_policies() {
+    let old = SystemTime::now() - Duration::from_secs(OLD_FILE_AGE * SECONDS_PER_DAY);
+    let setup = || {
+        let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+        let source = temp_dir.path();
+        fs::create_dir_all(source.join("a/b")).expect("Failed to create a/b");
+        create_file_with_age(&source.join("a/b"), "c.txt", OLD_FILE_AGE)
+            .expect("Failed to create c.txt");
+        fs::create_dir(source.join("d")).expect("Failed to create d");
+        create_file_with_age(&source.join("d"), "e.txt", OLD_FILE_AGE)
+            .expect("Failed to create e.txt");
+        fs::write(source.join("d/.keep"), "").expect("Failed to create .keep");
+        fs::create_dir(source.join("empty")).expect("Failed to create empty");
+        filetime::set_file_mtime(source.join("a"), filetime::FileTime::from_system_time(old))
+            .expect("Failed to set mtime on a");
+        temp_dir
+    };
+    let run = |temp_dir: &TempDir, policy: &str| {
+        refile_cmd()
+            .args(["--recursive", "--empty-dirs", policy])
+            .arg(
+                temp_dir
+                    .path()
+                    .to_str()
+                    .expect("Test path contains invalid UTF-8"),
+            )
+            .assert()
+            .success();
+        temp_dir
+            .child(format!("{OLD_STUFF_BUCKET}/c.txt"))
+            .assert(predicates::path::exists());
+    };
+
+    let temp_dir = setup();
+    run(&temp_dir, "keep");
+    temp_dir.child("a/b").assert(predicates::path::is_dir());
+
+    let temp_dir = setup();
+    run(&temp_dir, "remove");
+    temp_dir.child("a").assert(predicates::path::missing());
+    temp_dir.child("d/.keep").assert(predicates::path::exists());
+    temp_dir.child("empty").assert(predicates::path::is_dir());
+
+    let temp_dir = setup();
+    run(&temp_dir, "move");
+    temp_dir.child("a").assert(predicates::path::missing());
+    let moved = temp_dir.child(format!("{OLD_STUFF_BUCKET}/a"));
+    moved.child("b").assert(predicates::path::is_dir());
+    let modified = fs::metadata(moved.path())
+        .and_then(|m| m.modified())
+        .expect("Failed to read mtime of a");
+    assert_eq!(
+        filetime::FileTime::from_system_time(modified),
+        filetime::FileTime::from_system_time(old)
+    );
+    temp_dir.child("d/.keep").assert(predicates::path::exists());
+    temp_dir.child("empty").assert(predicates::path::is_dir());
+}
+
+/// Tests that `refile undo` also moves back directories `--empty-dirs move`
+/// bucketed.
+///
+/// **User Story**: User sweeps an old folder with `--empty-dirs move`, then
+/// changes their mind and undoes the run.
+///
+/// **Scenario**: An old folder `d` holds only `x.txt`. The source is refiled
+/// with `--recursive --empty-dirs move`, then `refile undo` is run.
+///
+/// **Expected**:
+/// - The run moves `x.txt` and then the emptied `d` into old-stuff
+/// - Undo reports both moves and puts `d/x.txt` back
+/// - No copy of `d` is left behind in the bucket
+#[test]
+fn test_empty_dirs_move_undo() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+    fs::create_dir(source.join("d")).expect("Failed to create d");
+    create_file_with_age(&source.join("d"), "x.txt", OLD_FILE_AGE).expect("Failed to create x.txt");
+    let old = SystemTime::now() - Duration::from_secs(OLD_FILE_AGE * SECONDS_PER_DAY);
+    filetime::set_file_mtime(source.join("d"), filetime::FileTime::from_system_time(old))
+        .expect("Failed to set mtime on d");
+
+    refile_cmd()
+        .args(["--recursive", "--empty-dirs", "move"])
+        .arg(source)
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/x.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/d"))
+        .assert(predicates::path::is_dir());
+
+    refile_cmd()
+        .arg("undo")
+        .arg(source)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Undid 2 moves"));
+    temp_dir.child("d/x.txt").assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/d"))
+        .assert(predicates::path::missing());
+}
+
+/// Tests that `refile flatten` dissolves the buckets again.
+///
+/// **User Story**: User experimented with refile and wants everything back at
+/// the top level, without the base folder.
+///
+/// **Scenario**: Files of different ages and a directory are refiled with
+/// `--source-metadata-cache`. A new file then takes the name of one of them,
+/// and `refile flatten` is run first as is, then with `--on-conflict rename`.
+///
+/// **Expected**:
+/// - The first flatten refuses the conflict and moves nothing
+/// - The second one moves every item back to the top level, the conflicting
+///   one under a numbered name, and removes the base folder along with the
+///   metadata cache in it
+#[test]
+fn test_flatten_restores_flat_layout() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "new.txt", RECENT_FILE_AGE).expect("Failed to create new.txt");
+    create_file_with_age(source, "old.txt", OLD_FILE_AGE).expect("Failed to create old.txt");
+    fs::create_dir(source.join("project")).expect("Failed to create project");
+    create_file_with_age(&source.join("project"), "notes.txt", MEDIUM_FILE_AGE)
+        .expect("Failed to create notes.txt");
+
+    refile_cmd()
+        .arg("--source-metadata-cache")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{REFILE_BASE}/.refile-cache.json"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+    fs::write(source.join("old.txt"), "newer").expect("Failed to create old.txt again");
+
+    refile_cmd()
+        .arg("flatten")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+    temp_dir
+        .child(format!("{LAST_WEEK_BUCKET}/new.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/old.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child("project")
+        .assert(predicates::path::missing());
+
+    refile_cmd()
+        .arg("flatten")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .args(["--on-conflict", "rename"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Flattened 3 items"));
+
+    for item in ["new.txt", "old.txt", "old (1).txt", "project/notes.txt"] {
+        temp_dir.child(item).assert(predicates::path::exists());
+    }
+    assert_eq!(
+        fs::read_to_string(source.join("old.txt")).expect("Failed to read old.txt"),
+        "newer"
+    );
+    temp_dir
+        .child(REFILE_BASE)
+        .assert(predicates::path::missing());
+}
+
+/// Tests that `refile flatten` takes the bucket cap of the runs.
+///
+/// **User Story**: User capped the buckets and wants everything back,
+/// overflow directories included.
+///
+/// **Scenario**: Three old files are refiled with `--bucket-max-files 1`, then
+/// `refile flatten` is run first without the cap, then with it.
+///
+/// **Expected**:
+/// - Without the cap the overflow directories are not buckets, so flatten
+///   fails, moves nothing and keeps the journal
+/// - With the cap every file is moved back and the base folder is removed
+#[test]
+fn test_flatten_overflow_buckets() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        create_file_with_age(source, name, OLD_FILE_AGE).expect("Failed to create file");
+    }
+
+    refile_cmd()
+        .args(["--bucket-max-files", "1"])
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}-3"))
+        .assert(predicates::path::is_dir());
+
+    refile_cmd()
+        .arg("flatten")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("old-stuff-2"))
+        .stderr(predicate::str::contains("--bucket-max-files"));
+    temp_dir
+        .child(format!("{OLD_STUFF_BUCKET}/a.txt"))
+        .assert(predicates::path::exists());
+    temp_dir
+        .child(format!("{REFILE_BASE}/.refile-journal.jsonl"))
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("flatten")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .args(["--bucket-max-files", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Flattened 3 items"));
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        temp_dir.child(name).assert(predicates::path::exists());
+    }
+    temp_dir
+        .child(REFILE_BASE)
+        .assert(predicates::path::missing());
+}
+
+/// Tests that `refile flatten` takes the buckets and layout of the runs.
+///
+/// **User Story**: User sorted by type and age with custom buckets and wants
+/// everything back at the top level.
+///
+/// **Scenario**: An old image and a recent document are refiled with
+/// `--buckets new=7,old=null --classify-by type,age`, then `refile flatten` is
+/// run first without those options, then with them.
+///
+/// **Expected**:
+/// - Without them flatten fails instead of flattening nothing, and keeps the
+///   journal
+/// - With them both files are moved back and the base folder is removed
+#[test]
+fn test_flatten_classified_buckets() {
+    let temp_dir = TempDir::new().expect("Failed to create temporary directory");
+    let source = temp_dir.path();
+
+    create_file_with_age(source, "photo.jpg", OLD_FILE_AGE).expect("Failed to create photo.jpg");
+    create_file_with_age(source, "notes.pdf", RECENT_FILE_AGE).expect("Failed to create notes.pdf");
+    let layout = ["--buckets", "new=7,old=null", "--classify-by", "type,age"];
+
+    refile_cmd()
+        .args(layout)
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .success();
+    temp_dir
+        .child(format!("{REFILE_BASE}/images/old/photo.jpg"))
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("flatten")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("holds items outside its buckets"));
+    temp_dir
+        .child(format!("{REFILE_BASE}/.refile-journal.jsonl"))
+        .assert(predicates::path::exists());
+
+    refile_cmd()
+        .arg("flatten")
+        .arg(source.to_str().expect("Test path contains invalid UTF-8"))
+        .args(layout)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Flattened 2 items"));
+    for name in ["photo.jpg", "notes.pdf"] {
+        temp_dir.child(name).assert(predicates::path::exists());
+    }
+    temp_dir
+        .child(REFILE_BASE)
+        .assert(predicates::path::missing());
+}